@@ -0,0 +1,9 @@
+// Pass the target triple on to the crate, for when the toolchain scripts are built with can't tell
+// the platform it builds for.
+fn main() {
+    println!(
+        "cargo:rustc-env=RUST_SCRIPT_HOST_TRIPLE={}",
+        std::env::var("TARGET").unwrap()
+    );
+    println!("cargo:rerun-if-changed=build.rs");
+}
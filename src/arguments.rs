@@ -1,31 +1,188 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
 use clap::ArgAction;
 
 use crate::build_kind::BuildKind;
+use crate::emit::Emit;
 
 #[derive(Debug)]
 pub struct Args {
     pub script: Option<String>,
     pub script_args: Vec<String>,
     pub expr: bool,
+    pub block: bool,
+    pub output: Output,
     pub loop_: bool,
+    pub json: bool,
+    pub csv: bool,
+    pub parallel_map: bool,
     pub count: bool,
+    pub async_: bool,
+    pub chunk_lines: Option<usize>,
+    pub clip_in: bool,
+    pub clip_out: bool,
+    pub pick: Option<usize>,
     pub base_path: Option<String>,
     pub pkg_path: Option<String>,
+    pub pkg_path_cached: bool,
     pub gen_pkg_only: bool,
+    pub message_format_json: bool,
     pub cargo_output: bool,
+    pub show_warnings: bool,
+    pub build_timings: bool,
+    pub deterministic: bool,
+    pub verify_deterministic: bool,
+    pub notify: Option<Duration>,
     pub clear_cache: bool,
+    pub local_cache: bool,
+    pub ephemeral: bool,
+    pub paths: bool,
+    pub refresh_cache: bool,
+    pub project_cache_max_age: Option<u64>,
+    pub binary_cache_max_size: Option<u64>,
+    pub alloc: Option<String>,
+    pub build_dep: Vec<String>,
     pub debug: bool,
     pub dep: Vec<String>,
+    pub dev_dep: Vec<String>,
+    pub registry: Option<String>,
+    pub patch: Vec<String>,
+    pub enable: Vec<String>,
     pub extern_: Vec<String>,
+    pub import: Vec<String>,
     pub force: bool,
+    pub pre_lint: bool,
+    pub paranoid: bool,
+    pub update: bool,
+    pub edit_on_error: bool,
+    pub structured_main: bool,
+    pub md_block: Option<String>,
+    pub verify_cache: bool,
+    pub remember_failures: bool,
+    pub extract_manifest: bool,
+    pub set_manifest: Option<String>,
+    pub manifest: Option<String>,
     pub unstable_features: Vec<String>,
+    pub build_arg: Vec<String>,
+    pub offline: bool,
+    pub locked: bool,
+    pub frozen: bool,
+    pub cache_key: Option<String>,
+    pub sh: bool,
+    pub session: Option<String>,
+    pub template: Option<String>,
+    pub prelude: Option<String>,
     pub build_kind: BuildKind,
     pub toolchain_version: Option<String>,
+    pub test_matrix: Option<Vec<String>>,
+    pub snapshot: Option<String>,
+    pub snapshot_update: bool,
+    pub bench_compare: Option<f64>,
+    pub doc: bool,
+    pub dep_cost: bool,
+    pub unused_deps: bool,
+    pub emit: Option<Emit>,
+    pub fatpack: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    /// With `--watch`, the command line to run the script with each time: this one, without `--watch`.
+    pub watch: Option<Vec<OsString>>,
+    pub doc_item: Option<String>,
     #[cfg(windows)]
     pub install_file_association: bool,
     #[cfg(windows)]
     pub uninstall_file_association: bool,
+    pub no_network: bool,
+    pub no_lockfile: bool,
+    pub socket_activate: Vec<String>,
     pub wrapper: Option<String>,
+    pub tee: Option<String>,
+    pub capture: bool,
+    pub timestamps: bool,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub with_secrets: Option<String>,
+    pub secrets_teardown: Option<String>,
+    pub subcommand: Option<Subcommand>,
+}
+
+/**
+How the value of an expression is printed.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    /// With the `Debug` formatter.
+    Debug,
+    /// As JSON, with `serde_json`.
+    Json,
+    /// Not at all if it's a `bool`, which is the exit status instead.
+    ExitStatus,
+}
+
+/**
+The subcommands of `rust-script`, which are about `rust-script` itself rather than running a script.
+*/
+#[derive(Debug)]
+pub enum Subcommand {
+    Templates(TemplatesCommand),
+    Add(AddCommand),
+    Remove(RemoveCommand),
+    /// Pin the dependencies of the given script to the versions they resolved to.
+    Pin(String),
+    Update(UpdateCommand),
+}
+
+/**
+Dependencies of a script to update to the newest versions its manifest allows.
+*/
+#[derive(Debug)]
+pub struct UpdateCommand {
+    /// The script whose dependencies to update.
+    pub script: String,
+    /// The dependencies to update, or none to update all of them.
+    pub names: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum TemplatesCommand {
+    /// List the built-in templates and the user's own.
+    List,
+    /// Print the template with the given name.
+    Show(String),
+    /// Write the built-in templates into the given directory.
+    Dump(String),
+}
+
+/**
+Dependencies to add to the manifest embedded in a script.
+*/
+#[derive(Debug)]
+pub struct AddCommand {
+    /// The script to edit.
+    pub script: String,
+    /// The dependencies, as given to `--dep`.
+    pub deps: Vec<String>,
+    /// Features to enable for each of the dependencies.
+    pub features: Vec<String>,
+    /// The registry to take the dependencies from, if not crates.io.
+    pub registry: Option<String>,
+    /// The section of the manifest to add them to, such as `dependencies`.
+    pub section: &'static str,
+}
+
+/**
+Dependencies to remove from the manifest embedded in a script.
+*/
+#[derive(Debug)]
+pub struct RemoveCommand {
+    /// The script to edit.
+    pub script: String,
+    /// The names of the dependencies.
+    pub names: Vec<String>,
+    /// The section of the manifest to remove them from, such as `dependencies`.
+    pub section: &'static str,
 }
 
 impl Args {
@@ -39,11 +196,11 @@ impl Args {
             .about(about)
             .arg(Arg::new("script")
                 .index(1)
-                .help("Script file or expression to execute")
+                .help("Script file (or URL, git:<url>#<ref>:<path>, gh:<user>/<repo>/<path>@<ref>, or - for stdin) or expression to execute")
                 .required_unless_present_any(if cfg!(windows) {
-                    ["clear-cache", "install-file-association", "uninstall-file-association"].iter()
+                    ["clear-cache", "paths", "refresh-cache", "replay", "install-file-association", "uninstall-file-association"].iter()
                 } else {
-                    ["clear-cache"].iter()
+                    ["clear-cache", "paths", "refresh-cache", "replay"].iter()
                 })
                 .conflicts_with_all(if cfg!(windows) {
                     ["install-file-association", "uninstall-file-association"].iter()
@@ -60,6 +217,36 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .requires("script")
             )
+            .arg(Arg::new("stmt")
+                .help("Run the given statement before the expression. Can be given multiple times")
+                .long("stmt")
+                .value_name("STMT")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .requires("expr")
+            )
+            .arg(Arg::new("block")
+                .help("Run the expression as a statement, without displaying a result")
+                .long("block")
+                .action(ArgAction::SetTrue)
+                .requires("expr")
+            )
+            .arg(Arg::new("output")
+                .help("How to print the value of the expression: with the Debug formatter, or as JSON")
+                .long("output")
+                .value_name("FORMAT")
+                .num_args(1)
+                .value_parser(["debug", "json"])
+                .requires("expr")
+                .conflicts_with("block")
+            )
+            .arg(Arg::new("test-expr")
+                .help("Exit with status 0 if the expression is true and 1 if it is false, instead of printing it, for use in shell conditionals")
+                .long("test-expr")
+                .action(ArgAction::SetTrue)
+                .requires("expr")
+                .conflicts_with_all(["block", "output"])
+            )
             .arg(Arg::new("loop")
                 .help("Execute <script> as a literal closure once for each line from stdin")
                 .long("loop")
@@ -67,8 +254,35 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .requires("script")
             )
+            .arg(Arg::new("json")
+                .help("Execute <script> as a literal expression once for each line of JSON from stdin, with the line bound to `json`, and print the result as JSON")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+            )
+            .arg(Arg::new("csv")
+                .help("Execute <script> as a literal expression once for each record of CSV from stdin, with the record bound to `row`, a map from column names to values")
+                .long("csv")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+            )
+            .arg(Arg::new("parallel-map")
+                .help("Execute <script> as a literal expression once for each line from stdin, with the line bound to `line`, on all cores, and print the results in the order of the input")
+                .long("parallel-map")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+            )
             .group(ArgGroup::new("expr_or_loop")
-                .args(["expr", "loop"])
+                .args(["expr", "loop", "json", "csv", "parallel-map"])
+            )
+            .arg(Arg::new("pick")
+                .help("When the script could be one of several files, run the Nth of them, counting from 1")
+                .long("pick")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("script")
+                .conflicts_with("expr_or_loop")
             )
 
             // Options that impact the script being executed.
@@ -85,30 +299,203 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .requires("script")
             )
+            .arg(Arg::new("show-warnings")
+                .help("Show compiler warnings when building, while keeping the rest of cargo's output quiet")
+                .long("show-warnings")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["cargo-output", "test", "bench"])
+            )
+            .arg(Arg::new("build-timings")
+                .help("Have Cargo write an HTML report of how long building each dependency took, and print where it is")
+                .long("build-timings")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["test", "bench", "test-matrix"])
+            )
+            .arg(Arg::new("deterministic")
+                .help("Build the script reproducibly: the same script and Cargo.lock always give a bit-identical binary")
+                .long("deterministic")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+            )
+            .arg(Arg::new("verify-deterministic")
+                .help("Build the script deterministically twice, the second time from scratch, and report whether the binaries are identical")
+                .long("verify-deterministic")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only", "doc", "dep-cost", "fatpack", "watch"])
+            )
+            .arg(Arg::new("notify")
+                .help("Show a desktop notification when building the script takes longer than the given duration (default 10s)")
+                .long("notify")
+                .value_name("DURATION")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("10s")
+                .value_parser(parse_duration)
+                .requires("script")
+            )
             .arg(Arg::new("count")
                 .help("Invoke the loop closure with two arguments: line, and line number")
                 .long("count")
                 .action(ArgAction::SetTrue)
                 .requires("loop")
             )
+            .arg(Arg::new("async")
+                .help("Run the expression or loop script on a tokio runtime, so that it can use `.await` (implied by using `.await`)")
+                .long("async")
+                .visible_alias("tokio")
+                .action(ArgAction::SetTrue)
+                .requires("expr_or_loop")
+                .conflicts_with_all(["output", "test-expr", "count", "chunk-lines", "json", "csv", "parallel-map", "template"])
+            )
+            .arg(Arg::new("chunk-lines")
+                .help("Invoke the loop closure with up to N lines at a time, rather than one")
+                .long("chunk-lines")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("loop")
+                .conflicts_with("count")
+            )
+            .arg(Arg::new("clip-in")
+                .help("Feed the contents of the clipboard to the expression or loop script as standard input")
+                .long("clip-in")
+                .action(ArgAction::SetTrue)
+                .requires("expr_or_loop")
+            )
+            .arg(Arg::new("clip-out")
+                .help("Copy the standard output of the expression or loop script to the clipboard, instead of showing it")
+                .long("clip-out")
+                .action(ArgAction::SetTrue)
+                .requires("expr_or_loop")
+                .conflicts_with_all(["tee_or_capture", "snapshot"])
+            )
             .arg(Arg::new("debug")
                 .help("Build a debug executable, not an optimised one")
                 .long("debug")
                 .action(ArgAction::SetTrue)
             )
             .arg(Arg::new("dep")
-                .help("Add a dependency - either just the package name (for the latest version) or as `name=version`")
+                .help("Add a dependency - either just the package name (for the latest version) or as `name=version`, with features as `name=version,features=[a,b]` or `name@version:a,b`, from git as `name=git+URL#branch=NAME`, from a directory as `name=path:DIR`, renamed as `name=crates:PACKAGE@version` or `PACKAGE=version as name`, from another registry as `name=version,registry=NAME`, or without its default features as `name!=version` or `name=version,no-default`")
                 .long("dep")
                 .short('d')
                 .action(ArgAction::Append)
             )
+            .arg(Arg::new("alloc")
+                .help("Use jemalloc or mimalloc as the global allocator")
+                .long("alloc")
+                .value_name("ALLOCATOR")
+                .num_args(1)
+                .value_parser(["jemalloc", "mimalloc"])
+            )
+            .arg(Arg::new("build-dep")
+                .help("Add a build-dependency, for the script's build script - either just the package name (for the latest version) or as `name=version`")
+                .long("build-dep")
+                .action(ArgAction::Append)
+            )
+            .arg(Arg::new("dev-dep")
+                .help("Add a dev-dependency, only used by `--test` and `--bench` - either just the package name (for the latest version) or as `name=version`")
+                .long("dev-dep")
+                .action(ArgAction::Append)
+            )
+            .arg(Arg::new("patch")
+                .help("Override a crate wherever it's used, as with `[patch]` in a manifest, given as the source to patch followed by a dependency, e.g. `crates-io:foo=path:../foo`; can be given multiple times")
+                .long("patch")
+                .value_name("SOURCE:DEP")
+                .action(ArgAction::Append)
+            )
+            .arg(Arg::new("registry")
+                .help("Take the dependencies given on the command line from this registry, as configured for Cargo, instead of crates.io, unless they name one with `name=version,registry=NAME`")
+                .long("registry")
+                .value_name("NAME")
+                .num_args(1)
+            )
+            .arg(Arg::new("enable")
+                .help("Enable a feature defined in the script's embedded manifest, along with the optional dependencies it activates")
+                .long("enable")
+                .value_name("FEATURE")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+            )
             .arg(Arg::new("extern")
-                .help("Adds an `#[macro_use] extern crate name;` item for expressions and loop scripts")
+                .help("Adds an `#[macro_use] extern crate name;` item for expressions and loop scripts. Given as `name=version`, the crate is also added as a dependency")
                 .long("extern")
                 .short('x')
                 .num_args(1..)
                 .requires("expr_or_loop")
             )
+            .arg(Arg::new("import")
+                .help("Adds a `use path;` item for expressions and loop scripts, e.g. `-i std::collections::HashMap`; can be given multiple times")
+                .long("import")
+                .short('i')
+                .value_name("PATH")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .requires("expr_or_loop")
+            )
+            .arg(Arg::new("sh")
+                .help("Provide a `sh!(\"cmd {}\", x)` macro to expressions and loop scripts, which runs a shell command and returns its exit status and standard output")
+                .long("sh")
+                .action(ArgAction::SetTrue)
+                .requires("expr_or_loop")
+            )
+            .arg(Arg::new("session")
+                .help("Provide a `session` module to expressions and loop scripts, for keeping named values between runs in the given session")
+                .long("session")
+                .value_name("NAME")
+                .num_args(1)
+                .requires("expr_or_loop")
+            )
+            .arg(Arg::new("template")
+                .help("Put expressions and loop scripts in the given template, one of the user's templates or a built-in one")
+                .long("template")
+                .value_name("NAME")
+                .num_args(1)
+                .requires("expr_or_loop")
+                .conflicts_with_all(["block", "count"])
+            )
+            .arg(Arg::new("prelude")
+                .help("Put the contents of the given file, such as `use` statements and helper functions, ahead of expressions, instead of the prelude.rs in the configuration directory")
+                .long("prelude")
+                .value_name("FILE")
+                .num_args(1)
+                .requires("expr")
+            )
+            .arg(Arg::new("build-arg")
+                .help("Pass an extra argument to cargo when building the script, e.g. `--build-arg=--offline`; can be given multiple times")
+                .long("build-arg")
+                .num_args(1)
+                .allow_hyphen_values(true)
+                .action(ArgAction::Append)
+            )
+            .arg(Arg::new("offline")
+                .help("Have cargo run without accessing the network, as with `cargo --offline`, also for --fatpack, --refresh-cache and the pin and update subcommands")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .global(true)
+            )
+            .arg(Arg::new("locked")
+                .help("Have cargo fail rather than update the script's Cargo.lock, as with `cargo --locked`, also for --fatpack, --refresh-cache and the pin and update subcommands")
+                .long("locked")
+                .action(ArgAction::SetTrue)
+                .global(true)
+            )
+            .arg(Arg::new("frozen")
+                .help("Both --offline and --locked, as with `cargo --frozen`")
+                .long("frozen")
+                .action(ArgAction::SetTrue)
+                .global(true)
+            )
+            .arg(Arg::new("cache-key")
+                .help("Cache the script separately for each given key, e.g. to keep builds apart that differ in ways rust-script can't see")
+                .long("cache-key")
+                .value_name("KEY")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("pkg_path")
+            )
             .arg(Arg::new("unstable_features")
                 .help("Add a #![feature] declaration to the crate")
                 .long("unstable-feature")
@@ -123,6 +510,45 @@ impl Args {
                 .long("clear-cache")
                 .action(ArgAction::SetTrue),
             )
+            .arg(Arg::new("local-cache")
+                .help("Keep the generated package and build artifacts in a .rust-script directory next to the script, instead of the user's cache directory")
+                .long("local-cache")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "clear-cache"])
+            )
+            .arg(Arg::new("ephemeral")
+                .help("Generate the package and build the script in a temporary directory that is removed afterwards, instead of the cache")
+                .long("ephemeral")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["local-cache", "clear-cache", "pkg_path"])
+            )
+            .arg(Arg::new("paths")
+                .help("Print the directories used by rust-script and Cargo, and exit")
+                .long("paths")
+                .exclusive(true)
+                .action(ArgAction::SetTrue),
+            )
+            .arg(Arg::new("refresh-cache")
+                .help("Rebuild the cached scripts whose toolchain or dependencies changed since they were built, and exit")
+                .long("refresh-cache")
+                .exclusive(true)
+                .action(ArgAction::SetTrue),
+            )
+            .arg(Arg::new("project-cache-max-age")
+                .help("Remove generated projects from the cache after this many days without use [default: 7]")
+                .long("project-cache-max-age")
+                .value_name("DAYS")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+            )
+            .arg(Arg::new("binary-cache-max-size")
+                .help("Remove the least recently used binary caches once they exceed this many megabytes together [default: 2048]")
+                .long("binary-cache-max-size")
+                .value_name("MB")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+            )
             .arg(Arg::new("force")
                 .help("Force the script to be rebuilt")
                 .long("force")
@@ -130,6 +556,82 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .requires("script")
             )
+            .arg(Arg::new("pre-lint")
+                .help("Check the script for common mistakes before compiling it")
+                .long("pre-lint")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("structured-main")
+                .help("Print the `Ok` value returned by the script's `main` as JSON, and an `Err` as a JSON object on stderr")
+                .long("structured-main")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "test", "bench"])
+            )
+            .arg(Arg::new("md-block")
+                .help("Only run the `rust` code blocks of a Markdown script that are named NAME, as in ```rust NAME")
+                .long("md-block")
+                .value_name("NAME")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("edit-on-error")
+                .help("If the script fails to compile, open it in $VISUAL or $EDITOR at the first error, and offer to run it again")
+                .long("edit-on-error")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("update")
+                .help("Fetch a script from a git repository or an HTTP(S) URL again, instead of using the copy in the cache")
+                .long("update")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("paranoid")
+                .help("Always read the script, even if its modification time and size are unchanged since the last run")
+                .long("paranoid")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("verify-cache")
+                .help("Check a cached binary against the sources and the binary recorded when it was built before executing it, rebuilding it if they don't match")
+                .long("verify-cache")
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("remember-failures")
+                .help("If the script failed to compile before and hasn't changed since, fail with the same error instead of building it again")
+                .long("remember-failures")
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("extract-manifest")
+                .help("Print the manifest embedded in the script, and exit")
+                .long("extract-manifest")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "set-manifest"])
+            )
+            .arg(Arg::new("manifest")
+                .help("Merge the manifest in the given file with the one embedded in the script, which takes precedence, e.g. to share dependencies between scripts")
+                .long("manifest")
+                .value_name("FILE")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
+            .arg(Arg::new("set-manifest")
+                .help("Replace the manifest embedded in the script with the contents of the given file ('-' for stdin), and exit")
+                .long("set-manifest")
+                .value_name("FILE")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("expr_or_loop")
+            )
             .arg(Arg::new("gen_pkg_only")
                 .help("Generate the Cargo package and print the path to it, but don't compile or run it")
                 .long("package")
@@ -138,6 +640,14 @@ impl Args {
                 .requires("script")
                 .conflicts_with_all(["debug", "force", "test", "bench"])
             )
+            .arg(Arg::new("message-format")
+                .help("With --package, how to print where the package is: its path (human), or a JSON object describing it (json)")
+                .long("message-format")
+                .value_name("FMT")
+                .num_args(1)
+                .value_parser(["human", "json"])
+                .requires("gen_pkg_only")
+            )
             .arg(Arg::new("pkg_path")
                 .help("Specify where to place the generated Cargo package")
                 .long("pkg-path")
@@ -145,6 +655,12 @@ impl Args {
                 .requires("script")
                 .conflicts_with_all(["clear-cache", "force"])
             )
+            .arg(Arg::new("pkg_path_cached")
+                .help("Reuse a previously built binary for a --pkg-path package if it is newer than the script and manifest, instead of always invoking cargo")
+                .long("pkg-path-cached")
+                .action(ArgAction::SetTrue)
+                .requires("pkg_path")
+            )
             .arg(Arg::new("test")
                 .help("Compile and run tests")
                 .long("test")
@@ -157,6 +673,103 @@ impl Args {
                 .action(ArgAction::SetTrue)
                 .conflicts_with_all(["test", "debug", "force"])
             )
+            .arg(Arg::new("bench-compare")
+                .help("Compare the benchmark results with those of the previous run, flagging benchmarks that got slower by more than the given percentage (default 10)")
+                .long("bench-compare")
+                .value_name("PERCENT")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("10")
+                .value_parser(clap::value_parser!(f64))
+                .requires("bench")
+            )
+            .arg(Arg::new("test-matrix")
+                .help("Compile and run tests with each of the given toolchains in turn, e.g. 'stable,beta,nightly', and report which passed")
+                .long("test-matrix")
+                .value_name("TOOLCHAINS")
+                .num_args(1)
+                .value_delimiter(',')
+                .requires("script")
+                .conflicts_with_all(["bench", "debug", "force", "gen_pkg_only", "toolchain"])
+            )
+            .arg(Arg::new("snapshot")
+                .help("Run the script with the inputs recorded in the given directory, and compare its output and exit code with the snapshots there")
+                .long("snapshot")
+                .value_name("DIR")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with_all(["bench", "test", "test-matrix", "gen_pkg_only"])
+            )
+            .arg(Arg::new("snapshot-update")
+                .help("With --snapshot, write the snapshots instead of comparing with them")
+                .long("snapshot-update")
+                .action(ArgAction::SetTrue)
+                .requires("snapshot")
+            )
+            .arg(Arg::new("dep-cost")
+                .help("Print how many crates each dependency of the script pulls in, how much source code they have and how long they took to build with --build-timings, and exit")
+                .long("dep-cost")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only", "doc", "doc-item"])
+            )
+            .arg(Arg::new("unused-deps")
+                .help("Report the dependencies the script declares but never uses, and exit")
+                .long("unused-deps")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only", "doc", "doc-item", "dep-cost", "fatpack", "watch"])
+            )
+            .arg(Arg::new("emit")
+                .help("Print the assembly, LLVM IR or MIR of the script, or write it to FILE, and exit")
+                .long("emit")
+                .value_name("KIND[=FILE]")
+                .num_args(1)
+                .value_parser(Emit::parse)
+                .requires("script")
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only", "doc", "doc-item", "dep-cost", "unused-deps", "fatpack", "watch"])
+            )
+            .arg(Arg::new("record")
+                .help("Record the command line, working directory, environment and script to the given file, for replaying the run with --replay")
+                .long("record")
+                .value_name("FILE")
+                .num_args(1)
+                .requires("script")
+            )
+            .arg(Arg::new("replay")
+                .help("Run a script again as recorded with --record")
+                .long("replay")
+                .value_name("FILE")
+                .num_args(1)
+                .exclusive(true)
+            )
+            .arg(Arg::new("watch")
+                .help("Run the script again whenever it, or a Rust file next to it, changes")
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "clear-cache", "gen_pkg_only", "test-matrix", "doc", "dep-cost", "fatpack", "record"])
+            )
+            .arg(Arg::new("fatpack")
+                .help("Bundle the script with the sources of its dependencies into a single file that builds offline, and exit")
+                .long("fatpack")
+                .value_name("OUT")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with_all(["expr_or_loop", "test", "bench", "test-matrix", "gen_pkg_only", "doc", "dep-cost"])
+            )
+            .arg(Arg::new("doc")
+                .help("Open the documentation of the script and its dependencies, building it only if it's missing or out of date")
+                .long("doc")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only"])
+            )
+            .arg(Arg::new("doc-item")
+                .help("Open the documentation of the given item, e.g. 'regex::Regex' (implies --doc)")
+                .long("doc-item")
+                .value_name("PATH")
+                .num_args(1)
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only"])
+            )
             .arg(Arg::new("toolchain")
                 .help("Build the script using the given toolchain version")
                 .long("toolchain")
@@ -170,6 +783,83 @@ impl Args {
                 .long("wrapper")
                 .short('w')
                 .num_args(1)
+            )
+            .arg(Arg::new("no-network")
+                .help("Run the script without network access (on Linux in a network namespace of its own, elsewhere only by pointing the proxy environment variables nowhere); it is still built with it")
+                .long("no-network")
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("no-lockfile")
+                .help("Don't keep a copy of the script's Cargo.lock next to it as <script>.lock, nor build with one kept there")
+                .long("no-lockfile")
+                .action(ArgAction::SetTrue)
+            )
+            .arg(Arg::new("socket-activate")
+                .help("Listen on the given address (e.g. 127.0.0.1:8080, 8080 or unix:/tmp/script.sock), and pass the socket to the script with the systemd LISTEN_FDS protocol; can be given more than once")
+                .long("socket-activate")
+                .value_name("ADDR")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .requires("script")
+                .conflicts_with_all(["test", "bench", "test-matrix", "gen_pkg_only", "snapshot", "doc", "dep-cost", "unused-deps", "emit", "fatpack"])
+            )
+            .arg(Arg::new("tee")
+                .help("Copy the standard output and standard error of the script to the given file")
+                .long("tee")
+                .value_name("FILE")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("gen_pkg_only")
+            )
+            .arg(Arg::new("capture")
+                .help("Record the output of the script like --tee (by default to a log file in the rust-script cache), but don't show it")
+                .long("capture")
+                .action(ArgAction::SetTrue)
+                .requires("script")
+                .conflicts_with("gen_pkg_only")
+            )
+            .group(ArgGroup::new("tee_or_capture")
+                .args(["tee", "capture"])
+                .multiple(true)
+            )
+            .arg(Arg::new("timestamps")
+                .help("Prefix each line recorded by --tee or --capture with a timestamp")
+                .long("timestamps")
+                .action(ArgAction::SetTrue)
+                .requires("tee_or_capture")
+            )
+            .arg(Arg::new("retries")
+                .help("Re-run the script up to this many times for as long as it exits with a nonzero code")
+                .long("retries")
+                .value_name("N")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u32))
+                .requires("script")
+                .conflicts_with("gen_pkg_only")
+            )
+            .arg(Arg::new("with-secrets")
+                .help("Run the given command before the script, and pass the NAME=VALUE lines it prints to the script as environment variables")
+                .long("with-secrets")
+                .value_name("CMD")
+                .num_args(1)
+                .requires("script")
+                .conflicts_with("gen_pkg_only")
+            )
+            .arg(Arg::new("secrets-teardown")
+                .help("Run the given command after the script exits, with the environment variables from --with-secrets, e.g. to revoke them")
+                .long("secrets-teardown")
+                .value_name("CMD")
+                .num_args(1)
+                .requires("with-secrets")
+            )
+            .arg(Arg::new("retry-delay")
+                .help("How long to wait between retries, e.g. '500ms', '10s' or '2m'")
+                .long("retry-delay")
+                .value_name("DURATION")
+                .num_args(1)
+                .default_value("1s")
+                .value_parser(parse_duration)
+                .requires("retries")
             );
 
         #[cfg(windows)]
@@ -195,7 +885,159 @@ impl Args {
                     .args(["install-file-association", "uninstall-file-association"]),
             );
 
-        let mut m = app.get_matches();
+        let app = app
+            // Subcommands, for when the first argument isn't a script.
+            .subcommand_negates_reqs(true)
+            .args_conflicts_with_subcommands(true)
+            .subcommand(Command::new("templates")
+                .about("List, show or dump the templates expressions and loop scripts are put in")
+                .subcommand_required(true)
+                .subcommand(Command::new("list")
+                    .about("List the built-in templates and your own")
+                )
+                .subcommand(Command::new("show")
+                    .about("Print a template")
+                    .arg(Arg::new("name").required(true))
+                )
+                .subcommand(Command::new("dump")
+                    .about("Write the built-in templates into a directory, as a starting point for your own")
+                    .arg(Arg::new("dir").required(true))
+                )
+            )
+            .subcommand(Command::new("add")
+                .about("Add dependencies to the manifest embedded in a script")
+                .arg(Arg::new("script").required(true))
+                .arg(Arg::new("deps")
+                    .help("Dependencies, in any of the forms --dep accepts, e.g. `serde`, `serde=1.0` or `regex@1:unicode`")
+                    .required(true)
+                    .num_args(1..)
+                )
+                .arg(Arg::new("features")
+                    .help("Features to enable for the dependencies")
+                    .long("features")
+                    .short('F')
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                )
+                .arg(Arg::new("registry")
+                    .help("Take the dependencies from this registry, as configured for Cargo")
+                    .long("registry")
+                    .num_args(1)
+                )
+                .arg(Arg::new("dev")
+                    .help("Add them as dev-dependencies")
+                    .long("dev")
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(Arg::new("build")
+                    .help("Add them as build-dependencies")
+                    .long("build")
+                    .conflicts_with("dev")
+                    .action(ArgAction::SetTrue)
+                )
+            )
+            .subcommand(Command::new("rm")
+                .about("Remove dependencies from the manifest embedded in a script")
+                .arg(Arg::new("script").required(true))
+                .arg(Arg::new("names")
+                    .help("Names of the dependencies")
+                    .required(true)
+                    .num_args(1..)
+                )
+                .arg(Arg::new("dev")
+                    .help("Remove them from the dev-dependencies")
+                    .long("dev")
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(Arg::new("build")
+                    .help("Remove them from the build-dependencies")
+                    .long("build")
+                    .conflicts_with("dev")
+                    .action(ArgAction::SetTrue)
+                )
+            )
+            .subcommand(Command::new("pin")
+                .about("Pin the dependencies in the manifest embedded in a script to the exact versions they resolved to")
+                .arg(Arg::new("script").required(true))
+            )
+            .subcommand(Command::new("update")
+                .about("Update the dependencies of a script to the newest versions its manifest allows, and rebuild it on the next run")
+                .arg(Arg::new("script").required(true))
+                .arg(Arg::new("names")
+                    .help("Only update these dependencies")
+                    .num_args(0..)
+                )
+            );
+
+        // A script named like a subcommand, such as `update.rs` run as `rust-script update`, is
+        // still run: everything after `--` is taken for the script and its arguments.
+        let mut argv: Vec<OsString> = std::env::args_os().collect();
+        if argv.get(1).is_some_and(|first| {
+            app.get_subcommands().any(|sub| first == sub.get_name())
+                && names_script_file(Path::new(first))
+        }) {
+            argv.insert(1, "--".into());
+        }
+        let mut m = app.clone().get_matches_from(argv);
+        let watch = m.get_flag("watch").then(|| command_line(&app, &m, "watch"));
+
+        // Cargo's flags are global, so they're in the matches of the subcommand they follow.
+        let cargo_flag = |name: &str| {
+            m.get_flag(name) || m.subcommand().is_some_and(|(_, sub)| sub.get_flag(name))
+        };
+        let (offline, locked, frozen) = (
+            cargo_flag("offline"),
+            cargo_flag("locked"),
+            cargo_flag("frozen"),
+        );
+
+        let subcommand = match m.remove_subcommand() {
+            Some((name, mut templates)) if name == "templates" => {
+                Some(Subcommand::Templates(match templates.remove_subcommand() {
+                    Some((name, mut sub)) if name == "show" => TemplatesCommand::Show(
+                        sub.remove_one::<String>("name").expect("name is required"),
+                    ),
+                    Some((name, mut sub)) if name == "dump" => TemplatesCommand::Dump(
+                        sub.remove_one::<String>("dir").expect("dir is required"),
+                    ),
+                    _ => TemplatesCommand::List,
+                }))
+            }
+            Some((name, mut add)) if name == "add" => Some(Subcommand::Add(AddCommand {
+                script: add.remove_one("script").expect("script is required"),
+                deps: add
+                    .remove_many("deps")
+                    .expect("deps are required")
+                    .collect(),
+                features: add
+                    .remove_many("features")
+                    .map(|features| features.filter(|f: &String| !f.is_empty()).collect())
+                    .unwrap_or_default(),
+                registry: add.remove_one("registry"),
+                section: deps_section(&add),
+            })),
+            Some((name, mut rm)) if name == "rm" => Some(Subcommand::Remove(RemoveCommand {
+                script: rm.remove_one("script").expect("script is required"),
+                names: rm
+                    .remove_many("names")
+                    .expect("names are required")
+                    .collect(),
+                section: deps_section(&rm),
+            })),
+            Some((name, mut pin)) if name == "pin" => Some(Subcommand::Pin(
+                pin.remove_one("script").expect("script is required"),
+            )),
+            Some((name, mut update)) if name == "update" => {
+                Some(Subcommand::Update(UpdateCommand {
+                    script: update.remove_one("script").expect("script is required"),
+                    names: update
+                        .remove_many("names")
+                        .map(|names| names.collect())
+                        .unwrap_or_default(),
+                }))
+            }
+            _ => None,
+        };
 
         let script_and_args: Option<Vec<String>> = m
             .remove_many::<String>("script")
@@ -214,40 +1056,327 @@ impl Args {
             script_args = Vec::new();
         }
 
+        let statements: Vec<String> = m
+            .remove_many::<String>("stmt")
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let script = script.map(|expr| compose_expr(&statements, &expr));
+
         Self {
             script,
             script_args,
 
             expr: m.get_flag("expr"),
+            block: m.get_flag("block"),
+            output: match m.get_one::<String>("output").map(String::as_str) {
+                Some("json") => Output::Json,
+                _ if m.get_flag("test-expr") => Output::ExitStatus,
+                _ => Output::Debug,
+            },
             loop_: m.get_flag("loop"),
+            json: m.get_flag("json"),
+            csv: m.get_flag("csv"),
+            parallel_map: m.get_flag("parallel-map"),
             count: m.get_flag("count"),
+            async_: m.get_flag("async"),
+            chunk_lines: m.get_one::<u64>("chunk-lines").map(|&n| n as usize),
+            clip_in: m.get_flag("clip-in"),
+            clip_out: m.get_flag("clip-out"),
 
+            pick: m.get_one::<u64>("pick").map(|&pick| pick as usize),
             base_path: m.get_one::<String>("base-path").map(Into::into),
+            message_format_json: m
+                .get_one::<String>("message-format")
+                .is_some_and(|format| format == "json"),
             pkg_path: m.get_one::<String>("pkg_path").map(Into::into),
+            pkg_path_cached: m.get_flag("pkg_path_cached"),
             gen_pkg_only: m.get_flag("gen_pkg_only"),
             cargo_output: m.get_flag("cargo-output"),
+            show_warnings: m.get_flag("show-warnings"),
+            build_timings: m.get_flag("build-timings"),
+            deterministic: m.get_flag("deterministic"),
+            verify_deterministic: m.get_flag("verify-deterministic"),
+            notify: m.get_one::<Duration>("notify").copied(),
             clear_cache: m.get_flag("clear-cache"),
+            local_cache: m.get_flag("local-cache"),
+            ephemeral: m.get_flag("ephemeral"),
+            paths: m.get_flag("paths"),
+            refresh_cache: m.get_flag("refresh-cache"),
+            project_cache_max_age: m.get_one::<u64>("project-cache-max-age").copied(),
+            binary_cache_max_size: m.get_one::<u64>("binary-cache-max-size").copied(),
+            alloc: m.get_one::<String>("alloc").cloned(),
+            build_dep: m
+                .remove_many::<String>("build-dep")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
             debug: m.get_flag("debug"),
             dep: m
                 .remove_many::<String>("dep")
                 .map(|values| values.collect())
                 .unwrap_or_default(),
+            dev_dep: m
+                .remove_many::<String>("dev-dep")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            registry: m.get_one::<String>("registry").cloned(),
+            patch: m
+                .remove_many::<String>("patch")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            enable: m
+                .remove_many::<String>("enable")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
             extern_: m
                 .remove_many::<String>("extern")
                 .map(|values| values.collect())
                 .unwrap_or_default(),
+            import: m
+                .remove_many::<String>("import")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
             force: m.get_flag("force"),
+            pre_lint: m.get_flag("pre-lint"),
+            paranoid: m.get_flag("paranoid"),
+            update: m.get_flag("update"),
+            edit_on_error: m.get_flag("edit-on-error"),
+            structured_main: m.get_flag("structured-main"),
+            md_block: m.get_one::<String>("md-block").map(Into::into),
+            verify_cache: m.get_flag("verify-cache"),
+            remember_failures: m.get_flag("remember-failures"),
+            extract_manifest: m.get_flag("extract-manifest"),
+            set_manifest: m.get_one::<String>("set-manifest").map(Into::into),
+            manifest: m.get_one::<String>("manifest").map(Into::into),
             unstable_features: m
                 .remove_many::<String>("unstable_features")
                 .map(|values| values.collect())
                 .unwrap_or_default(),
-            build_kind: BuildKind::from_flags(m.get_flag("test"), m.get_flag("bench")),
+            sh: m.get_flag("sh"),
+            session: m.get_one::<String>("session").map(Into::into),
+            template: m.get_one::<String>("template").map(Into::into),
+            prelude: m.get_one::<String>("prelude").map(Into::into),
+            cache_key: m.get_one::<String>("cache-key").map(Into::into),
+            build_arg: m
+                .remove_many::<String>("build-arg")
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            offline,
+            locked,
+            frozen,
+            build_kind: BuildKind::from_flags(
+                m.get_flag("test") || m.contains_id("test-matrix"),
+                m.get_flag("bench"),
+            ),
+            test_matrix: m
+                .remove_many::<String>("test-matrix")
+                .map(|values| values.collect()),
+            snapshot: m.get_one::<String>("snapshot").map(Into::into),
+            snapshot_update: m.get_flag("snapshot-update"),
+            bench_compare: m.get_one::<f64>("bench-compare").copied(),
+            doc: m.get_flag("doc") || m.contains_id("doc-item"),
+            dep_cost: m.get_flag("dep-cost"),
+            unused_deps: m.get_flag("unused-deps"),
+            emit: m.get_one::<Emit>("emit").cloned(),
+            fatpack: m.get_one::<String>("fatpack").map(Into::into),
+            record: m.get_one::<String>("record").map(Into::into),
+            replay: m.get_one::<String>("replay").map(Into::into),
+            watch,
+            doc_item: m.get_one::<String>("doc-item").map(Into::into),
             toolchain_version: m.get_one::<String>("toolchain").map(Into::into),
             #[cfg(windows)]
             install_file_association: m.get_flag("install-file-association"),
             #[cfg(windows)]
             uninstall_file_association: m.get_flag("uninstall-file-association"),
             wrapper: m.get_one::<String>("wrapper").map(Into::into),
+            no_network: m.get_flag("no-network"),
+            no_lockfile: m.get_flag("no-lockfile"),
+            socket_activate: m
+                .get_many::<String>("socket-activate")
+                .map(|addrs| addrs.cloned().collect())
+                .unwrap_or_default(),
+            tee: m.get_one::<String>("tee").map(Into::into),
+            capture: m.get_flag("capture"),
+            timestamps: m.get_flag("timestamps"),
+            retries: m.get_one::<u32>("retries").copied().unwrap_or(0),
+            retry_delay: m
+                .get_one::<Duration>("retry-delay")
+                .copied()
+                .unwrap_or_default(),
+            with_secrets: m.get_one::<String>("with-secrets").map(Into::into),
+            secrets_teardown: m.get_one::<String>("secrets-teardown").map(Into::into),
+            subcommand,
         }
     }
 }
+
+// The command line giving the matches `m` of `app` again, without the argument `skip`: the options
+// given on it, in the order `app` has them, then the positional arguments after a `--`.
+fn command_line(app: &clap::Command, m: &clap::ArgMatches, skip: &str) -> Vec<OsString> {
+    let mut options = Vec::new();
+    let mut positionals = Vec::new();
+    for arg in app.get_arguments() {
+        let id = arg.get_id().as_str();
+        if id == skip || m.value_source(id) != Some(clap::parser::ValueSource::CommandLine) {
+            continue;
+        }
+        let values = m.get_raw(id).into_iter().flatten();
+        match arg.get_long() {
+            _ if arg.is_positional() => positionals.extend(values.map(OsString::from)),
+            Some(long) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                options.push(format!("--{}", long).into())
+            }
+            Some(long) => options.extend(values.map(|value| {
+                let mut option = OsString::from(format!("--{}=", long));
+                option.push(value);
+                option
+            })),
+            None => unreachable!("all options have a long name"),
+        }
+    }
+    if !positionals.is_empty() {
+        options.push("--".into());
+        options.extend(positionals);
+    }
+    options
+}
+
+// Whether `path` names a script file, as the script argument would be looked up.
+fn names_script_file(path: &Path) -> bool {
+    path.is_file()
+        || (path.extension().is_none()
+            && ["ers", "rs"]
+                .iter()
+                .any(|ext| path.with_extension(ext).is_file()))
+}
+
+/**
+Composes the statements given with `--stmt` and the expression into one, in which the statements are evaluated before the expression.
+*/
+fn compose_expr(statements: &[String], expr: &str) -> String {
+    let mut composed = String::new();
+    for statement in statements {
+        let statement = statement.trim_end();
+        composed.push_str(statement);
+        if !statement.ends_with(';') {
+            composed.push(';');
+        }
+        composed.push('\n');
+    }
+    composed.push_str(expr);
+    composed
+}
+
+/**
+The section of the manifest the `add` and `rm` subcommands edit, as selected with `--dev` or `--build`.
+*/
+fn deps_section(m: &clap::ArgMatches) -> &'static str {
+    if m.get_flag("dev") {
+        "dev-dependencies"
+    } else if m.get_flag("build") {
+        "build-dependencies"
+    } else {
+        "dependencies"
+    }
+}
+
+/**
+Parses a duration such as `1500ms`, `10s`, `2m` or `1h`.  A plain number is taken as seconds.
+*/
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split_at = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split_at);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration: '{}'", s))?;
+    let secs = |per_unit: u64| {
+        amount
+            .checked_mul(per_unit)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration too long: '{}'", s))
+    };
+    match unit.trim() {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => secs(1),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        _ => Err(format!(
+            "invalid duration unit in '{}', expected one of 'ms', 's', 'm' or 'h'",
+            s
+        )),
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+    assert_eq!(parse_duration("1500ms"), Ok(Duration::from_millis(1500)));
+    assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    assert!(parse_duration("ms").is_err());
+    assert!(parse_duration("3d").is_err());
+    assert!(parse_duration("18446744073709551615m").is_err());
+    assert!(parse_duration("5124095576030432h").is_err());
+}
+
+#[test]
+fn test_command_line() {
+    let app = clap::Command::new("rust-script")
+        .arg(
+            clap::Arg::new("script")
+                .num_args(1..)
+                .trailing_var_arg(true),
+        )
+        .arg(
+            clap::Arg::new("dep")
+                .long("dep")
+                .short('d')
+                .action(ArgAction::Append),
+        )
+        .arg(
+            clap::Arg::new("debug")
+                .long("debug")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("watch")
+                .long("watch")
+                .action(ArgAction::SetTrue),
+        );
+    let m = app.clone().get_matches_from([
+        "rust-script",
+        "-d",
+        "time",
+        "--watch",
+        "-d=-x",
+        "now.rs",
+        "--watch",
+        "-d",
+    ]);
+    assert_eq!(
+        command_line(&app, &m, "watch"),
+        ["--dep=time", "--dep=-x", "--", "now.rs", "--watch", "-d"]
+    );
+}
+
+#[test]
+fn test_names_script_file() {
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::write(dir.path().join("update.rs"), "fn main() {}").unwrap();
+    std::fs::write(dir.path().join("pin"), "fn main() {}").unwrap();
+    std::fs::create_dir(dir.path().join("templates")).unwrap();
+    assert!(names_script_file(&dir.path().join("update")));
+    assert!(names_script_file(&dir.path().join("pin")));
+    assert!(!names_script_file(&dir.path().join("templates")));
+    assert!(!names_script_file(&dir.path().join("add")));
+}
+
+#[test]
+fn test_compose_expr() {
+    let statements = ["let x = 2".to_string(), "let y = x * 3;".to_string()];
+    assert_eq!(compose_expr(&[], "1 + 2"), "1 + 2");
+    assert_eq!(
+        compose_expr(&statements, "x + y"),
+        "let x = 2;\nlet y = x * 3;\nx + y"
+    );
+}
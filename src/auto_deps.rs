@@ -0,0 +1,65 @@
+/*!
+This module is concerned with adding well-known crates to expressions as dependencies when they are used, so that e.g. `rust-script -e 'rand::random::<u8>()'` works without `--dep rand`.
+
+Expressions are only scanned for paths starting with the name of a crate, like `regex::Regex`.  That's enough for one-liners, and cheap enough to do on every run.
+*/
+use regex::Regex;
+
+/// The crates added when used, unless the configuration says otherwise.
+pub const WELL_KNOWN: &[&str] = &[
+    "anyhow",
+    "base64",
+    "chrono",
+    "glob",
+    "itertools",
+    "rand",
+    "regex",
+    "serde_json",
+    "sha2",
+    "walkdir",
+];
+
+/**
+The crates among `known` that `source` refers to by path, in the order given.
+
+Each of `known` is a crate name, optionally followed by `=version` like `--dep`.
+*/
+pub fn referenced<'a>(source: &str, known: &'a [String]) -> Vec<&'a str> {
+    // Strings and comments may well mention paths, but don't need the crates.
+    let re_skip = Regex::new(r#"(?s)"(?:[^"\\]|\\.)*"|//[^\n]*|/\*.*?\*/"#).unwrap();
+    let re_path = Regex::new(r"(?:^|[^\w:])([A-Za-z_][A-Za-z0-9_]*)\s*::").unwrap();
+
+    let source = re_skip.replace_all(source, " ");
+    let roots: Vec<&str> = re_path
+        .captures_iter(&source)
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .collect();
+    known
+        .iter()
+        .map(String::as_str)
+        .filter(|dep| {
+            let name = dep.split_once('=').map_or(*dep, |(name, _)| name);
+            roots.contains(&name.replace('-', "_").as_str())
+        })
+        .collect()
+}
+
+#[test]
+fn test_referenced() {
+    let known: Vec<String> = ["rand", "regex=1.10", "serde-json", "chrono"]
+        .iter()
+        .map(|dep| dep.to_string())
+        .collect();
+    assert_eq!(
+        referenced(
+            r#"regex::Regex::new("chrono::Utc")?.is_match(&rand::random::<u8>().to_string()) // chrono::"#,
+            &known
+        ),
+        vec!["rand", "regex=1.10"]
+    );
+    assert_eq!(
+        referenced("serde_json::json!({})", &known),
+        vec!["serde-json"]
+    );
+    assert!(referenced("std::rand::x; my_rand::y()", &known).is_empty());
+}
@@ -0,0 +1,240 @@
+/*!
+This module is concerned with keeping the results of `--bench` runs, and comparing them with `--bench-compare`.
+
+The results of each run are archived in a file of their own, in a directory per script, so that the results of a run can be compared with those of the run before it.
+*/
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::info;
+use regex::Regex;
+
+use crate::error::{MainError, MainResult};
+use crate::platform;
+
+/**
+The result of a single benchmark.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    /// Name of the benchmark.
+    pub name: String,
+
+    /// Nanoseconds per iteration.
+    pub ns_per_iter: f64,
+
+    /// The spread of the measurements, in nanoseconds.
+    pub deviation: f64,
+}
+
+/**
+Runs the benchmarks, showing their output, and archives the results in `archive`.
+
+If `compare` is given, the results are compared with those of the previous run, and benchmarks that got slower by more than that percentage are flagged, failing the run.
+*/
+pub fn run(cmd: &mut Command, archive: &Path, compare: Option<f64>) -> MainResult<i32> {
+    info!("running benchmarks: {:?}", cmd);
+    let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut output = String::new();
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        println!("{}", line);
+        output.push_str(&line);
+        output.push('\n');
+    }
+    let exit_code = child.wait()?.code().unwrap_or(1);
+
+    let results = parse(&output);
+    if results.is_empty() {
+        return Ok(exit_code);
+    }
+    let previous = latest(archive)?;
+    save(archive, &results)?;
+
+    if let Some(threshold) = compare {
+        match previous {
+            Some(previous) => {
+                if print_comparison(&previous, &results, threshold) && exit_code == 0 {
+                    return Ok(1);
+                }
+            }
+            None => eprintln!("No previous benchmark results to compare with."),
+        }
+    }
+    Ok(exit_code)
+}
+
+/**
+The benchmark results in the output of `cargo bench`.
+*/
+pub fn parse(output: &str) -> Vec<BenchResult> {
+    let re_bench =
+        Regex::new(r"^test (\S+) +\.\.\. bench: +([\d,.]+) ns/iter \(\+/- ([\d,.]+)\)").unwrap();
+    let number = |s: &str| s.replace(',', "").parse::<f64>().ok();
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = re_bench.captures(line)?;
+            Some(BenchResult {
+                name: captures[1].to_string(),
+                ns_per_iter: number(&captures[2])?,
+                deviation: number(&captures[3])?,
+            })
+        })
+        .collect()
+}
+
+/**
+The directory archiving the benchmark results of the script with the given key.
+*/
+pub fn archive_path(key: &str) -> PathBuf {
+    platform::bench_results_path().join(key)
+}
+
+// Archive the results of a run, named after the time of the run.
+fn save(archive: &Path, results: &[BenchResult]) -> MainResult<()> {
+    let table: toml::value::Table = results
+        .iter()
+        .map(|result| {
+            let mut entry = toml::value::Table::new();
+            entry.insert("ns_per_iter".into(), result.ns_per_iter.into());
+            entry.insert("deviation".into(), result.deviation.into());
+            (result.name.clone(), entry.into())
+        })
+        .collect();
+
+    fs::create_dir_all(archive)?;
+    let path = archive.join(format!("{}.toml", platform::current_time()));
+    info!("archiving benchmark results to {:?}", path);
+    fs::write(path, table.to_string())?;
+    Ok(())
+}
+
+// The archived results of the latest run, if there is one.
+fn latest(archive: &Path) -> MainResult<Option<Vec<BenchResult>>> {
+    let entries = match fs::read_dir(archive) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let latest = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let time: u128 = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((time, path))
+        })
+        .max();
+    let Some((_, path)) = latest else {
+        return Ok(None);
+    };
+
+    let invalid = |what: String| {
+        MainError::Tag(
+            format!("invalid benchmark results {}", path.display()).into(),
+            Box::new(what.into()),
+        )
+    };
+    let table: toml::value::Table =
+        toml::from_str(&fs::read_to_string(&path)?).map_err(|e| invalid(e.to_string()))?;
+    table
+        .iter()
+        .map(|(name, entry)| {
+            let field = |key: &str| {
+                entry
+                    .get(key)
+                    .and_then(|v| v.as_float())
+                    .ok_or_else(|| invalid(format!("missing `{}` of `{}`", key, name)))
+            };
+            Ok(BenchResult {
+                name: name.clone(),
+                ns_per_iter: field("ns_per_iter")?,
+                deviation: field("deviation")?,
+            })
+        })
+        .collect::<MainResult<_>>()
+        .map(Some)
+}
+
+// Print how the results changed since the previous run, returning whether any benchmark got
+// slower by more than `threshold` percent.
+fn print_comparison(previous: &[BenchResult], current: &[BenchResult], threshold: f64) -> bool {
+    let mut regressed = false;
+    println!();
+    println!(
+        "{:<40}{:>16}{:>16}{:>10}",
+        "benchmark", "previous", "current", "change"
+    );
+    for result in current {
+        let Some(before) = previous.iter().find(|before| before.name == result.name) else {
+            println!(
+                "{:<40}{:>16}{:>16}{:>10}",
+                result.name,
+                "-",
+                format!("{:.0} ns", result.ns_per_iter),
+                "new"
+            );
+            continue;
+        };
+        let change = change(before.ns_per_iter, result.ns_per_iter);
+        let regression = change > threshold;
+        regressed |= regression;
+        println!(
+            "{:<40}{:>16}{:>16}{:>+9.1}%{}",
+            result.name,
+            format!("{:.0} ns", before.ns_per_iter),
+            format!("{:.0} ns", result.ns_per_iter),
+            change,
+            if regression { "  REGRESSION" } else { "" }
+        );
+    }
+    regressed
+}
+
+// The change from `before` to `after`, in percent.
+fn change(before: f64, after: f64) -> f64 {
+    if before == 0.0 {
+        0.0
+    } else {
+        (after - before) / before * 100.0
+    }
+}
+
+#[test]
+fn test_parse_and_archive() {
+    let output = "\
+running 3 tests
+test bench_parse   ... bench:       1,234 ns/iter (+/- 56)
+test bench_print   ... bench:          12.50 ns/iter (+/- 0.25)
+test tests::it_works ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 2 measured; 0 filtered out
+";
+    let results = parse(output);
+    assert_eq!(
+        results,
+        vec![
+            BenchResult {
+                name: "bench_parse".into(),
+                ns_per_iter: 1234.0,
+                deviation: 56.0,
+            },
+            BenchResult {
+                name: "bench_print".into(),
+                ns_per_iter: 12.5,
+                deviation: 0.25,
+            },
+        ]
+    );
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let archive = dir.path().join("script");
+    assert_eq!(latest(&archive).unwrap(), None);
+    save(&archive, &results).unwrap();
+    assert_eq!(latest(&archive).unwrap(), Some(results));
+
+    assert!((change(100.0, 115.0) - 15.0).abs() < 1e-9);
+    assert_eq!(change(0.0, 10.0), 0.0);
+}
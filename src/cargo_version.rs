@@ -0,0 +1,119 @@
+/*!
+This module is concerned with which version of Cargo a toolchain has, so that flags Cargo didn't always have are only passed to versions that understand them.
+
+Cargo is asked for its version at most once per toolchain and run.  A version that can't be determined is assumed to be recent, so that a broken probe doesn't take features away.
+*/
+use std::collections::HashMap;
+use std::fmt;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use log::info;
+
+/**
+A Cargo version, as in `cargo 1.74.0 (ecb9851af 2023-10-18)`.
+*/
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CargoVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl CargoVersion {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        CargoVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    // Parse the output of `cargo --version`.
+    fn parse(output: &str) -> Option<Self> {
+        let version = output.strip_prefix("cargo ")?.split_whitespace().next()?;
+        // Pre-release versions, such as `1.76.0-nightly`, have the features of the release.
+        let version = version.split('-').next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        Some(CargoVersion::new(
+            parts.next()??,
+            parts.next()??,
+            parts.next()??,
+        ))
+    }
+}
+
+impl fmt::Display for CargoVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// `cargo tree`, used by `--dep-cost`.
+pub const TREE: CargoVersion = CargoVersion::new(1, 44, 0);
+
+/// `--message-format=json-diagnostic-rendered-ansi`, for coloured diagnostics with `--show-warnings`.
+pub const RENDERED_ANSI: CargoVersion = CargoVersion::new(1, 38, 0);
+
+/// `--timings`, used by `--build-timings`.
+pub const TIMINGS: CargoVersion = CargoVersion::new(1, 60, 0);
+
+/// The `unused_crate_dependencies` lint, used by `--unused-deps`.
+pub const UNUSED_CRATE_DEPENDENCIES: CargoVersion = CargoVersion::new(1, 48, 0);
+
+/// `.cargo/config.toml` as the name of the Cargo configuration file, rather than `.cargo/config`.
+pub const CONFIG_TOML: CargoVersion = CargoVersion::new(1, 39, 0);
+
+/**
+Does the Cargo of the given toolchain (or the default one) have at least the given version?
+*/
+pub fn supports(toolchain: Option<&str>, required: CargoVersion) -> bool {
+    match version(toolchain) {
+        Some(version) => version >= required,
+        None => true,
+    }
+}
+
+/**
+The version of the Cargo of the given toolchain (or the default one), if it can be determined.
+*/
+pub fn version(toolchain: Option<&str>) -> Option<CargoVersion> {
+    static VERSIONS: OnceLock<Mutex<HashMap<Option<String>, Option<CargoVersion>>>> =
+        OnceLock::new();
+
+    let mut versions = VERSIONS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("cargo version lock poisoned");
+    *versions
+        .entry(toolchain.map(String::from))
+        .or_insert_with(|| probe(toolchain))
+}
+
+fn probe(toolchain: Option<&str>) -> Option<CargoVersion> {
+    let mut cmd = Command::new("cargo");
+    if let Some(toolchain) = toolchain {
+        cmd.arg(format!("+{}", toolchain));
+    }
+    cmd.arg("--version");
+    let output = cmd.output().ok().filter(|output| output.status.success())?;
+    let version = CargoVersion::parse(&String::from_utf8_lossy(&output.stdout));
+    info!("cargo version for {:?}: {:?}", toolchain, version);
+    version
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        CargoVersion::parse("cargo 1.74.0 (ecb9851af 2023-10-18)\n"),
+        Some(CargoVersion::new(1, 74, 0))
+    );
+    assert_eq!(
+        CargoVersion::parse("cargo 1.76.0-nightly (71cd3a926 2023-11-20)"),
+        Some(CargoVersion::new(1, 76, 0))
+    );
+    assert_eq!(CargoVersion::parse("cargo 1.74"), None);
+    assert_eq!(CargoVersion::parse("rustup 1.26.0"), None);
+    assert!(CargoVersion::new(1, 38, 0) < CargoVersion::new(1, 74, 0));
+    assert!(CargoVersion::new(1, 9, 0) < CargoVersion::new(1, 10, 0));
+}
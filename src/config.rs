@@ -0,0 +1,209 @@
+/*!
+This module is concerned with the user's `rust-script` configuration file.
+
+The configuration lives in `config.toml` inside the configuration directory (see `platform::config_dir`).  A missing file is the same as an empty one.
+*/
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::info;
+
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::fetch::http::HostPattern;
+use crate::platform;
+
+/// Name of the configuration file inside the configuration directory.
+const CONFIG_FILE: &str = "config.toml";
+
+/**
+The settings read from the configuration file.
+*/
+#[derive(Debug, Default)]
+pub struct Config {
+    /**
+    Command the body of script files is piped through before the package is generated.
+
+    The command is split into words like a shell would, but is not run by a shell.
+    */
+    pub preprocess: Option<String>,
+
+    /**
+    Dependencies added to every expression, as `name` or `name=version` like `--dep`.
+    */
+    pub expr_deps: Vec<String>,
+
+    /**
+    Crates added to expressions that use them, as `name` or `name=version`.  `None` means the built-in list in `auto_deps::WELL_KNOWN`.
+    */
+    pub auto_deps: Option<Vec<String>>,
+
+    /**
+    The hosts scripts may be fetched from by URL.  `None` means any host.
+    */
+    pub fetch_allow: Option<Vec<HostPattern>>,
+}
+
+impl Config {
+    /**
+    Loads the configuration from the configuration directory.
+    */
+    pub fn load() -> MainResult<Config> {
+        let path = platform::config_dir().join(CONFIG_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let config = Config::parse(&content).map_err(|err| {
+            MainError::with_kind(
+                FailureKind::Usage,
+                MainError::Tag(format!("invalid {}", path.display()).into(), Box::new(err)),
+            )
+        })?;
+        info!("config: {:?}", config);
+        Ok(config)
+    }
+
+    fn parse(content: &str) -> MainResult<Config> {
+        let table: toml::value::Table =
+            toml::from_str(content).map_err(|e| MainError::Other(Box::new(e)))?;
+
+        let preprocess = match table.get("preprocess") {
+            None => None,
+            Some(toml::Value::String(cmd)) if cmd.trim().is_empty() => None,
+            Some(toml::Value::String(cmd)) => Some(cmd.clone()),
+            Some(_) => return Err("`preprocess` must be a string".into()),
+        };
+
+        let expr_deps = dep_list(&table, "expr-deps")?.unwrap_or_default();
+        let auto_deps = dep_list(&table, "auto-deps")?;
+
+        let fetch_allow = match table.get("fetch-allow") {
+            None => None,
+            Some(toml::Value::Array(hosts)) => Some(
+                hosts
+                    .iter()
+                    .map(|host| {
+                        let host = host
+                            .as_str()
+                            .ok_or("`fetch-allow` must be a list of host names")?;
+                        HostPattern::parse(host)
+                            .map_err(|err| MainError::from(format!("`fetch-allow`: {}", err)))
+                    })
+                    .collect::<MainResult<_>>()?,
+            ),
+            Some(_) => return Err("`fetch-allow` must be a list of host names".into()),
+        };
+
+        Ok(Config {
+            preprocess,
+            expr_deps,
+            auto_deps,
+            fetch_allow,
+        })
+    }
+}
+
+// A list of dependencies in the form `--dep` takes, if the key is there.
+fn dep_list(table: &toml::value::Table, key: &str) -> MainResult<Option<Vec<String>>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(toml::Value::Array(deps)) => deps
+            .iter()
+            .map(|dep| match dep.as_str() {
+                Some(dep) if !dep.starts_with('=') && !dep.ends_with('=') => Ok(dep.into()),
+                _ => Err(MainError::from(format!(
+                    "`{}` must be a list of `name` or `name=version` strings",
+                    key
+                ))),
+            })
+            .collect::<MainResult<_>>()
+            .map(Some),
+        Some(_) => Err(format!("`{}` must be a list of strings", key).into()),
+    }
+}
+
+/**
+Pipes the body of a script through a preprocessor command, returning what it printed.
+
+The command gets the script on standard input, and its path in the `RUST_SCRIPT_PATH` environment variable.
+*/
+pub fn preprocess(cmd: &str, script_path: &Path, body: &str) -> MainResult<String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let words = shell_words::split(cmd).map_err(|e| MainError::Other(Box::new(e)))?;
+    let (program, args) = words
+        .split_first()
+        .ok_or("the preprocess command cannot be empty")?;
+
+    info!("preprocessing with {:?}", words);
+    let mut child = Command::new(program)
+        .args(args)
+        .env("RUST_SCRIPT_PATH", script_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            MainError::Tag(
+                format!("could not run preprocessor `{}`", cmd).into(),
+                Box::new(err.into()),
+            )
+        })?;
+
+    // Feed the script from another thread, so a preprocessor that writes before it's done
+    // reading can't deadlock with us.
+    let mut stdin = child.stdin.take().expect("child stdin is piped");
+    let body = body.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(body.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    match writer.join().expect("preprocessor input thread panicked") {
+        // A preprocessor doesn't have to read all of its input.
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
+
+    if !output.status.success() {
+        return Err(format!("preprocessor `{}` failed with {}", cmd, output.status).into());
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| format!("preprocessor `{}` did not output valid UTF-8", cmd).into())
+}
+
+#[test]
+fn test_parse_config() {
+    assert_eq!(Config::parse("").unwrap().preprocess, None);
+    assert_eq!(
+        Config::parse("preprocess = \"m4 -P\"").unwrap().preprocess,
+        Some("m4 -P".into())
+    );
+    assert_eq!(
+        Config::parse("preprocess = \" \"").unwrap().preprocess,
+        None
+    );
+    assert!(Config::parse("preprocess = 1").is_err());
+    assert_eq!(
+        Config::parse("expr-deps = [\"itertools\", \"rand=0.8\"]")
+            .unwrap()
+            .expr_deps,
+        vec!["itertools".to_string(), "rand=0.8".to_string()]
+    );
+    assert!(Config::parse("expr-deps = [\"=1\"]").is_err());
+    assert!(Config::parse("expr-deps = \"rand\"").is_err());
+    assert_eq!(Config::parse("").unwrap().auto_deps, None);
+    assert_eq!(
+        Config::parse("auto-deps = []").unwrap().auto_deps,
+        Some(Vec::new())
+    );
+    assert_eq!(Config::parse("").unwrap().fetch_allow, None);
+    assert_eq!(
+        Config::parse("fetch-allow = [\"*.example.com\"]")
+            .unwrap()
+            .fetch_allow,
+        Some(vec![HostPattern::Subdomains("example.com".into())])
+    );
+    assert!(Config::parse("fetch-allow = [\"https://example.com\"]").is_err());
+    assert!(Config::parse("preprocess = ").is_err());
+}
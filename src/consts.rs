@@ -14,6 +14,103 @@ pub const SCRIPT_BODY_SUB: &str = "script";
 /// Substitution for the script prelude.
 pub const SCRIPT_PRELUDE_SUB: &str = "prelude";
 
+/// Substitution for the number of lines per chunk given with `--chunk-lines`.
+pub const CHUNK_LINES_SUB: &str = "chunk_lines";
+
+/// The template used for preprocessed script file inputs that have a main function; they are copied into the package as is.
+pub const FILE_PREPROCESSED_TEMPLATE: &str = "#{script}";
+
+/// The template used for script file inputs with `--structured-main`; the script's `main` must return a `Result` of something serializable.
+pub const FILE_STRUCTURED_MAIN_TEMPLATE: &str = r#"
+mod rust_script_main {
+#{script}
+
+    pub(super) fn run() -> i32 {
+        super::StructuredMain::report(main())
+    }
+}
+
+trait StructuredMain {
+    fn report(self) -> i32;
+}
+
+impl<T: serde::Serialize, E: std::fmt::Display> StructuredMain for Result<T, E> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(value) => match serde_json::to_string(&value) {
+                Ok(json) => {
+                    println!("{}", json);
+                    0
+                }
+                Err(e) => {
+                    // Not 2, which `rust-script` exits with for a usage error.
+                    let message = format!("could not serialize the result: {}", e);
+                    eprintln!("{}", serde_json::json!({ "error": message }));
+                    1
+                }
+            },
+            Err(e) => {
+                eprintln!("{}", serde_json::json!({ "error": e.to_string() }));
+                1
+            }
+        }
+    }
+}
+
+fn main() {
+    std::process::exit(rust_script_main::run());
+}
+"#;
+
+/// The dependencies needed by `FILE_STRUCTURED_MAIN_TEMPLATE`.
+pub const STRUCTURED_MAIN_MANIFEST: &str = r#"
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+serde_json = "1"
+"#;
+
+/// The template used for script file inputs with an `async fn main` and no attribute like `#[tokio::main]` to run it, which is run on a tokio runtime.
+pub const FILE_ASYNC_MAIN_TEMPLATE: &str = r#"
+mod rust_script_main {
+#{script}
+
+    pub(super) fn run() -> impl std::process::Termination {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("could not start the tokio runtime")
+            .block_on(main())
+    }
+}
+
+fn main() -> impl std::process::Termination {
+    rust_script_main::run()
+}
+"#;
+
+/// The dependency added for async scripts and expressions, the tokio runtime.
+pub const ASYNC_DEPENDENCY: (&str, &str) = ("tokio", r#"{ version = "1", features = ["full"] }"#);
+
+/// The dependencies needed by `FILE_ASYNC_MAIN_TEMPLATE`.
+pub const ASYNC_MAIN_MANIFEST: &str = r#"
+[dependencies]
+tokio = { version = "1", features = ["full"] }
+"#;
+
+/// The allocators `--alloc` can use: their names, the dependency providing them, and the item making them the global allocator.
+pub const ALLOCATORS: [(&str, (&str, &str), &str); 2] = [
+    (
+        "jemalloc",
+        ("tikv-jemallocator", "0.6"),
+        "#[global_allocator]\nstatic RUST_SCRIPT_ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;\n",
+    ),
+    (
+        "mimalloc",
+        ("mimalloc", "0.1"),
+        "#[global_allocator]\nstatic RUST_SCRIPT_ALLOCATOR: mimalloc::MiMalloc = mimalloc::MiMalloc;\n",
+    ),
+];
+
 /// The template used for script file inputs that doesn't have main function.
 pub const FILE_NO_MAIN_TEMPLATE: &str = r#"
 fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
@@ -53,6 +150,221 @@ fn try_main() -> Result<(), Box<dyn std::error::Error>> {
 }
 "#;
 
+/// The template used for `--expr --async` input: like `EXPR_TEMPLATE`, but run on a tokio runtime.
+pub const EXPR_ASYNC_TEMPLATE: &str = r#"
+#{prelude}
+use std::any::{Any, TypeId};
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("could not start the tokio runtime");
+    if let Err(e) = runtime.block_on(try_main()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    fn _rust_script_is_empty_tuple<T: ?Sized + Any>(_s: &T) -> bool {
+        TypeId::of::<()>() == TypeId::of::<T>()
+    }
+    match {#{script}} {
+        __rust_script_expr if !_rust_script_is_empty_tuple(&__rust_script_expr) => println!("{:?}", __rust_script_expr),
+        _ => {}
+    }
+    Ok(())
+}
+"#;
+
+/// The template used for `--expr --output json` input: the value is printed as JSON.
+pub const EXPR_JSON_TEMPLATE: &str = r#"
+#{prelude}
+
+fn main() {
+    if let Err(e) = try_main() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    let value = {#{script}};
+    println!("{}", serde_json::to_string(&value)?);
+    Ok(())
+}
+"#;
+
+/// The template used for `--test-expr` input: a `bool` value is the exit status rather than printed.
+pub const EXPR_TEST_TEMPLATE: &str = r#"
+#{prelude}
+use std::any::Any;
+
+fn main() {
+    match try_main() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            // Failing isn't the same as being false.
+            std::process::exit(2);
+        }
+    }
+}
+
+fn try_main() -> Result<i32, Box<dyn std::error::Error>> {
+    fn _rust_script_as_bool<T: Any>(s: &T) -> Option<bool> {
+        (s as &dyn Any).downcast_ref::<bool>().copied()
+    }
+    fn _rust_script_is_empty_tuple<T: Any>(s: &T) -> bool {
+        (s as &dyn Any).is::<()>()
+    }
+    match {#{script}} {
+        __rust_script_expr => match _rust_script_as_bool(&__rust_script_expr) {
+            Some(truth) => return Ok(if truth { 0 } else { 1 }),
+            None if !_rust_script_is_empty_tuple(&__rust_script_expr) => println!("{:?}", __rust_script_expr),
+            None => {}
+        },
+    }
+    Ok(0)
+}
+"#;
+
+/// The template used for `--expr --block` input: statements, with nothing printed.
+pub const EXPR_BLOCK_TEMPLATE: &str = r#"
+#{prelude}
+
+fn main() {
+    if let Err(e) = try_main() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    {#{script}};
+    Ok(())
+}
+"#;
+
+/// The template used for `--expr --block --async` input: statements run on a tokio runtime.
+pub const EXPR_BLOCK_ASYNC_TEMPLATE: &str = r#"
+#{prelude}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("could not start the tokio runtime");
+    if let Err(e) = runtime.block_on(try_main()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    {#{script}};
+    Ok(())
+}
+"#;
+
+/// The prelude item added by `--sh`, providing the `sh!` macro.
+pub const SH_PRELUDE: &str = r#"#[macro_use]
+#[allow(dead_code)]
+mod rust_script_sh {
+    /// What running a command with `sh!` produced.
+    #[derive(Debug)]
+    pub struct ShOutput {
+        /// Exit code of the command, or -1 if it was killed by a signal.
+        pub status: i32,
+        /// Everything the command wrote to standard output.
+        pub stdout: String,
+    }
+
+    impl ShOutput {
+        pub fn success(&self) -> bool {
+            self.status == 0
+        }
+    }
+
+    impl std::fmt::Display for ShOutput {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(self.stdout.trim_end())
+        }
+    }
+
+    pub fn run(cmd: &str) -> ShOutput {
+        #[cfg(windows)]
+        let output = std::process::Command::new("cmd").arg("/C").arg(cmd).output();
+        #[cfg(not(windows))]
+        let output = std::process::Command::new("sh").arg("-c").arg(cmd).output();
+        let output = output.unwrap_or_else(|e| panic!("could not run `{}`: {}", cmd, e));
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+        ShOutput {
+            status: output.status.code().unwrap_or(-1),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        }
+    }
+
+    macro_rules! sh {
+        ($($arg:tt)*) => {
+            $crate::rust_script_sh::run(&format!($($arg)*))
+        };
+    }
+}"#;
+
+/// The prelude item added by `--session`, providing the `session` module.
+pub const SESSION_PRELUDE: &str = r#"#[allow(dead_code)]
+mod session {
+    use std::collections::BTreeMap;
+
+    type Bindings = BTreeMap<String, serde_json::Value>;
+
+    fn path() -> std::path::PathBuf {
+        std::env::var_os("RUST_SCRIPT_SESSION")
+            .expect("RUST_SCRIPT_SESSION is not set; run with --session")
+            .into()
+    }
+
+    fn load() -> Bindings {
+        std::fs::read_to_string(path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn store(bindings: &Bindings) {
+        let json = serde_json::to_string_pretty(bindings).expect("could not serialize the session");
+        std::fs::write(path(), json).expect("could not write the session");
+    }
+
+    /// Loads the binding with the given name, if there is one of the given type.
+    pub fn get<T: serde::de::DeserializeOwned>(name: &str) -> Option<T> {
+        load().remove(name).and_then(|value| serde_json::from_value(value).ok())
+    }
+
+    /// Stores a binding under the given name, replacing any previous one.
+    pub fn set<T: serde::Serialize>(name: &str, value: &T) {
+        let mut bindings = load();
+        let value = serde_json::to_value(value).expect("could not serialize the value");
+        bindings.insert(name.into(), value);
+        store(&bindings);
+    }
+
+    /// Removes the binding with the given name.
+    pub fn remove(name: &str) {
+        let mut bindings = load();
+        if bindings.remove(name).is_some() {
+            store(&bindings);
+        }
+    }
+
+    /// The names of all bindings.
+    pub fn names() -> Vec<String> {
+        load().into_keys().collect()
+    }
+}"#;
+
 /*
 Regarding the loop templates: what I *want* is for the result of the closure to be printed to standard output *only* if it's not `()`.
 
@@ -71,11 +383,17 @@ fn main() {
     let mut closure = enforce_closure(
 {#{script}}
     );
+    // Standard input and output are locked once rather than for each line, and input is read in
+    // large blocks.  The closure can still print, as the lock can be taken again by this thread.
+    // Not `stdin.lock()`, which would be held while the closure runs, so that a closure reading
+    // standard input itself doesn't deadlock.  It's only locked to fill the buffer.
+    let mut input = std::io::BufReader::with_capacity(1 << 16, std::io::stdin());
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
     let mut line_buffer = String::new();
-    let stdin = std::io::stdin();
     loop {
         line_buffer.clear();
-        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
+        let read_res = input.read_line(&mut line_buffer).unwrap_or(0);
         if read_res == 0 { break }
         let output = closure(&line_buffer);
 
@@ -84,8 +402,9 @@ fn main() {
             !output_any.is::<()>()
         };
 
-        if display {
-            println!("{:?}", output);
+        // Stop once nothing reads the output any more, as with `| head`.
+        if display && writeln!(out, "{:?}", output).is_err() {
+            break;
         }
     }
 }
@@ -96,6 +415,51 @@ where F: FnMut(&str) -> T, T: 'static {
 }
 "#;
 
+/// The template used for `--loop --async` input: the closure is given each line as a `String`, and returns a future whose output is printed.
+pub const LOOP_ASYNC_TEMPLATE: &str = r#"
+#![allow(unused_imports)]
+#![allow(unused_braces)]
+#{prelude}
+use std::any::Any;
+use std::io::prelude::*;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("could not start the tokio runtime");
+    let mut closure = enforce_closure(
+{#{script}}
+    );
+    // Not `stdin.lock()`, which would be held while the closure runs, so that a closure reading
+    // standard input itself doesn't deadlock.  It's only locked to fill the buffer.
+    let mut input = std::io::BufReader::with_capacity(1 << 16, std::io::stdin());
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut line_buffer = String::new();
+    loop {
+        line_buffer.clear();
+        let read_res = input.read_line(&mut line_buffer).unwrap_or(0);
+        if read_res == 0 { break }
+        let output = runtime.block_on(closure(line_buffer.clone()));
+
+        let display = {
+            let output_any: &dyn Any = &output;
+            !output_any.is::<()>()
+        };
+
+        if display && writeln!(out, "{:?}", output).is_err() {
+            break;
+        }
+    }
+}
+
+fn enforce_closure<F, R, T>(closure: F) -> F
+where F: FnMut(String) -> R, R: std::future::Future<Output = T>, T: 'static {
+    closure
+}
+"#;
+
 /// The template used for `--count --loop` input.
 pub const LOOP_COUNT_TEMPLATE: &str = r#"
 #![allow(unused_imports)]
@@ -107,12 +471,16 @@ fn main() {
     let mut closure = enforce_closure(
 {#{script}}
     );
+    // Not `stdin.lock()`, which would be held while the closure runs, so that a closure reading
+    // standard input itself doesn't deadlock.  It's only locked to fill the buffer.
+    let mut input = std::io::BufReader::with_capacity(1 << 16, std::io::stdin());
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
     let mut line_buffer = String::new();
-    let stdin = std::io::stdin();
     let mut count = 0;
     loop {
         line_buffer.clear();
-        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
+        let read_res = input.read_line(&mut line_buffer).unwrap_or(0);
         if read_res == 0 { break }
         count += 1;
         let output = closure(&line_buffer, count);
@@ -122,8 +490,8 @@ fn main() {
             !output_any.is::<()>()
         };
 
-        if display {
-            println!("{:?}", output);
+        if display && writeln!(out, "{:?}", output).is_err() {
+            break;
         }
     }
 }
@@ -134,6 +502,153 @@ where F: FnMut(&str, usize) -> T, T: 'static {
 }
 "#;
 
+/// The template used for `--chunk-lines N --loop` input, which passes the closure `N` lines at a time.
+pub const LOOP_CHUNK_TEMPLATE: &str = r#"
+#![allow(unused_imports)]
+#![allow(unused_braces)]
+#{prelude}
+use std::any::Any;
+use std::io::prelude::*;
+
+const CHUNK_LINES: usize = #{chunk_lines};
+
+fn main() {
+    let mut closure = enforce_closure(
+{#{script}}
+    );
+    // Not `stdin.lock()`, which would be held while the closure runs, so that a closure reading
+    // standard input itself doesn't deadlock.  It's only locked to fill the buffer.
+    let mut input = std::io::BufReader::with_capacity(1 << 20, std::io::stdin());
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut chunk = String::new();
+    loop {
+        chunk.clear();
+        let mut lines = 0;
+        while lines < CHUNK_LINES && input.read_line(&mut chunk).unwrap_or(0) != 0 {
+            lines += 1;
+        }
+        if lines == 0 { break }
+        let output = closure(&chunk);
+
+        let display = {
+            let output_any: &dyn Any = &output;
+            !output_any.is::<()>()
+        };
+
+        if display && writeln!(out, "{:?}", output).is_err() {
+            break;
+        }
+    }
+}
+
+fn enforce_closure<F, T>(closure: F) -> F
+where F: FnMut(&str) -> T, T: 'static {
+    closure
+}
+"#;
+
+/// The template used for `--json` input.
+pub const JSON_TEMPLATE: &str = r#"
+#![allow(unused_imports)]
+#![allow(unused_braces)]
+#{prelude}
+use std::io::prelude::*;
+
+fn main() {
+    let stdin = std::io::stdin();
+    for (line, line_number) in stdin.lock().lines().zip(1..) {
+        let line = line.expect("could not read standard input");
+        if line.trim().is_empty() {
+            continue;
+        }
+        let json: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(json) => json,
+            Err(err) => {
+                eprintln!("line {}: invalid JSON: {}", line_number, err);
+                std::process::exit(1);
+            }
+        };
+        let output = {#{script}};
+        println!("{}", serde_json::to_string(&output).expect("could not serialize the result as JSON"));
+    }
+}
+"#;
+
+/// The template used for `--csv` input.
+pub const CSV_TEMPLATE: &str = r#"
+#![allow(unused_imports)]
+#![allow(unused_braces)]
+#{prelude}
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+
+fn main() {
+    let mut reader = csv::Reader::from_reader(std::io::stdin().lock());
+    let headers = reader.headers().expect("could not read the CSV headers").clone();
+    for record in reader.records() {
+        let record = record.expect("could not read a CSV record");
+        let row: BTreeMap<&str, &str> = headers.iter().zip(record.iter()).collect();
+        let output = {#{script}};
+
+        // The output may borrow from the row, so it can't be checked for `()` with `Any`.
+        if !is_unit(&output) {
+            println!("{:?}", output);
+        }
+    }
+}
+
+fn is_unit<T>(_: &T) -> bool {
+    std::any::type_name::<T>() == "()"
+}
+"#;
+
+/// The template used for `--parallel-map` input: the expression is evaluated for batches of lines on rayon's thread pool, and the results are printed in the order of the lines.
+pub const PARALLEL_MAP_TEMPLATE: &str = r#"
+#![allow(unused_imports)]
+#![allow(unused_braces)]
+#{prelude}
+use rayon::prelude::*;
+use std::io::prelude::*;
+
+/// How many lines are read before they are processed, keeping memory bounded on large input.
+const BATCH_LINES: usize = 1 << 14;
+
+fn main() {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    loop {
+        let batch: Vec<String> = lines
+            .by_ref()
+            .take(BATCH_LINES)
+            .collect::<Result<_, _>>()
+            .expect("could not read standard input");
+        if batch.is_empty() {
+            break;
+        }
+        let outputs: Vec<_> = batch
+            .par_iter()
+            .map(|line| {
+                let line: &str = line;
+                {#{script}}
+            })
+            .collect();
+        for output in outputs {
+            // Stop once nothing reads the output any more, as with `| head`.
+            if !is_unit(&output) && writeln!(out, "{:?}", output).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn is_unit<T>(_: &T) -> bool {
+    std::any::type_name::<T>() == "()"
+}
+"#;
+
 /**
 When generating a package's unique ID, how many hex nibbles of the digest should be used *at most*?
 
@@ -142,8 +657,53 @@ The largest meaningful value is `40`.
 pub const ID_DIGEST_LEN_MAX: usize = 24;
 
 /**
-How old can stuff in the cache be before we automatically clear it out?
+How old can generated projects in the cache be before we automatically clear them out?
 
 Measured in milliseconds.
 */
-pub const MAX_CACHE_AGE_MS: u128 = 7 * 24 * 60 * 60 * 1000;
+pub const MAX_PROJECT_CACHE_AGE_MS: u128 = 7 * 24 * 60 * 60 * 1000;
+
+/**
+How large can the binary cache (the shared Cargo target directory) grow before we start pruning it?
+
+Measured in bytes.
+*/
+pub const MAX_BINARY_CACHE_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/**
+How long after pruning the binary cache do we leave it alone?  Finding out how large it is means walking all of it, which takes longer than running most scripts.
+
+Measured in milliseconds.
+*/
+pub const BINARY_CACHE_PRUNE_INTERVAL_MS: u128 = 60 * 60 * 1000;
+
+/// Name of the directory next to the script that holds the caches with `--local-cache`.
+pub const LOCAL_CACHE_DIR: &str = ".rust-script";
+
+/// The key of the embedded manifest giving the version of its schema.
+pub const MANIFEST_VERSION_KEY: &str = "rust-script-manifest-version";
+
+/// The newest version of the embedded manifest schema this version of `rust-script` understands.
+pub const MANIFEST_VERSION: i64 = 1;
+
+/// The top-level keys of the embedded manifest that are understood.
+pub const KNOWN_MANIFEST_KEYS: &[&str] = &[
+    "badges",
+    "bench",
+    "bin",
+    "build-dependencies",
+    "cargo-features",
+    "dependencies",
+    "dev-dependencies",
+    "example",
+    "features",
+    "lib",
+    "lints",
+    "package",
+    "patch",
+    "profile",
+    "replace",
+    "target",
+    "test",
+    "workspace",
+];
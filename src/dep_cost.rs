@@ -0,0 +1,321 @@
+/*!
+This module implements `--dep-cost`, which estimates how much each direct dependency of a script adds to its build.
+
+The estimate is based on how long the crates each dependency pulls in took to build, as recorded in the reports of Cargo's `--timings` left by `--build-timings`, along with how many crates there are and how much source code they have in the Cargo registry, for crates never timed.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use log::info;
+use regex::Regex;
+
+use crate::error::MainResult;
+use crate::platform;
+
+/// Dependencies pulling in at least this many crates are flagged as heavy.
+const HEAVY_CRATE_COUNT: usize = 50;
+
+/// Dependencies with at least this much source code (in bytes) are flagged as heavy.
+const HEAVY_SOURCE_SIZE: u64 = 20 * 1024 * 1024;
+
+/// Dependencies taking at least this long to build (in seconds) are flagged as heavy.
+const HEAVY_BUILD_TIME: f64 = 30.0;
+
+/**
+How long crates took to build, in seconds, by name and version.
+*/
+pub type Timings = BTreeMap<(String, String), f64>;
+
+/**
+What a direct dependency adds to the build.
+*/
+#[derive(Debug)]
+pub struct DepCost {
+    pub name: String,
+    pub version: String,
+
+    /// Number of crates built for the dependency, including itself.
+    pub crates: usize,
+
+    /// Size of the source code of those crates, in bytes.  Crates that aren't in the registry aren't counted.
+    pub source_size: u64,
+
+    /// How long those crates took to build, in seconds, if any of them were timed.
+    pub build_time: Option<f64>,
+}
+
+impl DepCost {
+    pub fn is_heavy(&self) -> bool {
+        self.crates >= HEAVY_CRATE_COUNT
+            || self.source_size >= HEAVY_SOURCE_SIZE
+            || self.build_time.is_some_and(|time| time >= HEAVY_BUILD_TIME)
+    }
+}
+
+/**
+Loads how long crates took to build from the `--timings` reports in `dir`.
+
+A crate rebuilt since takes the time of its latest build.  Crates that were fresh, and so took no time, are left out.
+*/
+pub fn load_timings(dir: &Path) -> Timings {
+    let mut reports: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        // Cargo keeps each report under a name with its timestamp, and copies the latest to
+        // `cargo-timing.html`.
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("cargo-timing-") && name.ends_with(".html"))
+        })
+        .collect();
+    reports.sort();
+
+    let mut timings = Timings::new();
+    for report in reports {
+        let Ok(html) = fs::read_to_string(&report) else {
+            continue;
+        };
+        for (package, time) in parse_report(&html) {
+            if time > 0.0 {
+                timings.insert(package, time);
+            }
+        }
+    }
+    timings
+}
+
+/**
+Works out the cost of each direct dependency of the package, using `cargo tree`.
+
+`cargo` creates a `cargo` command for the package, to which the subcommand and its arguments are added.
+*/
+pub fn dep_costs(
+    cargo: impl Fn() -> Command,
+    features: &[String],
+    timings: &Timings,
+) -> MainResult<Vec<DepCost>> {
+    let tree = |args: &[&str]| -> MainResult<String> {
+        let mut cmd = cargo();
+        cmd.args([
+            "tree",
+            "--quiet",
+            "--prefix",
+            "none",
+            "--edges",
+            "normal,build",
+        ]);
+        if !features.is_empty() {
+            cmd.arg("--features").arg(features.join(","));
+        }
+        cmd.args(args);
+        info!("running {:?}", cmd);
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "cargo tree failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    let direct = tree(&["--depth", "1"])?;
+    let mut costs = Vec::new();
+    for (name, version) in parse_tree(&direct).into_iter().skip(1) {
+        let crates = parse_tree(&tree(&["--package", &format!("{}@{}", name, version)])?);
+        let source_size = crates
+            .iter()
+            .map(|(name, version)| registry_source_size(name, version))
+            .sum();
+        let times: Vec<f64> = crates
+            .iter()
+            .filter_map(|package| timings.get(package).copied())
+            .collect();
+        costs.push(DepCost {
+            name,
+            version,
+            crates: crates.len(),
+            source_size,
+            build_time: (!times.is_empty()).then(|| times.iter().sum()),
+        });
+    }
+    // The slowest first, then those never timed by how many crates they pull in.
+    costs.sort_by(|a, b| {
+        b.build_time
+            .unwrap_or(-1.0)
+            .total_cmp(&a.build_time.unwrap_or(-1.0))
+            .then(b.crates.cmp(&a.crates))
+            .then(a.name.cmp(&b.name))
+    });
+    Ok(costs)
+}
+
+/**
+Prints the costs as a table.
+*/
+pub fn print_costs(costs: &[DepCost]) {
+    println!(
+        "{:<28}{:>8}{:>12}{:>10}",
+        "dependency", "crates", "source", "build"
+    );
+    for cost in costs {
+        println!(
+            "{:<28}{:>8}{:>9.1} MB{:>10}{}",
+            format!("{} {}", cost.name, cost.version),
+            cost.crates,
+            cost.source_size as f64 / (1024.0 * 1024.0),
+            match cost.build_time {
+                Some(time) => format!("{:.1} s", time),
+                None => "?".into(),
+            },
+            if cost.is_heavy() { "  (heavy)" } else { "" }
+        );
+    }
+    if costs.iter().any(|cost| cost.build_time.is_none()) {
+        eprintln!("note: build times are from earlier runs with --build-timings; `?` means none was recorded");
+    }
+}
+
+// Parse the build times of the crates out of a `--timings` report, adding up those of the units
+// of each crate, such as its library and build script.
+fn parse_report(html: &str) -> Timings {
+    let mut timings = Timings::new();
+    let Some(start) = html.find("const UNIT_DATA = ") else {
+        return timings;
+    };
+    let data = &html[start..];
+    let data = &data[..data.find("];").unwrap_or(data.len())];
+    let re_unit = Regex::new(
+        r#""name":\s*"([^"]*)",\s*"version":\s*"([^"]*)"[^{}]*?"duration":\s*([0-9.]+)"#,
+    )
+    .unwrap();
+    for unit in re_unit.captures_iter(data) {
+        let Ok(duration) = unit[3].parse::<f64>() else {
+            continue;
+        };
+        *timings
+            .entry((unit[1].to_string(), unit[2].to_string()))
+            .or_default() += duration;
+    }
+    timings
+}
+
+// Parse the output of `cargo tree --prefix none` into the packages listed, in order, without duplicates.
+fn parse_tree(output: &str) -> Vec<(String, String)> {
+    let mut seen = BTreeSet::new();
+    let mut packages = Vec::new();
+    for line in output.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(name), Some(version)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Some(version) = version.strip_prefix('v') else {
+            continue;
+        };
+        let package = (name.to_string(), version.to_string());
+        if seen.insert(package.clone()) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+// Total size of the unpacked sources of a crate in the Cargo registry, if it's there.
+fn registry_source_size(name: &str, version: &str) -> u64 {
+    let crate_dir = format!("{}-{}", name, version);
+    fs::read_dir(platform::cargo_registry_path().join("src"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|index| index.path().join(&crate_dir))
+        .find(|dir| dir.is_dir())
+        .map(|dir| dir_size(&dir))
+        .unwrap_or(0)
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => dir_size(&entry.path()),
+            Ok(_) => entry.metadata().map(|md| md.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[test]
+fn test_parse_report() {
+    let html = r#"<script>
+const UNIT_DATA = [
+  {
+    "i": 0,
+    "name": "proc-macro2",
+    "version": "1.0.69",
+    "mode": "run-custom-build",
+    "target": " build script (run)",
+    "features": ["default", "proc-macro"],
+    "start": 0.1,
+    "duration": 0.25,
+    "unblocked_units": [1],
+    "unblocked_rmeta_units": [],
+    "sections": null
+  },
+  {
+    "i": 1,
+    "name": "proc-macro2",
+    "version": "1.0.69",
+    "mode": "todo",
+    "target": "",
+    "features": ["default", "proc-macro"],
+    "start": 0.4,
+    "duration": 1.5,
+    "unblocked_units": [],
+    "unblocked_rmeta_units": [],
+    "sections": [["frontend", {"start": 0.4, "end": 1.2}]]
+  },
+  {"i":2,"name":"script_abc","version":"0.1.0","mode":"todo","target":" script_abc \"bin\"","features":[],"start":1.9,"duration":0.5,"rmeta_time":null,"unlocked_units":[],"unlocked_rmeta_units":[]}
+];
+const CONCURRENCY_DATA = [{"t": 0.0, "active": 0}];
+</script>"#;
+    let timings = parse_report(html);
+    assert_eq!(
+        timings.get(&("proc-macro2".into(), "1.0.69".into())),
+        Some(&1.75)
+    );
+    assert_eq!(
+        timings.get(&("script_abc".into(), "0.1.0".into())),
+        Some(&0.5)
+    );
+    assert_eq!(timings.len(), 2);
+    assert!(parse_report("<html></html>").is_empty());
+}
+
+#[test]
+fn test_parse_tree() {
+    let output = "\
+script_abc v0.1.0 (/cache/projects/abc)
+serde v1.0.190
+serde_derive v1.0.190 (proc-macro)
+proc-macro2 v1.0.69
+serde v1.0.190 (*)
+
+";
+    assert_eq!(
+        parse_tree(output),
+        vec![
+            ("script_abc".into(), "0.1.0".into()),
+            ("serde".into(), "1.0.190".into()),
+            ("serde_derive".into(), "1.0.190".into()),
+            ("proc-macro2".into(), "1.0.69".into()),
+        ]
+    );
+}
@@ -0,0 +1,638 @@
+/*!
+This module is concerned with dependencies given on the command line, as with `--dep`: parsing them, and turning them into entries of a manifest.
+
+Besides `name` and `name=version`, a dependency can have features, given either as `name=version,features=[a,b]` or more tersely as `name@version:a,b`.  Instead of a version, it can be fetched from a git repository given as `git+URL`, optionally followed by `#branch=NAME`, `#tag=NAME` or `#rev=COMMIT`, or be a crate in a local directory given as `path:DIR`.  Like the paths in an embedded manifest, `DIR` is relative to the script.
+
+A dependency can also be renamed, so that the name it is used by in the script differs from the name of its package: `name=crates:PACKAGE@version` (or just `name=crates:PACKAGE` for any version), or by following any of the above with ` as name`, as in `tokio=1 as tk`.
+
+Patches, which override a crate wherever it's used in the dependency graph as with `[patch]` in a manifest, are given like dependencies, preceded by the source they patch and a colon: `crates-io:name=path:DIR`, or `https://github.com/me/repo:name=path:DIR` for a git repository.
+
+A dependency from a registry other than crates.io, as configured for Cargo, is given as `name=version,registry=NAME`, or all of them at once with `--registry`.
+
+The default features of a dependency are turned off by following its name with `!`, as in `serde!=1` or `serde!@1:derive`, or its version with `,no-default`, as in `serde=1,no-default`.
+*/
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{FailureKind, MainError, MainResult};
+
+/**
+What a dependency given on the command line asks for.
+*/
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct DepSpec {
+    /// Where the dependency comes from.
+    source: Source,
+
+    /// The name of the package, if it isn't the name the dependency is given.
+    package: Option<String>,
+
+    /// The features to enable.
+    features: Vec<String>,
+
+    /// The registry to take the package from, if not crates.io.
+    registry: Option<String>,
+
+    /// Whether to enable the default features.
+    default_features: bool,
+}
+
+/**
+Where a dependency comes from.
+*/
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Source {
+    /// A version requirement for the registry, or a whole inline table like `{ path = "../lib" }`.
+    Version(String),
+
+    /// A git repository, optionally with the branch, tag or commit to use, as its key in the manifest and its value.
+    Git {
+        url: String,
+        reference: Option<(String, String)>,
+    },
+
+    /// A directory with the crate, as given.  It is made relative to the script along with the rest of the manifest.
+    Path(String),
+}
+
+/// The options that can follow the version in the `name=version,features=[a,b]` form, with the
+/// comma before them.
+const OPTIONS: [&str; 3] = [",features=", ",registry=", ",no-default"];
+
+/// The keys selecting what to use of a git repository.
+const GIT_REFERENCES: [&str; 3] = ["branch", "tag", "rev"];
+
+impl Source {
+    // Parse what comes after `name=` or `name@`, without the features.
+    fn parse(s: &str) -> Result<Self, String> {
+        if let Some(path) = s.strip_prefix("path:") {
+            if path.is_empty() {
+                return Err("cannot have empty path dependency".into());
+            }
+            return Ok(Source::Path(path.into()));
+        }
+        let Some(repo) = s.strip_prefix("git+") else {
+            return Ok(Source::Version(s.into()));
+        };
+        let (url, reference) = match repo.split_once('#') {
+            Some((url, reference)) => {
+                let reference = reference
+                    .split_once('=')
+                    .filter(|(key, value)| GIT_REFERENCES.contains(key) && !value.is_empty())
+                    .ok_or_else(|| {
+                        format!(
+                            "git dependency '{}' must be followed by `#branch=NAME`, `#tag=NAME` or `#rev=COMMIT`",
+                            url
+                        )
+                    })?;
+                (url, Some((reference.0.into(), reference.1.into())))
+            }
+            None => (repo, None),
+        };
+        if url.is_empty() {
+            return Err("cannot have empty git dependency URL".into());
+        }
+        Ok(Source::Git {
+            url: url.into(),
+            reference,
+        })
+    }
+}
+
+impl DepSpec {
+    /**
+    A dependency on the given version, without any features.
+    */
+    pub fn version(version: impl Into<String>) -> Self {
+        DepSpec {
+            source: Source::Version(version.into()),
+            package: None,
+            features: Vec::new(),
+            registry: None,
+            default_features: true,
+        }
+    }
+
+    /**
+    Parses a dependency as given on the command line, returning its name and what it asks for.
+    */
+    pub fn parse(dep: &str) -> MainResult<(String, Self)> {
+        let usage = |msg: String| MainError::with_kind(FailureKind::Usage, msg);
+
+        // `dep as alias` is a shorter way to rename a dependency than `alias=crates:dep`.
+        if let Some((dep, alias)) = dep.rsplit_once(" as ") {
+            let (name, mut spec) = DepSpec::parse(dep.trim())?;
+            let alias = alias.trim();
+            if spec.package.is_some() {
+                return Err(usage(format!("dependency '{}' is renamed twice", name)));
+            } else if alias.is_empty() || !alias.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(usage(format!(
+                    "cannot use `{}` as the name of dependency '{}'",
+                    alias, name
+                )));
+            }
+            spec.package = Some(name);
+            return Ok((alias.into(), spec));
+        }
+
+        let mut registry = None;
+        let mut default_features = true;
+        let (name, spec) = match (dep.find('@'), dep.find('=')) {
+            (Some(at), eq) if eq.iter().all(|&eq| at < eq) => {
+                let rest = &dep[at + 1..];
+                // The colons of `path:` and of URLs don't start the features.
+                let source_len = match rest.find("://") {
+                    _ if rest.starts_with("path:") => "path:".len(),
+                    _ if rest.starts_with("crates:") => "crates:".len(),
+                    Some(scheme_len) => scheme_len + "://".len(),
+                    None => 0,
+                };
+                let (version, features) = match rest[source_len..].split_once(':') {
+                    Some((_, features)) => (
+                        &rest[..rest.len() - features.len() - 1],
+                        features.split(',').collect(),
+                    ),
+                    None => (rest, Vec::new()),
+                };
+                (&dep[..at], (version, features))
+            }
+            (_, Some(eq)) => {
+                let rest = &dep[eq + 1..];
+                let options_start = OPTIONS.iter().filter_map(|option| rest.find(option)).min();
+                match options_start {
+                    // An inline table is taken as it is.
+                    Some(_) if rest.starts_with('{') => (&dep[..eq], (rest, Vec::new())),
+                    Some(start) => {
+                        let name = &dep[..eq];
+                        let options = parse_options(name, &rest[start + 1..]).map_err(usage)?;
+                        registry = options.registry;
+                        default_features = options.default_features;
+                        (name, (&rest[..start], options.features))
+                    }
+                    None => (&dep[..eq], (rest, Vec::new())),
+                }
+            }
+            (_, None) => (dep, ("*", Vec::new())),
+        };
+        let (version, features) = spec;
+        // `name!` is a shorter way to turn off the default features than `,no-default`.
+        let name = match name.strip_suffix('!') {
+            Some(name) => {
+                default_features = false;
+                name
+            }
+            None => name,
+        };
+        let (package, version) = match version.strip_prefix("crates:") {
+            Some(renamed) => {
+                let (package, version) = renamed.split_once('@').unwrap_or((renamed, "*"));
+                if package.is_empty() {
+                    return Err(usage(format!(
+                        "cannot have empty package name for dependency '{}'",
+                        name
+                    )));
+                } else if version.starts_with('{') {
+                    return Err(usage(format!(
+                        "dependency '{}' cannot be both renamed and given as a table",
+                        name
+                    )));
+                }
+                (Some(package), version)
+            }
+            None => (None, version),
+        };
+
+        if name.is_empty() {
+            return Err(usage("cannot have empty dependency package name".into()));
+        } else if version.is_empty() {
+            return Err(usage("cannot have empty dependency version".into()));
+        } else if features.iter().any(|feature| feature.is_empty()) {
+            return Err(usage(format!(
+                "cannot have empty feature in dependency '{}'",
+                name
+            )));
+        } else if !default_features && version.starts_with('{') {
+            return Err(usage(format!(
+                "dependency '{}' given as a table must set `default-features = false` itself",
+                name
+            )));
+        }
+
+        Ok((
+            name.into(),
+            DepSpec {
+                source: Source::parse(version).map_err(usage)?,
+                package: package.map(String::from),
+                features: features.into_iter().map(String::from).collect(),
+                registry: registry.map(String::from),
+                default_features,
+            },
+        ))
+    }
+
+    /**
+    Enables the given features of the dependency, in addition to those it was given with.
+    */
+    pub fn add_features(&mut self, features: &[String]) {
+        for feature in features {
+            if !self.features.contains(feature) {
+                self.features.push(feature.clone());
+            }
+        }
+    }
+
+    /**
+    Takes the dependency from the given registry, unless it comes from another one, a git repository or a directory, or is given as an inline table.
+    */
+    pub fn set_default_registry(&mut self, registry: &str) {
+        match &self.source {
+            Source::Version(version) if !version.starts_with('{') => {
+                self.registry.get_or_insert_with(|| registry.into());
+            }
+            _ => {}
+        }
+    }
+
+    /**
+    The version requirement of the dependency, or the inline table it was given as.
+
+    Returns `None` for a dependency from a git repository or a directory.
+    */
+    pub fn version_req(&self) -> Option<&str> {
+        match &self.source {
+            Source::Version(version) => Some(version),
+            Source::Git { .. } | Source::Path(_) => None,
+        }
+    }
+
+    /**
+    The dependency as the value of an entry in the dependencies of a manifest.
+    */
+    pub fn to_toml(&self) -> String {
+        let quote = |s: &str| toml::Value::String(s.into()).to_string();
+        let mut entries = Vec::new();
+        if let Some(package) = &self.package {
+            entries.push(format!("package = {}", quote(package)));
+        }
+        match &self.source {
+            // We only want to quote the version if it *isn't* a table.
+            Source::Version(version) if version.starts_with('{') => return version.clone(),
+            Source::Version(version)
+                if entries.is_empty()
+                    && self.features.is_empty()
+                    && self.registry.is_none()
+                    && self.default_features =>
+            {
+                return quote(version)
+            }
+            Source::Version(version) => entries.push(format!("version = {}", quote(version))),
+            Source::Git { url, reference } => {
+                entries.push(format!("git = {}", quote(url)));
+                if let Some((key, value)) = reference {
+                    entries.push(format!("{} = {}", key, quote(value)));
+                }
+            }
+            Source::Path(path) => entries.push(format!("path = {}", quote(path))),
+        }
+        if !self.default_features {
+            entries.push("default-features = false".into());
+        }
+        if !self.features.is_empty() {
+            let features: Vec<_> = self.features.iter().map(|f| quote(f)).collect();
+            entries.push(format!("features = [{}]", features.join(", ")));
+        }
+        if let Some(registry) = &self.registry {
+            entries.push(format!("registry = {}", quote(registry)));
+        }
+        format!("{{ {} }}", entries.join(", "))
+    }
+}
+
+/**
+Writes the dependency in the `version,features=[a,b],registry=NAME,no-default` form it can be given in after `name=`.
+*/
+impl fmt::Display for DepSpec {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(package) = &self.package {
+            write!(fmt, "crates:{}@", package)?;
+        }
+        match &self.source {
+            Source::Version(version) => write!(fmt, "{}", version)?,
+            Source::Git { url, reference } => {
+                write!(fmt, "git+{}", url)?;
+                if let Some((key, value)) = reference {
+                    write!(fmt, "#{}={}", key, value)?;
+                }
+            }
+            Source::Path(path) => write!(fmt, "path:{}", path)?,
+        }
+        if !self.features.is_empty() {
+            write!(fmt, ",features=[{}]", self.features.join(","))?;
+        }
+        if let Some(registry) = &self.registry {
+            write!(fmt, ",registry={}", registry)?;
+        }
+        if !self.default_features {
+            write!(fmt, ",no-default")?;
+        }
+        Ok(())
+    }
+}
+
+/// The options following the version in the `name=version,features=[a,b]` form.
+struct Options<'a> {
+    features: Vec<&'a str>,
+    registry: Option<&'a str>,
+    default_features: bool,
+}
+
+// Parse the options following the version in the
+// `name=version,features=[a,b],registry=NAME,no-default` form.
+fn parse_options<'a>(name: &str, mut options: &'a str) -> Result<Options<'a>, String> {
+    let mut parsed = Options {
+        features: Vec::new(),
+        registry: None,
+        default_features: true,
+    };
+    loop {
+        let rest = if let Some(rest) = options.strip_prefix("features=") {
+            let (list, rest) = rest
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .ok_or_else(|| {
+                    format!(
+                        "features of dependency '{}' must be in brackets, like `features=[a,b]`",
+                        name
+                    )
+                })?;
+            parsed.features.extend(
+                list.split(',')
+                    .map(|feature| feature.trim().trim_matches('"')),
+            );
+            rest
+        } else if let Some(rest) = options.strip_prefix("registry=") {
+            let (value, rest) = rest.split_at(rest.find(',').unwrap_or(rest.len()));
+            if value.is_empty() {
+                return Err(format!(
+                    "cannot have empty registry in dependency '{}'",
+                    name
+                ));
+            }
+            parsed.registry = Some(value);
+            rest
+        } else if let Some(rest) = options
+            .strip_prefix("no-default")
+            .filter(|rest| rest.is_empty() || rest.starts_with(','))
+        {
+            parsed.default_features = false;
+            rest
+        } else {
+            return Err(format!(
+                "unknown option `{}` in dependency '{}', expected `features=[a,b]`, `registry=NAME` or `no-default`",
+                options, name
+            ));
+        };
+        match rest.strip_prefix(',') {
+            Some(next) => options = next,
+            None if rest.is_empty() => return Ok(parsed),
+            None => return Err(format!("unexpected `{}` in dependency '{}'", rest, name)),
+        }
+    }
+}
+
+/**
+A patch given on the command line, as with `--patch`.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Patch {
+    /// The source being patched: `crates-io`, or the URL of a registry or a git repository.
+    pub source: String,
+
+    /// The crate being patched.
+    pub name: String,
+
+    /// What to use instead.
+    pub spec: DepSpec,
+}
+
+impl Patch {
+    /**
+    Parses a patch as given on the command line, as in `crates-io:name=path:DIR`.
+    */
+    pub fn parse(patch: &str) -> MainResult<Self> {
+        // The source ends at the last colon before the dependency, since URLs have colons too.
+        let dep_start = patch
+            .find('=')
+            .or_else(|| patch.rfind('@'))
+            .and_then(|end| patch[..end].rfind(':'))
+            .filter(|&colon| colon > 0)
+            .ok_or_else(|| {
+                MainError::with_kind(
+                    FailureKind::Usage,
+                    format!(
+                        "patch '{}' must be given as `SOURCE:name=...`, like `crates-io:name=path:DIR`",
+                        patch
+                    ),
+                )
+            })?;
+        let (name, spec) = DepSpec::parse(&patch[dep_start + 1..])?;
+        Ok(Patch {
+            source: patch[..dep_start].into(),
+            name,
+            spec,
+        })
+    }
+}
+
+/**
+Parses the dependencies given on the command line, checking for duplicates.
+
+Dependencies on a version are taken from `registry`, if given, unless they name a registry themselves.
+*/
+pub fn parse_args(args: &[String], registry: Option<&str>) -> MainResult<HashMap<String, DepSpec>> {
+    let mut deps = HashMap::new();
+    for dep in args {
+        let (name, mut spec) = DepSpec::parse(dep)?;
+        if let Some(registry) = registry {
+            spec.set_default_registry(registry);
+        }
+        if deps.contains_key(&name) {
+            return Err(MainError::with_kind(
+                FailureKind::Usage,
+                format!("duplicated dependency: '{}'", name),
+            ));
+        }
+        deps.insert(name, spec);
+    }
+    Ok(deps)
+}
+
+#[test]
+fn test_parse() {
+    let parse = |dep: &str| DepSpec::parse(dep).map(|(name, spec)| (name, spec.to_toml()));
+    assert_eq!(parse("regex").unwrap(), ("regex".into(), r#""*""#.into()));
+    assert_eq!(
+        parse("regex=1.10").unwrap(),
+        ("regex".into(), r#""1.10""#.into())
+    );
+    let derive = (
+        "serde".to_string(),
+        r#"{ version = "1", features = ["derive", "rc"] }"#.to_string(),
+    );
+    assert_eq!(parse("serde=1,features=[derive,rc]").unwrap(), derive);
+    assert_eq!(parse("serde@1:derive,rc").unwrap(), derive);
+    assert_eq!(
+        parse(r#"lib={ path = "a=b@c" }"#).unwrap(),
+        ("lib".into(), r#"{ path = "a=b@c" }"#.into())
+    );
+    assert_eq!(
+        DepSpec::parse("serde@1:derive").unwrap().1.to_string(),
+        "1,features=[derive]"
+    );
+    assert_eq!(
+        parse("mylib=git+https://github.com/me/mylib#branch=main,features=[x]").unwrap(),
+        (
+            "mylib".into(),
+            r#"{ git = "https://github.com/me/mylib", branch = "main", features = ["x"] }"#.into()
+        )
+    );
+    assert_eq!(
+        parse("mylib=git+ssh://git@example.com/mylib.git").unwrap(),
+        (
+            "mylib".into(),
+            r#"{ git = "ssh://git@example.com/mylib.git" }"#.into()
+        )
+    );
+    assert_eq!(
+        parse("helper@path:../libs/helper").unwrap(),
+        ("helper".into(), r#"{ path = "../libs/helper" }"#.into())
+    );
+    assert_eq!(
+        parse("helper@path:../libs/helper:fast").unwrap(),
+        (
+            "helper".into(),
+            r#"{ path = "../libs/helper", features = ["fast"] }"#.into()
+        )
+    );
+    assert_eq!(
+        parse("mylib@git+https://example.com/mylib:x").unwrap(),
+        (
+            "mylib".into(),
+            r#"{ git = "https://example.com/mylib", features = ["x"] }"#.into()
+        )
+    );
+    assert_eq!(
+        DepSpec::parse("mylib=git+https://example.com/mylib#tag=v1")
+            .unwrap()
+            .1
+            .to_string(),
+        "git+https://example.com/mylib#tag=v1"
+    );
+    assert_eq!(
+        parse("fancy_name=crates:actual-crate@1.2").unwrap(),
+        (
+            "fancy_name".into(),
+            r#"{ package = "actual-crate", version = "1.2" }"#.into()
+        )
+    );
+    assert_eq!(
+        parse("fancy@crates:actual-crate@1.2:x").unwrap(),
+        (
+            "fancy".into(),
+            r#"{ package = "actual-crate", version = "1.2", features = ["x"] }"#.into()
+        )
+    );
+    assert_eq!(
+        DepSpec::parse("fancy=crates:actual-crate")
+            .unwrap()
+            .1
+            .to_string(),
+        "crates:actual-crate@*"
+    );
+    let private = (
+        "serde".to_string(),
+        r#"{ version = "1", features = ["derive"], registry = "my-private" }"#.to_string(),
+    );
+    assert_eq!(
+        parse("serde=1,registry=my-private,features=[derive]").unwrap(),
+        private
+    );
+    assert_eq!(
+        parse("serde=1,features=[derive],registry=my-private").unwrap(),
+        private
+    );
+    let deps = parse_args(
+        &[
+            "serde=1".into(),
+            "mine=1,registry=other".into(),
+            "lib=path:../lib".into(),
+        ],
+        Some("my-private"),
+    )
+    .unwrap();
+    assert_eq!(deps["serde"].to_string(), "1,registry=my-private");
+    assert_eq!(deps["mine"].to_string(), "1,registry=other");
+    assert_eq!(deps["lib"].to_string(), "path:../lib");
+    assert_eq!(
+        parse("tokio@1:rt as tk").unwrap(),
+        (
+            "tk".into(),
+            r#"{ package = "tokio", version = "1", features = ["rt"] }"#.into()
+        )
+    );
+    assert_eq!(
+        DepSpec::parse("regex as re").unwrap().1.to_string(),
+        "crates:regex@*"
+    );
+    assert_eq!(
+        Patch::parse("https://github.com/me/repo:foo@path:/work/foo:x").unwrap(),
+        Patch {
+            source: "https://github.com/me/repo".into(),
+            name: "foo".into(),
+            spec: DepSpec::parse("foo=path:/work/foo,features=[x]").unwrap().1,
+        }
+    );
+
+    let no_default = (
+        "serde".to_string(),
+        r#"{ version = "1.0", default-features = false, features = ["derive"] }"#.to_string(),
+    );
+    assert_eq!(parse("serde!=1.0,features=[derive]").unwrap(), no_default);
+    assert_eq!(parse("serde!@1.0:derive").unwrap(), no_default);
+    assert_eq!(
+        parse("serde=1.0,no-default,features=[derive]").unwrap(),
+        no_default
+    );
+    assert_eq!(
+        parse("serde!").unwrap(),
+        (
+            "serde".into(),
+            r#"{ version = "*", default-features = false }"#.into()
+        )
+    );
+    assert_eq!(
+        DepSpec::parse("serde=1.0,no-default")
+            .unwrap()
+            .1
+            .to_string(),
+        "1.0,no-default"
+    );
+
+    assert!(parse("=1").is_err());
+    assert!(parse("serde@").is_err());
+    assert!(parse("serde=1,features=derive").is_err());
+    assert!(parse("serde@1:derive,").is_err());
+    assert!(parse("mylib=git+https://example.com/mylib#main").is_err());
+    assert!(parse("helper=path:").is_err());
+    assert!(parse("fancy=crates:@1").is_err());
+    assert!(Patch::parse("foo=path:/work/foo").is_err());
+    assert!(Patch::parse(":foo=path:/work/foo").is_err());
+    assert!(parse("tokio=1 as ").is_err());
+    assert!(parse("tokio=1 as t-k").is_err());
+    assert!(parse("fancy=crates:tokio@1 as tk").is_err());
+    assert!(parse("serde=1,registry=").is_err());
+    assert!(parse("serde=1,features=[derive]x").is_err());
+    assert!(parse("serde=1,no-defaults").is_err());
+    assert!(parse("!=1").is_err());
+    assert!(parse(r#"lib!={ path = "../lib" }"#).is_err());
+}
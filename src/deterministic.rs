@@ -0,0 +1,84 @@
+/*!
+This module implements `--deterministic` builds, whose binaries are the same byte for byte whenever and wherever they are built from the same script and `Cargo.lock`.
+
+The things that differ between builds are the time of the build, which some crates embed (taken from `SOURCE_DATE_EPOCH` instead), and the paths of the sources, which end up in panic messages and debug information (remapped to fixed ones).  The versions of the dependencies are pinned with `--locked`.
+*/
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use sha1::{Digest, Sha1};
+
+use crate::error::MainResult;
+use crate::platform;
+
+/// Separator of the flags in `CARGO_ENCODED_RUSTFLAGS`.
+const ENCODED_SEPARATOR: char = '\x1f';
+
+/**
+Sets up a Cargo command building into `target_dir` to build deterministically.
+*/
+pub fn configure(cmd: &mut Command, target_dir: &Path) {
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| "0".into());
+    cmd.env("SOURCE_DATE_EPOCH", epoch);
+
+    // `CARGO_ENCODED_RUSTFLAGS` takes precedence over `RUSTFLAGS`, and unlike it allows spaces in
+    // the paths.  For several prefixes that match, the last one is used, so the most specific
+    // comes last.
+    let mut flags = existing_rustflags();
+    let mut remap = |from: &Path, to: &str| {
+        flags.push(format!("--remap-path-prefix={}={}", from.display(), to));
+    };
+    if let Some(home) = dirs::home_dir() {
+        remap(&home, "~");
+    }
+    remap(&platform::cache_dir(), "/rust-script");
+    remap(target_dir, "/rust-script/target");
+
+    cmd.env_remove("RUSTFLAGS");
+    cmd.env(
+        "CARGO_ENCODED_RUSTFLAGS",
+        flags.join(&ENCODED_SEPARATOR.to_string()),
+    );
+}
+
+/**
+Digest of a built binary, for comparing builds.
+*/
+pub fn digest(path: &Path) -> MainResult<String> {
+    let mut hasher = Sha1::new();
+    hasher.update(fs::read(path)?);
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// The flags the user gave for rustc.
+fn existing_rustflags() -> Vec<String> {
+    if let Ok(encoded) = std::env::var("CARGO_ENCODED_RUSTFLAGS") {
+        return encoded
+            .split(ENCODED_SEPARATOR)
+            .filter(|flag| !flag.is_empty())
+            .map(String::from)
+            .collect();
+    }
+    std::env::var("RUSTFLAGS")
+        .map(|flags| flags.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_configure() {
+    let mut cmd = Command::new("cargo");
+    configure(&mut cmd, Path::new("/tmp/target dir"));
+    let env = |name: &str| {
+        cmd.get_envs()
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value)
+            .map(|value| value.to_string_lossy().into_owned())
+    };
+    assert!(env("SOURCE_DATE_EPOCH").is_some());
+    let flags = env("CARGO_ENCODED_RUSTFLAGS").unwrap();
+    assert_eq!(
+        flags.split(ENCODED_SEPARATOR).next_back(),
+        Some("--remap-path-prefix=/tmp/target dir=/rust-script/target")
+    );
+}
@@ -0,0 +1,112 @@
+/*!
+This module implements `--show-warnings`: building with Cargo's JSON messages, so that compiler diagnostics can be shown while the rest of Cargo's output stays quiet.
+*/
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+/**
+The Cargo argument making it report compiler diagnostics as JSON messages on standard output.
+*/
+pub const MESSAGE_FORMAT: &str = "--message-format=json";
+
+/**
+Like `MESSAGE_FORMAT`, but with the diagnostics rendered in colour.
+*/
+pub const MESSAGE_FORMAT_ANSI: &str = "--message-format=json-diagnostic-rendered-ansi";
+
+/**
+Runs the Cargo command, printing the rendered compiler diagnostics from its JSON messages to standard error.
+
+Returns the exit status of the command, along with the diagnostics printed.
+*/
+pub fn run_showing_diagnostics(cmd: &mut Command) -> io::Result<(ExitStatus, String)> {
+    let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("child stdout is piped");
+
+    let mut shown = String::new();
+    let mut console = io::stderr();
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        if !line.starts_with('{') {
+            // Not a message, so it's best passed on as is.
+            writeln!(console, "{}", line)?;
+        } else if let Some(rendered) = rendered_diagnostic(&line) {
+            write!(console, "{}", rendered)?;
+            shown.push_str(&rendered);
+        }
+    }
+
+    Ok((child.wait()?, shown))
+}
+
+/**
+Extracts the rendered diagnostic from a JSON message from Cargo, if it is a compiler message.
+
+Only the diagnostic itself has its `rendered` field set; those of its children are `null`, so the first string found is the one wanted.
+*/
+pub fn rendered_diagnostic(message: &str) -> Option<String> {
+    if !message.contains(r#""reason":"compiler-message""#) {
+        return None;
+    }
+    const KEY: &str = r#""rendered":""#;
+    let start = message.find(KEY)? + KEY.len();
+    unescape_json_string(&message[start..])
+}
+
+// Decode a JSON string, given what follows its opening quote.
+fn unescape_json_string(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'u' => {
+                    let high = hex_unit(&mut chars)?;
+                    let code = if (0xd800..0xdc00).contains(&high) {
+                        // A surrogate pair, the second half of which must follow.
+                        if chars.next()? != '\\' || chars.next()? != 'u' {
+                            return None;
+                        }
+                        let low = hex_unit(&mut chars)?.checked_sub(0xdc00)?;
+                        0x10000 + ((high - 0xd800) << 10) + low
+                    } else {
+                        high
+                    };
+                    out.push(char::from_u32(code)?);
+                }
+                other => out.push(other),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+// Decode the four hex digits of a `\u` escape.
+fn hex_unit(chars: &mut std::str::Chars) -> Option<u32> {
+    let hex: String = chars.take(4).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[test]
+fn test_rendered_diagnostic() {
+    let warning = r#"{"reason":"compiler-message","package_id":"path+file:///cache/projects/abc#script@0.1.0","message":{"$message_type":"diagnostic","message":"unused variable: `x`","children":[{"children":[],"code":null,"level":"help","message":"if this is intentional, prefix it with an underscore: `_x`","rendered":null,"spans":[]}],"code":{"code":"unused_variables","explanation":null},"level":"warning","rendered":"warning: unused variable: `x`\n --> script.rs:2:9\n  |\n2 |     let x = \"café 🦀\";\n  |         ^\n\n","spans":[]}}"#;
+    assert_eq!(
+        rendered_diagnostic(warning).as_deref(),
+        Some(
+            "warning: unused variable: `x`\n --> script.rs:2:9\n  |\n2 |     let x = \"café 🦀\";\n  |         ^\n\n"
+        )
+    );
+
+    let artifact = r#"{"reason":"compiler-artifact","package_id":"path+file:///cache/projects/abc#script@0.1.0","fresh":true}"#;
+    assert_eq!(rendered_diagnostic(artifact), None);
+    assert_eq!(
+        rendered_diagnostic(r#"{"reason":"build-finished","success":true}"#),
+        None
+    );
+}
@@ -0,0 +1,172 @@
+/*!
+This module implements `--edit-on-error`: opening the script in the user's editor where compilation failed, and running it again afterwards.
+*/
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+use log::info;
+use regex::Regex;
+
+use crate::error::MainResult;
+use crate::manifest;
+
+/**
+Runs the Cargo command, passing its standard error through while also capturing it.
+*/
+pub fn run_capturing_stderr(cmd: &mut Command) -> io::Result<(ExitStatus, String)> {
+    let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+    let stderr = child.stderr.take().expect("child stderr is piped");
+
+    let mut captured = String::new();
+    let mut console = io::stderr();
+    for line in BufReader::new(stderr).lines() {
+        let line = line?;
+        writeln!(console, "{}", line)?;
+        captured.push_str(&line);
+        captured.push('\n');
+    }
+
+    Ok((child.wait()?, captured))
+}
+
+/**
+Finds where in the script the first error reported by Cargo is.
+
+`generated` is the source file Cargo compiled, which is either `script` itself, or a file generated from it, in which case the line is mapped back to the script.
+*/
+pub fn error_location(
+    cargo_stderr: &str,
+    pkg_path: &Path,
+    generated: &Path,
+    script: &Path,
+) -> Option<(PathBuf, usize)> {
+    let re_ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let re_location = Regex::new(r"^\s*--> (.+):(\d+):\d+$").unwrap();
+
+    let cargo_stderr = re_ansi.replace_all(cargo_stderr, "");
+    let mut lines = cargo_stderr.lines();
+    lines.find(|line| line.starts_with("error"))?;
+    let captures = lines.find_map(|line| re_location.captures(line))?;
+
+    let path = pkg_path.join(&captures[1]);
+    let line: usize = captures[2].parse().ok()?;
+    info!("first error at {:?}:{}", path, line);
+
+    if path == script {
+        Some((path, line))
+    } else if path == generated {
+        let generated = fs::read_to_string(generated).ok()?;
+        let original = fs::read_to_string(script).ok()?;
+        Some((script.into(), map_line(&generated, &original, line)))
+    } else {
+        None
+    }
+}
+
+// Map a line of a file generated from a script back to the script.
+fn map_line(generated: &str, original: &str, line: usize) -> usize {
+    // Usually the whole script (sans shebang) is embedded verbatim in the generated file...
+    let body = manifest::strip_shebang(original);
+    if let Some(start) = generated.find(body).filter(|_| !body.is_empty()) {
+        let body_line = generated[..start].matches('\n').count() + 1;
+        let skipped = original[..original.len() - body.len()]
+            .matches('\n')
+            .count();
+        if line >= body_line {
+            return line - body_line + 1 + skipped;
+        }
+    }
+
+    // ... otherwise, look for the text of the line.
+    generated
+        .lines()
+        .nth(line.saturating_sub(1))
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .and_then(|text| original.lines().position(|l| l.trim() == text))
+        .map(|i| i + 1)
+        .unwrap_or(1)
+}
+
+/**
+Opens the script in the user's editor at the given line, and once it's closed, offers to run `rust-script` again with the same arguments.
+
+Only returns if the user doesn't want to run the script again.
+*/
+pub fn edit_and_rerun(path: &Path, line: usize) -> MainResult<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let words = shell_words::split(&editor).map_err(|e| e.to_string())?;
+    let (program, args) = words
+        .split_first()
+        .ok_or("the editor command cannot be empty")?;
+
+    eprintln!("opening {}:{} in {}", path.display(), line, program);
+    Command::new(program)
+        .args(args)
+        .arg(format!("+{}", line))
+        .arg(path)
+        .status()?;
+
+    eprint!("run the script again? [Y/n] ");
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "" | "y" | "Y" | "yes") {
+        return Ok(());
+    }
+
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Err(cmd.exec().into())
+    }
+    #[cfg(not(unix))]
+    {
+        std::process::exit(cmd.status()?.code().unwrap_or(1));
+    }
+}
+
+#[test]
+fn test_error_location() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("script.rs");
+    let generated = dir.path().join("pkg").join("script_abc.rs");
+    fs::create_dir_all(generated.parent().unwrap()).unwrap();
+    fs::write(
+        &script,
+        "#!/usr/bin/env rust-script\nlet x = 1;\nlet y: u8 = x + \"\";\n",
+    )
+    .unwrap();
+    fs::write(
+        &generated,
+        "\nfn main() {\n    {let x = 1;\nlet y: u8 = x + \"\";\n}\n}\n",
+    )
+    .unwrap();
+
+    let stderr = "   Compiling script v0.1.0\n\x1b[1m\x1b[31merror[E0277]\x1b[0m: cannot add `&str` to `{integer}`\n \x1b[1m\x1b[34m--> \x1b[0mscript_abc.rs:4:15\n";
+    assert_eq!(
+        error_location(stderr, &dir.path().join("pkg"), &generated, &script),
+        Some((script.clone(), 3))
+    );
+
+    let stderr = format!("error: oops\n --> {}:2:1\n", script.display());
+    assert_eq!(
+        error_location(&stderr, &dir.path().join("pkg"), &generated, &script),
+        Some((script.clone(), 2))
+    );
+
+    assert_eq!(
+        error_location(
+            "warning: unused\n --> other.rs:1:1\n",
+            dir.path(),
+            &generated,
+            &script
+        ),
+        None
+    );
+}
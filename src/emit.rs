@@ -0,0 +1,102 @@
+/*!
+This module implements `--emit`: printing the assembly, LLVM IR or MIR the compiler generates for a script, for seeing what the code turns into without setting up a project for it.
+
+Only the script's own crate is emitted, not its dependencies, as the flags are given to the compiler with `cargo rustc`.  The crate is compiled as a single codegen unit, so that there's a single file to print; this can make the code differ a little from that of the script's binary.
+*/
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+use log::info;
+
+use crate::error::{FailureKind, MainError, MainResult};
+
+/// The kinds of output `--emit` accepts, named as for the compiler's own `--emit`.
+const KINDS: [&str; 3] = ["asm", "llvm-ir", "mir"];
+
+/**
+What to emit, and where to, as given to `--emit`.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Emit {
+    /// One of `KINDS`.
+    pub kind: &'static str,
+
+    /// The file to write the output to, or `None` to print it.
+    pub path: Option<PathBuf>,
+}
+
+impl Emit {
+    /**
+    Parses the kind of output, optionally followed by `=FILE`, as in `asm` or `llvm-ir=script.ll`.
+    */
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (kind, path) = match s.split_once('=') {
+            Some((_, "")) => return Err(format!("missing file after '=' in '{}'", s)),
+            Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+        let kind = *KINDS.iter().find(|known| **known == kind).ok_or_else(|| {
+            format!(
+                "unknown kind of output '{}', expected one of {}",
+                kind,
+                KINDS.join(", ")
+            )
+        })?;
+        Ok(Emit { kind, path })
+    }
+}
+
+/**
+Compiles the script with the given `cargo rustc` command, emitting the output asked for.
+*/
+pub fn emit(cmd: &mut Command, emit: &Emit) -> MainResult<()> {
+    let out_dir = tempfile::TempDir::new()?;
+    let out_path = out_dir.path().join("emitted");
+    let mut emit_arg = std::ffi::OsString::from(format!("--emit={}=", emit.kind));
+    emit_arg.push(&out_path);
+    cmd.arg("-q")
+        .args(["--", "-C", "codegen-units=1"])
+        .arg(emit_arg);
+    info!("emitting {}: {:?}", emit.kind, cmd);
+
+    if !cmd.status()?.success() {
+        return Err(MainError::with_kind(
+            FailureKind::Compile,
+            "Could not compile the script",
+        ));
+    }
+    match &emit.path {
+        Some(path) => {
+            fs::copy(&out_path, path).map_err(|err| {
+                MainError::Tag(
+                    format!("could not write {}", path.display()).into(),
+                    Box::new(err.into()),
+                )
+            })?;
+        }
+        None => io::stdout().write_all(&fs::read(&out_path)?)?,
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_emit() {
+    assert_eq!(
+        Emit::parse("asm"),
+        Ok(Emit {
+            kind: "asm",
+            path: None
+        })
+    );
+    assert_eq!(
+        Emit::parse("llvm-ir=out/script.ll"),
+        Ok(Emit {
+            kind: "llvm-ir",
+            path: Some("out/script.ll".into())
+        })
+    );
+    assert!(Emit::parse("mir=").is_err());
+    assert!(Emit::parse("obj").is_err());
+}
@@ -11,10 +11,35 @@ use std::result::Result;
 /// Shorthand for the program's common result type.
 pub type MainResult<T> = Result<T, MainError>;
 
+/**
+The kinds of failure that get their own exit code, so that wrappers can tell them apart from each other and from the script itself failing.
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailureKind {
+    /// The command line doesn't make sense, or the script couldn't be found.
+    Usage,
+    /// The script failed to compile.
+    Compile,
+    /// The package or binary cache couldn't be updated.
+    Cache,
+}
+
+impl FailureKind {
+    /// The exit code used for this kind of failure.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Usage => 2,
+            FailureKind::Compile => 3,
+            FailureKind::Cache => 4,
+        }
+    }
+}
+
 /// An error in the program.
 #[derive(Debug)]
 pub enum MainError {
     Io(io::Error),
+    Failure(FailureKind, Box<MainError>),
     Tag(Cow<'static, str>, Box<MainError>),
     Other(Box<dyn Error>),
     OtherOwned(String),
@@ -27,6 +52,7 @@ impl fmt::Display for MainError {
         use std::fmt::Display;
         match self {
             Io(err) => Display::fmt(err, fmt),
+            Failure(_, err) => Display::fmt(err, fmt),
             Tag(msg, ref err) => write!(fmt, "{}: {}", msg, err),
             Other(err) => Display::fmt(err, fmt),
             OtherOwned(err) => Display::fmt(err, fmt),
@@ -37,6 +63,26 @@ impl fmt::Display for MainError {
 
 impl Error for MainError {}
 
+impl MainError {
+    /// Marks an error as a failure of the given kind.
+    pub fn with_kind(kind: FailureKind, err: impl Into<MainError>) -> MainError {
+        MainError::Failure(kind, Box::new(err.into()))
+    }
+
+    /**
+    The exit code to use when the program fails with this error.
+
+    Failures of a known kind get their own exit code; everything else exits with 1.
+    */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MainError::Failure(kind, _) => kind.exit_code(),
+            MainError::Tag(_, err) => err.exit_code(),
+            _ => 1,
+        }
+    }
+}
+
 macro_rules! from_impl {
     ($src_ty:ty => $dst_ty:ty, $src:ident -> $e:expr) => {
         impl From<$src_ty> for $dst_ty {
@@ -59,3 +105,18 @@ where
         Self::Other(src)
     }
 }
+
+#[test]
+fn test_exit_code() {
+    assert_eq!(MainError::from("oops").exit_code(), 1);
+    assert_eq!(
+        MainError::with_kind(FailureKind::Usage, "bad argument").exit_code(),
+        2
+    );
+    let tagged = MainError::Tag(
+        "context".into(),
+        Box::new(MainError::with_kind(FailureKind::Cache, "disk full")),
+    );
+    assert_eq!(tagged.exit_code(), 4);
+    assert_eq!(tagged.to_string(), "context: disk full");
+}
@@ -0,0 +1,211 @@
+/*!
+This module implements `--remember-failures`: recording that a package failed to compile, so that running it again unchanged can fail straight away instead of running Cargo.
+
+Only errors in the code are remembered.  Failures that can go away by themselves, such as not being able to download a dependency, are not.  Neither are errors in files whose changes wouldn't be noticed, such as another script the script depends on: a failure is only remembered if every error is in the sources it's recorded with.
+*/
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::error::MainResult;
+use crate::metadata;
+
+/// Name of the file recording the failure inside the package directory.
+const FAILURE_RECORD_FILE: &str = "failure.toml";
+
+/**
+A failed build of a package.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FailureRecord {
+    /// Digest of the sources and the toolchain the build failed with.
+    pub sources_digest: String,
+
+    /// The first error reported by the compiler.
+    pub error: String,
+}
+
+impl FailureRecord {
+    /**
+    Creates the record of a failed build of the given sources, from the output of Cargo run in `pkg_path`.
+
+    Returns `None` if the output has no compiler error, i.e. the build failed for some other reason, or if any error is in a file besides the sources.
+    */
+    pub fn compute(
+        sources: &[&Path],
+        toolchain: Option<&str>,
+        pkg_path: &Path,
+        cargo_output: &str,
+    ) -> MainResult<Option<Self>> {
+        let Some(error) = first_error(cargo_output) else {
+            return Ok(None);
+        };
+        if !errors_are_in(cargo_output, pkg_path, sources) {
+            return Ok(None);
+        }
+        Ok(Some(FailureRecord {
+            sources_digest: sources_digest(sources, toolchain)?,
+            error,
+        }))
+    }
+
+    /**
+    Is this the record of a build of the same sources, with the same toolchain?
+    */
+    pub fn matches(&self, sources: &[&Path], toolchain: Option<&str>) -> MainResult<bool> {
+        Ok(self.sources_digest == sources_digest(sources, toolchain)?)
+    }
+
+    /**
+    Loads the record stored in a package directory.
+
+    Returns `None` if there is none, or it can't be understood.
+    */
+    pub fn load(pkg_path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(pkg_path.join(FAILURE_RECORD_FILE)).ok()?;
+        let table: toml::value::Table = toml::from_str(&content).ok()?;
+
+        Some(FailureRecord {
+            sources_digest: table.get("sources_digest")?.as_str()?.to_string(),
+            error: table.get("error")?.as_str()?.to_string(),
+        })
+    }
+
+    /**
+    Stores the record in a package directory.
+    */
+    pub fn save(&self, pkg_path: &Path) -> MainResult<()> {
+        let mut table = toml::value::Table::new();
+        table.insert(
+            "sources_digest".to_string(),
+            toml::Value::String(self.sources_digest.clone()),
+        );
+        table.insert("error".to_string(), toml::Value::String(self.error.clone()));
+
+        crate::overwrite_file(&pkg_path.join(FAILURE_RECORD_FILE), &format!("{}", table))
+    }
+
+    /**
+    Forgets the failure recorded in a package directory, if any.
+    */
+    pub fn clear(pkg_path: &Path) -> MainResult<()> {
+        match fs::remove_file(pkg_path.join(FAILURE_RECORD_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Digest of the sources, and of the toolchain, which a build may fail with but not another.
+fn sources_digest(sources: &[&Path], toolchain: Option<&str>) -> MainResult<String> {
+    let digest = metadata::sources_digest(sources)?;
+    Ok(match toolchain {
+        Some(toolchain) => format!("{}+{}", digest, toolchain),
+        None => digest,
+    })
+}
+
+// The first compiler error in the output of Cargo, from its `error` line up to the blank line
+// ending it.  Errors without a location in the code, such as Cargo's own, don't count.
+fn first_error(cargo_output: &str) -> Option<String> {
+    let re_ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let re_location = Regex::new(r"^\s*--> ").unwrap();
+
+    let cargo_output = re_ansi.replace_all(cargo_output, "");
+    let mut error = Vec::new();
+    for line in cargo_output.lines() {
+        if error.is_empty() {
+            if line.starts_with("error") {
+                error.push(line);
+            }
+        } else if line.trim().is_empty() {
+            break;
+        } else {
+            error.push(line);
+        }
+    }
+    if error.iter().any(|line| re_location.is_match(line)) {
+        Some(error.join("\n"))
+    } else {
+        None
+    }
+}
+
+// Are all the compiler errors in the output of Cargo in the given files?  Relative paths in the
+// output are relative to the package directory.
+fn errors_are_in(cargo_output: &str, pkg_path: &Path, files: &[&Path]) -> bool {
+    let re_ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let re_location = Regex::new(r"^\s*--> (.+):\d+:\d+$").unwrap();
+
+    let canonical = |path: &Path| fs::canonicalize(path).ok();
+    let files: Vec<_> = files.iter().filter_map(|file| canonical(file)).collect();
+    let cargo_output = re_ansi.replace_all(cargo_output, "");
+    let mut in_error = false;
+    for line in cargo_output.lines() {
+        if line.starts_with("error") {
+            in_error = true;
+        } else if line.trim().is_empty() {
+            in_error = false;
+        } else if let Some(captures) = re_location.captures(line).filter(|_| in_error) {
+            let path = pkg_path.join(&captures[1]);
+            if !canonical(&path).is_some_and(|path| files.contains(&path)) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[test]
+fn test_first_error() {
+    let output = "\
+   Compiling script v0.1.0 (/tmp/script)
+warning: unused variable: `x`
+ --> script.rs:1:5
+
+error[E0425]: cannot find value `y` in this scope
+ --> script.rs:2:5
+  |
+2 |     y
+  |     ^ not found in this scope
+
+error: aborting due to 1 previous error
+";
+    assert_eq!(
+        first_error(output).as_deref(),
+        Some(
+            "error[E0425]: cannot find value `y` in this scope\n --> script.rs:2:5\n  |\n2 |     y\n  |     ^ not found in this scope"
+        )
+    );
+    assert_eq!(
+        first_error("error: failed to get `regex` as a dependency of package `script`\n"),
+        None
+    );
+}
+
+#[test]
+fn test_errors_are_in() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("script.rs");
+    let helper = dir.path().join("helper.rs");
+    fs::write(&script, "").unwrap();
+    fs::write(&helper, "").unwrap();
+    let output = |path: &str| {
+        format!(
+            "error[E0425]: cannot find value `y` in this scope\n --> {}:2:5\n  |\n\nwarning: unused\n --> {}:1:1\n",
+            path,
+            helper.display()
+        )
+    };
+
+    let sources = [script.as_path()];
+    assert!(errors_are_in(&output("script.rs"), dir.path(), &sources));
+    assert!(errors_are_in(
+        &output(&script.to_string_lossy()),
+        Path::new("/"),
+        &sources
+    ));
+    assert!(!errors_are_in(&output("helper.rs"), dir.path(), &sources));
+    assert!(!errors_are_in(&output("missing.rs"), dir.path(), &sources));
+}
@@ -0,0 +1,298 @@
+/*!
+This module implements `--fatpack`: bundling a script with the sources of its dependencies into a single "fat script", which can be built without network access.
+
+The sources, as vendored by `cargo vendor`, are appended to the script in a block comment, together with the `Cargo.lock` they were vendored for.  Each file is given by a `file <path>` line followed by its contents in base64, which contains neither `/*` nor `*/`, so can't end the comment early.  When a fat script is run, the files are unpacked into its package, along with a Cargo configuration that builds it offline from them.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+
+use crate::cargo_version;
+use crate::error::{MainError, MainResult};
+
+/// The line starting the archive in a fat script.
+const ARCHIVE_START: &str = "/* rust-script-fatpack v1";
+
+/// The line ending the archive in a fat script.
+const ARCHIVE_END: &str = "*/";
+
+/// Directory of the vendored sources, inside the package.
+const VENDOR_DIR: &str = "vendor";
+
+/// The Cargo configuration making the package build from the vendored sources.
+const CARGO_CONFIG: &str = r#"[source.crates-io]
+replace-with = "vendored-sources"
+
+[source.vendored-sources]
+directory = "vendor"
+
+[net]
+offline = true
+"#;
+
+/// Length of the base64 lines in the archive.
+const LINE_LEN: usize = 76;
+
+/**
+Bundles a script with the dependencies of its package, writing the fat script to `out`.
+
+`script` is the script as written, which may already be a fat script, in which case its archive is replaced.  `cargo_flags` are Cargo's `--offline`, `--locked` and `--frozen`, as given.
+*/
+pub fn create(
+    script: &str,
+    pkg_path: &Path,
+    toolchain: Option<&str>,
+    cargo_flags: &[&str],
+    out: &Path,
+) -> MainResult<()> {
+    let vendor_dir = tempfile::TempDir::new()?;
+    let mut cmd = Command::new("cargo");
+    if let Some(toolchain) = toolchain {
+        cmd.arg(format!("+{}", toolchain));
+    }
+    cmd.args(["vendor", "--quiet"])
+        .args(cargo_flags)
+        .arg("--manifest-path")
+        .arg(pkg_path.join("Cargo.toml"))
+        .arg(vendor_dir.path());
+    info!("running {:?}", cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(format!("cargo vendor failed with {}", status).into());
+    }
+
+    let mut files = vec![(
+        "Cargo.lock".to_string(),
+        fs::read(pkg_path.join("Cargo.lock"))?,
+    )];
+    let mut paths = Vec::new();
+    collect_files(vendor_dir.path(), &mut paths)?;
+    paths.sort();
+    for path in paths {
+        let relative = path
+            .strip_prefix(vendor_dir.path())
+            .expect("vendored file is in the vendor directory");
+        let name = Path::new(VENDOR_DIR).join(relative);
+        let name = name
+            .to_str()
+            .ok_or_else(|| format!("vendored file has a non-UTF-8 name: {:?}", path))?
+            .replace('\\', "/");
+        files.push((name, fs::read(&path)?));
+    }
+    info!("packing {} files", files.len());
+
+    let fat = format!("{}{}", strip_archive(script), archive(&files));
+    crate::overwrite_file(out, &fat)?;
+    eprintln!(
+        "wrote {} ({} vendored files, {:.1} MB)",
+        out.display(),
+        files.len() - 1,
+        fat.len() as f64 / (1024.0 * 1024.0)
+    );
+    Ok(())
+}
+
+/**
+Unpacks the archive of a fat script into its package, so it builds from the vendored sources.
+
+The Cargo configuration is written where the Cargo of `toolchain` looks for it.  For a script that isn't fat, any sources left over from when it was are removed instead, if `remove_stale` is set.
+*/
+pub fn unpack(
+    script: &str,
+    pkg_path: &Path,
+    toolchain: Option<&str>,
+    remove_stale: bool,
+) -> MainResult<()> {
+    let vendor_dir = pkg_path.join(VENDOR_DIR);
+    let config_dir = pkg_path.join(".cargo");
+    let config_names = ["config.toml", "config"];
+
+    let Some(files) = parse_archive(script)? else {
+        let stale = config_names
+            .iter()
+            .map(|name| config_dir.join(name))
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+        if remove_stale && !stale.is_empty() {
+            info!("removing vendored sources from {:?}", pkg_path);
+            for path in stale {
+                fs::remove_file(path)?;
+            }
+            if vendor_dir.is_dir() {
+                fs::remove_dir_all(&vendor_dir)?;
+            }
+        }
+        return Ok(());
+    };
+
+    info!("unpacking {} files into {:?}", files.len(), pkg_path);
+    if vendor_dir.is_dir() {
+        fs::remove_dir_all(&vendor_dir)?;
+    }
+    for (name, content) in files {
+        if name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(format!("invalid file name in fat script: {}", name).into());
+        }
+        let path = pkg_path.join(&name);
+        fs::create_dir_all(path.parent().expect("unpacked file has a parent"))?;
+        fs::write(path, content)?;
+    }
+
+    // Older Cargos only know the configuration file without the extension.
+    let config_name = if cargo_version::supports(toolchain, cargo_version::CONFIG_TOML) {
+        config_names[0]
+    } else {
+        config_names[1]
+    };
+    fs::create_dir_all(&config_dir)?;
+    crate::overwrite_file(&config_dir.join(config_name), CARGO_CONFIG)
+}
+
+// Recursively collect all files below `dir`.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> MainResult<()> {
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        if child.file_type()?.is_dir() {
+            collect_files(&child.path(), files)?;
+        } else {
+            files.push(child.path());
+        }
+    }
+    Ok(())
+}
+
+// The script without its archive, if it has one.
+fn strip_archive(script: &str) -> &str {
+    match script.find(&format!("\n{}\n", ARCHIVE_START)) {
+        Some(start) => &script[..start],
+        None => script,
+    }
+}
+
+// Format the archive of the given files.
+fn archive(files: &[(String, Vec<u8>)]) -> String {
+    let mut out = format!("\n{}\n", ARCHIVE_START);
+    for (name, content) in files {
+        out.push_str("file ");
+        out.push_str(name);
+        out.push('\n');
+        let encoded = base64_encode(content);
+        for line in encoded.as_bytes().chunks(LINE_LEN) {
+            out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+            out.push('\n');
+        }
+    }
+    out.push_str(ARCHIVE_END);
+    out.push('\n');
+    out
+}
+
+/// The files in the archive of a fat script, as their paths and contents.
+type Archive = Vec<(String, Vec<u8>)>;
+
+// Parse the archive of a fat script, if it is one.
+fn parse_archive(script: &str) -> MainResult<Option<Archive>> {
+    let Some(start) = script.find(&format!("\n{}\n", ARCHIVE_START)) else {
+        return Ok(None);
+    };
+    let invalid = || MainError::from("the archive of the fat script is corrupt");
+
+    let mut files = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    let mut lines = script[start..].lines().skip(2);
+    loop {
+        let line = lines.next().ok_or_else(invalid)?;
+        if line == ARCHIVE_END || line.starts_with("file ") {
+            if let Some((name, encoded)) = current.take() {
+                files.push((name, base64_decode(&encoded).ok_or_else(invalid)?));
+            }
+        }
+        if line == ARCHIVE_END {
+            return Ok(Some(files));
+        }
+        match (line.strip_prefix("file "), &mut current) {
+            (Some(name), _) => current = Some((name.to_string(), String::new())),
+            (None, Some((_, encoded))) => encoded.push_str(line.trim_end()),
+            (None, None) => return Err(invalid()),
+        }
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (u32::from(b) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut n = 0u32;
+    for (i, c) in s.bytes().enumerate() {
+        let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+        n = (n << 6) | value;
+        if i % 4 == 3 {
+            out.extend_from_slice(&n.to_be_bytes()[1..]);
+            n = 0;
+        }
+    }
+    match s.len() % 4 {
+        0 => {}
+        2 => out.push((n >> 4) as u8),
+        3 => out.extend_from_slice(&((n >> 2) as u16).to_be_bytes()),
+        _ => return None,
+    }
+    Some(out)
+}
+
+#[test]
+fn test_base64() {
+    for (bytes, encoded) in [
+        (&b""[..], ""),
+        (b"f", "Zg=="),
+        (b"fo", "Zm8="),
+        (b"foo", "Zm9v"),
+        (b"foob", "Zm9vYg=="),
+        (b"\xff\x00\xfe", "/wD+"),
+    ] {
+        assert_eq!(base64_encode(bytes), encoded);
+        assert_eq!(base64_decode(encoded).as_deref(), Some(bytes));
+    }
+    assert_eq!(base64_decode("Z"), None);
+    assert_eq!(base64_decode("Zm*v"), None);
+}
+
+#[test]
+fn test_archive() {
+    let script = "#!/usr/bin/env rust-script\nfn main() {}\n";
+    let files = vec![
+        ("Cargo.lock".to_string(), b"version = 3\n".to_vec()),
+        ("vendor/a/src/lib.rs".to_string(), vec![b'x'; 100]),
+        ("vendor/a/empty".to_string(), Vec::new()),
+    ];
+    let fat = format!("{}{}", script, archive(&files));
+    assert!(fat.ends_with("\n*/\n"));
+    assert_eq!(parse_archive(script).unwrap(), None);
+    assert_eq!(parse_archive(&fat).unwrap(), Some(files.clone()));
+    assert_eq!(strip_archive(&fat), script);
+
+    let truncated = &fat[..fat.len() - 3];
+    assert!(parse_archive(truncated).is_err());
+}
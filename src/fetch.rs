@@ -0,0 +1,46 @@
+/*!
+This module is concerned with fetching scripts that aren't on the local file system.
+
+Remote scripts are given as `<scheme>:<reference>`, or as an HTTP(S) URL, and are fetched into the cache, from where they are run like any other script.
+*/
+use std::path::PathBuf;
+
+use crate::error::MainResult;
+
+pub mod git;
+pub mod http;
+
+/**
+Does the given script argument refer to a remote script?
+*/
+pub fn is_remote(script: &str) -> bool {
+    script.starts_with(git::PREFIX) || script.starts_with(git::GITHUB_PREFIX) || is_url(script)
+}
+
+// Is the script given as an HTTP(S) URL?
+fn is_url(script: &str) -> bool {
+    http::PREFIXES
+        .iter()
+        .any(|prefix| script.starts_with(prefix))
+}
+
+/**
+Fetches a remote script, returning the path of the local copy.
+
+Previously fetched scripts are reused, unless `update` is set.  Scripts can only be fetched from the `allowed` hosts, if given, whether at HTTP(S) URLs or in git repositories.
+*/
+pub fn fetch(
+    script: &str,
+    update: bool,
+    allowed: Option<&[http::HostPattern]>,
+) -> MainResult<PathBuf> {
+    if let Some(reference) = script.strip_prefix(git::PREFIX) {
+        git::fetch(reference, update, allowed)
+    } else if let Some(reference) = script.strip_prefix(git::GITHUB_PREFIX) {
+        git::fetch(&git::github_reference(reference)?, update, allowed)
+    } else if is_url(script) {
+        http::fetch(script, update, allowed)
+    } else {
+        Err(format!("not a remote script: {}", script).into())
+    }
+}
@@ -0,0 +1,311 @@
+/*!
+Fetching scripts from git repositories, given as `git:<url>#<ref>:<path>`.
+
+Each repository gets a bare clone in the cache, into which refs are fetched shallowly.  Scripts are checked out into a directory named after the commit they come from, so every commit is cached (and built) separately.
+*/
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+use sha1::{Digest, Sha1};
+
+use super::http::{self, HostPattern};
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::platform;
+
+/// The prefix of scripts in git repositories.
+pub const PREFIX: &str = "git:";
+
+/// The prefix of scripts in GitHub repositories, given as `gh:<user>/<repo>/<path>@<ref>`.
+pub const GITHUB_PREFIX: &str = "gh:";
+
+/**
+A script in a git repository.
+*/
+#[derive(Debug, Eq, PartialEq)]
+struct GitScript<'a> {
+    /// URL of the repository.
+    url: &'a str,
+    /// The branch, tag or commit; `None` for the default branch.
+    git_ref: Option<&'a str>,
+    /// Path of the script in the repository.
+    path: &'a str,
+}
+
+// Parse `<url>#<ref>:<path>`.
+fn parse(reference: &str) -> MainResult<GitScript<'_>> {
+    let usage = || {
+        MainError::with_kind(
+            FailureKind::Usage,
+            format!(
+                "invalid git script '{}{}': expected {}<url>#<ref>:<path>",
+                PREFIX, reference, PREFIX
+            ),
+        )
+    };
+
+    let (url, rest) = reference.rsplit_once('#').ok_or_else(usage)?;
+    let (git_ref, path) = rest.split_once(':').ok_or_else(usage)?;
+    let path = path.trim_start_matches('/');
+    if url.is_empty() || path.is_empty() {
+        return Err(usage());
+    }
+
+    Ok(GitScript {
+        url,
+        git_ref: Some(git_ref).filter(|r| !r.is_empty()),
+        path,
+    })
+}
+
+/**
+Turns a script in a GitHub repository, given as `<user>/<repo>/<path>@<ref>`, into the `<url>#<ref>:<path>` of the repository.
+
+The ref is optional, defaulting to the default branch.
+*/
+pub fn github_reference(reference: &str) -> MainResult<String> {
+    let usage = || {
+        MainError::with_kind(
+            FailureKind::Usage,
+            format!(
+                "invalid GitHub script '{}{}': expected {}<user>/<repo>/<path>@<ref>",
+                GITHUB_PREFIX, reference, GITHUB_PREFIX
+            ),
+        )
+    };
+
+    let (location, git_ref) = match reference.rsplit_once('@') {
+        Some((location, git_ref)) => (location, git_ref),
+        None => (reference, ""),
+    };
+    let mut parts = location.splitn(3, '/');
+    let (Some(user), Some(repo), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(usage());
+    };
+    if user.is_empty() || repo.is_empty() || path.is_empty() {
+        return Err(usage());
+    }
+
+    Ok(format!(
+        "https://github.com/{}/{}.git#{}:{}",
+        user, repo, git_ref, path
+    ))
+}
+
+/**
+Fetches a script from a git repository, returning the path of the local copy.
+
+Scripts can only be fetched from repositories on the `allowed` hosts, if given.
+*/
+pub fn fetch(
+    reference: &str,
+    update: bool,
+    allowed: Option<&[HostPattern]>,
+) -> MainResult<PathBuf> {
+    let script = parse(reference)?;
+    info!("git script: {:?}", script);
+    http::check_host_allowed(repository_host(script.url).as_deref(), script.url, allowed)?;
+
+    let cache_dir = platform::git_cache_path();
+    let repo_dir = cache_dir.join("repos").join(digest(script.url));
+    if !repo_dir.join("HEAD").is_file() {
+        fs::create_dir_all(&repo_dir)?;
+        git(
+            None,
+            [
+                OsStr::new("init"),
+                OsStr::new("--bare"),
+                repo_dir.as_os_str(),
+            ],
+        )?;
+    }
+
+    // Fetched refs are kept under a name of our own, so that they can be reused offline.
+    let git_ref = script.git_ref.unwrap_or("HEAD");
+    let local_ref = format!("refs/rust-script/{}", digest(git_ref));
+    let fetched = !update
+        && git(
+            Some(&repo_dir),
+            [
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", local_ref),
+            ],
+        )
+        .is_ok();
+    if !fetched {
+        eprintln!("fetching {} from {}", git_ref, script.url);
+        git(
+            Some(&repo_dir),
+            [
+                "fetch",
+                "--depth",
+                "1",
+                "--quiet",
+                script.url,
+                &format!("+{}:{}", git_ref, local_ref),
+            ],
+        )?;
+    }
+    let commit = git(
+        Some(&repo_dir),
+        ["rev-parse", &format!("{}^{{commit}}", local_ref)],
+    )?;
+    let commit = commit.trim();
+    info!("git commit: {}", commit);
+
+    let checkout = cache_dir
+        .join("checkouts")
+        .join(digest(script.url))
+        .join(commit)
+        .join(script.path);
+    if !checkout.is_file() {
+        let content = git(
+            Some(&repo_dir),
+            ["show", &format!("{}:{}", commit, script.path)],
+        )?;
+        fs::create_dir_all(checkout.parent().expect("checkout path has a parent"))?;
+        crate::overwrite_file(&checkout, &content)?;
+    }
+    Ok(checkout)
+}
+
+// The host name of the URL of a repository, in lowercase, without any user name or port, as in
+// `https://host/repo.git`, `ssh://user@host:22/repo.git` or the scp-like `user@host:repo.git`.
+// Local repositories, given as a path or a `file://` URL, have none.
+fn repository_host(url: &str) -> Option<String> {
+    let authority = match url.split_once("://") {
+        Some((_, rest)) => rest.split('/').next()?,
+        // The scp-like syntax only applies if there's no slash before the first colon.
+        None => {
+            url.split_once(':')
+                .filter(|(host, _)| !host.contains('/'))?
+                .0
+        }
+    };
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        // An IPv6 address, which has colons of its own.
+        Some(address) => address.split(']').next()?,
+        None => authority.split(':').next()?,
+    };
+    Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty())
+}
+
+// Hex digest used to name things after arbitrary strings.
+fn digest(s: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(s);
+    let mut digest = format!("{:x}", hasher.finalize());
+    digest.truncate(crate::consts::ID_DIGEST_LEN_MAX);
+    digest
+}
+
+// Run git, in the given bare repository if any, returning its output.
+fn git<I, S>(repo_dir: Option<&Path>, args: I) -> MainResult<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("git");
+    if let Some(repo_dir) = repo_dir {
+        cmd.arg("--git-dir").arg(repo_dir);
+    }
+    cmd.args(args);
+    info!("running {:?}", cmd);
+
+    let output = cmd
+        .output()
+        .map_err(|err| MainError::Tag("could not run git".into(), Box::new(err.into())))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    String::from_utf8(output.stdout).map_err(|_| "git output is not valid UTF-8".into())
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        parse("https://example.com/ops.git#main:scripts/deploy.rs").unwrap(),
+        GitScript {
+            url: "https://example.com/ops.git",
+            git_ref: Some("main"),
+            path: "scripts/deploy.rs",
+        }
+    );
+    assert_eq!(
+        parse("git@example.com:ops.git#:/deploy.rs").unwrap(),
+        GitScript {
+            url: "git@example.com:ops.git",
+            git_ref: None,
+            path: "deploy.rs",
+        }
+    );
+    assert!(parse("https://example.com/ops.git").is_err());
+    assert!(parse("https://example.com/ops.git#main").is_err());
+    assert!(parse("https://example.com/ops.git#main:").is_err());
+}
+
+#[test]
+fn test_github_reference() {
+    assert_eq!(
+        github_reference("user/repo/scripts/deploy.rs@v1.2").unwrap(),
+        "https://github.com/user/repo.git#v1.2:scripts/deploy.rs"
+    );
+    assert_eq!(
+        github_reference("user/repo/deploy.rs").unwrap(),
+        "https://github.com/user/repo.git#:deploy.rs"
+    );
+    assert!(github_reference("user/repo").is_err());
+    assert!(github_reference("user//deploy.rs@main").is_err());
+}
+
+#[test]
+fn test_repository_host() {
+    assert_eq!(
+        repository_host("https://GitHub.com/user/repo.git"),
+        Some("github.com".into())
+    );
+    assert_eq!(
+        repository_host("ssh://git@example.com:2222/ops.git"),
+        Some("example.com".into())
+    );
+    assert_eq!(
+        repository_host("git@example.com:ops.git"),
+        Some("example.com".into())
+    );
+    assert_eq!(repository_host("file:///srv/ops.git"), None);
+    assert_eq!(repository_host("/srv/ops.git"), None);
+    assert_eq!(repository_host("../repos/a:b.git"), None);
+
+    // Nothing is fetched before the host is checked.
+    let allowed = [HostPattern::parse("git.corp.example").unwrap()];
+    let refused = |reference: &str, allowed: &[HostPattern]| {
+        fetch(reference, false, Some(allowed))
+            .unwrap_err()
+            .to_string()
+            .contains("not in `fetch-allow`")
+    };
+    assert!(refused(
+        "https://evil.example/ops.git#main:deploy.rs",
+        &allowed
+    ));
+    assert!(refused("/srv/ops.git#main:deploy.rs", &allowed));
+    assert!(refused(
+        &github_reference("user/repo/deploy.rs@main").unwrap(),
+        &allowed
+    ));
+    assert!(refused(
+        "https://git.corp.example/ops.git#main:deploy.rs",
+        &[]
+    ));
+}
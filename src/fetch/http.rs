@@ -0,0 +1,450 @@
+/*!
+Fetching scripts from HTTP(S) URLs.
+
+Each URL gets a directory in the cache, holding a copy of every version of the script fetched from it, named after a digest of its content, so every version is cached (and built) separately.  The URL, and the validators the server sent along with the current version, are recorded in an `origin.toml` next to them, so that later runs can ask the server whether the script changed instead of downloading it again.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+use sha1::{Digest, Sha1};
+
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::platform;
+
+/// The prefixes of scripts at HTTP(S) URLs.
+pub const PREFIXES: [&str; 2] = ["http://", "https://"];
+
+/// The most redirects followed when fetching a script.
+const MAX_REDIRECTS: usize = 10;
+
+/// Name of the file recording where a script came from.
+const ORIGIN_FILE: &str = "origin.toml";
+
+/**
+A host scripts may be fetched from, as given in `fetch-allow` in the configuration.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HostPattern {
+    /// Just the given host, as in `example.com`.
+    Exact(String),
+
+    /// Any host below the given domain but not the domain itself, as in `*.example.com`.
+    Subdomains(String),
+}
+
+impl HostPattern {
+    /**
+    Parses a host pattern, which is either a host name or `*.` followed by a domain.
+    */
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        let (domain, subdomains) = match pattern.strip_prefix("*.") {
+            Some(domain) => (domain, true),
+            None => (&pattern[..], false),
+        };
+        let valid = !domain.is_empty()
+            && domain
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'));
+        if !valid {
+            return Err(format!(
+                "`{}` is not a host name like `example.com` or `*.example.com`",
+                pattern
+            ));
+        }
+        Ok(if subdomains {
+            HostPattern::Subdomains(domain.into())
+        } else {
+            HostPattern::Exact(domain.into())
+        })
+    }
+
+    /**
+    Does the pattern match the given host name, which is in lowercase?
+    */
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => host == name,
+            HostPattern::Subdomains(domain) => host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        }
+    }
+}
+
+/**
+Fails unless the host of the URL matches one of the allowed hosts, if they are restricted.
+*/
+pub fn check_allowed(url: &str, allowed: Option<&[HostPattern]>) -> MainResult<()> {
+    check_host_allowed(host(url).as_deref(), url, allowed)
+}
+
+/**
+Fails unless the host a script is fetched from matches one of the allowed hosts, if they are restricted.  `location` is where the script is, for the error message; without a host, it can't be allowed.
+*/
+pub fn check_host_allowed(
+    host: Option<&str>,
+    location: &str,
+    allowed: Option<&[HostPattern]>,
+) -> MainResult<()> {
+    let Some(allowed) = allowed else {
+        return Ok(());
+    };
+    if let Some(host) = host {
+        if allowed.iter().any(|pattern| pattern.matches(host)) {
+            return Ok(());
+        }
+    }
+    Err(MainError::with_kind(
+        FailureKind::Usage,
+        format!(
+            "scripts may not be fetched from {}, which is not in `fetch-allow` in the configuration",
+            host.unwrap_or(location)
+        ),
+    ))
+}
+
+// The host name of an HTTP(S) URL, in lowercase, without any user name or port.
+fn host(url: &str) -> Option<String> {
+    let rest = PREFIXES
+        .iter()
+        .find_map(|prefix| url.strip_prefix(prefix))?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        // An IPv6 address, which has colons of its own.
+        Some(address) => address.split(']').next()?,
+        None => authority.split(':').next()?,
+    };
+    Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty())
+}
+
+/**
+Where the current copy of a script came from, and how to ask whether it is still current.
+*/
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Origin {
+    /// The URL the script was fetched from.
+    url: String,
+    /// Path of the current copy, relative to the directory of the URL.
+    path: String,
+    /// The `ETag` the server sent with it, if any.
+    etag: Option<String>,
+    /// The `Last-Modified` date the server sent with it, if any.
+    last_modified: Option<String>,
+}
+
+impl Origin {
+    fn load(dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(ORIGIN_FILE)).ok()?;
+        let table: toml::value::Table = toml::from_str(&content).ok()?;
+        let get = |key: &str| table.get(key).and_then(|v| v.as_str()).map(String::from);
+
+        Some(Origin {
+            url: get("url")?,
+            path: get("path")?,
+            etag: get("etag"),
+            last_modified: get("last_modified"),
+        })
+    }
+
+    fn save(&self, dir: &Path) -> MainResult<()> {
+        let mut table = toml::value::Table::new();
+        let fields = [
+            ("url", Some(&self.url)),
+            ("path", Some(&self.path)),
+            ("etag", self.etag.as_ref()),
+            ("last_modified", self.last_modified.as_ref()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                table.insert(key.to_string(), toml::Value::String(value.clone()));
+            }
+        }
+        crate::overwrite_file(&dir.join(ORIGIN_FILE), &format!("{}", table))
+    }
+}
+
+/**
+Fetches a script from an HTTP(S) URL, returning the path of the local copy.
+
+If there is a copy already, the server is only asked whether the script changed, unless `update` is set.  When the server can't be reached, the copy is used as is.
+
+If the hosts scripts may be fetched from are restricted to `allowed`, both the URL and any it redirects to must be on one of them.
+*/
+pub fn fetch(url: &str, update: bool, allowed: Option<&[HostPattern]>) -> MainResult<PathBuf> {
+    check_allowed(url, allowed)?;
+    let dir = platform::http_cache_path().join(digest(url));
+    fs::create_dir_all(&dir)?;
+
+    let cached =
+        Origin::load(&dir).filter(|origin| origin.url == url && dir.join(&origin.path).is_file());
+    let validators = cached.as_ref().filter(|_| !update);
+
+    let download = tempfile::NamedTempFile::new_in(&dir)?;
+    let headers = tempfile::NamedTempFile::new_in(&dir)?;
+    // Redirects are followed here rather than by curl, so that every URL is checked before it's
+    // requested.
+    let mut current = url.to_string();
+    let mut redirects = 0;
+    let result = loop {
+        match request(&current, validators, download.path(), headers.path()) {
+            Ok((_, Some(location))) => {
+                redirects += 1;
+                if redirects > MAX_REDIRECTS {
+                    break Err(format!("could not fetch {}: too many redirects", url).into());
+                }
+                info!("{} redirects to {}", current, location);
+                if host(&location).is_none() {
+                    return Err(format!(
+                        "{} redirects to {}, which is not an HTTP(S) URL",
+                        url, location
+                    )
+                    .into());
+                }
+                check_allowed(&location, allowed)?;
+                current = location;
+            }
+            Ok((status, None)) if status == "304" => break Ok(None),
+            Ok(_) => {
+                let content = fs::read_to_string(download.path())
+                    .map_err(|_| format!("{} did not return a valid UTF-8 text file", url))?;
+                let headers = fs::read_to_string(headers.path()).unwrap_or_default();
+                break Ok(Some((content, headers)));
+            }
+            Err(err) => break Err(err),
+        }
+    };
+
+    match (result, cached) {
+        (Ok(Some((content, headers))), _) => {
+            let mut origin = Origin {
+                url: url.into(),
+                path: Path::new(&digest(&content))
+                    .join(file_name(url))
+                    .to_string_lossy()
+                    .into_owned(),
+                ..Default::default()
+            };
+            for (name, value) in last_response_headers(&headers) {
+                if name.eq_ignore_ascii_case("etag") {
+                    origin.etag = Some(value.into());
+                } else if name.eq_ignore_ascii_case("last-modified") {
+                    origin.last_modified = Some(value.into());
+                }
+            }
+            let path = dir.join(&origin.path);
+            if !path.is_file() {
+                fs::create_dir_all(path.parent().expect("script path has a parent"))?;
+                crate::overwrite_file(&path, &content)?;
+            }
+            origin.save(&dir)?;
+            Ok(path)
+        }
+        (Ok(None), Some(origin)) => {
+            info!("{} is unchanged", url);
+            Ok(dir.join(origin.path))
+        }
+        (Ok(None), None) => {
+            Err(format!("{} is not modified, but there is no copy of it", url).into())
+        }
+        (Err(err), Some(origin)) if !update => {
+            eprintln!("warning: {}; using the cached copy", err);
+            Ok(dir.join(origin.path))
+        }
+        (Err(err), _) => Err(err),
+    }
+}
+
+// Request a URL with curl, without following redirects, saving the body and headers of the
+// response in the given files.  Returns the status, and the URL redirected to, if any.
+fn request(
+    url: &str,
+    validators: Option<&Origin>,
+    download: &Path,
+    headers: &Path,
+) -> MainResult<(String, Option<String>)> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--silent", "--show-error", "--fail", "--max-redirs", "0"])
+        .args(["--proto", "=http,https"])
+        .args(["--write-out", "%{http_code} %{redirect_url}"])
+        .arg("--dump-header")
+        .arg(headers)
+        .arg("--output")
+        .arg(download);
+    if let Some(origin) = validators {
+        if let Some(etag) = &origin.etag {
+            cmd.arg("--header").arg(format!("If-None-Match: {}", etag));
+        }
+        if let Some(last_modified) = &origin.last_modified {
+            cmd.arg("--header")
+                .arg(format!("If-Modified-Since: {}", last_modified));
+        }
+    }
+    cmd.arg(url);
+    info!("running {:?}", cmd);
+
+    let output = cmd
+        .output()
+        .map_err(|err| MainError::Tag("could not run curl".into(), Box::new(err.into())))?;
+    if !output.status.success() {
+        return Err(format!(
+            "could not fetch {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    let written = String::from_utf8_lossy(&output.stdout);
+    let (status, location) = written
+        .trim()
+        .split_once(' ')
+        .unwrap_or((written.trim(), ""));
+    info!("HTTP status: {}", status);
+    let location = Some(location.to_string()).filter(|location| !location.is_empty());
+    Ok((status.into(), location))
+}
+
+// The file name to give a copy of the script at a URL.
+fn file_name(url: &str) -> String {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    let name = PREFIXES
+        .iter()
+        .find_map(|prefix| url.strip_prefix(prefix))
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(_, path)| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("script.rs");
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.ends_with(".rs") || name.ends_with(".ers") {
+        name
+    } else {
+        format!("{}.rs", name)
+    }
+}
+
+// The headers of the last response in a `--dump-header` file, which can have those of interim
+// responses too.
+fn last_response_headers(dump: &str) -> Vec<(&str, &str)> {
+    let mut headers = Vec::new();
+    for line in dump.lines() {
+        if line.starts_with("HTTP/") {
+            headers.clear();
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim(), value.trim()));
+        }
+    }
+    headers
+}
+
+// Hex digest used to name things after arbitrary strings.
+fn digest(s: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(s);
+    let mut digest = format!("{:x}", hasher.finalize());
+    digest.truncate(crate::consts::ID_DIGEST_LEN_MAX);
+    digest
+}
+
+#[test]
+fn test_file_name() {
+    assert_eq!(file_name("https://example.com/tools/tool.rs"), "tool.rs");
+    assert_eq!(
+        file_name("https://example.com/tool.rs?raw=1#top"),
+        "tool.rs"
+    );
+    assert_eq!(file_name("https://example.com/tool"), "tool.rs");
+    assert_eq!(
+        file_name("https://example.com/my%20tool.ers"),
+        "my_20tool.ers"
+    );
+    assert_eq!(file_name("https://example.com/"), "script.rs");
+    assert_eq!(file_name("https://example.com"), "script.rs");
+}
+
+#[test]
+fn test_check_allowed() {
+    assert_eq!(
+        host("https://user@Raw.Example.com:8443/tool.rs?x=1"),
+        Some("raw.example.com".into())
+    );
+    assert_eq!(host("http://[::1]:8080/tool.rs"), Some("::1".into()));
+
+    let allowed = [
+        HostPattern::parse("tools.example.com").unwrap(),
+        HostPattern::parse("*.corp.example").unwrap(),
+    ];
+    assert!(check_allowed("https://evil.example/tool.rs", None).is_ok());
+    assert!(check_allowed("https://tools.example.com/tool.rs", Some(&allowed)).is_ok());
+    assert!(check_allowed("https://git.corp.example/tool.rs", Some(&allowed)).is_ok());
+    assert!(check_allowed("https://corp.example/tool.rs", Some(&allowed)).is_err());
+    assert!(check_allowed("https://evilcorp.example/tool.rs", Some(&allowed)).is_err());
+    assert!(check_allowed("https://tools.example.com.evil/tool.rs", Some(&allowed)).is_err());
+    assert!(check_allowed("https://evil@/tool.rs", Some(&allowed)).is_err());
+    assert!(HostPattern::parse("https://example.com").is_err());
+    assert!(HostPattern::parse("*.").is_err());
+}
+
+#[test]
+fn test_last_response_headers() {
+    let dump = "HTTP/1.1 302 Found\r\nLocation: /tool.rs\r\nETag: \"old\"\r\n\r\nHTTP/2 200\r\netag: \"abc\"\r\nlast-modified: Wed, 21 Oct 2026 07:28:00 GMT\r\n\r\n";
+    assert_eq!(
+        last_response_headers(dump),
+        vec![
+            ("etag", "\"abc\""),
+            ("last-modified", "Wed, 21 Oct 2026 07:28:00 GMT")
+        ]
+    );
+}
+
+#[test]
+fn test_fetch_checks_redirects() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // A server redirecting every request to another host, once per connection.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/tool.rs", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut requests = 0;
+        for stream in listener.incoming().take(2) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            requests += 1;
+            stream
+                .write_all(b"HTTP/1.1 302 Found\r\nLocation: http://evil.example/tool.rs\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+        }
+        requests
+    });
+
+    let allowed = [HostPattern::parse("127.0.0.1").unwrap()];
+    let err = fetch(&url, true, Some(&allowed)).unwrap_err().to_string();
+    assert!(
+        err.contains("evil.example, which is not in `fetch-allow`"),
+        "{}",
+        err
+    );
+
+    // The second connection is only to stop the server: the redirect was not followed.
+    let _ =
+        std::net::TcpStream::connect(url.trim_start_matches("http://").split('/').next().unwrap());
+    assert_eq!(server.join().unwrap(), 2);
+}
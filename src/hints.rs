@@ -0,0 +1,120 @@
+/*!
+This module is concerned with hints for common build failures.
+
+The most common one is using part of a crate that is behind a Cargo feature that isn't enabled, such as `tokio::net`.  The compiler can only say that it doesn't exist, so known feature-gated items of popular crates are looked up to suggest the feature instead.
+*/
+use regex::Regex;
+
+/// Items of crates that need a feature, as (crate, item, features).
+const FEATURE_GATED: &[(&str, &str, &[&str])] = &[
+    ("clap", "Args", &["derive"]),
+    ("clap", "Parser", &["derive"]),
+    ("clap", "Subcommand", &["derive"]),
+    ("clap", "ValueEnum", &["derive"]),
+    ("reqwest", "blocking", &["blocking"]),
+    ("serde", "Deserialize", &["derive"]),
+    ("serde", "Serialize", &["derive"]),
+    ("tokio", "AsyncBufReadExt", &["io-util"]),
+    ("tokio", "AsyncReadExt", &["io-util"]),
+    ("tokio", "AsyncWriteExt", &["io-util"]),
+    ("tokio", "fs", &["fs"]),
+    ("tokio", "join", &["macros"]),
+    ("tokio", "main", &["macros", "rt-multi-thread"]),
+    ("tokio", "net", &["net"]),
+    ("tokio", "process", &["process"]),
+    ("tokio", "runtime", &["rt"]),
+    ("tokio", "select", &["macros"]),
+    ("tokio", "signal", &["signal"]),
+    ("tokio", "spawn", &["rt"]),
+    ("tokio", "sync", &["sync"]),
+    ("tokio", "task", &["rt"]),
+    ("tokio", "test", &["macros", "rt"]),
+    ("tokio", "time", &["time"]),
+];
+
+/**
+Hints for the errors in the output of a failed build.
+*/
+pub fn build_hints(output: &str) -> Vec<String> {
+    let re_ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    let re_import = Regex::new(r"unresolved import `([\w:]+)`").unwrap();
+    let re_not_found = Regex::new(r"could not find `(\w+)` in `(\w+)`").unwrap();
+    let re_derive = Regex::new(r"cannot find derive macro `(\w+)` in this scope").unwrap();
+
+    let output = re_ansi.replace_all(output, "");
+    let mut gated = Vec::new();
+    for line in output.lines() {
+        if let Some(captures) = re_import.captures(line) {
+            let mut path = captures[1].split("::");
+            let krate = path.next().unwrap_or_default();
+            gated.extend(path.find_map(|item| lookup(Some(krate), item)));
+        } else if let Some(captures) = re_not_found.captures(line) {
+            gated.extend(lookup(Some(&captures[2]), &captures[1]));
+        } else if let Some(captures) = re_derive.captures(line) {
+            gated.extend(lookup(None, &captures[1]));
+        }
+    }
+
+    let mut hints = Vec::new();
+    for (krate, item, features) in gated {
+        let names = features
+            .iter()
+            .map(|feature| format!("`{}`", feature))
+            .collect::<Vec<_>>();
+        let list = features
+            .iter()
+            .map(|feature| format!("\"{}\"", feature))
+            .collect::<Vec<_>>();
+        let hint = format!(
+            "`{}::{}` needs the {} feature{} of `{}`; add `features = [{}]` to the `{}` dependency",
+            krate,
+            item,
+            names.join(" and "),
+            if features.len() > 1 { "s" } else { "" },
+            krate,
+            list.join(", "),
+            krate
+        );
+        if !hints.contains(&hint) {
+            hints.push(hint);
+        }
+    }
+    hints
+}
+
+// Look up a feature-gated item of the crate, or of any crate if it's not known.
+fn lookup(
+    krate: Option<&str>,
+    item: &str,
+) -> Option<(&'static str, &'static str, &'static [&'static str])> {
+    FEATURE_GATED
+        .iter()
+        .find(|(c, i, _)| (krate.is_none() || krate == Some(*c)) && item == *i)
+        .copied()
+}
+
+#[test]
+fn test_build_hints() {
+    let output = "\
+error[E0432]: unresolved import `tokio::net`
+ --> src/main.rs:1:5
+  |
+1 | use tokio::net::TcpListener;
+  |     ^^^^^^^^^^ could not find `net` in `tokio`
+
+error[E0433]: failed to resolve: could not find `main` in `tokio`
+ --> src/main.rs:3:10
+
+error: cannot find derive macro `Serialize` in this scope
+";
+    let hints = build_hints(output);
+    assert_eq!(hints.len(), 3);
+    assert_eq!(
+        hints[0],
+        "`tokio::net` needs the `net` feature of `tokio`; add `features = [\"net\"]` to the `tokio` dependency"
+    );
+    assert!(hints[1].starts_with("`tokio::main` needs the `macros` and `rt-multi-thread` features"));
+    assert!(hints[2].starts_with("`serde::Serialize` needs the `derive` feature"));
+
+    assert!(build_hints("error[E0432]: unresolved import `regex::Nope`").is_empty());
+}
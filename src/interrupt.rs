@@ -0,0 +1,111 @@
+/*!
+This module is concerned with interrupts, as from Ctrl-C: holding them off while `rust-script` does something it mustn't leave half done, and outliving them.
+*/
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use signal_hook::{flag, low_level};
+
+use crate::error::MainResult;
+
+/**
+The signals counted as interrupts: besides Ctrl-C, those asking `rust-script` to terminate, and the terminal going away.
+*/
+#[cfg(unix)]
+pub const SIGNALS: &[i32] = &[
+    signal_hook::consts::SIGINT,
+    signal_hook::consts::SIGTERM,
+    signal_hook::consts::SIGHUP,
+];
+#[cfg(not(unix))]
+pub const SIGNALS: &[i32] = &[signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM];
+
+// What the handlers of `SIGNALS` go by.
+struct Handlers {
+    // Whether a signal ends `rust-script` as it would without the handlers.
+    fatal: Arc<AtomicBool>,
+    // The last signal received while not fatal, or 0.
+    received: Arc<AtomicUsize>,
+    // Whether `survive` was called, after which signals are never fatal again.
+    surviving: AtomicBool,
+}
+
+// The handlers, installed the first time they're needed: signals behave as usual until then.
+fn handlers() -> MainResult<Arc<Handlers>> {
+    static HANDLERS: Mutex<Option<Arc<Handlers>>> = Mutex::new(None);
+    let mut installed = HANDLERS.lock().unwrap();
+    if let Some(handlers) = &*installed {
+        return Ok(handlers.clone());
+    }
+    let handlers = Arc::new(Handlers {
+        fatal: Arc::new(AtomicBool::new(true)),
+        received: Arc::new(AtomicUsize::new(0)),
+        surviving: AtomicBool::new(false),
+    });
+    for &signal in SIGNALS {
+        flag::register_usize(signal, handlers.received.clone(), signal as usize)?;
+        flag::register_conditional_default(signal, handlers.fatal.clone())?;
+    }
+    *installed = Some(handlers.clone());
+    Ok(handlers)
+}
+
+/**
+Holds off interrupts until dropped, when `rust-script` ends as the first one received in the meantime would have had it.
+*/
+pub struct Hold(Arc<Handlers>);
+
+/**
+Starts holding off interrupts.
+*/
+pub fn hold() -> MainResult<Hold> {
+    let handlers = handlers()?;
+    handlers.received.store(0, Ordering::SeqCst);
+    handlers.fatal.store(false, Ordering::SeqCst);
+    Ok(Hold(handlers))
+}
+
+impl Hold {
+    /**
+    Fails if an interrupt was received, so that what's being done can be cleaned up before the hold is dropped.
+    */
+    pub fn check(&self) -> MainResult<()> {
+        match self.0.received.load(Ordering::SeqCst) {
+            0 => Ok(()),
+            _ => Err("Interrupted".into()),
+        }
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        if self.0.surviving.load(Ordering::SeqCst) {
+            return;
+        }
+        // Interrupts are fatal again first, so that none slips in unnoticed before it.
+        self.0.fatal.store(true, Ordering::SeqCst);
+        let signal = self.0.received.load(Ordering::SeqCst);
+        if signal != 0 {
+            let _ = low_level::emulate_default_handler(signal as i32);
+        }
+    }
+}
+
+/**
+Keeps `rust-script` running when interrupted, for the rest of the run.
+*/
+pub fn survive() -> MainResult<()> {
+    let handlers = handlers()?;
+    handlers.surviving.store(true, Ordering::SeqCst);
+    handlers.fatal.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+#[test]
+fn test_hold() {
+    let hold = hold().unwrap();
+    low_level::raise(signal_hook::consts::SIGINT).unwrap();
+    assert!(hold.check().is_err());
+    // Dropping the hold would end the tests, as the interrupt would have.
+    std::mem::forget(hold);
+}
@@ -0,0 +1,237 @@
+/*!
+This module contains a quick lint pass over script files, catching common mistakes before they surface as confusing Cargo errors.
+
+Everything here works on the plain text of the script; nothing is parsed as Rust.
+*/
+use regex::Regex;
+
+use crate::manifest;
+
+/**
+A problem found in a script.
+*/
+#[derive(Debug, Eq, PartialEq)]
+pub struct Lint {
+    /// The (1-based) line the problem was found on.
+    pub line: usize,
+    pub message: String,
+}
+
+/**
+Lints the contents of a script file, returning the problems found ordered by line.
+*/
+pub fn lint_script(content: &str) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    check_shebang(content, &mut lints);
+    check_unterminated_comment(content, &mut lints);
+    check_manifest_position(content, &mut lints);
+    check_main(content, &mut lints);
+    lints.sort_by_key(|lint| lint.line);
+    lints
+}
+
+fn lint(line: usize, message: &str) -> Lint {
+    Lint {
+        line,
+        message: message.into(),
+    }
+}
+
+// A shebang is only honoured on the very first line.
+fn check_shebang(content: &str, lints: &mut Vec<Lint>) {
+    for (i, line) in content.lines().enumerate().skip(1) {
+        if line.starts_with("#!") && !line[2..].starts_with('[') {
+            lints.push(lint(
+                i + 1,
+                "a shebang line must be the first line of the script",
+            ));
+        }
+    }
+}
+
+// Block comments (including `/*!` and `/**` doc comments) must be closed.
+fn check_unterminated_comment(content: &str, lints: &mut Vec<Lint>) {
+    let chars: Vec<char> = content.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+
+    // Start line and kind of the outermost open block comment, and the current nesting depth.
+    let mut open_comment: Option<(usize, &str)> = None;
+    let mut depth = 0;
+
+    let at = |i: usize, s: &str| {
+        s.chars()
+            .enumerate()
+            .all(|(j, c)| chars.get(i + j) == Some(&c))
+    };
+
+    while i < chars.len() {
+        if chars[i] == '\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+
+        if depth > 0 {
+            if at(i, "/*") {
+                depth += 1;
+                i += 2;
+            } else if at(i, "*/") {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    open_comment = None;
+                }
+            } else {
+                i += 1;
+            }
+            continue;
+        }
+
+        if at(i, "//") {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if at(i, "/*") {
+            let kind = if at(i, "/*!") || (at(i, "/**") && !at(i, "/**/")) {
+                "doc comment"
+            } else {
+                "block comment"
+            };
+            open_comment = Some((line, kind));
+            depth = 1;
+            i += 2;
+        } else if chars[i] == '"' {
+            // Skip string literals, so that e.g. "/*" doesn't open a comment.
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&'\n') {
+                    line += 1;
+                }
+                i += 1;
+            }
+            i += 1;
+        } else if chars[i] == '\'' {
+            // Skip character literals, but not lifetimes.
+            if chars.get(i + 1) == Some(&'\\') {
+                i += 3;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+                i += 1;
+            } else if chars.get(i + 2) == Some(&'\'') {
+                i += 3;
+            } else {
+                i += 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some((line, kind)) = open_comment {
+        lints.push(Lint {
+            line,
+            message: format!("unterminated {}: no matching `*/` was found", kind),
+        });
+    }
+}
+
+// Embedded manifests are only picked up from the very top of the script.
+fn check_manifest_position(content: &str, lints: &mut Vec<Lint>) {
+    if manifest::has_embedded_manifest(manifest::strip_shebang(content)) {
+        return;
+    }
+
+    let re_code_block = Regex::new(r"(?i)^\s*(//[/!]?|/\*[!*]?|\*)\s*```\s*cargo\s*$").unwrap();
+    let re_cargo_deps = Regex::new(r"(?i)^\s*//\s*cargo-deps\s*:").unwrap();
+    for (i, line) in content.lines().enumerate() {
+        if re_code_block.is_match(line) {
+            lints.push(lint(
+                i + 1,
+                "this `cargo` code block is ignored: the manifest must be in a `//!` or `/*!` doc comment at the very top of the script, before any code",
+            ));
+            return;
+        } else if re_cargo_deps.is_match(line) {
+            lints.push(lint(
+                i + 1,
+                "this `cargo-deps` comment is ignored: it must be the first non-blank line of the script",
+            ));
+            return;
+        }
+    }
+}
+
+// Scripts without a `fn main` are wrapped in one, which some content doesn't survive.
+fn check_main(content: &str, lints: &mut Vec<Lint>) {
+    let source = manifest::strip_shebang(content);
+    if manifest::contains_main_method(source) {
+        return;
+    }
+
+    // Line numbers are relative to the whole file, including any shebang.
+    let first_line = content[..content.len() - source.len()].lines().count() + 1;
+
+    let re_main =
+        Regex::new(r#"^\s*(pub\s+)?(async\s+)?(unsafe\s+)?(extern\s+"C"\s+)?fn\s+main\s*[(<]"#)
+            .unwrap();
+    let re_inner_attr = Regex::new(r"^\s*#!\s*\[").unwrap();
+    for (i, line) in source.lines().enumerate() {
+        if re_main.is_match(line) {
+            lints.push(lint(
+                first_line + i,
+                "this `fn main` is not recognised (use single spaces and no indentation), so the whole script will be wrapped in another `fn main`",
+            ));
+        } else if re_inner_attr.is_match(line) {
+            lints.push(lint(
+                first_line + i,
+                "inner attributes can only be used in scripts that have a `fn main`",
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_lint_script() {
+    let lines = |content: &str| -> Vec<usize> {
+        lint_script(content)
+            .into_iter()
+            .map(|lint| lint.line)
+            .collect()
+    };
+
+    assert_eq!(lines("fn main() {}"), Vec::<usize>::new());
+    assert_eq!(lines("println!(\"no main is fine\");"), Vec::<usize>::new());
+    assert_eq!(
+        lines("#!/usr/bin/env rust-script\n//! ```cargo\n//! [dependencies]\n//! time = \"0.1\"\n//! ```\nfn main() {}"),
+        Vec::<usize>::new()
+    );
+
+    // Shebang not on the first line.
+    assert_eq!(lines("\n#!/usr/bin/env rust-script\nfn main() {}"), vec![2]);
+
+    // Unterminated doc comment.
+    assert_eq!(lines("fn main() {}\n/*!\nfoo\n"), vec![2]);
+    assert_eq!(lines("fn main() {}\n/* /* */ */"), Vec::<usize>::new());
+    assert_eq!(
+        lines("fn main() { let s = \"/*\"; let c = '\"'; let q = '\\''; }"),
+        Vec::<usize>::new()
+    );
+
+    // Manifest after code.
+    assert_eq!(
+        lines("use std::io;\n//! ```cargo\n//! [dependencies]\n//! ```\nfn main() {}"),
+        vec![2]
+    );
+    assert_eq!(
+        lines("use std::io;\n// cargo-deps: time=\"0.1\"\nfn main() {}"),
+        vec![2]
+    );
+
+    // Unrecognised `fn main`.
+    assert_eq!(lines("#!/usr/bin/env rust-script\nfn\tmain() {}"), vec![2]);
+    assert_eq!(lines("#![allow(unused)]\nprintln!();"), vec![1]);
+}
@@ -0,0 +1,143 @@
+/*!
+This module is concerned with literate scripts: Markdown files whose fenced `rust` code blocks make up the script.
+
+The code blocks are concatenated, in order, into a script which is run like any other.  A fenced `cargo` code block becomes the manifest of the script.  Code blocks can be named by adding the name to their info string, as in ` ```rust setup `, so that only the blocks with a given name are run.  Code blocks marked `ignore` are left out unless they are asked for by name.
+*/
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::error::MainResult;
+
+/// Extensions of the files treated as literate scripts.
+pub const EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/**
+A fenced code block in a Markdown file.
+*/
+#[derive(Debug)]
+struct CodeBlock {
+    /// The language of the block, the first word of its info string.
+    lang: String,
+    /// The other words of its info string.
+    attributes: Vec<String>,
+    /// The code.
+    code: String,
+}
+
+/**
+Turns a literate script into a Rust script.
+
+If `name` is given, only the `rust` code blocks with that name are used.
+*/
+pub fn extract_script(markdown: &str, name: Option<&str>) -> MainResult<String> {
+    let blocks = code_blocks(markdown);
+
+    let mut script = String::new();
+    if let Some(manifest) = blocks.iter().find(|block| block.lang == "cargo") {
+        script.push_str("//! ```cargo\n");
+        for line in manifest.code.lines() {
+            script.push_str("//! ");
+            script.push_str(line);
+            script.push('\n');
+        }
+        script.push_str("//! ```\n");
+    }
+
+    let mut found = false;
+    for block in blocks.iter().filter(|block| block.lang == "rust") {
+        let wanted = match name {
+            Some(name) => block.attributes.iter().any(|attr| attr == name),
+            None => !block.attributes.iter().any(|attr| attr == "ignore"),
+        };
+        if wanted {
+            found = true;
+            script.push_str(&block.code);
+            if !block.code.ends_with('\n') {
+                script.push('\n');
+            }
+        }
+    }
+
+    if !found {
+        return Err(match name {
+            Some(name) => format!("no `rust` code blocks named `{}` found", name),
+            None => "no `rust` code blocks found".to_string(),
+        }
+        .into());
+    }
+    Ok(script)
+}
+
+// All fenced code blocks in the Markdown, in order.
+fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+
+    for event in Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let mut words = info
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|word| !word.is_empty())
+                    .map(|word| word.to_string());
+                current = Some(CodeBlock {
+                    lang: words.next().unwrap_or_default().to_lowercase(),
+                    attributes: words.collect(),
+                    code: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = &mut current {
+                    block.code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => blocks.extend(current.take()),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+#[test]
+fn test_extract_script() {
+    let markdown = r#"# Notes
+
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+
+First, some setup:
+
+```rust
+let greeting = "Hello";
+```
+
+```rust,ignore
+this is not compiled
+```
+
+```rust greet
+println!("{}, World!", greeting);
+```
+
+```sh
+$ rust-script notes.md
+```
+"#;
+
+    assert_eq!(
+        extract_script(markdown, None).unwrap(),
+        r#"//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+let greeting = "Hello";
+println!("{}, World!", greeting);
+"#
+    );
+    assert!(extract_script(markdown, Some("greet"))
+        .unwrap()
+        .ends_with("//! ```\nprintln!(\"{}, World!\", greeting);\n"));
+    assert!(extract_script(markdown, Some("missing")).is_err());
+    assert!(extract_script("No code here.", None).is_err());
+}
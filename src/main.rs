@@ -1,13 +1,47 @@
 #![forbid(unsafe_code)]
 
 mod arguments;
+mod auto_deps;
+mod bench;
 mod build_kind;
+mod cargo_version;
+mod config;
 mod consts;
 mod defer;
+mod dep_cost;
+mod dep_spec;
+mod deterministic;
+mod diagnostics;
+mod edit;
+mod emit;
 mod error;
+mod failures;
+mod fatpack;
+mod fetch;
+mod hints;
+mod interrupt;
+mod lint;
+mod literate;
 mod manifest;
+mod manifest_edit;
+mod metadata;
+mod network;
+mod pin;
 mod platform;
+mod refresh;
+mod replay;
+mod requirements;
+mod script_deps;
+mod secrets;
+mod snapshot;
+mod socket_activation;
+mod tee;
+mod telemetry;
 mod templates;
+mod trampoline;
+mod unused_deps;
+mod update;
+mod watch;
 
 #[cfg(windows)]
 mod file_assoc;
@@ -22,25 +56,38 @@ use arguments::Args;
 use log::{debug, error, info};
 use std::ffi::OsString;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 
 use crate::build_kind::BuildKind;
+use crate::config::Config;
 use crate::defer::Defer;
-use crate::error::{MainError, MainResult};
+use crate::dep_spec::{DepSpec, Patch};
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::failures::FailureRecord;
+use crate::metadata::{BinaryRecord, PackageMetadata};
 use sha1::{Digest, Sha1};
 
 fn main() {
-    env_logger::init();
+    // Before anything else, so that nothing is in the way of the file descriptors put in place.
+    let mut args = std::env::args_os().skip(1);
+    let result = if args.next().is_some_and(|arg| arg == trampoline::ARG) {
+        trampoline::run(args.collect())
+    } else {
+        env_logger::init();
+        try_main()
+    };
+    telemetry::export();
 
-    match try_main() {
+    match result {
         Ok(code) => {
             std::process::exit(code);
         }
         Err(err) => {
             eprintln!("error: {}", err);
-            std::process::exit(1);
+            std::process::exit(err.exit_code());
         }
     }
 }
@@ -60,68 +107,247 @@ fn try_main() -> MainResult<i32> {
         }
     }
 
+    if let Some(subcommand) = &args.subcommand {
+        return match subcommand {
+            arguments::Subcommand::Templates(command) => templates::run(command),
+            arguments::Subcommand::Add(command) => manifest_edit::add(command),
+            arguments::Subcommand::Remove(command) => manifest_edit::remove(command),
+            arguments::Subcommand::Pin(script) => pin::pin(script, &cargo_flags(&args)),
+            arguments::Subcommand::Update(command) => update::update(command, &cargo_flags(&args)),
+        };
+    }
+
+    if args.paths {
+        print_paths();
+        return Ok(0);
+    }
+
+    if args.refresh_cache {
+        return refresh::refresh_cache(&cargo_flags(&args));
+    }
+
+    if let Some(recording) = &args.replay {
+        return replay::Recording::load(Path::new(recording))?.replay();
+    }
+
+    if let Some(watch_args) = &args.watch {
+        let script = args.script.as_deref().expect("--watch requires a script");
+        if script == "-" || fetch::is_remote(script) {
+            return Err(MainError::with_kind(
+                FailureKind::Usage,
+                "--watch needs a script file",
+            ));
+        }
+        let (path, _) = find_script(script.as_ref(), args.pick)?.ok_or_else(|| {
+            MainError::with_kind(
+                FailureKind::Usage,
+                format!("could not find script: {}", script),
+            )
+        })?;
+        let listeners = socket_activation::Listeners::bind(&args.socket_activate)?;
+        return watch::watch(&path, watch_args, &listeners);
+    }
+
     if args.clear_cache {
-        clean_cache(0)?;
+        clean_cache(0, 0).map_err(|err| MainError::with_kind(FailureKind::Cache, err))?;
         if args.script.is_none() {
             println!("rust-script cache cleared.");
             return Ok(0);
         }
     }
 
+    // Without a cache that can be written to, the package is generated and built in a temporary
+    // directory instead, so the script still runs, if more slowly.
+    if args.ephemeral || (!args.local_cache && !platform::cache_dir_is_writable()) {
+        if !args.ephemeral {
+            eprintln!(
+                "warning: cannot write to the cache in {}; building in a temporary directory instead",
+                platform::cache_dir().display()
+            );
+        }
+        platform::use_ephemeral_cache_dir()?;
+    }
+    let _remove_ephemeral_cache = Defer::<_, MainError>::new(platform::remove_ephemeral_cache_dir);
+
+    let config = Config::load()?;
+
     // Sort out the dependencies.  We want to do a few things:
     // - Sort them so that they hash consistently.
     // - Check for duplicates.
     // - Expand `pkg` into `pkg=*`.
     let dependencies_from_args = {
-        use std::collections::HashMap;
-
-        let mut deps: HashMap<String, String> = HashMap::new();
-        for dep in args.dep.iter().cloned() {
-            // Append '=*' if it needs it.
-            let dep = match dep.find('=') {
-                Some(_) => dep,
-                None => dep + "=*",
-            };
+        let mut deps = dep_spec::parse_args(&args.dep, args.registry.as_deref())?;
+
+        // `--extern name=version` adds the dependency too, so a matching `--dep` isn't needed.
+        for ext in &args.extern_ {
+            if let Some((name, version)) = ext.split_once('=') {
+                if name.is_empty() {
+                    return Err(MainError::with_kind(
+                        FailureKind::Usage,
+                        "cannot have empty extern crate name",
+                    ));
+                } else if version.is_empty() {
+                    return Err(MainError::with_kind(
+                        FailureKind::Usage,
+                        "cannot have empty extern crate version",
+                    ));
+                }
 
-            let mut parts = dep.splitn(2, '=');
-            let name = parts.next().expect("dependency is missing name");
-            let version = parts.next().expect("dependency is missing version");
-            assert!(
-                parts.next().is_none(),
-                "dependency somehow has three parts?!"
-            );
+                match deps.get(name) {
+                    Some(existing) if existing.version_req() == Some(version) => {}
+                    Some(existing) => {
+                        return Err(MainError::with_kind(
+                            FailureKind::Usage,
+                            format!(
+                                "conflicting versions for dependency '{}': '{}' and '{}'",
+                                name, existing, version
+                            ),
+                        ));
+                    }
+                    None => {
+                        deps.insert(name.into(), DepSpec::version(version));
+                    }
+                }
+            }
+        }
 
-            if name.is_empty() {
-                return Err(("cannot have empty dependency package name").into());
-            } else if version.is_empty() {
-                return Err(("cannot have empty dependency version").into());
+        // Expressions also get the dependencies configured for them, unless the crate was given
+        // on the command line.
+        if args.expr {
+            for dep in &config.expr_deps {
+                let (name, spec) = DepSpec::parse(dep)?;
+                deps.entry(name).or_insert(spec);
             }
+        }
 
-            if deps.insert(name.into(), version.into()).is_some() {
-                return Err((format!("duplicated dependency: '{}'", name)).into());
+        // Well-known crates that snippets use are added too, so that one-liners don't need
+        // `--dep` for them.
+        if args.expr || args.loop_ || args.json || args.csv || args.parallel_map {
+            let known = config.auto_deps.clone().unwrap_or_else(|| {
+                auto_deps::WELL_KNOWN
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect()
+            });
+            let source = std::iter::once(args.script.as_deref().unwrap_or_default())
+                .chain(args.import.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join("\n");
+            for dep in auto_deps::referenced(&source, &known) {
+                let (name, spec) = DepSpec::parse(dep)?;
+                if deps.contains_key(&name) {
+                    continue;
+                }
+                info!("adding dependency on {} used by the expression", name);
+                deps.insert(name, spec);
+            }
+        }
+
+        // The allocator given with `--alloc` needs its crate.
+        if let Some(((name, version), _)) = allocator(&args) {
+            deps.entry(name.into())
+                .or_insert_with(|| DepSpec::version(version));
+        }
+
+        // Async expressions and loop scripts are run on a tokio runtime.
+        if async_template(&args).is_some() {
+            let (name, version) = consts::ASYNC_DEPENDENCY;
+            deps.entry(name.into())
+                .or_insert_with(|| DepSpec::version(version));
+        }
+
+        // Values printed as JSON are serialized with `serde_json`.
+        if args.expr && args.output == arguments::Output::Json {
+            deps.entry("serde_json".into())
+                .or_insert_with(|| DepSpec::version("1"));
+        }
+
+        // `--json` expressions are given a `serde_json::Value`.
+        if args.json {
+            deps.entry("serde_json".into())
+                .or_insert_with(|| DepSpec::version("1"));
+        }
+
+        // `--csv` expressions are run over the records of a `csv::Reader`.
+        if args.csv {
+            deps.entry("csv".into())
+                .or_insert_with(|| DepSpec::version("1"));
+        }
+
+        // `--parallel-map` expressions are spread over a rayon thread pool.
+        if args.parallel_map {
+            deps.entry("rayon".into())
+                .or_insert_with(|| DepSpec::version("1"));
+        }
+
+        // Sessions are stored as JSON.
+        if args.session.is_some() {
+            for (name, version) in [("serde", "1"), ("serde_json", "1")] {
+                deps.entry(name.into())
+                    .or_insert_with(|| DepSpec::version(version));
             }
         }
 
         // Sort and turn into a regular vec.
-        let mut deps: Vec<(String, String)> = deps.into_iter().collect();
+        let mut deps: Vec<(String, DepSpec)> = deps.into_iter().collect();
         deps.sort();
         deps
     };
 
-    let input = match (args.script.clone().unwrap(), args.expr, args.loop_) {
+    let mut fresh = false;
+    let mut package_metadata = None;
+    let mut input = match (args.script.clone().unwrap(), args.expr, args.loop_) {
+        (expr, false, false) if args.json => Input::Json(expr, expr_base_path(&args)?),
+        (expr, false, false) if args.csv => Input::Csv(expr, expr_base_path(&args)?),
+        (expr, false, false) if args.parallel_map => {
+            Input::ParallelMap(expr, expr_base_path(&args)?)
+        }
+        (script, false, false) if script == "-" => {
+            let file_only = [
+                ("--local-cache", args.local_cache),
+                ("--set-manifest", args.set_manifest.is_some()),
+                ("--edit-on-error", args.edit_on_error),
+            ];
+            if let Some((flag, _)) = file_only.iter().find(|(_, given)| *given) {
+                return Err(MainError::with_kind(
+                    FailureKind::Usage,
+                    format!("{} cannot be used with a script read from stdin", flag),
+                ));
+            }
+
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)?;
+            let body = script_body_from_bytes(Path::new("<stdin>"), bytes)?;
+            Input::Stdin(body, expr_base_path(&args)?)
+        }
         (script, false, false) => {
-            let (path, mut file) =
-                find_script(script.as_ref()).ok_or(format!("could not find script: {}", script))?;
+            let script = if fetch::is_remote(&script) {
+                fetch::fetch(&script, args.update, config.fetch_allow.as_deref())?
+                    .to_string_lossy()
+                    .into_owned()
+            } else {
+                script
+            };
+            let (path, mut file) = find_script(script.as_ref(), args.pick)?.ok_or_else(|| {
+                MainError::with_kind(
+                    FailureKind::Usage,
+                    format!("could not find script: {}", script),
+                )
+            })?;
 
             let script_name = path
                 .file_stem()
                 .map(|os| os.to_string_lossy().into_owned())
                 .unwrap_or_else(|| "unknown".into());
 
-            let mut body = String::new();
-            file.read_to_string(&mut body)?;
+            let script_path = std::env::current_dir()?.join(&path);
 
-            let script_path = std::env::current_dir()?.join(path);
+            if args.local_cache {
+                let script_dir = script_path
+                    .parent()
+                    .expect("couldn't get parent directory for script file");
+                platform::use_local_cache_dir(script_dir.join(consts::LOCAL_CACHE_DIR))?;
+            }
 
             let base_path = if let Some(base_path_arg) = &args.base_path {
                 Path::new(base_path_arg).into()
@@ -132,23 +358,51 @@ fn try_main() -> MainResult<i32> {
                     .into()
             };
 
-            Input::File(script_name, script_path, body, base_path)
-        }
-        (expr, true, false) => {
-            let base_path = if let Some(base_path_arg) = &args.base_path {
-                Path::new(base_path_arg).into()
-            } else {
-                std::env::current_dir().expect("couldn't get current directory for input base path")
-            };
-            Input::Expr(expr, base_path)
+            let mut input = Input::File(script_name, script_path.clone(), String::new(), base_path);
+
+            // If neither the script nor anything else that goes into the package changed since
+            // the package was generated, there's no need to even read the script.  That includes
+            // the scripts it depends on, as far as the package says.
+            let cached_path = cached_package_path(&input, &args);
+            let metadata = PackageMetadata::new(
+                &file.metadata()?,
+                package_digest(
+                    &script_path,
+                    &dependencies_from_args,
+                    &args,
+                    &config,
+                    &script_deps::load(&cached_path).unwrap_or_default(),
+                ),
+            )?;
+            fresh = may_reuse_package(&args)
+                && PackageMetadata::load(&cached_path).as_ref() == Some(&metadata);
+            info!("fresh: {:?}", fresh);
+            package_metadata = Some(metadata);
+
+            if !fresh {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)?;
+                if let Input::File(_, _, body, _) = &mut input {
+                    *body = script_body_from_bytes(&path, bytes)?;
+                }
+                if input.is_markdown() {
+                    if let Input::File(_, _, body, _) = &mut input {
+                        *body = literate::extract_script(body, args.md_block.as_deref()).map_err(
+                            |err| {
+                                MainError::Tag(
+                                    format!("in {}", path.display()).into(),
+                                    Box::new(err),
+                                )
+                            },
+                        )?;
+                    }
+                }
+            }
+            input
         }
+        (expr, true, false) => Input::Expr(expr, args.block, args.output, expr_base_path(&args)?),
         (loop_, false, true) => {
-            let base_path = if let Some(base_path_arg) = &args.base_path {
-                Path::new(base_path_arg).into()
-            } else {
-                std::env::current_dir().expect("couldn't get current directory for input base path")
-            };
-            Input::Loop(loop_, args.count, base_path)
+            Input::Loop(loop_, args.count, args.chunk_lines, expr_base_path(&args)?)
         }
         (_, _, _) => {
             panic!("Internal error: Invalid args");
@@ -156,6 +410,104 @@ fn try_main() -> MainResult<i32> {
     };
     info!("input: {:?}", input);
 
+    if let Some(recording) = &args.record {
+        let script = match &input {
+            Input::File(name, path, _, _) => Some((
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| format!("{}.rs", name)),
+                fs::read_to_string(path)?,
+            )),
+            Input::Stdin(body, _) => Some(("stdin.rs".to_string(), body.clone())),
+            Input::Expr(..)
+            | Input::Loop(..)
+            | Input::Json(..)
+            | Input::Csv(..)
+            | Input::ParallelMap(..) => None,
+        };
+        let script = script
+            .as_ref()
+            .map(|(name, content)| (name.as_str(), content.as_str()));
+        replay::Recording::capture(&args.script_args, script)?.save(Path::new(recording))?;
+    }
+
+    if let Input::Stdin(body, _) = &input {
+        if args.extract_manifest {
+            let manifest =
+                manifest::extract_manifest(body)?.ok_or("no embedded manifest found in stdin")?;
+            print!("{}", manifest);
+            return Ok(0);
+        }
+    }
+
+    if let Input::File(_, path, body, _) = &input {
+        if args.extract_manifest {
+            let manifest = manifest::extract_manifest(body)?
+                .ok_or(format!("no embedded manifest found in {}", path.display()))?;
+            print!("{}", manifest);
+            return Ok(0);
+        }
+
+        if let Some(manifest_file) = &args.set_manifest {
+            if input.is_markdown() {
+                return Err(MainError::with_kind(
+                    FailureKind::Usage,
+                    "--set-manifest cannot change the manifest of a Markdown script",
+                ));
+            }
+            let mut manifest = String::new();
+            if manifest_file == "-" {
+                std::io::stdin().read_to_string(&mut manifest)?;
+            } else {
+                fs::File::open(manifest_file)?.read_to_string(&mut manifest)?;
+            }
+            toml::from_str::<toml::value::Table>(&manifest).map_err(|e| {
+                MainError::with_kind(
+                    FailureKind::Usage,
+                    MainError::Tag(
+                        "could not parse new manifest".into(),
+                        Box::new(MainError::Other(Box::new(e))),
+                    ),
+                )
+            })?;
+
+            overwrite_file(path, &manifest::replace_manifest(body, &manifest)?)?;
+            return Ok(0);
+        }
+    }
+
+    // Script files are piped through the configured preprocessor, if any, before anything else
+    // looks at them.
+    if let (Some(cmd), Input::File(_, path, body, _)) = (&config.preprocess, &mut input) {
+        if !fresh {
+            *body = config::preprocess(cmd, path, body)?;
+        }
+    }
+
+    if args.pre_lint {
+        let script = match &input {
+            Input::File(_, path, body, _) => Some((path.display().to_string(), body)),
+            Input::Stdin(body, _) => Some(("<stdin>".into(), body)),
+            Input::Expr(..)
+            | Input::Loop(..)
+            | Input::Json(..)
+            | Input::Csv(..)
+            | Input::ParallelMap(..) => None,
+        };
+        if let Some((name, body)) = script {
+            let lints = lint::lint_script(body);
+            for lint in &lints {
+                eprintln!("{}:{}: {}", name, lint.line, lint.message);
+            }
+            if !lints.is_empty() {
+                return Err(MainError::with_kind(
+                    FailureKind::Compile,
+                    format!("found {} problem(s) in the script", lints.len()),
+                ));
+            }
+        }
+    }
+
     // Setup environment variables early so it's available at compilation time of scripts,
     // to allow e.g. include!(concat!(env!("RUST_SCRIPT_BASE_PATH"), "/script-module.rs"));
     std::env::set_var(
@@ -165,6 +517,9 @@ fn try_main() -> MainResult<i32> {
     std::env::set_var("RUST_SCRIPT_SAFE_NAME", input.safe_name());
     std::env::set_var("RUST_SCRIPT_PKG_NAME", input.package_name());
     std::env::set_var("RUST_SCRIPT_BASE_PATH", input.base_path());
+    if let Some(session) = &args.session {
+        std::env::set_var("RUST_SCRIPT_SESSION", session_path(session)?);
+    }
 
     // Generate the prelude items, if we need any. Ensure consistent and *valid* sorting.
     let prelude_items = {
@@ -173,40 +528,302 @@ fn try_main() -> MainResult<i32> {
             .iter()
             .map(|uf| format!("#![feature({})]", uf));
 
-        let externs = args
-            .extern_
+        let externs = args.extern_.iter().map(|n| {
+            let name = n.split('=').next().unwrap_or(n);
+            format!("#[macro_use] extern crate {};", name.replace('-', "_"))
+        });
+
+        let imports = args
+            .import
             .iter()
-            .map(|n| format!("#[macro_use] extern crate {};", n));
+            .map(|path| format!("use {};", path.trim().trim_end_matches(';')));
 
-        let mut items: Vec<_> = unstable_features.chain(externs).collect();
+        let mut items: Vec<_> = unstable_features.chain(externs).chain(imports).collect();
+        if args.sh {
+            items.push(consts::SH_PRELUDE.into());
+        }
+        if args.session.is_some() {
+            items.push(consts::SESSION_PRELUDE.into());
+        }
+        // Script files get the allocator when their package is generated instead.
+        if !matches!(input, Input::File(..) | Input::Stdin(..)) {
+            items.extend(allocator(&args).map(|(_, item)| item.to_string()));
+        }
         items.sort();
+        items.dedup();
+
+        // The user's own prelude comes last, so it can use the crates brought in above.
+        if let Input::Expr(..) = input {
+            items.extend(user_prelude(&args)?);
+        }
         items
     };
     info!("prelude_items: {:?}", prelude_items);
 
-    let action = decide_action_for(&input, dependencies_from_args, prelude_items, &args)?;
-    info!("action: {:?}", action);
-
-    generate_package(&action)?;
+    let mut action = telemetry::span("generate package", || -> MainResult<InputAction> {
+        let action = decide_action_for(
+            &input,
+            dependencies_from_args.clone(),
+            prelude_items,
+            &args,
+            &config,
+            fresh,
+        )?;
+        info!("action: {:?}", action);
+
+        if !fresh {
+            if action.using_cache {
+                // Failing to write to the cache is a cache error, not a problem with the script.
+                let cache_error = |err| MainError::with_kind(FailureKind::Cache, err);
+                generate_package(&action).map_err(cache_error)?;
+                if let (Some(metadata), Some(script_path)) = (&package_metadata, input.path()) {
+                    // The scripts it depends on may have changed along with the script.
+                    let metadata = PackageMetadata {
+                        digest: package_digest(
+                            script_path,
+                            &dependencies_from_args,
+                            &args,
+                            &config,
+                            &action.script_deps,
+                        ),
+                        ..metadata.clone()
+                    };
+                    metadata.save(&action.pkg_path).map_err(cache_error)?;
+                }
+            } else {
+                generate_package(&action)?;
+            }
+            if let Input::File(_, _, body, _) | Input::Stdin(body, _) = &input {
+                fatpack::unpack(
+                    body,
+                    &action.pkg_path,
+                    action.toolchain_version.as_deref(),
+                    action.using_cache,
+                )?;
+            }
+        }
+        Ok(action)
+    })?;
 
     // Once we're done, clean out old packages from the cache.
-    let _defer_clear = {
+    let max_project_age = args
+        .project_cache_max_age
+        .map(|days| u128::from(days) * 24 * 60 * 60 * 1000)
+        .unwrap_or(consts::MAX_PROJECT_CACHE_AGE_MS);
+    let max_binary_size = args
+        .binary_cache_max_size
+        .map(|mb| mb.saturating_mul(1024 * 1024))
+        .unwrap_or(consts::MAX_BINARY_CACHE_SIZE);
+    // Nothing to do if the cache was cleared explicitly.  Nor if the package was generated for
+    // someone else to build, who's better off not waiting for the cache to be cleaned.
+    let _defer_clear = (!args.clear_cache && action.execute).then(|| {
         Defer::<_, MainError>::new(move || {
-            if args.clear_cache {
-                // Do nothing if cache was cleared explicitly.
+            clean_cache(max_project_age, max_binary_size)?;
+            Ok(())
+        })
+    });
+
+    if let Some(toolchains) = &args.test_matrix {
+        return run_test_matrix(&mut action, toolchains, &args.script_args);
+    }
+
+    if args.doc {
+        return open_docs(&action, args.doc_item.as_deref());
+    }
+
+    if args.dep_cost {
+        let toolchain = action.toolchain_version.as_deref();
+        if !cargo_version::supports(toolchain, cargo_version::TREE) {
+            return Err(format!("--dep-cost needs cargo {} or newer", cargo_version::TREE).into());
+        }
+        let cargo = || {
+            let mut cmd = Command::new("cargo");
+            if let Some(toolchain_version) = &action.toolchain_version {
+                cmd.arg(format!("+{}", toolchain_version));
+            }
+            cmd.current_dir(&action.pkg_path);
+            action.add_cargo_flags(&mut cmd);
+            cmd
+        };
+        let timings =
+            dep_cost::load_timings(&platform::binary_cache_path(toolchain).join("cargo-timings"));
+        let costs = telemetry::span("cargo tree", || {
+            dep_cost::dep_costs(cargo, &action.features, &timings)
+        })?;
+        dep_cost::print_costs(&costs);
+        return Ok(0);
+    }
+
+    if args.unused_deps {
+        let toolchain = action.toolchain_version.as_deref();
+        if !cargo_version::supports(toolchain, cargo_version::UNUSED_CRATE_DEPENDENCIES) {
+            return Err(format!(
+                "--unused-deps needs cargo {} or newer",
+                cargo_version::UNUSED_CRATE_DEPENDENCIES
+            )
+            .into());
+        }
+        // Build as the script would be, so that its dependencies are built once for both.
+        let mut cmd = action.cargo_command("rustc");
+        if !action.debug {
+            cmd.arg("--release");
+        }
+        let unused = telemetry::span("cargo rustc", || {
+            unused_deps::unused_deps(&mut cmd, &action.manifest)
+        })?;
+        unused_deps::print_unused(&unused);
+        return Ok(0);
+    }
+
+    if let Some(emit) = &args.emit {
+        let mut cmd = action.cargo_command("rustc");
+        if !action.debug {
+            cmd.arg("--release");
+        }
+        telemetry::span("cargo rustc", || emit::emit(&mut cmd, emit))?;
+        return Ok(0);
+    }
+
+    if let Some(out) = &args.fatpack {
+        let script = match &input {
+            Input::File(_, path, _, _) => fs::read_to_string(path)?,
+            Input::Stdin(body, _) => body.clone(),
+            Input::Expr(..)
+            | Input::Loop(..)
+            | Input::Json(..)
+            | Input::Csv(..)
+            | Input::ParallelMap(..) => {
+                unreachable!("--fatpack needs a script")
+            }
+        };
+        telemetry::span("cargo vendor", || {
+            fatpack::create(
+                &script,
+                &action.pkg_path,
+                action.toolchain_version.as_deref(),
+                &action.cargo_flags,
+                Path::new(out),
+            )
+        })?;
+        return Ok(0);
+    }
+
+    if args.verify_deterministic {
+        return verify_deterministic(&action);
+    }
+
+    if !action.execute {
+        if args.message_format_json {
+            println!("{}", action.package_json());
+        } else {
+            println!("{}", action.pkg_path.display());
+        }
+        return Ok(0);
+    }
+
+    if let Some(dir) = &args.snapshot {
+        return snapshot::run(Path::new(dir), args.snapshot_update, |case_args| {
+            let script_args = [&args.script_args[..], case_args].concat();
+            let cmd = action.command_to_execute(&script_args, args.wrapper.clone())?;
+            if args.no_network {
+                network::isolate(cmd, action.arg0(args.wrapper.as_deref()))
             } else {
-                clean_cache(consts::MAX_CACHE_AGE_MS)?;
+                Ok(cmd)
+            }
+        });
+    }
+
+    // Commands that run the script through another program pass on the name it's run as, which
+    // only the innermost one needs.
+    let mut arg0 = action.arg0(args.wrapper.as_deref());
+    let mut cmd = action.command_to_execute(&args.script_args, args.wrapper.clone())?;
+    if args.no_network {
+        cmd = network::isolate(cmd, arg0.take())?;
+    }
+    if !args.socket_activate.is_empty() {
+        cmd =
+            socket_activation::Listeners::bind(&args.socket_activate)?.pass_to(cmd, arg0.take())?;
+    }
+    if args.clip_in {
+        let mut input = tempfile::tempfile()?;
+        input.write_all(&platform::read_clipboard()?)?;
+        input.seek(SeekFrom::Start(0))?;
+        cmd.stdin(input);
+    }
+
+    // The credentials are only asked for once the script is built, so they're fresh when it runs.
+    let secrets = match &args.with_secrets {
+        Some(provider) => secrets::provide(provider)?,
+        None => Vec::new(),
+    };
+    cmd.envs(secrets.iter().map(|(name, value)| (name, value)));
+    if args.secrets_teardown.is_some() {
+        secrets::survive_interrupts()?;
+    }
+    let _teardown = args.secrets_teardown.as_deref().map(|teardown| {
+        Defer::<_, MainError>::new(move || {
+            if let Err(err) = secrets::teardown(teardown, &secrets) {
+                eprintln!("error: {}", err);
             }
             Ok(())
         })
+    });
+
+    if matches!(args.build_kind, BuildKind::Bench) {
+        let archive = bench::archive_path(&bench_key(&input));
+        return bench::run(&mut cmd, &archive, args.bench_compare);
+    }
+
+    let tee = if args.tee.is_some() || args.capture {
+        Some(tee::Tee {
+            path: args.tee.map(PathBuf::from).unwrap_or_else(|| {
+                platform::capture_logs_path().join(format!("{}.log", input.safe_name()))
+            }),
+            echo: !args.capture,
+            timestamps: args.timestamps,
+        })
+    } else {
+        None
     };
 
-    if !action.execute {
-        println!("{}", action.pkg_path.display());
-        return Ok(0);
+    // The output is only copied to the clipboard if the script succeeds, so it's shown instead if
+    // it fails.
+    if args.clip_out {
+        return telemetry::span("execute", || {
+            let output = cmd.stdout(Stdio::piped()).spawn()?.wait_with_output()?;
+            if output.status.success() {
+                platform::write_clipboard(&output.stdout)?;
+            } else {
+                std::io::stdout().write_all(&output.stdout)?;
+            }
+            Ok(output.status.code().unwrap_or(1))
+        });
+    }
+
+    // Execing would leave no chance to record the execution span, to tear down secrets, or to
+    // remove an ephemeral cache, so run the script as a child then.
+    if tee.is_some()
+        || args.retries > 0
+        || telemetry::enabled()
+        || args.secrets_teardown.is_some()
+        || platform::is_ephemeral()
+    {
+        return telemetry::span("execute", || {
+            let retry = |exit_code: i32| match exit_code {
+                0 => false,
+                1 => !action.exit_status_is_answer,
+                _ => true,
+            };
+            run_with_retries(
+                &mut cmd,
+                tee.as_ref(),
+                retry,
+                args.retries,
+                args.retry_delay,
+            )
+        });
     }
 
-    let mut cmd = action.command_to_execute(&args.script_args, args.wrapper)?;
     #[cfg(unix)]
     {
         let err = cmd.exec();
@@ -220,22 +837,233 @@ fn try_main() -> MainResult<i32> {
 }
 
 /**
-Clean up the cache folder.
+Print all directories rust-script uses, so that they can be controlled (e.g. mounted as volumes) when running in a container.
+*/
+fn print_paths() {
+    let paths = [
+        ("config", platform::config_dir()),
+        ("templates", platform::templates_path()),
+        ("prelude", platform::prelude_path()),
+        ("project cache", platform::generated_projects_cache_path()),
+        ("binary cache", platform::binary_cache_path(None)),
+        ("capture logs", platform::capture_logs_path()),
+        ("sessions", platform::sessions_path()),
+        ("git scripts", platform::git_cache_path()),
+        ("http scripts", platform::http_cache_path()),
+        ("replays", platform::replays_path()),
+        ("benchmarks", platform::bench_results_path()),
+        ("cargo home", platform::cargo_home()),
+        ("cargo registry", platform::cargo_registry_path()),
+        ("rustup home", platform::rustup_home()),
+    ];
+    for (name, path) in paths {
+        println!("{:<16}{}", format!("{}:", name), path.display());
+    }
+}
 
-Looks for all folders whose metadata says they were created at least `max_age` in the past and kills them dead.
+/**
+Runs the tests of the script with each of the given toolchains in turn, and prints a table of the results.
+
+Returns a nonzero exit code if the tests failed with any of the toolchains.
 */
-fn clean_cache(max_age: u128) -> MainResult<()> {
-    info!("cleaning cache with max_age: {:?}", max_age);
+fn run_test_matrix(
+    action: &mut InputAction,
+    toolchains: &[String],
+    script_args: &[String],
+) -> MainResult<i32> {
+    let mut results = Vec::with_capacity(toolchains.len());
+    for toolchain in toolchains {
+        eprintln!("Running tests with the {} toolchain...", toolchain);
+        action.toolchain_version = Some(toolchain.clone());
+        let start = Instant::now();
+        let status = action.command_to_execute(script_args, None)?.status()?;
+        results.push((toolchain, status.success(), start.elapsed()));
+    }
 
-    if max_age == 0 {
-        info!("max_age is 0, clearing binary cache...");
-        let cache_dir = platform::binary_cache_path();
-        if let Err(err) = fs::remove_dir_all(&cache_dir) {
-            error!("failed to remove binary cache {:?}: {}", cache_dir, err);
+    println!();
+    println!("{:<16}{:<8}time", "toolchain", "result");
+    for (toolchain, success, elapsed) in &results {
+        println!(
+            "{:<16}{:<8}{:.1}s",
+            toolchain,
+            if *success { "ok" } else { "FAILED" },
+            elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(if results.iter().all(|(_, success, _)| *success) {
+        0
+    } else {
+        1
+    })
+}
+
+/**
+Opens the documentation of the package and its dependencies, or of the given item in them.
+
+The documentation is only built if it's missing or older than the package.
+*/
+fn open_docs(action: &InputAction, item: Option<&str>) -> MainResult<i32> {
+    let doc_dir = platform::binary_cache_path(action.toolchain_version.as_deref()).join("doc");
+    let index = doc_dir
+        .join(action.bin_name.replace('-', "_"))
+        .join("index.html");
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|md| md.modified()).ok();
+    let up_to_date = match modified(&index) {
+        Some(built) => [action.manifest_path(), action.script_path.clone()]
+            .iter()
+            .all(|source| modified(source).is_some_and(|time| time <= built)),
+        None => false,
+    };
+
+    if up_to_date {
+        info!("documentation is up to date");
+    } else {
+        let status = telemetry::span("cargo doc", || action.cargo_command("doc").status())?;
+        if !status.success() {
+            return Err(MainError::with_kind(
+                FailureKind::Compile,
+                "Could not build the documentation",
+            ));
+        }
+    }
+
+    let page = match item {
+        Some(item) => find_doc_page(&doc_dir, item)
+            .ok_or_else(|| format!("could not find documentation for `{}`", item))?,
+        None => index,
+    };
+    platform::open_in_browser(&page)?;
+    Ok(0)
+}
+
+/**
+Finds the documentation page of an item given by its path, e.g. `regex::Regex`.
+*/
+fn find_doc_page(doc_dir: &Path, item: &str) -> Option<PathBuf> {
+    let parts: Vec<String> = item
+        .split("::")
+        .map(|part| part.replace('-', "_"))
+        .collect();
+    let (name, modules) = parts.split_last()?;
+    let dir = modules
+        .iter()
+        .fold(doc_dir.to_path_buf(), |dir, m| dir.join(m));
+
+    // Items have pages named after their kind, e.g. `struct.Regex.html`...
+    let suffix = format!(".{}.html", name);
+    let mut pages: Vec<PathBuf> = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|file| file.to_string_lossy().ends_with(&suffix))
+        })
+        .collect();
+    pages.sort();
+    if let Some(page) = pages.into_iter().next() {
+        return Some(page);
+    }
+
+    // ... while crates and modules have an index.
+    Some(dir.join(name).join("index.html")).filter(|page| page.is_file())
+}
+
+/**
+Builds the script twice, the second time from scratch in a target directory of its own, and checks that both builds produce the same binary.
+*/
+fn verify_deterministic(action: &InputAction) -> MainResult<i32> {
+    action.command_to_execute(&[], None)?;
+    let release_mode = !action.debug;
+    let built = action.binary_path_in(
+        &platform::binary_cache_path(action.toolchain_version.as_deref()),
+        release_mode,
+    );
+
+    let target_dir = tempfile::Builder::new()
+        .prefix(".tmp-verify-")
+        .tempdir_in(platform::cache_dir())?;
+    let mut cmd = action.cargo_command_in("build", target_dir.path());
+    if release_mode {
+        cmd.arg("--release");
+    }
+    if !action.cargo_output {
+        cmd.arg("-q");
+    }
+    info!("rebuilding from scratch: {:?}", cmd);
+    let status = telemetry::span("cargo build", || cmd.status())?;
+    if !status.success() {
+        return Err(MainError::with_kind(
+            FailureKind::Compile,
+            "Could not rebuild the script",
+        ));
+    }
+    let rebuilt = action.binary_path_in(target_dir.path(), release_mode);
+
+    let (first, second) = (
+        deterministic::digest(&built)?,
+        deterministic::digest(&rebuilt)?,
+    );
+    if first == second {
+        println!("Builds are identical: {}", first);
+        Ok(0)
+    } else {
+        println!("Builds differ: {} and {}", first, second);
+        Ok(1)
+    }
+}
+
+/**
+Runs the command, re-running it up to `retries` times for as long as `retry` says its exit code is a failure.
+
+Returns the exit code of the last attempt.
+*/
+fn run_with_retries(
+    cmd: &mut Command,
+    tee: Option<&tee::Tee>,
+    retry: impl Fn(i32) -> bool,
+    retries: u32,
+    retry_delay: Duration,
+) -> MainResult<i32> {
+    let mut attempt = 0;
+    loop {
+        let exit_code = match tee {
+            Some(tee) => tee::run(cmd, tee)?,
+            None => cmd.status().map(|st| st.code().unwrap_or(1))?,
+        };
+        if !retry(exit_code) || attempt == retries {
+            return Ok(exit_code);
         }
+
+        attempt += 1;
+        eprintln!(
+            "script exited with code {}, retrying in {:?} ({}/{})",
+            exit_code, retry_delay, attempt, retries
+        );
+        std::thread::sleep(retry_delay);
     }
+}
+
+/**
+Clean up the cache folder.
 
-    let cutoff = platform::current_time() - max_age;
+Generated projects and the binary cache have separate retention policies, since the former are cheap to regenerate while the latter can be expensive to rebuild:
+
+- Looks for all project folders whose metadata says they were created at least `max_project_age` in the past and kills them dead.
+- Removes the least recently used binary caches until they are at most `max_binary_size` bytes together.
+*/
+fn clean_cache(max_project_age: u128, max_binary_size: u64) -> MainResult<()> {
+    info!(
+        "cleaning cache with max_project_age: {:?}, max_binary_size: {:?}",
+        max_project_age, max_binary_size
+    );
+
+    prune_binary_cache(max_binary_size)?;
+
+    let cutoff = platform::current_time().saturating_sub(max_project_age);
     info!("cutoff:     {:>20?} ms", cutoff);
 
     let cache_dir = platform::generated_projects_cache_path();
@@ -266,8 +1094,103 @@ fn clean_cache(max_age: u128) -> MainResult<()> {
     Ok(())
 }
 
+/**
+Prune the binary caches of all host triples down to at most `max_size` bytes together.
+
+Each cache is a Cargo target directory, which is removed as a whole, least recently built in first, rather than file by file, which would leave Cargo's bookkeeping of what's built out of step with what's there.  Cargo rebuilds whatever is needed in a removed cache on the next run.
+*/
+fn prune_binary_cache(max_size: u64) -> MainResult<()> {
+    let cache_dir = platform::binary_caches_path();
+
+    if max_size == 0 {
+        info!("max_size is 0, clearing binary cache...");
+        if let Err(err) = fs::remove_dir_all(&cache_dir) {
+            error!("failed to remove binary cache {:?}: {}", cache_dir, err);
+        }
+        return Ok(());
+    }
+
+    if !cache_dir.is_dir() {
+        return Ok(());
+    }
+    let stamp = platform::cache_dir().join(PRUNE_STAMP_FILE);
+    if !prune_due(&stamp, consts::BINARY_CACHE_PRUNE_INTERVAL_MS) {
+        info!("binary cache was pruned recently");
+        return Ok(());
+    }
+    overwrite_file(&stamp, "")?;
+
+    let mut caches = Vec::new();
+    for child in fs::read_dir(&cache_dir)? {
+        let child = child?;
+        if child.file_type()?.is_dir() {
+            let (size, mtime) = dir_usage(&child.path())?;
+            caches.push((child.path(), size, mtime));
+        }
+    }
+
+    let mut total_size: u64 = caches.iter().map(|(_, size, _)| size).sum();
+    info!("binary cache size: {} bytes", total_size);
+    if total_size <= max_size {
+        return Ok(());
+    }
+
+    caches.sort_by_key(|(_, _, mtime)| *mtime);
+    for (path, size, _) in caches {
+        if total_size <= max_size {
+            break;
+        }
+        info!("removing {:?}", path);
+        match fs::remove_dir_all(&path) {
+            Ok(()) => total_size -= size,
+            Err(err) => error!("failed to remove {:?} from binary cache: {}", path, err),
+        }
+    }
+    info!("binary cache size after pruning: {} bytes", total_size);
+    Ok(())
+}
+
+/// Name of the file in the cache whose modification time is when the binary cache was last pruned.
+const PRUNE_STAMP_FILE: &str = "binaries-pruned";
+
+// Is it at least `interval` milliseconds since the binary cache was pruned, going by `stamp`?
+fn prune_due(stamp: &Path, interval: u128) -> bool {
+    let pruned = fs::metadata(stamp)
+        .and_then(|md| md.modified())
+        .map(|mtime| {
+            mtime
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis())
+        });
+    match pruned {
+        Ok(pruned) => platform::current_time().saturating_sub(pruned) >= interval,
+        Err(_) => true,
+    }
+}
+
+// The total size of the files below `dir`, and when the most recently modified one was.
+fn dir_usage(dir: &Path) -> MainResult<(u64, u128)> {
+    let (mut size, mut mtime) = (0, 0);
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        let (child_size, child_mtime) = if child.file_type()?.is_dir() {
+            dir_usage(&child.path())?
+        } else {
+            let child_size = child.metadata().map(|md| md.len()).unwrap_or(0);
+            (child_size, platform::dir_last_modified(&child))
+        };
+        size += child_size;
+        mtime = mtime.max(child_mtime);
+    }
+    Ok((size, mtime))
+}
+
 // Generate a package from the input.
 fn generate_package(action: &InputAction) -> MainResult<()> {
+    if action.using_cache && !action.pkg_path.exists() {
+        return generate_package_atomically(action);
+    }
+
     info!("creating pkg dir...");
     fs::create_dir_all(&action.pkg_path)?;
     let cleanup_dir: Defer<_, MainError> = Defer::new(|| {
@@ -288,6 +1211,7 @@ fn generate_package(action: &InputAction) -> MainResult<()> {
     if let Some(script) = &action.script {
         overwrite_file(&action.script_path, script)?;
     }
+    write_script_deps(action, &action.pkg_path)?;
 
     info!("disarming pkg dir cleanup...");
     cleanup_dir.disarm();
@@ -295,6 +1219,69 @@ fn generate_package(action: &InputAction) -> MainResult<()> {
     Ok(())
 }
 
+/**
+Generate a new package in the cache.
+
+The package is written to a temporary directory next to where it belongs, which is then renamed into place, so that several `rust-script`s generating the same package at once (say, the same expression from a parallel `make`) never see, or remove, each other's half-written package.  Whoever renames last finds the package already there and leaves it be; as packages in the cache are named after a digest of their contents, it's the same package either way.
+*/
+fn generate_package_atomically(action: &InputAction) -> MainResult<()> {
+    let cache_dir = action
+        .pkg_path
+        .parent()
+        .ok_or("The package path should be in a directory")?;
+    fs::create_dir_all(cache_dir)?;
+
+    // An interrupt, as from Ctrl-C, is held off until the staging directory is removed, after
+    // which `rust-script` ends as it would have right away.  Stray temporary directories, as left
+    // by a `rust-script` killed outright, are removed along with old packages by `clean_cache`.
+    let hold = interrupt::hold()?;
+    let staging = tempfile::Builder::new()
+        .prefix(".tmp-")
+        .tempdir_in(cache_dir)?
+        .into_path();
+    let remove_staging = Defer::new(|| {
+        info!("removing {:?}...", staging);
+        fs::remove_dir_all(&staging)
+    });
+    info!("generating Cargo package in {:?}...", staging);
+
+    overwrite_file(&staging.join("Cargo.toml"), &action.manifest)?;
+    if let Some(script) = &action.script {
+        let script_path = action
+            .script_path
+            .strip_prefix(&action.pkg_path)
+            .map_err(|_| "The generated script should be inside the package")?;
+        overwrite_file(&staging.join(script_path), script)?;
+    }
+    write_script_deps(action, &staging)?;
+    hold.check()?;
+
+    info!("moving package into place...");
+    match fs::rename(&staging, &action.pkg_path) {
+        Ok(()) => {
+            // The directory is gone, so there's nothing left to clean up.
+            remove_staging.disarm();
+            Ok(())
+        }
+        Err(_) if action.pkg_path.join("Cargo.toml").is_file() => {
+            info!("package was generated concurrently, using that one");
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Write the packages generated for the script dependencies of the script into the package in
+// `pkg_dir`.
+fn write_script_deps(action: &InputAction, pkg_dir: &Path) -> MainResult<()> {
+    for package in &action.script_deps {
+        let dir = pkg_dir.join(script_deps::DIR).join(&package.dir);
+        fs::create_dir_all(&dir)?;
+        overwrite_file(&dir.join("Cargo.toml"), &package.manifest)?;
+    }
+    Ok(())
+}
+
 /**
 This represents what to do with the input provided by the user.
 */
@@ -303,6 +1290,15 @@ struct InputAction {
     /// Always show cargo output?
     cargo_output: bool,
 
+    /// Show compiler diagnostics, even when cargo is otherwise quiet?
+    show_warnings: bool,
+
+    /// Have Cargo write a report of how long the build took?
+    build_timings: bool,
+
+    /// Show a desktop notification when building takes at least this long.
+    notify_after: Option<Duration>,
+
     /**
     Force Cargo to do a recompile, even if it thinks it doesn't have to.
 
@@ -319,13 +1315,32 @@ struct InputAction {
     /// Path of the source code that Cargo.toml refers.
     script_path: PathBuf,
 
+    /// Path of the build script that Cargo.toml refers, if any.
+    build_script_path: Option<PathBuf>,
+
     /**
     Is the package directory in the cache?
 
-    Currently, this can be inferred from `emit_metadata`, but there's no *intrinsic* reason they should be tied together.
+    Currently, this implies `reuse_binary`, but there's no *intrinsic* reason they should be tied together.
     */
     using_cache: bool,
 
+    /**
+    May a previously built binary be executed directly if it is newer than the script and manifest?
+
+    Always the case for packages in the cache.  A package directory given with `--pkg-path` is managed by the user and may contain other files that have changed, so there we always hand off to Cargo unless `--pkg-path-cached` was given.
+    */
+    reuse_binary: bool,
+
+    /// Check a cached binary against the record made when it was built before executing it?
+    verify_cache: bool,
+
+    /// Where the copy of the package's `Cargo.lock` kept next to the script is, if there is one.
+    script_lockfile: Option<PathBuf>,
+
+    /// The script to open in an editor if it fails to compile.
+    edit_on_error: Option<PathBuf>,
+
     /**
     Which toolchain the script should be built with.
 
@@ -342,12 +1357,38 @@ struct InputAction {
     /// The script source in case it has to be written.
     script: Option<String>,
 
+    /// Packages for the scripts the script depends on, in case they have to be written.
+    script_deps: Vec<script_deps::Package>,
+
     /// Did the user ask to run tests or benchmarks?
     build_kind: BuildKind,
 
+    /// Cargo features of the script to enable.
+    features: Vec<String>,
+
+    /// Extra arguments for the `cargo build` (or `test` or `bench`) command.
+    build_args: Vec<String>,
+
+    /// Cargo's `--offline`, `--locked` and `--frozen`, as given, for every Cargo command run on the
+    /// package.
+    cargo_flags: Vec<&'static str>,
+
+    /// Build deterministically?
+    deterministic: bool,
+
+    /// Fail without building if the package failed to compile before, and hasn't changed since?
+    remember_failures: bool,
+
+    /// Is the script's exit status its answer, as with `--test-expr`?  Then a status of 1 means
+    /// "false" rather than failure.
+    exit_status_is_answer: bool,
+
     // Name of the built binary
     bin_name: String,
 
+    // Name of the script, for showing to the user
+    script_name: String,
+
     // How the script was called originally
     #[cfg(unix)]
     original_script_path: Option<String>,
@@ -358,14 +1399,105 @@ impl InputAction {
         self.pkg_path.join("Cargo.toml")
     }
 
-    fn command_to_execute(
-        &self,
-        script_args: &[String],
-        wrapper: Option<String>,
-    ) -> MainResult<Command> {
-        let release_mode = !self.debug && !matches!(self.build_kind, BuildKind::Bench);
+    // The name the script is run as, unless it's run through a wrapper.
+    fn arg0(&self, wrapper: Option<&str>) -> Option<&str> {
+        #[cfg(unix)]
+        {
+            self.original_script_path
+                .as_deref()
+                .filter(|_| wrapper.is_none())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = wrapper;
+            None
+        }
+    }
+
+    // The host triple the script is built for, by the toolchain it's built with.
+    fn host_triple(&self) -> String {
+        platform::host_triple(self.toolchain_version.as_deref())
+    }
 
-        let built_binary_path = platform::binary_cache_path()
+    // The files the package is built from.
+    fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = vec![self.manifest_path(), self.script_path.clone()];
+        sources.extend(self.build_script_path.clone());
+        sources
+    }
+
+    /**
+    Adds `--offline`, `--locked` and `--frozen` to the Cargo command as given, unless they're among the build arguments already.
+    */
+    fn add_cargo_flags(&self, cmd: &mut Command) {
+        for flag in &self.cargo_flags {
+            if !self.build_args.iter().any(|arg| arg == flag) {
+                cmd.arg(flag);
+            }
+        }
+    }
+
+    /**
+    Describes the generated package as a JSON object on a single line, for `--package --message-format json`.
+    */
+    fn package_json(&self) -> String {
+        let path = |path: &Path| json_string(&path.to_string_lossy());
+        format!(
+            r#"{{"reason":"package-generated","package_path":{},"manifest_path":{},"script_path":{},"bin_name":{},"cached":{}}}"#,
+            path(&self.pkg_path),
+            path(&self.manifest_path()),
+            path(&self.script_path),
+            json_string(&self.bin_name),
+            self.using_cache
+        )
+    }
+
+    /**
+    Creates a Cargo command with the given subcommand for the package.
+    */
+    fn cargo_command(&self, subcommand: &str) -> Command {
+        self.cargo_command_in(
+            subcommand,
+            &platform::binary_cache_path(self.toolchain_version.as_deref()),
+        )
+    }
+
+    /**
+    Creates a Cargo command with the given subcommand for the package, building into the given target directory.
+    */
+    fn cargo_command_in(&self, subcommand: &str, target_dir: &Path) -> Command {
+        let mut cmd = Command::new("cargo");
+        if let Some(toolchain_version) = &self.toolchain_version {
+            cmd.arg(format!("+{}", toolchain_version));
+        }
+        cmd.arg(subcommand);
+
+        if !self.features.is_empty() {
+            cmd.arg("--features").arg(self.features.join(","));
+        }
+        cmd.args(&self.build_args);
+        self.add_cargo_flags(&mut cmd);
+
+        cmd.current_dir(&self.pkg_path);
+
+        if platform::force_cargo_color() {
+            cmd.arg("--color").arg("always");
+        }
+
+        if self.deterministic {
+            deterministic::configure(&mut cmd, target_dir);
+        }
+
+        cmd.arg("--target-dir");
+        cmd.arg(target_dir);
+        cmd
+    }
+
+    /**
+    The path of the binary built into the given target directory.
+    */
+    fn binary_path_in(&self, target_dir: &Path, release_mode: bool) -> PathBuf {
+        target_dir
             .join(if release_mode { "release" } else { "debug" })
             .join({
                 #[cfg(windows)]
@@ -376,7 +1508,194 @@ impl InputAction {
                 {
                     &self.bin_name
                 }
-            });
+            })
+    }
+
+    /**
+    Generates the `Cargo.lock` of the package if it has none, so that it can be built with `--locked`.
+    */
+    fn ensure_lockfile(&self) -> MainResult<()> {
+        if self.pkg_path.join("Cargo.lock").exists() {
+            return Ok(());
+        }
+        let mut cmd = Command::new("cargo");
+        if let Some(toolchain_version) = &self.toolchain_version {
+            cmd.arg(format!("+{}", toolchain_version));
+        }
+        cmd.arg("generate-lockfile").current_dir(&self.pkg_path);
+        self.add_cargo_flags(&mut cmd);
+        info!("generating lockfile: {:?}", cmd);
+        if !cmd.status()?.success() {
+            return Err(MainError::with_kind(
+                FailureKind::Compile,
+                "Could not generate Cargo.lock",
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+    Checks a cached binary against the record made when it was built, reporting any corruption.
+    */
+    fn binary_is_intact(&self, binary: &Path) -> MainResult<bool> {
+        let Some(expected) = BinaryRecord::load(&self.pkg_path, &self.host_triple()) else {
+            info!("no record of {:?}, rebuilding", binary);
+            return Ok(false);
+        };
+        let sources = self.sources();
+        let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        let actual = BinaryRecord::compute(&sources, binary)?;
+        match expected.mismatch(&actual) {
+            Some(problem) => {
+                eprintln!(
+                    "warning: cached binary {} is corrupted ({}); rebuilding",
+                    binary.display(),
+                    problem
+                );
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    // Put the copy of `Cargo.lock` kept next to the script into the package, returning whether it
+    // differed from the one there.
+    fn restore_lockfile(&self) -> MainResult<bool> {
+        let Some(script_lockfile) = &self.script_lockfile else {
+            return Ok(false);
+        };
+        let lockfile = match fs::read_to_string(script_lockfile) {
+            Ok(lockfile) => lockfile,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        let pkg_lockfile = self.pkg_path.join("Cargo.lock");
+        if fs::read_to_string(&pkg_lockfile).is_ok_and(|current| current == lockfile) {
+            return Ok(false);
+        }
+        info!("restoring {:?} from {:?}", pkg_lockfile, script_lockfile);
+        overwrite_file(&pkg_lockfile, &lockfile)?;
+        Ok(true)
+    }
+
+    // Keep a copy of the package's `Cargo.lock` next to the script, for `restore_lockfile`.
+    // Failing to do so, as when the script is in a read-only directory, doesn't fail the run.
+    fn save_lockfile(&self) {
+        let Some(script_lockfile) = &self.script_lockfile else {
+            return;
+        };
+        let Ok(lockfile) = fs::read_to_string(self.pkg_path.join("Cargo.lock")) else {
+            return;
+        };
+        if let Err(err) = overwrite_file(script_lockfile, &lockfile) {
+            error!("failed to save {:?}: {}", script_lockfile, err);
+        }
+    }
+
+    // Record a freshly built binary for `binary_is_intact`.  Failing to do so only means it
+    // can't be verified later, so it doesn't fail the run.
+    fn record_binary(&self, binary: &Path) {
+        let sources = self.sources();
+        let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        let record = BinaryRecord::compute(&sources, binary)
+            .and_then(|record| record.save(&self.pkg_path, &self.host_triple()));
+        if let Err(err) = record {
+            error!("failed to record {:?}: {}", binary, err);
+        }
+    }
+
+    // Record how the package was built for `--refresh-cache`.  Like with `record_binary`, failing
+    // to do so doesn't fail the run.
+    fn record_build(&self, release: bool) {
+        let toolchain = self.toolchain_version.clone();
+        let Some(rustc) = platform::rustc_version(toolchain.as_deref()) else {
+            return;
+        };
+        let record = refresh::BuildRecord {
+            toolchain,
+            rustc,
+            features: self.features.clone(),
+            build_args: self.build_args.clone(),
+            release,
+            deterministic: self.deterministic,
+        };
+        if let Err(err) = record.save(&self.pkg_path) {
+            error!("failed to record the build of {:?}: {}", self.pkg_path, err);
+        }
+    }
+
+    // Fail with the error of the previous build, if it failed and nothing changed since.
+    fn check_remembered_failure(&self) -> MainResult<()> {
+        let Some(failure) = FailureRecord::load(&self.pkg_path) else {
+            return Ok(());
+        };
+        let sources = self.sources();
+        let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        if !failure.matches(&sources, self.toolchain_version.as_deref())? {
+            return Ok(());
+        }
+        eprintln!("{}", failure.error);
+        Err(MainError::with_kind(
+            FailureKind::Compile,
+            "the script failed to compile before, and hasn't changed since (use --force to build it anyway)",
+        ))
+    }
+
+    // Record the first error of a failed build for `check_remembered_failure`, or forget the
+    // previous one after a successful build.  Like with `record_binary`, failing to do so
+    // doesn't fail the run.
+    fn record_build_outcome(&self, success: bool, output: &str) {
+        let result = if success {
+            FailureRecord::clear(&self.pkg_path)
+        } else {
+            let sources = self.sources();
+            let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+            FailureRecord::compute(
+                &sources,
+                self.toolchain_version.as_deref(),
+                &self.pkg_path,
+                output,
+            )
+            .and_then(|failure| match failure {
+                Some(failure) => failure.save(&self.pkg_path),
+                None => FailureRecord::clear(&self.pkg_path),
+            })
+        };
+        if let Err(err) = result {
+            error!("failed to record the outcome of the build: {}", err);
+        }
+    }
+
+    // Let the user know a build is done, if it took long enough for them to have switched to
+    // something else.  A notification that can't be shown doesn't fail the run.
+    fn notify_if_slow(&self, build_time: Duration, success: bool) {
+        let Some(threshold) = self.notify_after else {
+            return;
+        };
+        if build_time < threshold {
+            return;
+        }
+        let message = if success {
+            format!("Finished building {}", self.script_name)
+        } else {
+            format!("Failed to build {}", self.script_name)
+        };
+        if let Err(err) = platform::notify(consts::PROGRAM_NAME, &message) {
+            error!("failed to show notification: {}", err);
+        }
+    }
+
+    fn command_to_execute(
+        &self,
+        script_args: &[String],
+        wrapper: Option<String>,
+    ) -> MainResult<Command> {
+        let release_mode = !self.debug && !matches!(self.build_kind, BuildKind::Bench);
+
+        let built_binary_path = self.binary_path_in(
+            &platform::binary_cache_path(self.toolchain_version.as_deref()),
+            release_mode,
+        );
 
         let manifest_path = self.manifest_path();
 
@@ -406,7 +1725,16 @@ impl InputAction {
             }
         };
 
-        if matches!(self.build_kind, BuildKind::Normal) && !self.force_compile {
+        // A lock file that came with the script may resolve the dependencies differently.
+        let lockfile_restored = self.restore_lockfile()?;
+
+        // A report of the build needs a build, even if the binary is up to date.
+        if matches!(self.build_kind, BuildKind::Normal)
+            && self.reuse_binary
+            && !self.force_compile
+            && !self.build_timings
+            && !lockfile_restored
+        {
             match fs::File::open(&built_binary_path) {
                 Ok(built_binary_file) => {
                     // When possible, use creation time instead of modified time as cargo may copy
@@ -422,11 +1750,33 @@ impl InputAction {
                         (Ok(script_file), Ok(manifest_file)) => {
                             let script_mtime = script_file.metadata()?.modified()?;
                             let manifest_mtime = manifest_file.metadata()?.modified()?;
+                            // A script it depends on that's gone needs a build to say so.
+                            let script_deps_older = self.script_deps.iter().all(|package| {
+                                fs::metadata(&package.source)
+                                    .and_then(|meta| meta.modified())
+                                    .is_ok_and(|mtime| built_binary_time.cmp(&mtime).is_ge())
+                            });
+                            let build_script_mtime = match &self.build_script_path {
+                                Some(path) => Some(fs::metadata(path)?.modified()?),
+                                None => None,
+                            };
                             if built_binary_time.cmp(&script_mtime).is_ge()
                                 && built_binary_time.cmp(&manifest_mtime).is_ge()
+                                && script_deps_older
+                                && build_script_mtime
+                                    .iter()
+                                    .all(|mtime| built_binary_time.cmp(mtime).is_ge())
                             {
-                                debug!("Keeping old binary");
-                                return execute_command();
+                                if !(self.verify_cache && self.using_cache)
+                                    || self.binary_is_intact(&built_binary_path)?
+                                {
+                                    debug!("Keeping old binary");
+                                    self.save_lockfile();
+                                    return execute_command();
+                                }
+                                // Make sure Cargo doesn't consider the broken binary up to date.
+                                drop(built_binary_file);
+                                fs::remove_file(&built_binary_path)?;
                             } else {
                                 debug!("Old binary too old - rebuilding");
                             }
@@ -445,37 +1795,96 @@ impl InputAction {
             }
         }
 
-        let maybe_toolchain_version = self.toolchain_version.as_deref();
+        if matches!(self.build_kind, BuildKind::Normal)
+            && self.remember_failures
+            && !self.force_compile
+        {
+            self.check_remembered_failure()?;
+        }
 
-        let mut cmd = Command::new("cargo");
-        if let Some(toolchain_version) = maybe_toolchain_version {
-            cmd.arg(format!("+{}", toolchain_version));
+        if self.deterministic {
+            self.ensure_lockfile()?;
         }
-        cmd.arg(self.build_kind.exec_command());
+        let mut cmd = self.cargo_command(self.build_kind.exec_command());
 
         if matches!(self.build_kind, BuildKind::Normal) && !self.cargo_output {
             cmd.arg("-q");
+            if self.show_warnings {
+                // `-q` hides warnings, so have them reported separately.
+                let ansi = platform::force_cargo_color()
+                    && cargo_version::supports(
+                        self.toolchain_version.as_deref(),
+                        cargo_version::RENDERED_ANSI,
+                    );
+                cmd.arg(if ansi {
+                    diagnostics::MESSAGE_FORMAT_ANSI
+                } else {
+                    diagnostics::MESSAGE_FORMAT
+                });
+            }
         }
 
-        cmd.current_dir(&self.pkg_path);
-
-        if platform::force_cargo_color() {
-            cmd.arg("--color").arg("always");
-        }
-
-        let cargo_target_dir = format!("{}", platform::binary_cache_path().display(),);
-        cmd.arg("--target-dir");
-        cmd.arg(cargo_target_dir);
-
         if release_mode {
             cmd.arg("--release");
         }
 
+        let timings = self.build_timings
+            && cargo_version::supports(self.toolchain_version.as_deref(), cargo_version::TIMINGS);
+        if timings {
+            // Without a value, which Cargo no longer accepts; the report is HTML either way.
+            cmd.arg("--timings");
+        } else if self.build_timings {
+            eprintln!(
+                "warning: --build-timings needs Cargo {} or later",
+                cargo_version::TIMINGS
+            );
+        }
+
         if matches!(self.build_kind, BuildKind::Normal) {
-            if cmd.status()?.code() == Some(0) {
+            let build_start = Instant::now();
+            let (status, output) = telemetry::span("cargo build", || {
+                if self.show_warnings {
+                    diagnostics::run_showing_diagnostics(&mut cmd)
+                } else if self.cargo_output && self.edit_on_error.is_none() {
+                    // Leave Cargo the terminal, for its progress bar.
+                    cmd.status().map(|status| (status, String::new()))
+                } else {
+                    // The output is kept to look for errors to give hints for, or to edit.
+                    edit::run_capturing_stderr(&mut cmd)
+                }
+            })?;
+            self.notify_if_slow(build_start.elapsed(), status.success());
+            if timings {
+                let report = platform::binary_cache_path(self.toolchain_version.as_deref())
+                    .join("cargo-timings")
+                    .join("cargo-timing.html");
+                eprintln!("Build timings: {}", report.display());
+            }
+            if !status.success() {
+                for hint in hints::build_hints(&output) {
+                    eprintln!("hint: {}", hint);
+                }
+                if let Some(script) = &self.edit_on_error {
+                    if let Some((path, line)) =
+                        edit::error_location(&output, &self.pkg_path, &self.script_path, script)
+                    {
+                        edit::edit_and_rerun(&path, line)?;
+                    }
+                }
+            }
+            self.record_build_outcome(status.success(), &output);
+            if status.code() == Some(0) {
+                self.save_lockfile();
+                if self.using_cache {
+                    self.record_binary(&built_binary_path);
+                    self.record_build(release_mode);
+                }
                 cmd = execute_command()?;
             } else {
-                return Err(MainError::OtherOwned("Could not execute cargo".to_string()));
+                return Err(MainError::with_kind(
+                    FailureKind::Compile,
+                    "Could not execute cargo",
+                ));
             }
         }
 
@@ -488,29 +1897,46 @@ For the given input, this constructs the package metadata and checks the cache t
 */
 fn decide_action_for(
     input: &Input,
-    deps: Vec<(String, String)>,
+    deps: Vec<(String, DepSpec)>,
     prelude: Vec<String>,
     args: &Args,
+    config: &Config,
+    fresh: bool,
 ) -> MainResult<InputAction> {
+    let features = enabled_features(args);
+    let template = args.template.as_deref().map(templates::load).transpose()?;
+    let template = template.as_deref().or_else(|| async_template(args));
+
     let input_id = {
+        let deps: Vec<_> = deps.iter().map(|(n, v)| (n, v.to_string())).collect();
         let deps_iter = deps.iter().map(|(n, v)| (n as &str, v as &str));
-        input.compute_id(deps_iter)
+        input.compute_id(
+            deps_iter,
+            &features,
+            &build_args(args),
+            args.cache_key.as_deref(),
+            template,
+            &prelude,
+        )
     };
     info!("id: {:?}", input_id);
 
     let pkg_name = input.package_name();
     let bin_name = format!("{}_{}", &*pkg_name, input_id.to_str().unwrap());
 
-    let (pkg_path, using_cache) = args
-        .pkg_path
-        .as_ref()
-        .map(|p| (p.into(), false))
-        .unwrap_or_else(|| {
+    let (pkg_path, using_cache) = match &args.pkg_path {
+        // Cargo is run in the package directory, so a relative path would mean something else
+        // there; and whoever asked for the package wants to know where it ended up.
+        Some(pkg_path) => (std::env::current_dir()?.join(pkg_path), false),
+        None => {
             let cache_path = platform::generated_projects_cache_path();
             (cache_path.join(&input_id), true)
-        });
+        }
+    };
+    let reuse_binary = using_cache || args.pkg_path_cached;
     info!("pkg_path: {:?}", pkg_path);
     info!("using_cache: {:?}", using_cache);
+    info!("reuse_binary: {:?}", reuse_binary);
 
     let toolchain_version = args
         .toolchain_version
@@ -522,16 +1948,69 @@ fn decide_action_for(
 
     let script_name = format!("{}.rs", input.safe_name());
 
-    let (mani_str, script_path, script_str) = manifest::split_input(
-        input,
-        input.base_path(),
-        &deps,
-        &prelude,
-        &pkg_path,
-        &bin_name,
-        &script_name,
-        toolchain_version.clone(),
-    )?;
+    let (mani_str, script_path, script_str, script_deps) = if fresh {
+        // The package is up to date, so there's nothing to generate.
+        // The script is wherever the existing manifest says it is.
+        let mani_str = fs::read_to_string(pkg_path.join("Cargo.toml"))?;
+        let script_path = manifest::bin_source_path(&mani_str)
+            .map(|path| pkg_path.join(path))
+            .ok_or("could not find the script in the cached package")?;
+        (mani_str, script_path, None, script_deps::load(&pkg_path)?)
+    } else {
+        let mut build_deps: Vec<_> =
+            dep_spec::parse_args(&args.build_dep, args.registry.as_deref())?
+                .into_iter()
+                .collect();
+        build_deps.sort();
+        let mut dev_deps: Vec<_> = dep_spec::parse_args(&args.dev_dep, args.registry.as_deref())?
+            .into_iter()
+            .collect();
+        dev_deps.sort();
+        let patches = args
+            .patch
+            .iter()
+            .map(|patch| Patch::parse(patch))
+            .collect::<MainResult<Vec<_>>>()?;
+        let external_manifest = args
+            .manifest
+            .as_deref()
+            .map(|path| manifest::load_external_manifest(Path::new(path)))
+            .transpose()?;
+        let (mani_str, script_path, script_str) = manifest::split_input(
+            input,
+            input.base_path(),
+            &pkg_path,
+            &bin_name,
+            &script_name,
+            manifest::SplitOptions {
+                deps: &deps,
+                build_deps: &build_deps,
+                dev_deps: &dev_deps,
+                patches: &patches,
+                external_manifest,
+                prelude_items: &prelude,
+                toolchain: toolchain_version.clone(),
+                preprocessed: config.preprocess.is_some(),
+                structured_main: args.structured_main,
+                template,
+                allocator: allocator(args).map(|(_, item)| item),
+            },
+        )?;
+        let (mani_str, script_deps) = script_deps::resolve(&mani_str)?;
+
+        // A package generated into the script's own repository refers to the script relative to
+        // itself, so that building it with plain Cargo picks up changes to the script, and it
+        // still works wherever the repository is checked out.
+        let mani_str = match (using_cache, &script_str) {
+            (false, None) => match relative_script_path(&pkg_path, &script_path)? {
+                Some(relative) => manifest::set_bin_source_path(&mani_str, &relative)?,
+                None => mani_str,
+            },
+            _ => mani_str,
+        };
+        (mani_str, script_path, script_str, script_deps)
+    };
+    requirements::check(&mani_str)?;
 
     // Forcibly override some flags based on build kind.
     let debug = match args.build_kind {
@@ -542,38 +2021,446 @@ fn decide_action_for(
 
     Ok(InputAction {
         cargo_output: args.cargo_output,
+        show_warnings: args.show_warnings,
+        build_timings: args.build_timings,
+        notify_after: args.notify,
         force_compile: args.force,
         execute: !args.gen_pkg_only,
+        build_script_path: manifest::build_script_source_path(&mani_str)
+            .map(|path| pkg_path.join(path)),
         pkg_path,
         script_path,
         using_cache,
+        reuse_binary,
+        verify_cache: args.verify_cache,
+        script_lockfile: match input {
+            // Scripts fetched into the cache have nowhere of their own to keep it.
+            Input::File(_, path, _, _)
+                if !args.no_lockfile && !args.script.as_deref().is_some_and(fetch::is_remote) =>
+            {
+                Some(script_lockfile_path(path))
+            }
+            _ => None,
+        },
+        edit_on_error: input.path().filter(|_| args.edit_on_error).map(Into::into),
         toolchain_version,
         debug,
         manifest: mani_str,
         script: script_str,
+        script_deps,
         build_kind: args.build_kind,
+        features,
+        build_args: build_args(args),
+        cargo_flags: cargo_flags(args),
+        deterministic: args.deterministic || args.verify_deterministic,
+        remember_failures: args.remember_failures,
+        exit_status_is_answer: args.output == arguments::Output::ExitStatus,
         bin_name,
+        script_name: input.safe_name().into(),
         #[cfg(unix)]
         original_script_path: args.script.clone(),
     })
 }
 
-/// Attempts to locate the script specified by the given path.
-fn find_script(path: &Path) -> Option<(PathBuf, fs::File)> {
+/**
+Returns where the copy of the `Cargo.lock` of a script is kept: next to it, as `script.rs.lock`.
+*/
+fn script_lockfile_path(script: &Path) -> PathBuf {
+    let mut path = script.as_os_str().to_owned();
+    path.push(".lock");
+    path.into()
+}
+
+/**
+Returns the built-in template to run an expression or loop script on a tokio runtime with, if it should be.
+
+That's the case with `--async`, or when the code uses `.await`, unless it's given a template or a mode that has no async template.
+*/
+fn async_template(args: &Args) -> Option<&'static str> {
+    let plain_expr = args.expr && args.output == arguments::Output::Debug;
+    let plain_loop = args.loop_ && !args.count && args.chunk_lines.is_none();
+    let wants_async = args.async_ || args.script.as_deref().is_some_and(manifest::uses_await);
+    if !(plain_expr || plain_loop) || args.template.is_some() || !wants_async {
+        return None;
+    }
+    Some(if args.loop_ {
+        consts::LOOP_ASYNC_TEMPLATE
+    } else if args.block {
+        consts::EXPR_BLOCK_ASYNC_TEMPLATE
+    } else {
+        consts::EXPR_ASYNC_TEMPLATE
+    })
+}
+
+/**
+Returns the file holding the bindings of the named `--session`, creating its directory if needed.
+*/
+fn session_path(name: &str) -> MainResult<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(MainError::with_kind(
+            FailureKind::Usage,
+            format!(
+                "invalid session name '{}': use only letters, digits, '-' and '_'",
+                name
+            ),
+        ));
+    }
+
+    let dir = platform::sessions_path();
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+// Key of the benchmark results of the script: results are kept per script file, whatever its
+// contents, so that a run can be compared with runs of earlier versions of it.
+fn bench_key(input: &Input) -> String {
+    let mut hasher = Sha1::new();
+    if let Some(path) = input.path() {
+        hasher.update(normalized_path(path));
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    format!("{}-{}", input.safe_name(), &digest[..16])
+}
+
+// The allocator given with `--alloc`, as the dependency providing it and the item making it the
+// global allocator.
+fn allocator(args: &Args) -> Option<((&'static str, &'static str), &'static str)> {
+    let name = args.alloc.as_deref()?;
+    consts::ALLOCATORS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|&(_, dep, item)| (dep, item))
+}
+
+// The user's prelude for expressions: the `--prelude` file, or else the one in the configuration
+// directory, if there is one.
+fn user_prelude(args: &Args) -> MainResult<Option<String>> {
+    let path = args
+        .prelude
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(platform::prelude_path);
+    match fs::read_to_string(&path) {
+        Ok(prelude) => Ok(Some(prelude)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && args.prelude.is_none() => Ok(None),
+        Err(e) => Err(MainError::Tag(
+            format!("could not read prelude {}", path.display()).into(),
+            Box::new(e.into()),
+        )),
+    }
+}
+
+// The Cargo features to enable for the script, in a consistent order.
+fn enabled_features(args: &Args) -> Vec<String> {
+    let mut features = args.enable.clone();
+    features.sort();
+    features.dedup();
+    features
+}
+
+// The extra arguments for Cargo when building the script.
+fn build_args(args: &Args) -> Vec<String> {
+    let mut build_args = args.build_arg.clone();
+    if args.deterministic || args.verify_deterministic {
+        build_args.push("--locked".into());
+    }
+    build_args
+}
+
+// Cargo's options for how strict to be about the network and the lock file, as given.  Unlike the
+// build arguments, they don't change what's built, so the package isn't cached separately for them.
+fn cargo_flags(args: &Args) -> Vec<&'static str> {
+    [
+        ("--offline", args.offline),
+        ("--locked", args.locked),
+        ("--frozen", args.frozen),
+    ]
+    .into_iter()
+    .filter_map(|(flag, given)| given.then_some(flag))
+    .collect()
+}
+
+// The directory relative paths in the manifest of an input other than a script file are resolved
+// against: `--base-path`, or the current directory.
+fn expr_base_path(args: &Args) -> MainResult<PathBuf> {
+    match &args.base_path {
+        Some(base_path) => Ok(base_path.into()),
+        None => Ok(std::env::current_dir()?),
+    }
+}
+
+// The package directory in the cache for a script file.
+fn cached_package_path(input: &Input, args: &Args) -> PathBuf {
+    let input_id = input.compute_id(
+        std::iter::empty(),
+        &enabled_features(args),
+        &build_args(args),
+        args.cache_key.as_deref(),
+        None,
+        &[],
+    );
+    platform::generated_projects_cache_path().join(input_id)
+}
+
+/**
+Can a previously generated package be reused without reading the script?
+
+This is only done for plain runs out of the package cache; anything that needs the contents of the script, or builds the package differently, reads it.
+*/
+fn may_reuse_package(args: &Args) -> bool {
+    matches!(args.build_kind, BuildKind::Normal)
+        && !args.paranoid
+        && !args.force
+        && !args.pre_lint
+        && !args.extract_manifest
+        && args.set_manifest.is_none()
+        && args.pkg_path.is_none()
+        && args.test_matrix.is_none()
+}
+
+/**
+Computes a digest of everything besides the script itself that goes into its generated package, including the scripts it depends on.
+*/
+fn package_digest(
+    script_path: &Path,
+    deps: &[(String, DepSpec)],
+    args: &Args,
+    config: &Config,
+    script_deps: &[script_deps::Package],
+) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"));
+    if let Some(cmd) = &config.preprocess {
+        hasher.update(format!("preprocess={};", cmd));
+    }
+    if args.structured_main {
+        hasher.update("structured_main;");
+    }
+    for (name, version) in deps {
+        hasher.update(format!("dep={}={};", name, version));
+    }
+    if let Some(registry) = &args.registry {
+        hasher.update(format!("registry={};", registry));
+    }
+    for (kind, deps) in [
+        ("build_dep", &args.build_dep),
+        ("dev_dep", &args.dev_dep),
+        ("patch", &args.patch),
+    ] {
+        let mut deps: Vec<_> = deps.iter().collect();
+        deps.sort();
+        for dep in deps {
+            hasher.update(format!("{}={};", kind, dep));
+        }
+    }
+    // Unlike the script, a manifest given with `--manifest` is read on every run.
+    if let Some(manifest) = &args.manifest {
+        hasher.update(format!("manifest={};", manifest));
+        hasher.update(fs::read(manifest).unwrap_or_default());
+    }
+    // The build script isn't read until the package is built, but whether there is one goes into
+    // the manifest.
+    if let Some(build_script) = manifest::build_script_path(script_path) {
+        hasher.update(format!("build_script={};", build_script.display()));
+    }
+    for ext in &args.extern_ {
+        hasher.update(format!("extern={};", ext));
+    }
+    for path in &args.import {
+        hasher.update(format!("import={};", path));
+    }
+    for feature in &args.unstable_features {
+        hasher.update(format!("unstable_feature={};", feature));
+    }
+    for feature in enabled_features(args) {
+        hasher.update(format!("feature={};", feature));
+    }
+    if let Some(toolchain) = &args.toolchain_version {
+        hasher.update(format!("toolchain={};", toolchain));
+    }
+    if let Some(base_path) = &args.base_path {
+        hasher.update(format!("base_path={};", base_path));
+    }
+    if let Some(block) = &args.md_block {
+        hasher.update(format!("md_block={};", block));
+    }
+    if !script_deps.is_empty() {
+        hasher.update(format!(
+            "script_deps={};",
+            script_deps::sources_digest(script_deps)
+        ));
+    }
+    if let Some(alloc) = &args.alloc {
+        hasher.update(format!("alloc={};", alloc));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/**
+Attempts to locate the script specified by the given path.
+
+If the path could mean more than one script, such as `foo` when both `foo.ers` and `foo.rs` exist, the one to run is chosen with `choose_script`.
+*/
+fn find_script(path: &Path, pick: Option<usize>) -> MainResult<Option<(PathBuf, fs::File)>> {
+    if path.is_dir() {
+        let path = find_script_in_dir(path, pick)?;
+        let file = fs::File::open(&path)?;
+        return Ok(Some((path, file)));
+    }
+
     if let Ok(file) = fs::File::open(path) {
-        return Some((path.into(), file));
+        return Ok(Some((path.into(), file)));
     }
 
     if path.extension().is_none() {
-        for &ext in &["ers", "rs"] {
-            let path = path.with_extension(ext);
-            if let Ok(file) = fs::File::open(&path) {
-                return Some((path, file));
+        let mut candidates: Vec<PathBuf> = ["ers", "rs"]
+            .iter()
+            .map(|ext| path.with_extension(ext))
+            .filter(|path| path.is_file())
+            .collect();
+        if !candidates.is_empty() {
+            let path = if candidates.len() == 1 {
+                candidates.remove(0)
+            } else {
+                let what = format!("{} could mean more than one script", path.display());
+                choose_script(candidates, pick, &what)?
+            };
+            let file = fs::File::open(&path)?;
+            return Ok(Some((path, file)));
+        }
+    }
+
+    Ok(None)
+}
+
+/**
+Picks the script to run when the given path is a directory.
+
+If exactly one of `main.ers`, `main.rs`, `<dirname>.ers` or `<dirname>.rs` exists in the directory, that's the script.  Otherwise, it's chosen with `choose_script` from those, or if there are none of those, from all the scripts in the directory.
+*/
+fn find_script_in_dir(dir: &Path, pick: Option<usize>) -> MainResult<PathBuf> {
+    let mut names = vec!["main.ers".to_string(), "main.rs".to_string()];
+    if let Some(dir_name) = dir.canonicalize()?.file_name() {
+        let dir_name = dir_name.to_string_lossy();
+        for name in [format!("{}.ers", dir_name), format!("{}.rs", dir_name)] {
+            if !names.contains(&name) {
+                names.push(name);
             }
         }
     }
 
-    None
+    let mut found: Vec<PathBuf> = names
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.is_file())
+        .collect();
+
+    if found.len() == 1 {
+        return Ok(found.remove(0));
+    }
+
+    let candidates = if found.is_empty() {
+        let mut scripts: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && matches!(
+                        path.extension().and_then(|ext| ext.to_str()),
+                        Some("ers") | Some("rs")
+                    )
+            })
+            .collect();
+        scripts.sort();
+        scripts
+    } else {
+        found
+    };
+
+    if candidates.is_empty() {
+        return Err(MainError::with_kind(
+            FailureKind::Usage,
+            format!("{} is a directory without any scripts in it", dir.display()),
+        ));
+    }
+
+    // Even a single other script has to be chosen, as it may not be meant to be run at all.
+    let what = format!(
+        "{} is a directory; specify which script to run",
+        dir.display()
+    );
+    choose_script(candidates, pick, &what)
+}
+
+/**
+Chooses which of several scripts a path means.
+
+That's the `pick`th one, counting from 1, if given.  Otherwise the user is asked, if they can be; if not, the error lists the candidates, after `what` describing the problem.
+*/
+fn choose_script(
+    mut candidates: Vec<PathBuf>,
+    pick: Option<usize>,
+    what: &str,
+) -> MainResult<PathBuf> {
+    let usage_error = |msg: String| MainError::with_kind(FailureKind::Usage, msg);
+    let mut listing = String::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        listing.push_str(&format!("\n    {}) {}", i + 1, candidate.display()));
+    }
+
+    let pick = match pick {
+        Some(pick) => pick,
+        None if platform::is_interactive() => {
+            eprint!("{}:{}\nwhich one? [1-{}] ", what, listing, candidates.len());
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            answer
+                .trim()
+                .parse()
+                .map_err(|_| usage_error(format!("not a number: {}", answer.trim())))?
+        }
+        None => {
+            return Err(usage_error(format!(
+                "{}; choose one with --pick N:{}",
+                what, listing
+            )))
+        }
+    };
+
+    if pick == 0 || pick > candidates.len() {
+        return Err(usage_error(format!(
+            "there is no script number {}; {}:{}",
+            pick, what, listing
+        )));
+    }
+    Ok(candidates.remove(pick - 1))
+}
+
+/**
+Turns the raw contents of a script file into its source text.
+
+Fails if the file looks like a binary (it contains a NUL byte near the start), or isn't valid UTF-8, rather than generating a package out of garbage.
+*/
+fn script_body_from_bytes(path: &Path, bytes: Vec<u8>) -> MainResult<String> {
+    if bytes.iter().take(8192).any(|&b| b == 0) {
+        return Err(format!(
+            "{} looks like a binary file, not a Rust script",
+            path.display()
+        )
+        .into());
+    }
+
+    String::from_utf8(bytes).map_err(|err| {
+        format!(
+            "{} is not a valid UTF-8 text file: {}",
+            path.display(),
+            err.utf8_error()
+        )
+        .into()
+    })
 }
 
 /**
@@ -591,16 +2478,44 @@ pub enum Input {
     /**
     The input is an expression.
 
-    The tuple member is: the script contents, base path.
+    The tuple members are: the script contents, whether the `--block` flag was given, how the value is printed, base path.
     */
-    Expr(String, PathBuf),
+    Expr(String, bool, arguments::Output, PathBuf),
 
     /**
     The input is a loop expression.
 
-    The tuple member is: the script contents, whether the `--count` flag was given, base path.
+    The tuple members are: the script contents, whether the `--count` flag was given, the number of lines per call given with `--chunk-lines`, base path.
+    */
+    Loop(String, bool, Option<usize>, PathBuf),
+
+    /**
+    The input is an expression evaluated for each line of JSON from standard input.
+
+    The tuple members are: the expression, base path.
+    */
+    Json(String, PathBuf),
+
+    /**
+    The input is an expression evaluated for each record of CSV from standard input.
+
+    The tuple members are: the expression, base path.
+    */
+    Csv(String, PathBuf),
+
+    /**
+    The input is an expression evaluated for each line from standard input, on a thread pool.
+
+    The tuple members are: the expression, base path.
+    */
+    ParallelMap(String, PathBuf),
+
+    /**
+    The input is a script read from standard input.
+
+    The tuple members are: the script contents, base path.
     */
-    Loop(String, bool, PathBuf),
+    Stdin(String, PathBuf),
 }
 
 impl Input {
@@ -614,9 +2529,23 @@ impl Input {
             File(_, path, _, _) => Some(path),
             Expr(..) => None,
             Loop(..) => None,
+            Json(..) => None,
+            Csv(..) => None,
+            ParallelMap(..) => None,
+            Stdin(..) => None,
         }
     }
 
+    /**
+    Is the input a literate script, a Markdown file with the script in its code blocks?
+    */
+    pub fn is_markdown(&self) -> bool {
+        self.path()
+            .and_then(|path| path.extension())
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| literate::EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+    }
+
     /**
     Return the "safe name" for the input.  This should be filename-safe.
 
@@ -629,6 +2558,10 @@ impl Input {
             File(name, _, _, _) => name,
             Expr(..) => "expr",
             Loop(..) => "loop",
+            Json(..) => "json",
+            Csv(..) => "csv",
+            ParallelMap(..) => "parallel_map",
+            Stdin(..) => "stdin",
         }
     }
 
@@ -667,20 +2600,64 @@ impl Input {
     pub fn base_path(&self) -> &PathBuf {
         match self {
             Input::File(_, _, _, base_path)
-            | Input::Expr(_, base_path)
-            | Input::Loop(_, _, base_path) => base_path,
+            | Input::Expr(_, _, _, base_path)
+            | Input::Loop(_, _, _, base_path)
+            | Input::Json(_, base_path)
+            | Input::Csv(_, base_path)
+            | Input::ParallelMap(_, base_path)
+            | Input::Stdin(_, base_path) => base_path,
         }
     }
 
     // Compute the package ID for the input.
     // This is used as the name of the cache folder into which the Cargo package
     // will be generated.
-    pub fn compute_id<'dep, DepIt>(&self, deps: DepIt) -> OsString
+    pub fn compute_id<'dep, DepIt>(
+        &self,
+        deps: DepIt,
+        features: &[String],
+        build_args: &[String],
+        cache_key: Option<&str>,
+        template: Option<&str>,
+        prelude: &[String],
+    ) -> OsString
     where
         DepIt: IntoIterator<Item = (&'dep str, &'dep str)>,
     {
         use crate::Input::*;
 
+        // Features can pull in additional dependencies, and build arguments can change the binary,
+        // so each combination of them gets its own cache entry.  So does each cache key, which
+        // stands for whatever else the user knows to change the build, each user template, and
+        // each prelude.
+        let hash_build = |hasher: &mut Sha1| {
+            for feature in features {
+                hasher.update(b"feature=");
+                hasher.update(feature);
+                hasher.update(b";");
+            }
+            for arg in build_args {
+                hasher.update(b"build_arg=");
+                hasher.update(arg);
+                hasher.update(b";");
+            }
+            if let Some(key) = cache_key {
+                hasher.update(b"cache_key=");
+                hasher.update(key);
+                hasher.update(b";");
+            }
+            if let Some(template) = template {
+                hasher.update(b"template=");
+                hasher.update(template);
+                hasher.update(b";");
+            }
+            for item in prelude {
+                hasher.update(b"prelude=");
+                hasher.update(item);
+                hasher.update(b";");
+            }
+        };
+
         let hash_deps = || {
             let mut hasher = Sha1::new();
             for dep in deps {
@@ -698,7 +2675,30 @@ impl Input {
                 let mut hasher = Sha1::new();
 
                 // Hash the path to the script.
-                hasher.update(&*path.to_string_lossy());
+                hasher.update(normalized_path(path));
+                hash_build(&mut hasher);
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            Expr(content, block, output, _) => {
+                let mut hasher = hash_deps();
+                hash_build(&mut hasher);
+
+                // Statements are put in a different template than expressions, as are expressions
+                // printed in other ways.
+                if *block {
+                    hasher.update(b"block;");
+                }
+                match output {
+                    arguments::Output::Debug => {}
+                    arguments::Output::Json => hasher.update(b"output=json;"),
+                    arguments::Output::ExitStatus => hasher.update(b"output=exit_status;"),
+                }
+                hasher.update(content);
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
 
@@ -706,9 +2706,13 @@ impl Input {
                 id.push(&*digest);
                 id
             }
-            Expr(content, _) => {
+            Stdin(content, _) => {
+                // There's no path to key the cache on, so use the script itself, like for
+                // expressions.
                 let mut hasher = hash_deps();
+                hash_build(&mut hasher);
 
+                hasher.update(b"stdin;");
                 hasher.update(content);
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
@@ -717,13 +2721,56 @@ impl Input {
                 id.push(&*digest);
                 id
             }
-            Loop(content, count, _) => {
+            Loop(content, count, chunk_lines, _) => {
                 let mut hasher = hash_deps();
+                hash_build(&mut hasher);
 
                 // Make sure to include the [non-]presence of the `--count` flag in the flag, since it changes the actual generated script output.
                 hasher.update("count:");
                 hasher.update(if *count { "true;" } else { "false;" });
+                if let Some(chunk_lines) = chunk_lines {
+                    hasher.update(format!("chunk_lines:{};", chunk_lines));
+                }
+
+                hasher.update(content);
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            Json(content, _) => {
+                let mut hasher = hash_deps();
+                hash_build(&mut hasher);
+
+                hasher.update(b"json;");
+                hasher.update(content);
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            Csv(content, _) => {
+                let mut hasher = hash_deps();
+                hash_build(&mut hasher);
+
+                hasher.update(b"csv;");
+                hasher.update(content);
+                let mut digest = format!("{:x}", hasher.finalize());
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push(&*digest);
+                id
+            }
+            ParallelMap(content, _) => {
+                let mut hasher = hash_deps();
+                hash_build(&mut hasher);
 
+                hasher.update(b"parallel_map;");
                 hasher.update(content);
                 let mut digest = format!("{:x}", hasher.finalize());
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
@@ -736,6 +2783,80 @@ impl Input {
     }
 }
 
+/**
+The path of the script relative to the package directory, if both are in the same repository.
+
+The package directory is created if it doesn't exist yet.
+*/
+fn relative_script_path(pkg_path: &Path, script: &Path) -> MainResult<Option<String>> {
+    let script = fs::canonicalize(script)?;
+    let Some(repo) = script
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+    else {
+        return Ok(None);
+    };
+    fs::create_dir_all(pkg_path)?;
+    let pkg_path = fs::canonicalize(pkg_path)?;
+    if !pkg_path.starts_with(repo) {
+        return Ok(None);
+    }
+
+    let common = pkg_path
+        .ancestors()
+        .find(|dir| script.starts_with(dir))
+        .expect("the package and the script are in the same repository");
+    let mut relative = PathBuf::new();
+    for _ in pkg_path.strip_prefix(common).unwrap().components() {
+        relative.push("..");
+    }
+    relative.push(script.strip_prefix(common).unwrap());
+    // Forward slashes work everywhere, so the package can be shared between systems.
+    Ok(relative.to_str().map(|path| path.replace('\\', "/")))
+}
+
+/**
+Normalizes the path of a script, so that every way of referring to the same file keys the cache the same.
+
+The path is made canonical, resolving `.`, `..` and symbolic links.  On Windows, the `\\?\` prefix that gives is removed and the path is case-folded, as file names aren't case-sensitive there.
+*/
+fn normalized_path(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+    let path = path.to_string_lossy();
+    #[cfg(windows)]
+    {
+        let path = if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+            format!(r"\\{}", unc)
+        } else {
+            path.strip_prefix(r"\\?\").unwrap_or(&path).to_string()
+        };
+        path.to_lowercase()
+    }
+    #[cfg(not(windows))]
+    {
+        path.into_owned()
+    }
+}
+
+// Quote a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 // Overwrite a file if and only if the contents have changed.
 fn overwrite_file(path: &Path, content: &str) -> MainResult<()> {
     debug!("overwrite_file({:?}, _)", path);
@@ -765,6 +2886,29 @@ fn overwrite_file(path: &Path, content: &str) -> MainResult<()> {
     Ok(())
 }
 
+#[test]
+fn test_dir_usage() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("release/deps")).unwrap();
+    fs::write(dir.path().join("CACHEDIR.TAG"), "abc").unwrap();
+    fs::write(dir.path().join("release/deps/script"), "abcdef").unwrap();
+    let (size, mtime) = dir_usage(dir.path()).unwrap();
+    assert_eq!(size, 9);
+    let script = fs::read_dir(dir.path().join("release/deps")).unwrap();
+    let script = script.map(|entry| entry.unwrap()).next().unwrap();
+    assert!(mtime >= platform::dir_last_modified(&script));
+}
+
+#[test]
+fn test_prune_due() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let stamp = dir.path().join(PRUNE_STAMP_FILE);
+    assert!(prune_due(&stamp, consts::BINARY_CACHE_PRUNE_INTERVAL_MS));
+    fs::write(&stamp, "").unwrap();
+    assert!(!prune_due(&stamp, consts::BINARY_CACHE_PRUNE_INTERVAL_MS));
+    assert!(prune_due(&stamp, 0));
+}
+
 #[test]
 fn test_package_name() {
     let input = Input::File(
@@ -782,3 +2926,116 @@ fn test_package_name() {
     );
     assert_eq!("_1script", input.package_name());
 }
+
+#[test]
+fn test_compute_id_normalizes_path() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("script.rs"), "fn main() {}").unwrap();
+
+    let id = |path: PathBuf| {
+        Input::File("script".into(), path, String::new(), dir.path().into()).compute_id(
+            std::iter::empty(),
+            &[],
+            &[],
+            None,
+            None,
+            &[],
+        )
+    };
+    let direct = id(dir.path().join("script.rs"));
+    assert_eq!(id(dir.path().join(".").join("script.rs")), direct);
+    assert_eq!(
+        id(dir.path().join("sub").join("..").join("script.rs")),
+        direct
+    );
+    assert_ne!(id(dir.path().join("other.rs")), direct);
+}
+
+#[test]
+fn test_find_script_pick() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::write(dir.path().join("tool.ers"), "").unwrap();
+    fs::write(dir.path().join("tool.rs"), "").unwrap();
+    let tool = dir.path().join("tool");
+
+    let (path, _) = find_script(&tool, Some(2)).unwrap().unwrap();
+    assert_eq!(path, dir.path().join("tool.rs"));
+    let (path, _) = find_script(&dir.path().join("tool.ers"), None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(path, dir.path().join("tool.ers"));
+    assert!(find_script(&tool, Some(3)).is_err());
+    assert!(find_script(&dir.path().join("other"), Some(1))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_relative_script_path() {
+    let repo = tempfile::TempDir::new().unwrap();
+    fs::create_dir_all(repo.path().join(".git")).unwrap();
+    fs::create_dir_all(repo.path().join("scripts")).unwrap();
+    let script = repo.path().join("scripts").join("tool.rs");
+    fs::write(&script, "fn main() {}").unwrap();
+
+    assert_eq!(
+        relative_script_path(&repo.path().join("build").join("tool"), &script).unwrap(),
+        Some("../../scripts/tool.rs".into())
+    );
+    assert_eq!(
+        relative_script_path(&repo.path().join("scripts").join("pkg"), &script).unwrap(),
+        Some("../tool.rs".into())
+    );
+
+    let elsewhere = tempfile::TempDir::new().unwrap();
+    assert_eq!(
+        relative_script_path(&elsewhere.path().join("pkg"), &script).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_find_doc_page() {
+    let doc_dir = tempfile::TempDir::new().unwrap();
+    let regex_dir = doc_dir.path().join("regex");
+    fs::create_dir_all(regex_dir.join("bytes")).unwrap();
+    for page in ["index.html", "struct.Regex.html", "bytes/index.html"] {
+        fs::write(regex_dir.join(page), "").unwrap();
+    }
+
+    let find = |item| find_doc_page(doc_dir.path(), item);
+    assert_eq!(find("regex"), Some(regex_dir.join("index.html")));
+    assert_eq!(
+        find("regex::Regex"),
+        Some(regex_dir.join("struct.Regex.html"))
+    );
+    assert_eq!(
+        find("regex::bytes"),
+        Some(regex_dir.join("bytes/index.html"))
+    );
+    assert_eq!(find("regex::Nope"), None);
+}
+
+#[test]
+fn test_script_body_from_bytes() {
+    let path = Path::new("script.rs");
+    assert_eq!(
+        script_body_from_bytes(path, b"fn main() {}".to_vec()).unwrap(),
+        "fn main() {}"
+    );
+    assert!(script_body_from_bytes(path, b"\x7fELF\x02\x01\x01\x00".to_vec()).is_err());
+    assert!(script_body_from_bytes(path, b"fn main() { \xff }".to_vec()).is_err());
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(
+        json_string("/cache/projects/abc"),
+        r#""/cache/projects/abc""#
+    );
+    assert_eq!(
+        json_string("C:\\Users\\me\\\"quoted\"\n\u{1}"),
+        r#""C:\\Users\\me\\\"quoted\"\n\u0001""#
+    );
+}
@@ -6,47 +6,138 @@ use regex;
 
 use self::regex::Regex;
 use std::collections::HashMap;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
 
+use crate::arguments::Output;
 use crate::consts;
-use crate::error::{MainError, MainResult};
+use crate::dep_spec::{DepSpec, Patch};
+use crate::error::{FailureKind, MainError, MainResult};
 use crate::templates;
 use crate::Input;
 use log::{error, info};
 
+/**
+What goes into the package generated for a script besides the script itself, for `split_input`.
+*/
+#[derive(Default)]
+pub struct SplitOptions<'a> {
+    /// Dependencies to add, as given on the command line.
+    pub deps: &'a [(String, DepSpec)],
+    /// Build-dependencies to add.
+    pub build_deps: &'a [(String, DepSpec)],
+    /// Dev-dependencies to add.
+    pub dev_deps: &'a [(String, DepSpec)],
+    /// Patches added to the `[patch]` sections of the manifest, taking the place of any for the same crates in the script's manifest.
+    pub patches: &'a [Patch],
+    /// A manifest, as loaded by `load_external_manifest`, merged with the manifest embedded in the script, if any, with the latter taking precedence.
+    pub external_manifest: Option<toml::value::Table>,
+    /// Items injected into expressions and loop scripts.
+    pub prelude_items: &'a [String],
+    /// The toolchain to build the script with.
+    pub toolchain: Option<String>,
+    /// Whether the script file was preprocessed, so that it has to be compiled from a copy.
+    pub preprocessed: bool,
+    /// Whether to print the result of the script's `main` as JSON.
+    pub structured_main: bool,
+    /// The template expressions and loop scripts are put in, instead of the built-in template for their mode.
+    pub template: Option<&'a str>,
+    /// An item making a crate the global allocator, added to script files, or to the prelude of other inputs.
+    pub allocator: Option<&'a str>,
+}
+
 /**
 Splits input into a complete Cargo manifest and unadultered Rust source.
 
 Unless we have prelude items to inject, in which case it will be *slightly* adulterated.
 */
-#[allow(clippy::too_many_arguments)]
 pub fn split_input(
     input: &Input,
     base_path: &Path,
-    deps: &[(String, String)],
-    prelude_items: &[String],
     package_path: impl AsRef<Path>,
     bin_name: &str,
     script_name: &str,
-    toolchain: Option<String>,
+    options: SplitOptions,
 ) -> MainResult<(String, PathBuf, Option<String>)> {
-    fn contains_main_method(source: &str) -> bool {
-        let re_main: Regex =
-            Regex::new(r#"(?m)^ *(pub )?(async )?(extern "C" )?fn main *\("#).unwrap();
-        re_main.is_match(source)
-    }
-
+    let SplitOptions {
+        deps,
+        build_deps,
+        dev_deps,
+        patches,
+        external_manifest,
+        prelude_items,
+        toolchain,
+        preprocessed,
+        structured_main,
+        template,
+        allocator,
+    } = options;
     let source_in_package = package_path.as_ref().join(script_name);
+    let async_main = match input {
+        Input::File(_, _, content, _) | Input::Stdin(content, _) => {
+            !structured_main && needs_async_main_shim(strip_shebang(content))
+        }
+        _ => false,
+    };
+    let without_frontmatter;
     let (part_mani, source_path, source, template, sub_prelude) = match input {
-        Input::File(_, path, content, _) => {
+        Input::File(_, _, content, _) | Input::Stdin(content, _) => {
             assert_eq!(prelude_items.len(), 0);
             let content = strip_shebang(content);
-            let (manifest, source) =
-                find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content));
+            // A frontmatter manifest isn't Rust, so the script is compiled from a copy in which
+            // it's blanked out.
+            let frontmatter = split_frontmatter(content);
+            let has_frontmatter = frontmatter.is_some();
+            let (manifest, content) = match frontmatter {
+                Some((manifest, rest)) => {
+                    without_frontmatter = rest;
+                    (Some(Manifest::Toml(manifest)), &without_frontmatter[..])
+                }
+                None => (None, content),
+            };
+            let (manifest, source) = match manifest {
+                Some(manifest) => (manifest, content),
+                None => find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content)),
+            };
 
-            if contains_main_method(content) {
-                (manifest, path.clone(), source.to_string(), None, false)
+            if structured_main {
+                if !contains_main_method(content) {
+                    return Err("--structured-main requires the script to have a `fn main`".into());
+                }
+                (
+                    manifest,
+                    source_in_package,
+                    source.to_string(),
+                    Some(consts::FILE_STRUCTURED_MAIN_TEMPLATE),
+                    false,
+                )
+            } else if async_main {
+                // Nothing would run the future `main` returns, so run it on a runtime.
+                (
+                    manifest,
+                    source_in_package,
+                    source.to_string(),
+                    Some(consts::FILE_ASYNC_MAIN_TEMPLATE),
+                    false,
+                )
+            } else if contains_main_method(content)
+                && (preprocessed
+                    || allocator.is_some()
+                    || has_frontmatter
+                    || input.is_markdown()
+                    || input.path().is_none())
+            {
+                // The original file isn't what should be compiled, or there is none.
+                (
+                    manifest,
+                    source_in_package,
+                    source.to_string(),
+                    Some(consts::FILE_PREPROCESSED_TEMPLATE),
+                    false,
+                )
+            } else if let Some(path) = input.path().filter(|_| contains_main_method(content)) {
+                (manifest, path.into(), source.to_string(), None, false)
             } else {
                 (
                     manifest,
@@ -57,31 +148,65 @@ pub fn split_input(
                 )
             }
         }
-        Input::Expr(content, _) => (
+        Input::Expr(content, block, output, _) => (
+            Manifest::Toml(""),
+            source_in_package,
+            content.to_string(),
+            Some(template.unwrap_or(match output {
+                _ if *block => consts::EXPR_BLOCK_TEMPLATE,
+                Output::Debug => consts::EXPR_TEMPLATE,
+                Output::Json => consts::EXPR_JSON_TEMPLATE,
+                Output::ExitStatus => consts::EXPR_TEST_TEMPLATE,
+            })),
+            true,
+        ),
+        Input::Csv(content, _) => (
+            Manifest::Toml(""),
+            source_in_package,
+            content.to_string(),
+            Some(template.unwrap_or(consts::CSV_TEMPLATE)),
+            true,
+        ),
+        Input::ParallelMap(content, _) => (
+            Manifest::Toml(""),
+            source_in_package,
+            content.to_string(),
+            Some(template.unwrap_or(consts::PARALLEL_MAP_TEMPLATE)),
+            true,
+        ),
+        Input::Json(content, _) => (
             Manifest::Toml(""),
             source_in_package,
             content.to_string(),
-            Some(consts::EXPR_TEMPLATE),
+            Some(template.unwrap_or(consts::JSON_TEMPLATE)),
             true,
         ),
-        Input::Loop(content, count, _) => (
+        Input::Loop(content, count, chunk_lines, _) => (
             Manifest::Toml(""),
             source_in_package,
             content.to_string(),
-            Some(if *count {
+            Some(template.unwrap_or(if chunk_lines.is_some() {
+                consts::LOOP_CHUNK_TEMPLATE
+            } else if *count {
                 consts::LOOP_COUNT_TEMPLATE
             } else {
                 consts::LOOP_TEMPLATE
-            }),
+            })),
             true,
         ),
     };
 
     let mut prelude_str;
-    let mut subs = HashMap::with_capacity(2);
+    let chunk_lines_str;
+    let mut subs = HashMap::with_capacity(3);
 
     subs.insert(consts::SCRIPT_BODY_SUB, &source[..]);
 
+    if let Input::Loop(_, _, Some(chunk_lines), _) = input {
+        chunk_lines_str = chunk_lines.to_string();
+        subs.insert(consts::CHUNK_LINES_SUB, &chunk_lines_str[..]);
+    }
+
     if sub_prelude {
         prelude_str =
             String::with_capacity(prelude_items.iter().map(|i| i.len() + 1).sum::<usize>());
@@ -92,10 +217,22 @@ pub fn split_input(
         subs.insert(consts::SCRIPT_PRELUDE_SUB, &prelude_str[..]);
     }
 
-    let source = template
+    let mut source = template
         .map(|template| templates::expand(template, &subs))
         .transpose()?;
-    let part_mani = part_mani.into_toml()?;
+    if let (Some(source), Some(allocator)) = (&mut source, allocator.filter(|_| !sub_prelude)) {
+        source.push_str(allocator);
+    }
+    let mut part_mani = match external_manifest {
+        Some(external_mani) => merge_manifest(external_mani, part_mani.into_toml()?)?,
+        None => part_mani.into_toml()?,
+    };
+    check_manifest_version(&mut part_mani)?;
+    if let Input::File(_, path, _, _) = input {
+        if let Some(build_script) = build_script_path(path) {
+            set_default_build_script(&mut part_mani, &build_script)?;
+        }
+    }
     info!("part_mani: {:?}", part_mani);
     info!("source: {:?}", source);
 
@@ -109,10 +246,39 @@ pub fn split_input(
 
     // It's-a mergin' time!
     let def_mani = default_manifest(bin_name, source_path_from_package, toolchain);
-    let dep_mani = deps_manifest(deps)?;
+    // Most packages have neither build- nor dev-dependencies, and don't get empty tables for them.
+    let mut dep_manis = vec![deps_manifest("dependencies", deps)?];
+    for (section, deps) in [
+        ("build-dependencies", build_deps),
+        ("dev-dependencies", dev_deps),
+    ] {
+        if !deps.is_empty() {
+            dep_manis.push(deps_manifest(section, deps)?);
+        }
+    }
+    let dep_manis = dep_manis
+        .into_iter()
+        .map(|dep_mani| unify_deps(dep_mani, &part_mani))
+        .collect::<MainResult<Vec<_>>>()?;
+
+    // The dependencies of the structured main template can be overridden by the script.
+    let def_mani = if structured_main {
+        let structured_mani = toml::from_str(consts::STRUCTURED_MAIN_MANIFEST)
+            .map_err(|e| MainError::Other(Box::new(e)))?;
+        merge_manifest(def_mani, structured_mani)?
+    } else if async_main {
+        let async_mani = toml::from_str(consts::ASYNC_MAIN_MANIFEST)
+            .map_err(|e| MainError::Other(Box::new(e)))?;
+        merge_manifest(def_mani, async_mani)?
+    } else {
+        def_mani
+    };
 
-    let mani = merge_manifest(def_mani, part_mani)?;
-    let mani = merge_manifest(mani, dep_mani)?;
+    let mut mani = merge_manifest(def_mani, part_mani)?;
+    for dep_mani in dep_manis {
+        mani = merge_manifest(mani, dep_mani)?;
+    }
+    add_patches(&mut mani, patches)?;
 
     // Fix up relative paths.
     let mani = fix_manifest_paths(mani, base_path)?;
@@ -134,18 +300,15 @@ strip = true
 fn test_split_input() {
     let bin_name = "binary-name".to_string();
     let script_name = "main.rs".to_string();
-    let toolchain = None;
     macro_rules! si {
         ($i:expr) => {
             split_input(
                 &$i,
                 &$i.base_path(),
-                &[],
-                &[],
                 "/package",
                 &bin_name,
                 &script_name,
-                toolchain.clone(),
+                SplitOptions::default(),
             )
             .ok()
         };
@@ -242,12 +405,13 @@ version = "0.1.0""#,
         split_input(
             &f(r#"fn main() {}"#),
             f(r#"fn main() {}"#).base_path(),
-            &[],
-            &[],
             "",
             &bin_name,
             "main.rs",
-            Some("stable".to_string())
+            SplitOptions {
+                toolchain: Some("stable".to_string()),
+                ..Default::default()
+            },
         )
         .ok(),
         r!(
@@ -448,12 +612,253 @@ fn main() -> Result<(), Box<dyn std::error::Error+Sync+Send>> {
             )
         )
     );
+
+    // A script from stdin has no file of its own to compile, so it's always written to the package.
+    assert_eq!(
+        si!(Input::Stdin(r#"fn main() {}"#.into(), "/dummy".into())),
+        r!(
+            format!(
+                "{}{}",
+                r#"[[bin]]
+name = "binary-name"
+path = "main.rs"
+
+[dependencies]
+
+[package]
+authors = ["Anonymous"]
+edition = "2021"
+name = "binary-name"
+version = "0.1.0""#,
+                STRIP_SECTION
+            ),
+            "/package/main.rs",
+            Some("fn main() {}".to_string())
+        )
+    );
+
+    let (_, source_path, source) = si!(Input::Json(r#"json["name"]"#.into(), "/dummy".into()))
+        .expect("--json input should split");
+    assert_eq!(source_path, PathBuf::from("/package/main.rs"));
+    assert!(source.unwrap().contains(r#"let output = {json["name"]};"#));
+
+    let (_, _, source) = si!(Input::Loop(
+        "|chunk| chunk.len()".into(),
+        false,
+        Some(1000),
+        "/dummy".into()
+    ))
+    .expect("--chunk-lines input should split");
+    assert!(source.unwrap().contains("const CHUNK_LINES: usize = 1000;"));
+
+    let allocator = consts::ALLOCATORS[1].2;
+    let (mani, path, source) = split_input(
+        &f("fn main() {}"),
+        Path::new("/dummy"),
+        "/package",
+        &bin_name,
+        &script_name,
+        SplitOptions {
+            deps: &[("regex".into(), DepSpec::version("1"))],
+            build_deps: &[("cc".into(), DepSpec::version("1"))],
+            dev_deps: &[("proptest".into(), DepSpec::version("1"))],
+            patches: &[Patch::parse("crates-io:regex=path:../regex").unwrap()],
+            external_manifest: Some(
+                toml::from_str("[dependencies]\nregex = \"1\"\nlog = \"0.4\"").unwrap(),
+            ),
+            allocator: Some(allocator),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(path, Path::new("/package/main.rs"));
+    assert_eq!(source.unwrap(), format!("fn main() {{}}{}", allocator));
+    assert!(
+        mani.contains("[dependencies]\nlog = \"0.4\"\n\n[dependencies.regex]\nversion = \"1\"\n")
+    );
+    assert!(mani.contains("[build-dependencies]\ncc = \"1\"\n"));
+    assert!(mani.contains("[dev-dependencies]\nproptest = \"1\"\n"));
+    assert!(mani.contains("[patch.crates-io.regex]\npath = \"/dummy/../regex\"\n"));
+}
+
+/**
+Returns `true` if the source has a `fn main` of its own, and so shouldn't be wrapped in one.
+*/
+pub fn contains_main_method(source: &str) -> bool {
+    let re_main: Regex = Regex::new(r#"(?m)^ *(pub )?(async )?(extern "C" )?fn main *\("#).unwrap();
+    re_main.is_match(source)
+}
+
+/**
+Returns `true` if the source has an `async fn main` without an attribute like `#[tokio::main]` to run it, so it needs a synchronous `main` around it.
+*/
+pub fn needs_async_main_shim(source: &str) -> bool {
+    let re_async_main = Regex::new(r"(?m)^ *(pub )?async fn main *\(").unwrap();
+    let re_main_attr = Regex::new(r"(?m)^\s*#\[\s*[A-Za-z_][A-Za-z0-9_:]*::main\b").unwrap();
+    re_async_main.is_match(source) && !re_main_attr.is_match(source)
+}
+
+/**
+Returns the build script of a script file, which is the file next to it named like it but ending in `.build.rs`, if there is one.
+
+For example, the build script of `gen.rs` is `gen.build.rs`.
+*/
+pub fn build_script_path(script_path: &Path) -> Option<PathBuf> {
+    let mut name = script_path.file_stem()?.to_os_string();
+    name.push(".build.rs");
+    let path = script_path.with_file_name(name);
+    path.is_file().then_some(path)
+}
+
+// Use the build script, unless the script's manifest has a build script of its own (or none, with
+// `build = false`).
+fn set_default_build_script(mani: &mut toml::value::Table, build_script: &Path) -> MainResult<()> {
+    let build_script = build_script
+        .to_str()
+        .ok_or_else(|| format!("Unable to stringify {build_script:?}"))?;
+    if let toml::Value::Table(package) = mani
+        .entry("package")
+        .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+    {
+        package
+            .entry("build")
+            .or_insert_with(|| toml::Value::String(build_script.into()));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_build_script() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("gen.rs");
+    assert_eq!(build_script_path(&script), None);
+    std::fs::write(dir.path().join("gen.build.rs"), "fn main() {}").unwrap();
+    assert_eq!(
+        build_script_path(&script),
+        Some(dir.path().join("gen.build.rs"))
+    );
+
+    let mut mani = toml::value::Table::new();
+    set_default_build_script(&mut mani, Path::new("/a/gen.build.rs")).unwrap();
+    assert_eq!(mani["package"]["build"].as_str(), Some("/a/gen.build.rs"));
+    let mut mani: toml::value::Table = toml::from_str("[package]\nbuild = false").unwrap();
+    set_default_build_script(&mut mani, Path::new("/a/gen.build.rs")).unwrap();
+    assert_eq!(mani["package"]["build"].as_bool(), Some(false));
+}
+
+/**
+Returns `true` if the code awaits a future, and so has to be run on an async runtime.
+*/
+pub fn uses_await(source: &str) -> bool {
+    let re_await = Regex::new(r"\.\s*await\b").unwrap();
+    re_await.is_match(source)
+}
+
+#[test]
+fn test_async_detection() {
+    assert!(needs_async_main_shim("async fn main() {}"));
+    assert!(!needs_async_main_shim("#[tokio::main]\nasync fn main() {}"));
+    assert!(!needs_async_main_shim(
+        "#[async_std::main]\nasync fn main() {}"
+    ));
+    assert!(!needs_async_main_shim("fn main() {}"));
+    assert!(uses_await("reqwest::get(url).await?.text().await"));
+    assert!(!uses_await("awaited.len()"));
+}
+
+/**
+Returns `true` if the script (with any shebang already stripped) has an embedded manifest that will be picked up.
+*/
+pub fn has_embedded_manifest(s: &str) -> bool {
+    find_frontmatter(s).is_some() || find_embedded_manifest(s).is_some()
+}
+
+/**
+Locates a frontmatter manifest, as used by Cargo's own support for scripts: a block at the top of the script (with any shebang already stripped) between two fences of the same number of `-`, the first of which may be followed by `cargo`.  The older form of fences of backticks followed by `cargo` is supported as well.
+
+Returns the byte ranges of the whole block, including the fences, and of the manifest inside it.
+*/
+fn find_frontmatter(s: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let mut offset = 0;
+    let mut lines = s.split_inclusive('\n');
+
+    // Only blank lines may come before the opening fence.
+    let (start, fence) = loop {
+        let line = lines.next()?;
+        let text = line.trim_end();
+        if text.is_empty() {
+            offset += line.len();
+            continue;
+        }
+        let fence_len = |c: char| text.len() - text.trim_start_matches(c).len();
+        let (fence, info) = match (fence_len('-'), fence_len('`')) {
+            (dashes, _) if dashes >= 3 => (&text[..dashes], text[dashes..].trim()),
+            (_, ticks) if ticks >= 3 && text[ticks..].trim().eq_ignore_ascii_case("cargo") => {
+                (&text[..ticks], "cargo")
+            }
+            _ => return None,
+        };
+        if !(info.is_empty() || info == "cargo") {
+            return None;
+        }
+        let start = offset;
+        offset += line.len();
+        break (start, fence);
+    };
+
+    let manifest_start = offset;
+    for line in lines {
+        if line.trim_end() == fence {
+            return Some((start..offset + line.len(), manifest_start..offset));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/**
+Splits a frontmatter manifest off the script (with any shebang already stripped).
+
+Returns the manifest, and the script with the frontmatter replaced by blank lines, so that lines are numbered the same as in the original.
+*/
+fn split_frontmatter(s: &str) -> Option<(&str, String)> {
+    let (block, manifest) = find_frontmatter(s)?;
+    let blank = "\n".repeat(s[block.clone()].matches('\n').count());
+    Some((
+        &s[manifest],
+        format!("{}{}{}", &s[..block.start], blank, &s[block.end..]),
+    ))
+}
+
+#[test]
+fn test_frontmatter() {
+    let script = "\n---cargo\n[dependencies]\nregex = \"1\"\n---\n\nfn main() {}\n";
+    assert_eq!(
+        split_frontmatter(script),
+        Some((
+            "[dependencies]\nregex = \"1\"\n",
+            "\n\n\n\n\n\nfn main() {}\n".to_string()
+        ))
+    );
+    assert_eq!(
+        split_frontmatter("```cargo\n[dependencies]\n```\nfn main() {}\n")
+            .map(|(manifest, _)| manifest),
+        Some("[dependencies]\n")
+    );
+    assert_eq!(
+        split_frontmatter("----\n[dependencies]\n---\n----\n").map(|(manifest, _)| manifest),
+        Some("[dependencies]\n---\n")
+    );
+    assert_eq!(split_frontmatter("---\nfn main() {}\n"), None);
+    assert_eq!(split_frontmatter("---toml\n---\n"), None);
+    assert_eq!(split_frontmatter("```\n[dependencies]\n```\n"), None);
+    assert_eq!(split_frontmatter("fn main() {}\n---\n---\n"), None);
 }
 
 /**
 Returns a slice of the input string with the leading shebang, if there is one, omitted.
 */
-fn strip_shebang(s: &str) -> &str {
+pub fn strip_shebang(s: &str) -> &str {
     let re_shebang: Regex = Regex::new(r"^#![^\[].*?(\r\n|\n)").unwrap();
     match re_shebang.find(s) {
         Some(m) => &s[m.end()..],
@@ -726,6 +1131,186 @@ fn main() {}
     );
 }
 
+/**
+Returns the manifest embedded in a script, as written in the script.
+
+Short comment manifests are returned converted to TOML.
+*/
+pub fn extract_manifest(s: &str) -> MainResult<Option<String>> {
+    if let Some((manifest, _)) = split_frontmatter(strip_shebang(s)) {
+        return Ok(Some(manifest.to_string()));
+    }
+    match find_embedded_manifest(strip_shebang(s)) {
+        Some((Manifest::TomlOwned(manifest), _)) => Ok(Some(manifest)),
+        Some((manifest, _)) => Ok(Some(format!("{}", manifest.into_toml()?))),
+        None => Ok(None),
+    }
+}
+
+/**
+Replaces the manifest embedded in a script, keeping the comment style and everything else in the script intact.
+
+If the script has no embedded manifest, one is added as a `//!` doc comment at the top of the script (after the shebang, if any).
+*/
+pub fn replace_manifest(s: &str, manifest: &str) -> MainResult<String> {
+    let line_ending = if s.contains("\r\n") { "\r\n" } else { "\n" };
+    let prefixed_lines = |prefix: &str| {
+        let mut r = String::new();
+        for line in manifest.lines() {
+            if line.is_empty() {
+                r.push_str(prefix.trim_end());
+            } else {
+                r.push_str(prefix);
+                r.push_str(line);
+            }
+            r.push_str(line_ending);
+        }
+        r
+    };
+
+    let body = strip_shebang(s);
+    if let Some((_, range)) = find_frontmatter(body) {
+        let offset = s.len() - body.len();
+        return Ok(format!(
+            "{}{}{}",
+            &s[..offset + range.start],
+            prefixed_lines(""),
+            &s[offset + range.end..]
+        ));
+    }
+
+    if let Some((range, prefix)) = find_manifest_block(s) {
+        return Ok(format!(
+            "{}{}{}",
+            &s[..range.start],
+            prefixed_lines(&prefix),
+            &s[range.end..]
+        ));
+    }
+
+    if has_embedded_manifest(strip_shebang(s)) {
+        return Err(
+            "the embedded manifest can only be replaced if it is a `cargo` code block on lines of its own"
+                .into(),
+        );
+    }
+
+    let shebang = &s[..s.len() - body.len()];
+    Ok(format!(
+        "{}//! ```cargo{}{}//! ```{}{}",
+        shebang,
+        line_ending,
+        prefixed_lines("//! "),
+        line_ending,
+        body
+    ))
+}
+
+/**
+Locates the contents of the `cargo` code block of an embedded manifest.
+
+Returns the byte range of the lines between the fences, and the comment prefix of the opening fence line (e.g. `//! ` or ` * `).
+*/
+fn find_manifest_block(s: &str) -> Option<(std::ops::Range<usize>, String)> {
+    if !matches!(
+        find_embedded_manifest(strip_shebang(s)),
+        Some((Manifest::TomlOwned(_), _))
+    ) {
+        return None;
+    }
+
+    let re_open = Regex::new(r"(?i)^(\s*(//[!/]|\*)?\s*)```\s*cargo\s*$").unwrap();
+    let re_close = Regex::new(r"^\s*(//[!/]|\*)?\s*```\s*$").unwrap();
+
+    let mut offset = 0;
+    let mut block: Option<(usize, String)> = None;
+    for line in s.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        match &block {
+            None => {
+                if let Some(m) = re_open.captures(text).and_then(|cap| cap.get(1)) {
+                    block = Some((offset + line.len(), m.as_str().to_string()));
+                }
+            }
+            Some((start, prefix)) => {
+                if re_close.is_match(text) {
+                    return Some((*start..offset, prefix.clone()));
+                }
+            }
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[test]
+fn test_replace_manifest() {
+    assert_eq!(
+        replace_manifest(
+            r#"#!/usr/bin/env rust-script
+//! A script.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+fn main() {}
+"#,
+            "[dependencies]\ntime = \"0.3\"\n\n[features]\n"
+        )
+        .unwrap(),
+        r#"#!/usr/bin/env rust-script
+//! A script.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.3"
+//!
+//! [features]
+//! ```
+fn main() {}
+"#
+    );
+
+    assert_eq!(
+        replace_manifest(
+            r#"/*!
+ * ```Cargo
+ * [dependencies]
+ * time = "0.1.25"
+ * ```
+ */
+fn main() {}
+"#,
+            "[dependencies]\nlibc = \"0.2\"\n"
+        )
+        .unwrap(),
+        r#"/*!
+ * ```Cargo
+ * [dependencies]
+ * libc = "0.2"
+ * ```
+ */
+fn main() {}
+"#
+    );
+
+    assert_eq!(
+        replace_manifest(
+            "#!/usr/bin/env rust-script\nfn main() {}\n",
+            "[dependencies]\nlibc = \"0.2\"\n"
+        )
+        .unwrap(),
+        "#!/usr/bin/env rust-script\n//! ```cargo\n//! [dependencies]\n//! libc = \"0.2\"\n//! ```\nfn main() {}\n"
+    );
+
+    assert!(replace_manifest(
+        "// cargo-deps: time=\"0.1.25\"\nfn main() {}\n",
+        "[dependencies]\n"
+    )
+    .is_err());
+}
+
 /**
 Locates a "short comment manifest" in Rust source.
 */
@@ -1142,6 +1727,108 @@ time = "*"
     );
 }
 
+/**
+Checks, and removes, the `rust-script-manifest-version` key of an embedded manifest.
+
+If the manifest is written for a newer version of the schema than this version of `rust-script` understands, a warning listing the keys it doesn't know about is printed, rather than ignoring them silently.
+*/
+fn check_manifest_version(manifest: &mut toml::value::Table) -> MainResult<()> {
+    let version = match manifest.remove(consts::MANIFEST_VERSION_KEY) {
+        None => return Ok(()),
+        Some(toml::Value::Integer(version)) if version >= 1 => version,
+        Some(_) => {
+            return Err(format!(
+                "`{}` must be a positive integer",
+                consts::MANIFEST_VERSION_KEY
+            )
+            .into())
+        }
+    };
+
+    if version > consts::MANIFEST_VERSION {
+        let unknown_keys: Vec<&str> = manifest
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !consts::KNOWN_MANIFEST_KEYS.contains(key))
+            .collect();
+        eprintln!(
+            "warning: the script's manifest is version {}, but this version of rust-script only understands version {}; upgrade rust-script",
+            version,
+            consts::MANIFEST_VERSION
+        );
+        if !unknown_keys.is_empty() {
+            eprintln!(
+                "warning: these manifest keys are not understood: {}",
+                unknown_keys.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_manifest_version() {
+    let check = |s: &str| {
+        let mut manifest: toml::value::Table = toml::from_str(s).unwrap();
+        check_manifest_version(&mut manifest).map(|()| manifest)
+    };
+
+    let manifest =
+        check("rust-script-manifest-version = 1\n[dependencies]\ntime = \"0.1\"").unwrap();
+    assert_eq!(manifest.keys().collect::<Vec<_>>(), vec!["dependencies"]);
+    let manifest = check("rust-script-manifest-version = 99\nscripts = 1").unwrap();
+    assert_eq!(manifest.keys().collect::<Vec<_>>(), vec!["scripts"]);
+    assert!(check("rust-script-manifest-version = \"1\"").is_err());
+    assert!(check("rust-script-manifest-version = 0").is_err());
+}
+
+/**
+Returns the source path of the binary in a generated manifest, as written in it.
+*/
+pub fn bin_source_path(manifest: &str) -> Option<String> {
+    let manifest: toml::value::Table = toml::from_str(manifest).ok()?;
+    let bin = manifest.get("bin")?.as_array()?.first()?;
+    Some(bin.get("path")?.as_str()?.to_string())
+}
+
+/**
+Returns the path of the build script in a generated manifest, as written in it.
+*/
+pub fn build_script_source_path(manifest: &str) -> Option<String> {
+    let manifest: toml::value::Table = toml::from_str(manifest).ok()?;
+    Some(manifest.get("package")?.get("build")?.as_str()?.to_string())
+}
+
+/**
+Replaces the source path of the binary in a generated manifest.
+*/
+pub fn set_bin_source_path(manifest: &str, path: &str) -> MainResult<String> {
+    let mut manifest: toml::value::Table =
+        toml::from_str(manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+    let bin = manifest
+        .get_mut("bin")
+        .and_then(|bins| bins.as_array_mut())
+        .and_then(|bins| bins.first_mut())
+        .and_then(|bin| bin.as_table_mut())
+        .ok_or("the generated manifest has no binary")?;
+    bin.insert("path".into(), path.into());
+    Ok(manifest.to_string())
+}
+
+#[test]
+fn test_bin_source_path() {
+    let manifest = format!("{}", default_manifest("n_123", "/path/to/script.rs", None));
+    assert_eq!(
+        bin_source_path(&manifest),
+        Some("/path/to/script.rs".into())
+    );
+    assert_eq!(bin_source_path("[package]\nname = \"n\""), None);
+
+    let manifest = set_bin_source_path(&manifest, "../script.rs").unwrap();
+    assert_eq!(bin_source_path(&manifest), Some("../script.rs".into()));
+    assert!(set_bin_source_path("[package]\nname = \"n\"", "script.rs").is_err());
+}
+
 /**
 Generates a default Cargo manifest for the given input.
 */
@@ -1219,24 +1906,73 @@ fn default_manifest(
 }
 
 /**
-Generates a partial Cargo manifest containing the specified dependencies.
+Generates the manifest of a library package for a script that another script depends on.
+
+The script itself is the library, and its embedded manifest, if it has one, is merged in.
+*/
+pub fn library_manifest(
+    name: &str,
+    script_path: &Path,
+    content: &str,
+) -> MainResult<toml::value::Table> {
+    let content = strip_shebang(content);
+    let (manifest, _) = find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content));
+    let mut part_mani = manifest.into_toml()?;
+    check_manifest_version(&mut part_mani)?;
+
+    let lib_path = script_path
+        .to_str()
+        .ok_or_else(|| format!("Unable to stringify {script_path:?}"))?;
+    let mut lib_map = toml::map::Map::new();
+    lib_map.insert(
+        "path".to_string(),
+        toml::value::Value::String(lib_path.to_string()),
+    );
+
+    // Cargo ignores the profiles of dependencies, with a warning.
+    let mut def_mani = default_manifest(name, lib_path, None);
+    def_mani.remove("bin");
+    def_mani.remove("profile");
+    def_mani.insert("lib".to_string(), toml::value::Value::Table(lib_map));
+
+    let mani = merge_manifest(def_mani, part_mani)?;
+    let base = script_path
+        .parent()
+        .ok_or("The script dependency should be in a directory")?;
+    fix_manifest_paths(mani, base)
+}
+
+/**
+Loads a manifest kept in a file of its own, as given with `--manifest`, to be merged with the script's.
+
+Relative paths in it are relative to the file, wherever the script is.
 */
-fn deps_manifest(deps: &[(String, String)]) -> MainResult<toml::value::Table> {
-    let mut mani_str = String::new();
-    mani_str.push_str("[dependencies]\n");
+pub fn load_external_manifest(path: &Path) -> MainResult<toml::value::Table> {
+    let usage = |err: MainError| {
+        MainError::with_kind(
+            FailureKind::Usage,
+            MainError::Tag(
+                format!("could not load manifest {}", path.display()).into(),
+                Box::new(err),
+            ),
+        )
+    };
+    let content = std::fs::read_to_string(path).map_err(|err| usage(err.into()))?;
+    let mani = toml::from_str(&content).map_err(|err| usage(MainError::Other(Box::new(err))))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    fix_manifest_paths(mani, &std::env::current_dir()?.join(base))
+}
 
-    for (name, ver) in deps {
+/**
+Generates a partial Cargo manifest containing the specified dependencies, in the given section.
+*/
+fn deps_manifest(section: &str, deps: &[(String, DepSpec)]) -> MainResult<toml::value::Table> {
+    let mut mani_str = format!("[{}]\n", section);
+
+    for (name, spec) in deps {
         mani_str.push_str(name);
         mani_str.push('=');
-
-        // We only want to quote the version if it *isn't* a table.
-        let quotes = match ver.starts_with('{') {
-            true => "",
-            false => "\"",
-        };
-        mani_str.push_str(quotes);
-        mani_str.push_str(ver);
-        mani_str.push_str(quotes);
+        mani_str.push_str(&spec.to_toml());
         mani_str.push('\n');
     }
 
@@ -1248,6 +1984,243 @@ fn deps_manifest(deps: &[(String, String)]) -> MainResult<toml::value::Table> {
     })
 }
 
+/**
+Adds patches given on the command line to the manifest, in the `[patch]` section for their source.
+
+Unlike the dependencies, which are merged with those of the script, a patch replaces any for the same crate in the manifest, since a crate can only be patched with one thing.
+*/
+fn add_patches(mani: &mut toml::value::Table, patches: &[Patch]) -> MainResult<()> {
+    for patch in patches {
+        let entry = deps_manifest("patch", &[(patch.name.clone(), patch.spec.clone())])?
+            .remove("patch")
+            .and_then(|entries| entries.as_table()?.get(&patch.name).cloned())
+            .expect("deps_manifest generates the section it was given");
+        mani.entry("patch")
+            .or_insert_with(|| toml::value::Table::new().into())
+            .as_table_mut()
+            .and_then(|sources| {
+                sources
+                    .entry(&patch.source)
+                    .or_insert_with(|| toml::value::Table::new().into())
+                    .as_table_mut()
+            })
+            .ok_or("the script's manifest has a `patch` that isn't a table of tables")?
+            .insert(patch.name.clone(), entry);
+    }
+    Ok(())
+}
+
+/**
+Unifies the dependencies given on the command line, as generated by `deps_manifest`, with the dependencies of the same names in the script's manifest.
+
+Each unified dependency has everything either of them asks for, with the features of both and both version requirements, so that the command line doesn't silently override the manifest.  If they ask for versions no release can satisfy at once, or different sources or the like, it's an error naming both, rather than leaving Cargo to report a problem without saying where it came from.
+*/
+fn unify_deps(
+    mut dep_mani: toml::value::Table,
+    part_mani: &toml::value::Table,
+) -> MainResult<toml::value::Table> {
+    for (section, deps) in dep_mani.iter_mut() {
+        let (Some(deps), Some(script_deps)) = (
+            deps.as_table_mut(),
+            part_mani.get(section).and_then(|deps| deps.as_table()),
+        ) else {
+            continue;
+        };
+        for (name, dep) in deps.iter_mut() {
+            let Some(script_dep) = script_deps.get(name) else {
+                continue;
+            };
+            let mut unified = dep_as_table(script_dep);
+            for (key, value) in dep_as_table(dep) {
+                match unified.get_mut(&key) {
+                    None => {
+                        unified.insert(key, value);
+                    }
+                    Some(script_value) if *script_value == value => {}
+                    Some(toml::Value::Array(script_features)) if key == "features" => {
+                        for feature in value.as_array().into_iter().flatten() {
+                            if !script_features.contains(feature) {
+                                script_features.push(feature.clone());
+                            }
+                        }
+                    }
+                    Some(script_value) => {
+                        *script_value = unify_versions(&key, script_value, &value).ok_or_else(|| {
+                            MainError::with_kind(
+                                FailureKind::Usage,
+                                format!(
+                                    "conflicting requirements for `{}` in [{}]: `{} = {}` on the command line, but `{} = {}` in the script's manifest",
+                                    name, section, key, value, key, script_value
+                                ),
+                            )
+                        })?;
+                    }
+                }
+            }
+            *dep = toml::Value::Table(unified);
+        }
+    }
+    Ok(dep_mani)
+}
+
+// Combines two version requirements into one that asks for both, as in `1, 1.2`, unless no version
+// can satisfy both.  Other keys can't be combined.
+fn unify_versions(key: &str, a: &toml::Value, b: &toml::Value) -> Option<toml::Value> {
+    if key != "version" {
+        return None;
+    }
+    let (a, b) = (a.as_str()?, b.as_str()?);
+    let ranges = [a, b]
+        .iter()
+        .map(|req| semver::VersionReq::parse(req).ok())
+        .collect::<Option<Vec<_>>>()?
+        .iter()
+        .flat_map(|req| &req.comparators)
+        .map(comparator_range)
+        .collect::<Option<Vec<_>>>();
+    // Leave requirements on pre-releases, which don't fit the ranges, for Cargo to resolve.
+    if let Some(ranges) = ranges {
+        let start = ranges
+            .iter()
+            .map(|(start, _)| *start)
+            .max()
+            .unwrap_or((0, 0, 0));
+        let end = ranges.iter().filter_map(|(_, end)| *end).min();
+        if end.is_some_and(|end| end <= start) {
+            return None;
+        }
+    }
+    Some(format!("{}, {}", a, b).into())
+}
+
+// A version as `(major, minor, patch)`.
+type Triple = (u64, u64, u64);
+
+// The versions a comparator matches, as a half-open range, with no end if there's no upper bound.
+// None for a comparator on a pre-release.
+fn comparator_range(comparator: &semver::Comparator) -> Option<(Triple, Option<Triple>)> {
+    use semver::Op;
+
+    if !comparator.pre.is_empty() {
+        return None;
+    }
+    let major = comparator.major;
+    let (minor, patch) = (comparator.minor, comparator.patch);
+    let lowest = (major, minor.unwrap_or(0), patch.unwrap_or(0));
+    // The first version past the ones the comparator's version stands for: `1.2` stands for 1.2.*.
+    let past = match (minor, patch) {
+        (None, _) => (major + 1, 0, 0),
+        (Some(minor), None) => (major, minor + 1, 0),
+        (Some(minor), Some(patch)) => (major, minor, patch + 1),
+    };
+    let range = match comparator.op {
+        Op::Exact | Op::Wildcard => (lowest, Some(past)),
+        Op::Greater => (past, None),
+        Op::GreaterEq => (lowest, None),
+        Op::Less => ((0, 0, 0), Some(lowest)),
+        Op::LessEq => ((0, 0, 0), Some(past)),
+        Op::Tilde => match minor {
+            Some(minor) => (lowest, Some((major, minor + 1, 0))),
+            None => (lowest, Some((major + 1, 0, 0))),
+        },
+        Op::Caret => match (major, minor, patch) {
+            (0, Some(0), Some(patch)) => (lowest, Some((0, 0, patch + 1))),
+            (0, Some(minor), _) => (lowest, Some((0, minor + 1, 0))),
+            _ => (lowest, Some((major + 1, 0, 0))),
+        },
+        _ => ((0, 0, 0), None),
+    };
+    Some(range)
+}
+
+// A dependency as a table, with a plain version requirement as its `version`.  `*`, which is what
+// `--dep name` asks for, doesn't count as a requirement.
+fn dep_as_table(dep: &toml::Value) -> toml::value::Table {
+    let mut table = match dep {
+        toml::Value::Table(table) => table.clone(),
+        version => {
+            let mut table = toml::value::Table::new();
+            table.insert("version".into(), version.clone());
+            table
+        }
+    };
+    if table.get("version").and_then(|v| v.as_str()) == Some("*") {
+        table.remove("version");
+    }
+    table
+}
+
+#[test]
+fn test_unify_deps() {
+    let part_mani: toml::value::Table = toml::from_str(
+        r#"
+[dependencies]
+regex = "1"
+serde = { version = "1", features = ["derive"] }
+"#,
+    )
+    .unwrap();
+    let deps = |deps: &[(&str, &str)]| {
+        let deps: Vec<_> = deps
+            .iter()
+            .map(|(name, version)| (name.to_string(), DepSpec::version(*version)))
+            .collect();
+        unify_deps(deps_manifest("dependencies", &deps).unwrap(), &part_mani)
+    };
+
+    let unified = deps(&[
+        ("regex", "*"),
+        ("serde", "{ version = \"1\", features = [\"rc\"] }"),
+        ("rand", "0.8"),
+    ])
+    .unwrap();
+    let unified = unified["dependencies"].as_table().unwrap();
+    assert_eq!(unified["regex"]["version"].as_str(), Some("1"));
+    assert_eq!(
+        unified["serde"]["features"],
+        toml::Value::Array(vec!["derive".into(), "rc".into()])
+    );
+    assert_eq!(unified["rand"].as_str(), Some("0.8"));
+
+    let unified = deps(&[("regex", "1.10"), ("serde", ">=1.0.100, <2")]).unwrap();
+    let unified = unified["dependencies"].as_table().unwrap();
+    assert_eq!(unified["regex"]["version"].as_str(), Some("1, 1.10"));
+    assert_eq!(
+        unified["serde"]["version"].as_str(),
+        Some("1, >=1.0.100, <2")
+    );
+
+    let err = deps(&[("regex", "0.2")]).unwrap_err().to_string();
+    assert!(
+        err.contains("`version = \"0.2\"` on the command line"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("`version = \"1\"` in the script's manifest"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_unify_versions() {
+    let unify = |a: &str, b: &str| {
+        unify_versions("version", &a.into(), &b.into()).map(|v| v.as_str().unwrap().to_string())
+    };
+    assert_eq!(unify("1", "1.2").as_deref(), Some("1, 1.2"));
+    assert_eq!(unify("~1.2.3", "<1.2.5").as_deref(), Some("~1.2.3, <1.2.5"));
+    assert_eq!(unify("0.2", "0.2.5").as_deref(), Some("0.2, 0.2.5"));
+    assert_eq!(unify("1.0.0-beta", "1").as_deref(), Some("1.0.0-beta, 1"));
+    assert_eq!(unify("1", "0.2"), None);
+    assert_eq!(unify("0.1", "0.2"), None);
+    assert_eq!(unify("^0.0.3", "0.0.4"), None);
+    assert_eq!(unify("=1.2.3", ">1.2.3"), None);
+    assert_eq!(unify("<1.5", "1.5"), None);
+    assert_eq!(unify("1", "not a requirement"), None);
+    assert_eq!(unify_versions("git", &"a".into(), &"b".into()), None);
+}
+
 /**
 Given two Cargo manifests, merges the second *into* the first.
 
@@ -1298,10 +2271,16 @@ fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> MainResult<toml:
     // Values that need to be rewritten:
     let paths: &[&[&str]] = &[
         &["build-dependencies", "*", "path"],
+        &["build-dependencies", "*", "script"],
         &["dependencies", "*", "path"],
+        &["dependencies", "*", "script"],
         &["dev-dependencies", "*", "path"],
+        &["dev-dependencies", "*", "script"],
         &["package", "build"],
+        &["patch", "*", "*", "path"],
+        &["replace", "*", "path"],
         &["target", "*", "dependencies", "*", "path"],
+        &["target", "*", "dependencies", "*", "script"],
     ];
 
     let mut mani = toml::Value::Table(mani);
@@ -0,0 +1,396 @@
+/*!
+This module implements the `add` and `rm` subcommands: adding dependencies to the manifest embedded in a script, and removing them, as `cargo add` and `cargo remove` do for a package.
+
+The manifest is edited line by line rather than parsed and written out again, so its comments, formatting and order are kept; only the lines of the dependencies added, removed or pinned (by `pin`) change.  The rest of the script is left untouched, as with `--set-manifest`.
+*/
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::arguments::{AddCommand, RemoveCommand};
+use crate::dep_spec::DepSpec;
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::{literate, manifest};
+
+/**
+Runs the `add` subcommand.
+*/
+pub fn add(command: &AddCommand) -> MainResult<i32> {
+    let mut deps = Vec::new();
+    for dep in &command.deps {
+        let (name, mut spec) = DepSpec::parse(dep)?;
+        spec.add_features(&command.features);
+        if let Some(registry) = &command.registry {
+            spec.set_default_registry(registry);
+        }
+        deps.push((name, spec));
+    }
+
+    edit_script(&command.script, |mut manifest| {
+        for (name, spec) in &deps {
+            manifest = add_dependency(&manifest, command.section, name, &spec.to_toml());
+            println!("added {} to [{}]", name, command.section);
+        }
+        Ok(manifest)
+    })
+}
+
+/**
+Runs the `rm` subcommand.
+*/
+pub fn remove(command: &RemoveCommand) -> MainResult<i32> {
+    edit_script(&command.script, |mut manifest| {
+        for name in &command.names {
+            manifest = remove_dependency(&manifest, command.section, name).ok_or_else(|| {
+                MainError::with_kind(
+                    FailureKind::Usage,
+                    format!(
+                        "dependency '{}' not found in [{}] of {}",
+                        name, command.section, command.script
+                    ),
+                )
+            })?;
+            println!("removed {} from [{}]", name, command.section);
+        }
+        Ok(manifest)
+    })
+}
+
+/**
+Replaces the manifest embedded in the script with the one `edit` makes of it, if it succeeds and changes anything.
+*/
+pub fn edit_script(
+    script: &str,
+    edit: impl FnOnce(String) -> MainResult<String>,
+) -> MainResult<i32> {
+    let path = Path::new(script);
+    let is_markdown = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| literate::EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if is_markdown {
+        return Err(MainError::with_kind(
+            FailureKind::Usage,
+            "cannot change the manifest of a Markdown script",
+        ));
+    }
+
+    let body = fs::read_to_string(path).map_err(|err| {
+        MainError::Tag(
+            format!("could not read {}", path.display()).into(),
+            Box::new(err.into()),
+        )
+    })?;
+    let original = manifest::extract_manifest(&body)?.unwrap_or_default();
+    let manifest = edit(original.clone())?;
+    if manifest == original {
+        return Ok(0);
+    }
+    toml::from_str::<toml::value::Table>(&manifest).map_err(|e| {
+        MainError::Tag(
+            "could not parse the edited manifest".into(),
+            Box::new(MainError::Other(Box::new(e))),
+        )
+    })?;
+
+    crate::overwrite_file(path, &manifest::replace_manifest(&body, &manifest)?)?;
+    Ok(0)
+}
+
+/**
+Adds a dependency with the given value to a section of a manifest, such as `dependencies`, in place of any already there by that name.
+
+A new dependency goes after the last one in the section, which is added to the end of the manifest if there is none.
+*/
+pub fn add_dependency(manifest: &str, section: &str, name: &str, value: &str) -> String {
+    edit_dependency(
+        manifest,
+        section,
+        name,
+        Some(format!("{} = {}", name, value)),
+    )
+    .0
+}
+
+/**
+Removes a dependency from a section of a manifest.
+
+Returns `None` if there is no dependency by that name in the section.
+*/
+pub fn remove_dependency(manifest: &str, section: &str, name: &str) -> Option<String> {
+    match edit_dependency(manifest, section, name, None) {
+        (manifest, true) => Some(manifest),
+        (_, false) => None,
+    }
+}
+
+/**
+Sets the version requirement of a dependency in a section of a manifest, keeping the rest of it as it is.
+
+Returns `None` if there is no dependency by that name in the section, or it has no version requirement.
+*/
+pub fn set_dependency_version(
+    manifest: &str,
+    section: &str,
+    name: &str,
+    version: &str,
+) -> Option<String> {
+    let re_version = Regex::new(r#"(\bversion\s*=\s*)"[^"]*""#).unwrap();
+    let re_plain = Regex::new(r#"^(\s*[^=\s]+\s*=\s*)"[^"]*"(\s*(#.*)?)$"#).unwrap();
+    let quoted = toml::Value::String(version.into()).to_string();
+
+    let lines: Vec<&str> = manifest.lines().collect();
+    let found = find_dependency(&lines, section, name);
+    let mut set = false;
+    let mut edited = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let mut line = (*line).to_string();
+        if found.lines[i] && !set {
+            for re in [&re_version, &re_plain] {
+                if re.is_match(&line) {
+                    line = re
+                        .replacen(&line, 1, |caps: &regex::Captures| {
+                            let rest = caps.get(2).map_or("", |m| m.as_str());
+                            format!("{}{}{}", &caps[1], quoted, rest)
+                        })
+                        .into_owned();
+                    set = true;
+                    break;
+                }
+            }
+        }
+        edited.push_str(&line);
+        edited.push('\n');
+    }
+    set.then_some(edited)
+}
+
+/// Where a dependency is in the lines of a manifest.
+struct DependencyLines {
+    /// Which lines are of the dependency, as an entry of the section or a table of its own.
+    lines: Vec<bool>,
+    /// The first line of its entry in the section, if any.
+    entry: Option<usize>,
+    /// The line after the last entry of the section, if there is a section.
+    section_end: Option<usize>,
+}
+
+// Find the lines of a dependency in a section of the manifest.
+fn find_dependency(lines: &[&str], section: &str, name: &str) -> DependencyLines {
+    let dep_table = format!("{}.{}", section, name);
+    let mut found = DependencyLines {
+        lines: vec![false; lines.len()],
+        entry: None,
+        section_end: None,
+    };
+    let mut table = String::new();
+
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(header) = table_header(lines[i]) {
+            table = header;
+            if table == section {
+                found.section_end = Some(i + 1);
+            }
+            found.lines[i] = table == dep_table;
+            i += 1;
+            continue;
+        }
+
+        let key = entry_key(lines[i]);
+        let len = if key.is_some() {
+            entry_len(&lines[i..])
+        } else {
+            1
+        };
+        if table == dep_table || (table == section && key.as_deref() == Some(name)) {
+            found.lines[i..i + len].fill(true);
+            if table == section {
+                found.entry.get_or_insert(i);
+            }
+        } else if table == section && key.is_some() {
+            found.section_end = Some(i + len);
+        }
+        i += len;
+    }
+    found
+}
+
+// Remove the lines of a dependency from a section of the manifest, whether it's given as an entry
+// of the section or as a table of its own, and put the entry given in its place.  Returns the
+// manifest, and whether the dependency was there.
+fn edit_dependency(
+    manifest: &str,
+    section: &str,
+    name: &str,
+    entry: Option<String>,
+) -> (String, bool) {
+    let lines: Vec<&str> = manifest.lines().collect();
+    let found = find_dependency(&lines, section, name);
+    let removed = found.lines;
+    let insert_at = found.entry.or(found.section_end);
+    let mut edited = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(entry) = &entry {
+            if insert_at == Some(i) {
+                edited.push_str(entry);
+                edited.push('\n');
+            }
+        }
+        if !removed[i] {
+            edited.push_str(line);
+            edited.push('\n');
+        }
+    }
+    if let Some(entry) = &entry {
+        if insert_at == Some(lines.len()) {
+            edited.push_str(entry);
+            edited.push('\n');
+        } else if insert_at.is_none() {
+            if !edited.is_empty() && !edited.ends_with("\n\n") {
+                edited.push('\n');
+            }
+            edited.push_str(&format!("[{}]\n{}\n", section, entry));
+        }
+    }
+    (edited, removed.contains(&true))
+}
+
+// The key of the table the line is the header of, with its parts unquoted, as in `dependencies`
+// or `dependencies.serde`.  Headers of arrays of tables, such as `[[bin]]`, are kept as they are.
+fn table_header(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.starts_with("[[") {
+        return Some(line.into());
+    }
+    let re_header = Regex::new(r"^\[([^\]]+)\]\s*(#.*)?$").unwrap();
+    let key = re_header.captures(line)?.get(1)?.as_str();
+    let parts: Vec<_> = key
+        .split('.')
+        .map(|part| part.trim().trim_matches(['"', '\'']))
+        .collect();
+    Some(parts.join("."))
+}
+
+// The first part of the key of the entry the line starts, unquoted.  That's the name of the
+// dependency for entries like `serde = "1"` as well as `serde.version = "1"`.
+fn entry_key(line: &str) -> Option<String> {
+    let re_key = Regex::new(r#"^\s*("[^"]*"|'[^']*'|[A-Za-z0-9_-]+)\s*[.=]"#).unwrap();
+    let key = re_key.captures(line)?.get(1)?.as_str();
+    Some(key.trim_matches(['"', '\'']).into())
+}
+
+// The number of lines of the entry starting with the first line, as an array or inline table can
+// go on over the following lines.
+fn entry_len(lines: &[&str]) -> usize {
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let mut quote = None;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some('"'), '\\') => {
+                    chars.next();
+                }
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '#') => break,
+                (None, '[' | '{') => depth += 1,
+                (None, ']' | '}') => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth <= 0 {
+            return i + 1;
+        }
+    }
+    lines.len()
+}
+
+#[test]
+fn test_edit_dependency() {
+    let manifest = r#"# Parsing.
+[dependencies]
+regex = "1"   # For the patterns.
+serde = { version = "1",
+    features = ["derive"] }
+time = "0.3"
+
+[dependencies.log]
+version = "0.4"
+
+[features]
+"#;
+
+    assert_eq!(
+        add_dependency(manifest, "dependencies", "serde", "\"1.0.200\""),
+        r#"# Parsing.
+[dependencies]
+regex = "1"   # For the patterns.
+serde = "1.0.200"
+time = "0.3"
+
+[dependencies.log]
+version = "0.4"
+
+[features]
+"#
+    );
+    assert_eq!(
+        add_dependency(manifest, "dependencies", "anyhow", "\"1\""),
+        r#"# Parsing.
+[dependencies]
+regex = "1"   # For the patterns.
+serde = { version = "1",
+    features = ["derive"] }
+time = "0.3"
+anyhow = "1"
+
+[dependencies.log]
+version = "0.4"
+
+[features]
+"#
+    );
+    assert_eq!(
+        add_dependency(manifest, "dev-dependencies", "anyhow", "\"1\""),
+        format!("{}\n[dev-dependencies]\nanyhow = \"1\"\n", manifest)
+    );
+    assert_eq!(
+        add_dependency("", "dependencies", "anyhow", "\"1\""),
+        "[dependencies]\nanyhow = \"1\"\n"
+    );
+
+    assert_eq!(
+        remove_dependency(manifest, "dependencies", "log").unwrap(),
+        r#"# Parsing.
+[dependencies]
+regex = "1"   # For the patterns.
+serde = { version = "1",
+    features = ["derive"] }
+time = "0.3"
+
+[features]
+"#
+    );
+    assert_eq!(
+        remove_dependency(manifest, "dependencies", "serde").unwrap(),
+        r#"# Parsing.
+[dependencies]
+regex = "1"   # For the patterns.
+time = "0.3"
+
+[dependencies.log]
+version = "0.4"
+
+[features]
+"#
+    );
+    assert_eq!(remove_dependency(manifest, "dependencies", "anyhow"), None);
+    assert_eq!(
+        remove_dependency(manifest, "dev-dependencies", "regex"),
+        None
+    );
+}
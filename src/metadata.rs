@@ -0,0 +1,351 @@
+/*!
+This module is concerned with the metadata `rust-script` stores alongside the packages it generates in the cache.
+*/
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use log::{error, info};
+use sha1::{Digest, Sha1};
+use toml::value::Table;
+
+use crate::error::MainResult;
+
+/// Name of the metadata file inside the package directory.
+const METADATA_FILE: &str = "metadata.toml";
+
+/// Version of the layout of the metadata file.  Whenever the layout changes, this is bumped and a
+/// migration from the previous layout added to `MIGRATIONS`.
+const SCHEMA_VERSION: i64 = 1;
+
+/// Migrations of the metadata from each schema to the next, starting from the files written
+/// before the schema had a version.
+const MIGRATIONS: [fn(&mut Table) -> Option<()>; SCHEMA_VERSION as usize] = [migrate_unversioned];
+
+/// Name of the file recording the built binary inside the package directory, from before there
+/// was one for each host triple.
+const LEGACY_BINARY_RECORD_FILE: &str = "binary.toml";
+
+// Name of the file recording the binary built for the given host triple inside the package
+// directory.
+fn binary_record_file(triple: &str) -> String {
+    format!("binary-{}.toml", triple)
+}
+
+/**
+What a cached package was generated from.
+
+If the metadata recorded for a package matches that of the current run, the package is still up to date, and there's no need to read the script and generate the package again.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PackageMetadata {
+    /// Modification time of the script, in milliseconds since the UNIX epoch.
+    pub script_mtime: u128,
+
+    /// Size of the script, in bytes.
+    pub script_size: u64,
+
+    /// Digest of everything besides the script itself that goes into the package.
+    pub digest: String,
+}
+
+impl PackageMetadata {
+    /**
+    Creates the metadata for a script with the given file metadata.
+    */
+    pub fn new(script: &fs::Metadata, digest: String) -> MainResult<Self> {
+        let script_mtime = script
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+
+        Ok(PackageMetadata {
+            script_mtime,
+            script_size: script.len(),
+            digest,
+        })
+    }
+
+    /**
+    Loads the metadata stored in a package directory.
+
+    Metadata written by an older version of `rust-script` is migrated in place, so that upgrading doesn't make every package look out of date.  Returns `None` if there is none, or it can't be understood, e.g. because a newer version wrote it.
+    */
+    pub fn load(pkg_path: &Path) -> Option<Self> {
+        let path = pkg_path.join(METADATA_FILE);
+        let content = fs::read_to_string(&path).ok()?;
+        let mut table: Table = toml::from_str(&content).ok()?;
+
+        let schema = match table.get("schema") {
+            None => 0,
+            Some(schema) => schema.as_integer()?,
+        };
+        if schema > SCHEMA_VERSION {
+            info!("{:?} has newer schema {}, ignoring it", path, schema);
+            return None;
+        }
+        if schema < SCHEMA_VERSION {
+            info!("migrating {:?} from schema {}", path, schema);
+            for migrate in &MIGRATIONS[usize::try_from(schema).ok()?..] {
+                migrate(&mut table)?;
+            }
+            table.insert("schema".to_string(), toml::Value::Integer(SCHEMA_VERSION));
+            // Failing to store the migrated metadata only means it's migrated again next time.
+            if let Err(err) = crate::overwrite_file(&path, &format!("{}", table)) {
+                error!("failed to store migrated {:?}: {}", path, err);
+            }
+        }
+
+        Some(PackageMetadata {
+            script_mtime: u128::try_from(table.get("script_mtime")?.as_integer()?).ok()?,
+            script_size: u64::try_from(table.get("script_size")?.as_integer()?).ok()?,
+            digest: table.get("digest")?.as_str()?.to_string(),
+        })
+    }
+
+    /**
+    Stores the metadata in a package directory.
+    */
+    pub fn save(&self, pkg_path: &Path) -> MainResult<()> {
+        let mut table = Table::new();
+        table.insert("schema".to_string(), toml::Value::Integer(SCHEMA_VERSION));
+        table.insert(
+            "script_mtime".to_string(),
+            toml::Value::Integer(i64::try_from(self.script_mtime).unwrap_or(i64::MAX)),
+        );
+        table.insert(
+            "script_size".to_string(),
+            toml::Value::Integer(i64::try_from(self.script_size).unwrap_or(i64::MAX)),
+        );
+        table.insert(
+            "digest".to_string(),
+            toml::Value::String(self.digest.clone()),
+        );
+
+        crate::overwrite_file(&pkg_path.join(METADATA_FILE), &format!("{}", table))
+    }
+}
+
+// The metadata written before the schema had a version has the same fields as schema 1.
+fn migrate_unversioned(_table: &mut Table) -> Option<()> {
+    Some(())
+}
+
+/**
+What a binary built from a cached package looked like right after it was built, so that it can be checked before it's used again.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BinaryRecord {
+    /// Digest of the sources the binary was built from.
+    pub sources_digest: String,
+
+    /// Size of the binary, in bytes.
+    pub binary_size: u64,
+
+    /// Digest of the binary.
+    pub binary_digest: String,
+}
+
+impl BinaryRecord {
+    /**
+    Creates the record for a binary built from the given sources.
+    */
+    pub fn compute(sources: &[&Path], binary: &Path) -> MainResult<Self> {
+        let sources_digest = sources_digest(sources)?;
+
+        let mut hasher = Sha1::new();
+        let binary_size = hash_file(&mut hasher, binary)?;
+
+        Ok(BinaryRecord {
+            sources_digest,
+            binary_size,
+            binary_digest: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    /**
+    Describes how `actual` differs from this record, if it does.
+    */
+    pub fn mismatch(&self, actual: &BinaryRecord) -> Option<String> {
+        if self.sources_digest != actual.sources_digest {
+            Some("the sources don't match the ones it was built from".into())
+        } else if self.binary_size != actual.binary_size {
+            Some(format!(
+                "it is {} bytes, but was {} bytes when built",
+                actual.binary_size, self.binary_size
+            ))
+        } else if self.binary_digest != actual.binary_digest {
+            Some("its contents changed since it was built".into())
+        } else {
+            None
+        }
+    }
+
+    /**
+    Loads the record of the binary for the given host triple stored in a package directory.
+
+    A record from before there was one for each host triple is taken to be for the given one, like the binary cache it was made with.  Returns `None` if there is none, or it can't be understood.
+    */
+    pub fn load(pkg_path: &Path, triple: &str) -> Option<Self> {
+        let content = fs::read_to_string(pkg_path.join(binary_record_file(triple)))
+            .or_else(|_| fs::read_to_string(pkg_path.join(LEGACY_BINARY_RECORD_FILE)))
+            .ok()?;
+        let table: Table = toml::from_str(&content).ok()?;
+
+        Some(BinaryRecord {
+            sources_digest: table.get("sources_digest")?.as_str()?.to_string(),
+            binary_size: u64::try_from(table.get("binary_size")?.as_integer()?).ok()?,
+            binary_digest: table.get("binary_digest")?.as_str()?.to_string(),
+        })
+    }
+
+    /**
+    Stores the record in a package directory, as that of the binary for the given host triple.
+    */
+    pub fn save(&self, pkg_path: &Path, triple: &str) -> MainResult<()> {
+        let mut table = Table::new();
+        table.insert(
+            "sources_digest".to_string(),
+            toml::Value::String(self.sources_digest.clone()),
+        );
+        table.insert(
+            "binary_size".to_string(),
+            toml::Value::Integer(i64::try_from(self.binary_size).unwrap_or(i64::MAX)),
+        );
+        table.insert(
+            "binary_digest".to_string(),
+            toml::Value::String(self.binary_digest.clone()),
+        );
+
+        crate::overwrite_file(
+            &pkg_path.join(binary_record_file(triple)),
+            &format!("{}", table),
+        )
+    }
+
+    /**
+    Forgets the record of the binary for the given host triple stored in a package directory, if any, as when the binary was rebuilt some other way.
+    */
+    pub fn clear(pkg_path: &Path, triple: &str) -> MainResult<()> {
+        for file in [binary_record_file(triple), LEGACY_BINARY_RECORD_FILE.into()] {
+            match fs::remove_file(pkg_path.join(file)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+Digest of the contents of the given source files.
+*/
+pub fn sources_digest(sources: &[&Path]) -> MainResult<String> {
+    let mut hasher = Sha1::new();
+    for source in sources {
+        hash_file(&mut hasher, source)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Feed the contents of a file to the hasher, returning its size.
+fn hash_file(hasher: &mut Sha1, path: &Path) -> MainResult<u64> {
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(size);
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+}
+
+#[test]
+fn test_package_metadata_roundtrip() {
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    assert_eq!(PackageMetadata::load(pkg_dir.path()), None);
+
+    let metadata = PackageMetadata {
+        script_mtime: 1_700_000_000_000,
+        script_size: 1234,
+        digest: "0123456789abcdef".into(),
+    };
+    metadata.save(pkg_dir.path()).unwrap();
+    assert_eq!(PackageMetadata::load(pkg_dir.path()), Some(metadata));
+}
+
+#[test]
+fn test_package_metadata_migration() {
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    let path = pkg_dir.path().join(METADATA_FILE);
+    fs::write(
+        &path,
+        "script_mtime = 1700000000000\nscript_size = 1234\ndigest = \"0123456789abcdef\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        PackageMetadata::load(pkg_dir.path()),
+        Some(PackageMetadata {
+            script_mtime: 1_700_000_000_000,
+            script_size: 1234,
+            digest: "0123456789abcdef".into(),
+        })
+    );
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains(&format!("schema = {}", SCHEMA_VERSION)));
+
+    fs::write(&path, "schema = 1000\n").unwrap();
+    assert_eq!(PackageMetadata::load(pkg_dir.path()), None);
+}
+
+#[test]
+fn test_binary_record() {
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    let source = pkg_dir.path().join("script.rs");
+    let binary = pkg_dir.path().join("script");
+    fs::write(&source, "fn main() {}").unwrap();
+    fs::write(&binary, "binary").unwrap();
+
+    let record = BinaryRecord::compute(&[&source], &binary).unwrap();
+    assert_eq!(record.binary_size, 6);
+    record.save(pkg_dir.path(), "aarch64-apple-darwin").unwrap();
+    let loaded = BinaryRecord::load(pkg_dir.path(), "aarch64-apple-darwin").unwrap();
+    assert_eq!(loaded, record);
+    assert_eq!(
+        BinaryRecord::load(pkg_dir.path(), "x86_64-apple-darwin"),
+        None
+    );
+
+    // A record from before there was one for each host triple is used as well.
+    let legacy_dir = tempfile::TempDir::new().unwrap();
+    fs::copy(
+        pkg_dir
+            .path()
+            .join(binary_record_file("aarch64-apple-darwin")),
+        legacy_dir.path().join(LEGACY_BINARY_RECORD_FILE),
+    )
+    .unwrap();
+    assert_eq!(
+        BinaryRecord::load(legacy_dir.path(), "x86_64-apple-darwin"),
+        Some(record.clone())
+    );
+
+    fs::write(&binary, "bin").unwrap();
+    let actual = BinaryRecord::compute(&[&source], &binary).unwrap();
+    assert_eq!(
+        record.mismatch(&actual),
+        Some("it is 3 bytes, but was 6 bytes when built".into())
+    );
+    fs::write(&binary, "BINARY").unwrap();
+    let actual = BinaryRecord::compute(&[&source], &binary).unwrap();
+    assert_eq!(
+        record.mismatch(&actual),
+        Some("its contents changed since it was built".into())
+    );
+}
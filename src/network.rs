@@ -0,0 +1,108 @@
+/*!
+This module implements `--no-network`: running the script without access to the network, while it is still built with it.
+
+On Linux, the script is run in a network namespace of its own with `unshare`, which has nothing but a loopback interface that is down.  Elsewhere, there is no such isolation without privileges, so the usual proxy environment variables are pointed at a port nothing listens on instead, which only stops programs that honour them.
+*/
+use std::process::Command;
+
+use crate::error::{FailureKind, MainError, MainResult};
+
+/// A proxy that refuses connections: the discard port on the loopback interface.
+const DEAD_PROXY: &str = "http://127.0.0.1:9";
+
+/// The environment variables proxies are taken from, in the case programs commonly look for.
+const PROXY_VARS: [&str; 6] = [
+    "http_proxy",
+    "https_proxy",
+    "all_proxy",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "ALL_PROXY",
+];
+
+/**
+Turns the command running the script into one running it without network access, as `arg0` if given.
+*/
+pub fn isolate(cmd: Command, arg0: Option<&str>) -> MainResult<Command> {
+    #[cfg(target_os = "linux")]
+    let mut cmd = unshare(cmd, arg0)?;
+    #[cfg(not(target_os = "linux"))]
+    let mut cmd = {
+        let _ = arg0;
+        cmd
+    };
+    poison_proxies(&mut cmd);
+    Ok(cmd)
+}
+
+// Run the command in a new network namespace, inside a new user namespace so that no privileges
+// are needed, as the same user.  The arguments, environment and working directory are carried
+// over; `unshare` can't set the name the command is run as, so the trampoline does that.
+#[cfg(target_os = "linux")]
+fn unshare(cmd: Command, arg0: Option<&str>) -> MainResult<Command> {
+    const UNSHARE_ARGS: [&str; 3] = ["--net", "--map-current-user", "--"];
+
+    // Make sure namespaces can be created here, rather than failing in a way that looks like
+    // the script failed.
+    let probe = Command::new("unshare")
+        .args(UNSHARE_ARGS)
+        .arg("true")
+        .output();
+    match probe {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            return Err(MainError::with_kind(
+                FailureKind::Usage,
+                format!(
+                    "--no-network could not create a network namespace: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))
+        }
+        Err(err) => {
+            return Err(MainError::with_kind(
+                FailureKind::Usage,
+                MainError::Tag("--no-network needs `unshare`".into(), Box::new(err.into())),
+            ))
+        }
+    }
+
+    let cmd = match arg0 {
+        Some(arg0) => crate::trampoline::wrap(cmd, &[], Some(arg0))?,
+        None => cmd,
+    };
+    let mut isolated = Command::new("unshare");
+    isolated.args(UNSHARE_ARGS);
+    isolated.arg(cmd.get_program());
+    isolated.args(cmd.get_args());
+    for (name, value) in cmd.get_envs() {
+        match value {
+            Some(value) => isolated.env(name, value),
+            None => isolated.env_remove(name),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        isolated.current_dir(dir);
+    }
+    Ok(isolated)
+}
+
+// Point the proxy environment variables at a proxy that refuses connections, and make sure no
+// hosts bypass it.
+fn poison_proxies(cmd: &mut Command) {
+    for name in PROXY_VARS {
+        cmd.env(name, DEAD_PROXY);
+    }
+    cmd.env_remove("no_proxy");
+    cmd.env_remove("NO_PROXY");
+}
+
+#[test]
+fn test_poison_proxies() {
+    let mut cmd = Command::new("script");
+    cmd.env("NO_PROXY", "example.com");
+    poison_proxies(&mut cmd);
+    let envs: Vec<_> = cmd.get_envs().collect();
+    assert!(envs.contains(&("HTTPS_PROXY".as_ref(), Some(DEAD_PROXY.as_ref()))));
+    assert!(envs.contains(&("NO_PROXY".as_ref(), None)));
+}
@@ -0,0 +1,226 @@
+/*!
+This module implements the `pin` subcommand: rewriting the version requirements in the manifest embedded in a script to the exact versions its dependencies resolved to, so that the script keeps being built with the same versions wherever it goes.
+
+The versions are taken from the `Cargo.lock` of the script's package in the cache, so they are those the script was last built with.  If it was never built, the dependencies are resolved without building it.  Requirements that already say something more specific than "compatible with", such as `=1.2.3`, `~1.2` or `>=1, <3`, are left alone, as are dependencies on git repositories or local directories.
+*/
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use log::info;
+
+use crate::error::{MainError, MainResult};
+use crate::manifest_edit;
+
+/// The sections of the manifest with dependencies to pin.
+const SECTIONS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/**
+Runs the `pin` subcommand on the given script, passing `cargo_flags` on to Cargo.
+*/
+pub fn pin(script: &str, cargo_flags: &[&str]) -> MainResult<i32> {
+    let pkg_path = package_path(script)?;
+    if !pkg_path.join("Cargo.lock").exists() {
+        info!("resolving the dependencies of {:?}", pkg_path);
+        let status = Command::new("cargo")
+            .arg("generate-lockfile")
+            .args(cargo_flags)
+            .current_dir(&pkg_path)
+            .status()?;
+        if !status.success() {
+            return Err("could not resolve the dependencies of the script".into());
+        }
+    }
+
+    let package_manifest: toml::value::Table =
+        toml::from_str(&fs::read_to_string(pkg_path.join("Cargo.toml"))?)
+            .map_err(|e| MainError::Other(Box::new(e)))?;
+    let root = package_manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_str())
+        .ok_or("the package of the script has no name")?;
+    let locked = root_dependencies(&fs::read_to_string(pkg_path.join("Cargo.lock"))?, root)?;
+
+    let mut pinned = 0;
+    let code = manifest_edit::edit_script(script, |mut manifest| {
+        let table: toml::value::Table =
+            toml::from_str(&manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+        for section in SECTIONS {
+            let Some(deps) = table.get(section).and_then(|deps| deps.as_table()) else {
+                continue;
+            };
+            for (name, dep) in deps {
+                let Some((package, req)) = version_req(name, dep) else {
+                    continue;
+                };
+                let Some(version) = locked.get(package).filter(|_| is_pinnable(req)) else {
+                    continue;
+                };
+                let exact = format!("={}", version);
+                if let Some(edited) =
+                    manifest_edit::set_dependency_version(&manifest, section, name, &exact)
+                {
+                    println!("pinned {} to {}", name, exact);
+                    manifest = edited;
+                    pinned += 1;
+                }
+            }
+        }
+        Ok(manifest)
+    })?;
+
+    if pinned == 0 {
+        println!("no dependencies to pin");
+    }
+    Ok(code)
+}
+
+/**
+Returns the package of the script in the cache, as printed by `--package`, generating it if needed.
+*/
+pub fn package_path(script: &str) -> MainResult<PathBuf> {
+    let output = Command::new(std::env::current_exe()?)
+        .arg("--package")
+        .arg(script)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("could not generate the package of {}", script).into());
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+// The name of the package of a dependency in a manifest, and its version requirement, if it's a
+// dependency on a registry.
+fn version_req<'a>(name: &'a str, dep: &'a toml::Value) -> Option<(&'a str, &'a str)> {
+    match dep {
+        toml::Value::String(req) => Some((name, req.as_str())),
+        toml::Value::Table(table) => {
+            if ["path", "git", "workspace"]
+                .iter()
+                .any(|key| table.contains_key(*key))
+            {
+                return None;
+            }
+            let package = table.get("package").and_then(|p| p.as_str());
+            Some((package.unwrap_or(name), table.get("version")?.as_str()?))
+        }
+        _ => None,
+    }
+}
+
+// Is the version requirement a plain one, that any compatible version satisfies?
+fn is_pinnable(req: &str) -> bool {
+    let req = req.trim();
+    !req.is_empty() && !req.starts_with('=') && !req.contains(['<', '>', '~', ','])
+}
+
+// The versions the dependencies of the root package resolved to in the lock file, by package name.
+fn root_dependencies(lock: &str, root: &str) -> MainResult<HashMap<String, String>> {
+    let lock: toml::value::Table =
+        toml::from_str(lock).map_err(|e| MainError::Other(Box::new(e)))?;
+    let packages: Vec<_> = lock
+        .get("package")
+        .and_then(|packages| packages.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|package| package.as_table())
+        .collect();
+    let field = |package: &toml::value::Table, key: &str| {
+        package
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(String::from)
+    };
+    let root = packages
+        .iter()
+        .find(|package| field(package, "name").as_deref() == Some(root))
+        .ok_or("the package of the script is missing from its Cargo.lock")?;
+
+    let mut versions = HashMap::new();
+    let deps = root.get("dependencies").and_then(|deps| deps.as_array());
+    for dep in deps.into_iter().flatten().filter_map(|dep| dep.as_str()) {
+        // Entries are `name`, or `name version` if several versions of the package are locked.
+        let mut parts = dep.split_whitespace();
+        let Some(name) = parts.next() else {
+            continue;
+        };
+        let version = match parts.next() {
+            Some(version) => Some(version.to_string()),
+            None => packages
+                .iter()
+                .find(|package| field(package, "name").as_deref() == Some(name))
+                .and_then(|package| field(package, "version")),
+        };
+        if let Some(version) = version {
+            versions.insert(name.to_string(), version);
+        }
+    }
+    Ok(versions)
+}
+
+#[test]
+fn test_pin() {
+    let lock = r#"
+version = 3
+
+[[package]]
+name = "regex"
+version = "1.10.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "script_abc"
+version = "0.1.0"
+dependencies = [
+ "regex",
+ "time 0.3.36",
+]
+
+[[package]]
+name = "time"
+version = "0.1.45"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "time"
+version = "0.3.36"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+    let versions = root_dependencies(lock, "script_abc").unwrap();
+    assert_eq!(versions["regex"], "1.10.5");
+    assert_eq!(versions["time"], "0.3.36");
+    assert_eq!(versions.len(), 2);
+
+    assert!(is_pinnable("1"));
+    assert!(is_pinnable("^0.3"));
+    assert!(is_pinnable("*"));
+    assert!(!is_pinnable("=1.10.5"));
+    assert!(!is_pinnable("~1.10"));
+    assert!(!is_pinnable(">=1, <3"));
+
+    assert_eq!(
+        manifest_edit::set_dependency_version(
+            "[dependencies]\nregex = \"1\" # Patterns.\ntime = { version = \"0.3\", features = [\"macros\"] }\n",
+            "dependencies",
+            "time",
+            "=0.3.36"
+        )
+        .unwrap(),
+        "[dependencies]\nregex = \"1\" # Patterns.\ntime = { version = \"=0.3.36\", features = [\"macros\"] }\n"
+    );
+    assert_eq!(
+        manifest_edit::set_dependency_version(
+            "[dependencies]\nregex = \"1\" # Patterns.\n",
+            "dependencies",
+            "regex",
+            "=1.10.5"
+        )
+        .unwrap(),
+        "[dependencies]\nregex = \"=1.10.5\" # Patterns.\n"
+    );
+}
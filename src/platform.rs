@@ -2,13 +2,64 @@
 This module is for platform-specific stuff.
 */
 
-pub use self::inner::force_cargo_color;
+pub use self::inner::{force_cargo_color, is_interactive};
 
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::Write;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, Once, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use log::{error, info};
+
+use crate::error::MainResult;
+
+/// The target triple `rust-script` was built for, for when the toolchain can't tell its own.
+const BUILD_TRIPLE: &str = env!("RUST_SCRIPT_HOST_TRIPLE");
+
+/**
+The host triple of the given toolchain, or of the default one, such as `aarch64-apple-darwin`.
+
+Scripts are built for it, since Cargo builds for the host unless told otherwise.  It's asked from `rustc` once per run and toolchain; if `rustc` can't tell, the target triple `rust-script` was built for is assumed.
+*/
+pub fn host_triple(toolchain: Option<&str>) -> String {
+    static HOSTS: Mutex<BTreeMap<Option<String>, String>> = Mutex::new(BTreeMap::new());
+
+    let mut hosts = HOSTS.lock().unwrap();
+    hosts
+        .entry(toolchain.map(String::from))
+        .or_insert_with(|| {
+            let host = rustc_version(toolchain).as_deref().and_then(parse_host);
+            host.unwrap_or_else(|| BUILD_TRIPLE.into())
+        })
+        .clone()
+}
+
+/**
+The version of the compiler of the given toolchain (or the default one), as reported by `rustc -vV`.
+*/
+pub fn rustc_version(toolchain: Option<&str>) -> Option<String> {
+    let mut cmd = Command::new("rustc");
+    if let Some(toolchain) = toolchain {
+        cmd.arg(format!("+{}", toolchain));
+    }
+    cmd.arg("-vV");
+    let output = cmd.output().ok().filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// The host triple in the output of `rustc -vV`.
+fn parse_host(version: &str) -> Option<String> {
+    version
+        .lines()
+        .find_map(|line| line.strip_prefix("host:"))
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
+
 // Last-modified time of a directory, in milliseconds since the UNIX epoch.
 pub fn dir_last_modified(dir: &fs::DirEntry) -> u128 {
     dir.metadata()
@@ -27,7 +78,69 @@ pub fn current_time() -> u128 {
         .as_millis()
 }
 
+/// The cache directory used instead of the user's one, if any.
+static LOCAL_CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/**
+Makes the caches live in the given directory instead of the user's cache directory.
+
+The directory is created if needed, along with a `.gitignore` that keeps it out of version control.
+*/
+pub fn use_local_cache_dir(dir: PathBuf) -> MainResult<()> {
+    fs::create_dir_all(&dir)?;
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(gitignore, "*\n")?;
+    }
+    LOCAL_CACHE_DIR
+        .set(dir)
+        .map_err(|_| "the cache directory is already set".into())
+}
+
+/// The temporary directory the caches live in for this run only, if they are ephemeral.
+static EPHEMERAL_CACHE_DIR: Mutex<Option<tempfile::TempDir>> = Mutex::new(None);
+
+/**
+Makes the caches live in a temporary directory for this run only, until `remove_ephemeral_cache_dir` is called.
+*/
+pub fn use_ephemeral_cache_dir() -> MainResult<()> {
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("{}-", crate::consts::PROGRAM_NAME))
+        .tempdir()?;
+    LOCAL_CACHE_DIR
+        .set(dir.path().to_path_buf())
+        .map_err(|_| "the cache directory is already set")?;
+    *EPHEMERAL_CACHE_DIR.lock().unwrap() = Some(dir);
+    Ok(())
+}
+
+/**
+Removes the temporary directory made by `use_ephemeral_cache_dir`, if any.
+*/
+pub fn remove_ephemeral_cache_dir() -> MainResult<()> {
+    match EPHEMERAL_CACHE_DIR.lock().unwrap().take() {
+        Some(dir) => dir.close().map_err(Into::into),
+        None => Ok(()),
+    }
+}
+
+/// Do the caches live in a temporary directory for this run only?
+pub fn is_ephemeral() -> bool {
+    EPHEMERAL_CACHE_DIR.lock().unwrap().is_some()
+}
+
+/**
+Returns `true` if files can be created in the cache directory, creating it if needed.
+*/
+pub fn cache_dir_is_writable() -> bool {
+    let dir = cache_dir();
+    fs::create_dir_all(&dir).is_ok() && tempfile::NamedTempFile::new_in(&dir).is_ok()
+}
+
 pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = LOCAL_CACHE_DIR.get() {
+        return dir.clone();
+    }
     #[cfg(not(test))]
     {
         dirs::cache_dir()
@@ -44,14 +157,315 @@ pub fn cache_dir() -> PathBuf {
     }
 }
 
+/**
+Opens a file in the web browser given by the `BROWSER` environment variable, or else with the default application for it.
+*/
+pub fn open_in_browser(path: &Path) -> MainResult<()> {
+    let mut cmd = match std::env::var("BROWSER") {
+        Ok(browser) if !browser.is_empty() => Command::new(browser),
+        _ if cfg!(windows) => {
+            let mut cmd = Command::new("cmd");
+            cmd.args(["/C", "start", ""]);
+            cmd
+        }
+        _ if cfg!(target_os = "macos") => Command::new("open"),
+        _ => Command::new("xdg-open"),
+    };
+    let status = cmd.arg(path).status()?;
+    if !status.success() {
+        return Err(format!("could not open {}", path.display()).into());
+    }
+    Ok(())
+}
+
+/**
+Shows a desktop notification with the given title and message.
+
+This uses `osascript` on macOS, a PowerShell toast on Windows, and `notify-send` elsewhere.
+*/
+pub fn notify(title: &str, message: &str) -> MainResult<()> {
+    let mut cmd = if cfg!(windows) {
+        let script = format!(
+            "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] > $null; \
+             $xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+             $text = $xml.GetElementsByTagName('text'); \
+             $text.Item(0).AppendChild($xml.CreateTextNode('{}')) > $null; \
+             $text.Item(1).AppendChild($xml.CreateTextNode('{}')) > $null; \
+             [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('{{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}}\\WindowsPowerShell\\v1.0\\powershell.exe').Show([Windows.UI.Notifications.ToastNotification]::new($xml))",
+            powershell_quote(title),
+            powershell_quote(message)
+        );
+        let mut cmd = Command::new("powershell");
+        cmd.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+        cmd
+    } else if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_quote(message),
+            applescript_quote(title)
+        ));
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["--app-name", crate::consts::PROGRAM_NAME, title, message]);
+        cmd
+    };
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(format!("{:?} failed with {}", cmd.get_program(), status).into());
+    }
+    Ok(())
+}
+
+/**
+Reads the text on the clipboard.
+
+This uses `pbpaste` on macOS, PowerShell on Windows, `wl-paste` under Wayland, and `xclip` or `xsel` (whichever is installed) elsewhere.
+*/
+pub fn read_clipboard() -> MainResult<Vec<u8>> {
+    let candidates: &[&[&str]] = if cfg!(windows) {
+        &[&[
+            "powershell",
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "Get-Clipboard -Raw",
+        ]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbpaste"]]
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        &[&["wl-paste", "--no-newline"]]
+    } else {
+        &[
+            &["xclip", "-selection", "clipboard", "-out"],
+            &["xsel", "--clipboard", "--output"],
+        ]
+    };
+    for words in candidates {
+        let output = match Command::new(words[0]).args(&words[1..]).output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed to read the clipboard: {}",
+                words[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        return Ok(output.stdout);
+    }
+    Err(format!(
+        "could not read the clipboard: none of {} is installed",
+        clipboard_tools(candidates)
+    )
+    .into())
+}
+
+/**
+Replaces the contents of the clipboard with the given text.
+
+This uses the counterparts of the commands used by `read_clipboard`.
+*/
+pub fn write_clipboard(text: &[u8]) -> MainResult<()> {
+    let candidates: &[&[&str]] = if cfg!(windows) {
+        &[&[
+            "powershell",
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "Set-Clipboard -Value ([Console]::In.ReadToEnd())",
+        ]]
+    } else if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        &[&["wl-copy"]]
+    } else {
+        &[
+            &["xclip", "-selection", "clipboard", "-in"],
+            &["xsel", "--clipboard", "--input"],
+        ]
+    };
+    for words in candidates {
+        let child = Command::new(words[0])
+            .args(&words[1..])
+            .stdin(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        child
+            .stdin
+            .take()
+            .expect("clipboard stdin is piped")
+            .write_all(text)?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(
+                format!("{} failed to write the clipboard with {}", words[0], status).into(),
+            );
+        }
+        return Ok(());
+    }
+    Err(format!(
+        "could not write the clipboard: none of {} is installed",
+        clipboard_tools(candidates)
+    )
+    .into())
+}
+
+// List the programs of the candidate clipboard commands, for saying none of them is installed.
+fn clipboard_tools(candidates: &[&[&str]]) -> String {
+    let tools: Vec<_> = candidates
+        .iter()
+        .map(|words| format!("`{}`", words[0]))
+        .collect();
+    tools.join(", ")
+}
+
+// Escape a string for inside single quotes in PowerShell.
+fn powershell_quote(s: &str) -> String {
+    s.replace('\'', "''")
+}
+
+// Quote a string for AppleScript.
+fn applescript_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
 pub fn generated_projects_cache_path() -> PathBuf {
     cache_dir().join("projects")
 }
 
-pub fn binary_cache_path() -> PathBuf {
+/**
+Directory the binaries of scripts are built in with the given toolchain, or the default one.
+
+There is one for each host triple of the toolchains, so that on machines running more than one architecture, such as x86-64 under emulation on ARM64, a binary built for one is never run on the other.
+*/
+pub fn binary_cache_path(toolchain: Option<&str>) -> PathBuf {
+    static MIGRATION: Once = Once::new();
+
+    let path = binary_caches_path().join(host_triple(toolchain));
+    MIGRATION.call_once(|| {
+        if let Err(err) = migrate_binary_cache(&binary_caches_path(), &path) {
+            error!("failed to migrate the binary cache to {:?}: {}", path, err);
+        }
+    });
+    path
+}
+
+/**
+Directory holding the binary caches of all host triples.
+*/
+pub fn binary_caches_path() -> PathBuf {
     cache_dir().join("binaries")
 }
 
+// Move a binary cache from before there was one for each host triple into the directory of the
+// toolchain in use, assuming it was built for it.  Such a cache is a Cargo target directory
+// directly in `root`; the caches of other host triples in there are left alone.
+fn migrate_binary_cache(root: &Path, triple_dir: &Path) -> MainResult<()> {
+    let is_target_dir = |dir: &Path| dir.join("CACHEDIR.TAG").exists();
+    if triple_dir.exists() || !is_target_dir(root) {
+        return Ok(());
+    }
+    info!("migrating binary cache {:?} to {:?}", root, triple_dir);
+    fs::create_dir_all(triple_dir)?;
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path != triple_dir && !is_target_dir(&path) {
+            fs::rename(&path, triple_dir.join(path.file_name().unwrap()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn capture_logs_path() -> PathBuf {
+    cache_dir().join("logs")
+}
+
+pub fn sessions_path() -> PathBuf {
+    cache_dir().join("sessions")
+}
+
+pub fn git_cache_path() -> PathBuf {
+    cache_dir().join("git")
+}
+
+pub fn http_cache_path() -> PathBuf {
+    cache_dir().join("http")
+}
+
+pub fn replays_path() -> PathBuf {
+    cache_dir().join("replays")
+}
+
+pub fn bench_results_path() -> PathBuf {
+    cache_dir().join("benches")
+}
+
+/**
+Directory holding the `rust-script` configuration.
+
+Can be overridden with the `RUST_SCRIPT_CONFIG_DIR` environment variable.
+*/
+pub fn config_dir() -> PathBuf {
+    env_path("RUST_SCRIPT_CONFIG_DIR").unwrap_or_else(|| {
+        dirs::config_dir()
+            .map(|dir| dir.join(crate::consts::PROGRAM_NAME))
+            .expect("Cannot get config directory")
+    })
+}
+
+/// Where users keep templates of their own, selected with `--template`.
+pub fn templates_path() -> PathBuf {
+    config_dir().join("templates")
+}
+
+/// The user's prelude for expressions, used unless `--prelude` gives another.
+pub fn prelude_path() -> PathBuf {
+    config_dir().join("prelude.rs")
+}
+
+/**
+The Cargo home directory, honouring the `CARGO_HOME` environment variable like Cargo itself does.
+*/
+pub fn cargo_home() -> PathBuf {
+    env_path("CARGO_HOME").unwrap_or_else(|| {
+        dirs::home_dir()
+            .map(|dir| dir.join(".cargo"))
+            .expect("Cannot get home directory")
+    })
+}
+
+/// Where Cargo is assumed to keep its registry index and downloaded crates.
+pub fn cargo_registry_path() -> PathBuf {
+    cargo_home().join("registry")
+}
+
+/**
+The rustup home directory, honouring the `RUSTUP_HOME` environment variable like rustup itself does.
+*/
+pub fn rustup_home() -> PathBuf {
+    env_path("RUSTUP_HOME").unwrap_or_else(|| {
+        dirs::home_dir()
+            .map(|dir| dir.join(".rustup"))
+            .expect("Cannot get home directory")
+    })
+}
+
+// Path from a non-empty environment variable.
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
 #[cfg(unix)]
 mod inner {
     use is_terminal::IsTerminal as _;
@@ -64,6 +478,13 @@ mod inner {
     pub fn force_cargo_color() -> bool {
         std::io::stderr().is_terminal()
     }
+
+    /**
+    Returns `true` if the user can be asked questions, i.e. STDIN and STDERR are both connected to a TTY.
+    */
+    pub fn is_interactive() -> bool {
+        std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+    }
 }
 
 #[cfg(windows)]
@@ -76,4 +497,41 @@ pub mod inner {
     pub fn force_cargo_color() -> bool {
         false
     }
+
+    /**
+    Returns `true` if the user can be asked questions.
+
+    Always returns `false` on Windows, where choices have to be made with command-line arguments instead.
+    */
+    pub fn is_interactive() -> bool {
+        false
+    }
+}
+
+#[test]
+fn test_parse_host() {
+    let version = "rustc 1.80.0 (051478957 2024-07-21)\nbinary: rustc\ncommit-hash: 051478957\nhost: aarch64-apple-darwin\nrelease: 1.80.0\n";
+    assert_eq!(parse_host(version), Some("aarch64-apple-darwin".into()));
+    assert_eq!(parse_host("rustc 1.80.0\n"), None);
+}
+
+#[test]
+fn test_migrate_binary_cache() {
+    let root = tempfile::TempDir::new().unwrap();
+    let root = root.path();
+    fs::create_dir_all(root.join("release")).unwrap();
+    fs::write(root.join("CACHEDIR.TAG"), "").unwrap();
+    fs::create_dir_all(root.join("x86_64-apple-darwin")).unwrap();
+    fs::write(root.join("x86_64-apple-darwin/CACHEDIR.TAG"), "").unwrap();
+
+    let triple_dir = root.join("aarch64-apple-darwin");
+    migrate_binary_cache(root, &triple_dir).unwrap();
+    assert!(triple_dir.join("release").is_dir());
+    assert!(triple_dir.join("CACHEDIR.TAG").exists());
+    assert!(!root.join("release").exists());
+    assert!(root.join("x86_64-apple-darwin/CACHEDIR.TAG").exists());
+
+    // Once migrated, there's nothing left to do.
+    migrate_binary_cache(root, &triple_dir).unwrap();
+    assert!(triple_dir.join("release").is_dir());
 }
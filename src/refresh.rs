@@ -0,0 +1,271 @@
+/*!
+This module implements `--refresh-cache`: rebuilding the cached packages that have gone stale since they were last built, so that the scripts start straight away the next time they're run.
+
+A package is stale when the toolchain it was built with has changed, as after `rustup update`, or when its dependencies would resolve to different versions than the ones in its `Cargo.lock`.  Packages are rebuilt the way they were last built, which is recorded with each successful build.
+*/
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use log::info;
+use regex::Regex;
+
+use crate::error::MainResult;
+use crate::metadata::BinaryRecord;
+use crate::{deterministic, platform};
+
+/// Name of the file recording how the package was last built inside the package directory.
+const BUILD_RECORD_FILE: &str = "build.toml";
+
+/**
+How a cached package was last built successfully.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BuildRecord {
+    /// The toolchain given to Cargo, if any.
+    pub toolchain: Option<String>,
+
+    /// The version of the compiler, as reported by `rustc -vV`.
+    pub rustc: String,
+
+    /// The Cargo features enabled.
+    pub features: Vec<String>,
+
+    /// The extra arguments passed to Cargo.
+    pub build_args: Vec<String>,
+
+    /// Was the package built in release mode?
+    pub release: bool,
+
+    /// Was the package built deterministically?
+    pub deterministic: bool,
+}
+
+impl BuildRecord {
+    /**
+    Loads the record stored in a package directory.
+
+    Returns `None` if there is none, or it can't be understood.
+    */
+    pub fn load(pkg_path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(pkg_path.join(BUILD_RECORD_FILE)).ok()?;
+        let table: toml::value::Table = toml::from_str(&content).ok()?;
+        let strings = |key: &str| -> Option<Vec<String>> {
+            table
+                .get(key)?
+                .as_array()?
+                .iter()
+                .map(|value| value.as_str().map(String::from))
+                .collect()
+        };
+
+        Some(BuildRecord {
+            toolchain: match table.get("toolchain") {
+                Some(toolchain) => Some(toolchain.as_str()?.to_string()),
+                None => None,
+            },
+            rustc: table.get("rustc")?.as_str()?.to_string(),
+            features: strings("features")?,
+            build_args: strings("build_args")?,
+            release: table.get("release")?.as_bool()?,
+            deterministic: table.get("deterministic")?.as_bool()?,
+        })
+    }
+
+    /**
+    Stores the record in a package directory.
+    */
+    pub fn save(&self, pkg_path: &Path) -> MainResult<()> {
+        let strings = |values: &[String]| {
+            toml::Value::Array(values.iter().cloned().map(toml::Value::String).collect())
+        };
+        let mut table = toml::value::Table::new();
+        if let Some(toolchain) = &self.toolchain {
+            table.insert(
+                "toolchain".to_string(),
+                toml::Value::String(toolchain.clone()),
+            );
+        }
+        table.insert("rustc".to_string(), toml::Value::String(self.rustc.clone()));
+        table.insert("features".to_string(), strings(&self.features));
+        table.insert("build_args".to_string(), strings(&self.build_args));
+        table.insert("release".to_string(), toml::Value::Boolean(self.release));
+        table.insert(
+            "deterministic".to_string(),
+            toml::Value::Boolean(self.deterministic),
+        );
+
+        crate::overwrite_file(&pkg_path.join(BUILD_RECORD_FILE), &format!("{}", table))
+    }
+}
+
+/**
+Rebuilds every stale package in the cache, and prints a summary.
+
+Returns the exit code: 0 unless a package failed to rebuild.  `cargo_flags` are passed on to Cargo.
+*/
+pub fn refresh_cache(cargo_flags: &[&str]) -> MainResult<i32> {
+    let cache_dir = platform::generated_projects_cache_path();
+    let mut packages = Vec::new();
+    if cache_dir.is_dir() {
+        for entry in fs::read_dir(&cache_dir)? {
+            let path = entry?.path();
+            if path.join("Cargo.toml").is_file() {
+                packages.push(path);
+            }
+        }
+    }
+    packages.sort();
+
+    let (mut refreshed, mut fresh, mut unknown, mut failed) = (0, 0, 0, 0);
+    for pkg_path in &packages {
+        let name = pkg_path.file_name().unwrap().to_string_lossy();
+        let Some(record) = BuildRecord::load(pkg_path) else {
+            // Never built, or built before builds were recorded.
+            info!("no build record in {:?}", pkg_path);
+            unknown += 1;
+            continue;
+        };
+
+        let rustc = platform::rustc_version(record.toolchain.as_deref());
+        let toolchain_drifted = rustc.as_ref().is_some_and(|rustc| *rustc != record.rustc);
+        // A deterministic build is pinned to its `Cargo.lock` on purpose.
+        let deps_drifted =
+            !record.deterministic && dependencies_drifted(pkg_path, &record, cargo_flags)?;
+        if !toolchain_drifted && !deps_drifted {
+            fresh += 1;
+            continue;
+        }
+
+        let reason = match (toolchain_drifted, deps_drifted) {
+            (true, true) => "toolchain and dependencies changed",
+            (true, false) => "toolchain changed",
+            _ => "dependencies changed",
+        };
+        eprintln!("refreshing {} ({})", name, reason);
+        if deps_drifted
+            && !cargo(pkg_path, &record, "update", cargo_flags)
+                .status()?
+                .success()
+        {
+            eprintln!("FAILED {}: could not update its dependencies", name);
+            failed += 1;
+            continue;
+        }
+        if !build_command(pkg_path, &record, cargo_flags)
+            .status()?
+            .success()
+        {
+            eprintln!("FAILED {}: could not build it", name);
+            failed += 1;
+            continue;
+        }
+        // The binary changed, so any record of it for `--verify-cache` is out of date.
+        BinaryRecord::clear(
+            pkg_path,
+            &platform::host_triple(record.toolchain.as_deref()),
+        )?;
+        BuildRecord {
+            rustc: rustc.unwrap_or(record.rustc),
+            ..record
+        }
+        .save(pkg_path)?;
+        refreshed += 1;
+    }
+
+    println!(
+        "{} refreshed, {} up to date, {} failed, {} never built by this version",
+        refreshed, fresh, failed, unknown
+    );
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// A Cargo command with the given subcommand for the package, with the toolchain it was built with,
+// and the given flags unless the package was built with them anyway.
+fn cargo(pkg_path: &Path, record: &BuildRecord, subcommand: &str, cargo_flags: &[&str]) -> Command {
+    let mut cmd = Command::new("cargo");
+    if let Some(toolchain) = &record.toolchain {
+        cmd.arg(format!("+{}", toolchain));
+    }
+    cmd.arg(subcommand).current_dir(pkg_path);
+    for flag in cargo_flags {
+        if !record.build_args.iter().any(|arg| arg == flag) {
+            cmd.arg(flag);
+        }
+    }
+    cmd
+}
+
+// The Cargo command building the package the way it was last built.
+fn build_command(pkg_path: &Path, record: &BuildRecord, cargo_flags: &[&str]) -> Command {
+    let target_dir = platform::binary_cache_path(record.toolchain.as_deref());
+    let mut cmd = cargo(pkg_path, record, "build", cargo_flags);
+    if !record.features.is_empty() {
+        cmd.arg("--features").arg(record.features.join(","));
+    }
+    cmd.args(&record.build_args);
+    if record.release {
+        cmd.arg("--release");
+    }
+    if record.deterministic {
+        deterministic::configure(&mut cmd, &target_dir);
+    }
+    cmd.arg("-q").arg("--target-dir").arg(&target_dir);
+    cmd
+}
+
+// Would the dependencies of the package resolve to other versions than those in its lock file?
+fn dependencies_drifted(
+    pkg_path: &Path,
+    record: &BuildRecord,
+    cargo_flags: &[&str],
+) -> MainResult<bool> {
+    if !pkg_path.join("Cargo.lock").exists() {
+        return Ok(true);
+    }
+    let output = cargo(pkg_path, record, "update", cargo_flags)
+        .arg("--dry-run")
+        .output()?;
+    if !output.status.success() {
+        // Most likely offline; the package still builds with the versions it has.
+        info!(
+            "could not check the dependencies of {:?}: {}",
+            pkg_path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Ok(false);
+    }
+    Ok(lock_changes(&String::from_utf8_lossy(&output.stderr)))
+}
+
+// Does the output of `cargo update --dry-run` have any changes to the lock file?
+fn lock_changes(output: &str) -> bool {
+    let re_change =
+        Regex::new(r"(?m)^\s*(Updating|Upgrading|Downgrading|Adding|Removing|Locking)\s+\S+ v\d")
+            .unwrap();
+    re_change.is_match(output)
+}
+
+#[test]
+fn test_build_record() {
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    assert_eq!(BuildRecord::load(pkg_dir.path()), None);
+
+    let record = BuildRecord {
+        toolchain: Some("nightly".into()),
+        rustc: "rustc 1.80.0-nightly (804421dff 2024-06-07)".into(),
+        features: vec!["fast".into()],
+        build_args: vec!["--offline".into()],
+        release: true,
+        deterministic: false,
+    };
+    record.save(pkg_dir.path()).unwrap();
+    assert_eq!(BuildRecord::load(pkg_dir.path()), Some(record));
+
+    assert!(lock_changes(
+        "    Updating crates.io index\n    Updating regex v1.10.0 -> v1.10.5\nwarning: not updating lockfile due to dry run\n"
+    ));
+    assert!(!lock_changes(
+        "    Updating crates.io index\nwarning: not updating lockfile due to dry run\n"
+    ));
+}
@@ -0,0 +1,332 @@
+/*!
+This module implements `--record` and `--replay`: saving the context a script was run in, and running it again in that context later, or on another machine.
+
+A recording holds the command line, the working directory, the environment and the script itself.  Environment variables that look like they hold secrets aren't recorded; on replay, they are taken from the environment `rust-script` is replayed in.
+*/
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::info;
+use sha1::{Digest, Sha1};
+
+use crate::error::{FailureKind, MainError, MainResult};
+use crate::platform;
+
+/// Version of the recording format.
+const FORMAT_VERSION: i64 = 1;
+
+/// Parts of environment variable names that suggest the value is a secret.
+const SECRET_NAME_PARTS: [&str; 5] = ["TOKEN", "SECRET", "PASSWORD", "CREDENTIAL", "KEY"];
+
+/**
+The context a script was run in.
+*/
+#[derive(Debug, Eq, PartialEq)]
+pub struct Recording {
+    /// Version of `rust-script` that made the recording.
+    pub version: String,
+
+    /// Arguments `rust-script` was given, without `--record`.
+    pub args: Vec<String>,
+
+    /// Index into `args` of the script, if it was run from a file or stdin.
+    pub script_index: Option<usize>,
+
+    /// The working directory.
+    pub cwd: PathBuf,
+
+    /// The environment, without the variables named in `redacted`.
+    pub env: BTreeMap<String, String>,
+
+    /// Environment variables that weren't recorded, as they may hold secrets.
+    pub redacted: Vec<String>,
+
+    /// The script, if it was run from a file or stdin.
+    pub script: Option<RecordedScript>,
+}
+
+/**
+A script as it was when it was recorded.
+*/
+#[derive(Debug, Eq, PartialEq)]
+pub struct RecordedScript {
+    /// File name of the script.
+    pub name: String,
+
+    /// Digest of the script.
+    pub digest: String,
+
+    /// The script itself.
+    pub content: String,
+}
+
+impl Recording {
+    /**
+    Captures the context of the current run.
+
+    `script_args` are the arguments passed to the script, which come after the script on the command line.  `script` is the file name and content of the script, if it was run from a file or stdin.
+    */
+    pub fn capture(script_args: &[String], script: Option<(&str, &str)>) -> MainResult<Self> {
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        let script_index = args.len().checked_sub(script_args.len() + 1);
+        let script_index = script_index.filter(|_| script.is_some());
+
+        // Leave out `--record`, which comes before the script.
+        let options_end = script_index.unwrap_or(args.len());
+        let record = args[..options_end]
+            .iter()
+            .position(|arg| arg == "--record" || arg.starts_with("--record="));
+        if let Some(i) = record {
+            let len = if args[i] == "--record" { 2 } else { 1 };
+            args.drain(i..(i + len).min(options_end));
+        }
+        let script_index = script_index.map(|_| args.len() - script_args.len() - 1);
+
+        let mut env = BTreeMap::new();
+        let mut redacted = Vec::new();
+        for (name, value) in std::env::vars() {
+            if is_secret(&name) {
+                redacted.push(name);
+            } else {
+                env.insert(name, value);
+            }
+        }
+
+        Ok(Recording {
+            version: option_env!("CARGO_PKG_VERSION").unwrap_or("unknown").into(),
+            args,
+            script_index,
+            cwd: std::env::current_dir()?,
+            env,
+            redacted,
+            script: script.map(|(name, content)| RecordedScript {
+                name: name.into(),
+                digest: digest(content),
+                content: content.into(),
+            }),
+        })
+    }
+
+    /**
+    Saves the recording to a file.
+    */
+    pub fn save(&self, path: &Path) -> MainResult<()> {
+        let mut table = toml::value::Table::new();
+        table.insert("format".into(), FORMAT_VERSION.into());
+        table.insert("version".into(), self.version.clone().into());
+        table.insert("args".into(), self.args.clone().into());
+        if let Some(index) = self.script_index {
+            table.insert("script_index".into(), (index as i64).into());
+        }
+        table.insert("cwd".into(), self.cwd.to_string_lossy().into_owned().into());
+        table.insert(
+            "env".into(),
+            toml::Value::Table(
+                self.env
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone().into()))
+                    .collect(),
+            ),
+        );
+        table.insert("redacted".into(), self.redacted.clone().into());
+        if let Some(script) = &self.script {
+            let mut script_table = toml::value::Table::new();
+            script_table.insert("name".into(), script.name.clone().into());
+            script_table.insert("digest".into(), script.digest.clone().into());
+            script_table.insert("content".into(), script.content.clone().into());
+            table.insert("script".into(), script_table.into());
+        }
+
+        info!("recording to {:?}", path);
+        fs::write(path, table.to_string())?;
+        Ok(())
+    }
+
+    /**
+    Loads a recording from a file.
+    */
+    pub fn load(path: &Path) -> MainResult<Self> {
+        let invalid = |what: &str| {
+            MainError::with_kind(
+                FailureKind::Usage,
+                format!("invalid recording {}: {}", path.display(), what),
+            )
+        };
+
+        let content = fs::read_to_string(path)?;
+        let table: toml::value::Table =
+            toml::from_str(&content).map_err(|e| invalid(&e.to_string()))?;
+        match table.get("format").and_then(|v| v.as_integer()) {
+            Some(FORMAT_VERSION) => {}
+            Some(format) => return Err(invalid(&format!("unsupported format {}", format))),
+            None => return Err(invalid("missing `format`")),
+        }
+
+        let string = |table: &toml::value::Table, key: &str| {
+            table
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(String::from)
+                .ok_or_else(|| invalid(&format!("missing `{}`", key)))
+        };
+        let strings = |key: &str| -> MainResult<Vec<String>> {
+            table
+                .get(key)
+                .and_then(|v| v.as_array())
+                .ok_or_else(|| invalid(&format!("missing `{}`", key)))?
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(String::from)
+                        .ok_or_else(|| invalid(&format!("`{}` must be strings", key)))
+                })
+                .collect()
+        };
+
+        let env = table
+            .get("env")
+            .and_then(|v| v.as_table())
+            .ok_or_else(|| invalid("missing `env`"))?
+            .iter()
+            .map(|(name, value)| {
+                value
+                    .as_str()
+                    .map(|value| (name.clone(), value.to_string()))
+                    .ok_or_else(|| invalid("`env` must be strings"))
+            })
+            .collect::<MainResult<_>>()?;
+
+        let script = match table.get("script").and_then(|v| v.as_table()) {
+            Some(script) => Some(RecordedScript {
+                name: string(script, "name")?,
+                digest: string(script, "digest")?,
+                content: string(script, "content")?,
+            }),
+            None => None,
+        };
+
+        let args = strings("args")?;
+        let script_index = match table.get("script_index").and_then(|v| v.as_integer()) {
+            Some(index) => Some(
+                usize::try_from(index)
+                    .ok()
+                    .filter(|&index| index < args.len())
+                    .ok_or_else(|| invalid("`script_index` is out of range"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Recording {
+            version: string(&table, "version")?,
+            args,
+            script_index,
+            cwd: string(&table, "cwd")?.into(),
+            env,
+            redacted: strings("redacted")?,
+            script,
+        })
+    }
+
+    /**
+    Runs `rust-script` again in the recorded context, returning its exit code.
+
+    If the script the recording refers to isn't there anymore, or has changed, the recorded copy of it is run instead.
+    */
+    pub fn replay(&self) -> MainResult<i32> {
+        let current_version = option_env!("CARGO_PKG_VERSION").unwrap_or("unknown");
+        if self.version != current_version {
+            eprintln!(
+                "warning: recorded with rust-script {}, replaying with {}",
+                self.version, current_version
+            );
+        }
+
+        let cwd = if self.cwd.is_dir() {
+            self.cwd.clone()
+        } else {
+            eprintln!(
+                "warning: {} doesn't exist, replaying in the current directory",
+                self.cwd.display()
+            );
+            std::env::current_dir()?
+        };
+
+        let mut args = self.args.clone();
+        if let (Some(index), Some(script)) = (self.script_index, &self.script) {
+            let recorded_path = cwd.join(&args[index]);
+            let unchanged = fs::read_to_string(&recorded_path)
+                .map(|content| digest(&content) == script.digest)
+                .unwrap_or(false);
+            if !unchanged {
+                let copy = platform::replays_path()
+                    .join(&script.digest)
+                    .join(&script.name);
+                eprintln!(
+                    "note: {} is not the recorded script, running the recorded copy {}",
+                    args[index],
+                    copy.display()
+                );
+                fs::create_dir_all(copy.parent().expect("script copy has a parent"))?;
+                crate::overwrite_file(&copy, &script.content)?;
+                args[index] = copy.to_string_lossy().into_owned();
+            }
+        }
+
+        let mut cmd = Command::new(std::env::current_exe()?);
+        cmd.args(&args).current_dir(cwd).env_clear().envs(&self.env);
+        for name in &self.redacted {
+            if let Some(value) = std::env::var_os(name) {
+                cmd.env(name, value);
+            }
+        }
+        info!("replaying {:?}", cmd);
+        Ok(cmd.status()?.code().unwrap_or(1))
+    }
+}
+
+// Does the name of the environment variable suggest it holds a secret?
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRET_NAME_PARTS.iter().any(|part| name.contains(part))
+}
+
+fn digest(content: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(content);
+    format!("{:x}", hasher.finalize())
+}
+
+#[test]
+fn test_save_and_load() {
+    let recording = Recording {
+        version: "0.35.0".into(),
+        args: vec!["-d".into(), "time".into(), "now.rs".into(), "--utc".into()],
+        script_index: Some(2),
+        cwd: "/home/user".into(),
+        env: [("HOME".to_string(), "/home/user".to_string())].into(),
+        redacted: vec!["GITHUB_TOKEN".into()],
+        script: Some(RecordedScript {
+            name: "now.rs".into(),
+            digest: digest("fn main() {\n    println!(\"\\\"now\\\"\");\n}\n"),
+            content: "fn main() {\n    println!(\"\\\"now\\\"\");\n}\n".into(),
+        }),
+    };
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("now.replay");
+    recording.save(&path).unwrap();
+    assert_eq!(Recording::load(&path).unwrap(), recording);
+
+    fs::write(&path, "format = 2").unwrap();
+    assert!(Recording::load(&path).is_err());
+}
+
+#[test]
+fn test_is_secret() {
+    assert!(is_secret("GITHUB_TOKEN"));
+    assert!(is_secret("aws_secret_access_key"));
+    assert!(!is_secret("HOME"));
+    assert!(!is_secret("RUST_LOG"));
+}
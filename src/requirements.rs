@@ -0,0 +1,122 @@
+/*!
+This module is concerned with what a script requires of the system it runs on, as declared in its manifest:
+
+```toml
+[package.metadata.rust-script]
+require-os = ["linux", "macos"]
+min-version = "0.36"
+```
+
+The requirements are checked before the script is built, so that a script meant for another system fails with a clear error rather than somewhere in its build.
+*/
+use crate::error::{FailureKind, MainError, MainResult};
+
+/**
+Checks the requirements declared in a manifest against this system and `rust-script`.
+*/
+pub fn check(manifest: &str) -> MainResult<()> {
+    check_against(
+        manifest,
+        std::env::consts::OS,
+        std::env::consts::FAMILY,
+        option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"),
+    )
+}
+
+fn check_against(manifest: &str, os: &str, family: &str, version: &str) -> MainResult<()> {
+    let mani: toml::value::Table =
+        toml::from_str(manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+    let Some(requirements) = mani
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("rust-script"))
+    else {
+        return Ok(());
+    };
+    let unmet = |msg: String| MainError::with_kind(FailureKind::Usage, msg);
+
+    if let Some(require_os) = requirements.get("require-os") {
+        let oses = require_os
+            .as_array()
+            .and_then(|oses| {
+                oses.iter()
+                    .map(|os| os.as_str())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or("`require-os` must be a list of operating systems")?;
+        // Families, such as `unix`, are accepted as well as specific systems.
+        if !oses
+            .iter()
+            .any(|&required| required == os || required == family)
+        {
+            return Err(unmet(format!(
+                "this script requires {}, but this is {}",
+                oses.join(" or "),
+                os
+            )));
+        }
+    }
+
+    if let Some(min_version) = requirements.get("min-version") {
+        let min_version = min_version
+            .as_str()
+            .ok_or("`min-version` must be a version string")?;
+        let required = parse_version(min_version)
+            .ok_or_else(|| format!("invalid `min-version`: {}", min_version))?;
+        // A version that can't be determined is assumed to be recent enough.
+        if parse_version(version).is_some_and(|version| version < required) {
+            return Err(unmet(format!(
+                "this script requires rust-script {} or newer, but this is {}",
+                min_version, version
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// Parse a version such as `0.36` or `0.36.0` into its major, minor and patch parts.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split('-').next()?;
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+#[test]
+fn test_check() {
+    let manifest = r#"
+[package.metadata.rust-script]
+require-os = ["linux", "macos"]
+min-version = "0.36"
+"#;
+    assert!(check_against(manifest, "linux", "unix", "0.36.0").is_ok());
+    assert!(check_against(manifest, "macos", "unix", "1.2.3").is_ok());
+    let err = check_against(manifest, "windows", "windows", "0.36.0").unwrap_err();
+    assert_eq!(err.exit_code(), 2);
+    assert!(err.to_string().contains("requires linux or macos"));
+    let err = check_against(manifest, "linux", "unix", "0.35.1").unwrap_err();
+    assert!(err.to_string().contains("rust-script 0.36 or newer"));
+    assert!(check_against(manifest, "linux", "unix", "unknown").is_ok());
+
+    assert!(check_against(
+        "[package.metadata.rust-script]\nrequire-os = [\"unix\"]\n",
+        "freebsd",
+        "unix",
+        "0.36.0"
+    )
+    .is_ok());
+    assert!(check_against(
+        "[package.metadata.rust-script]\nrequire-os = \"linux\"\n",
+        "linux",
+        "unix",
+        "0.36.0"
+    )
+    .is_err());
+    assert!(check_against("[dependencies]\nregex = \"1\"\n", "linux", "unix", "0.36.0").is_ok());
+}
@@ -0,0 +1,247 @@
+/*!
+This module is concerned with scripts depending on other scripts, as in `helper = { script = "helper.rs" }` in the manifest of a script.
+
+Each script a script depends on becomes a library package inside the package of the script: the other script is the library, and its embedded manifest is the manifest of the library.  The dependency is then an ordinary path dependency on that package.
+*/
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use sha1::{Digest, Sha1};
+
+use crate::error::{MainError, MainResult};
+use crate::manifest;
+
+/// Directory of the generated library packages, inside the package of the script.
+pub const DIR: &str = "script-deps";
+
+/// Tables of a manifest that list dependencies.
+const DEPENDENCY_TABLES: [&str; 3] = ["build-dependencies", "dependencies", "dev-dependencies"];
+
+/**
+A library package generated for a script dependency.
+*/
+#[derive(Debug)]
+pub struct Package {
+    /// Name of the directory of the package, inside `DIR`.
+    pub dir: String,
+
+    /// The package manifest contents.
+    pub manifest: String,
+
+    /// Path of the script the package is generated for.
+    pub source: PathBuf,
+}
+
+/**
+Loads the packages generated for the script dependencies of the package in `pkg_path`, if any.
+*/
+pub fn load(pkg_path: &Path) -> MainResult<Vec<Package>> {
+    let entries = match fs::read_dir(pkg_path.join(DIR)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut packages = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let manifest = fs::read_to_string(entry.path().join("Cargo.toml"))?;
+        let mani: toml::value::Table =
+            toml::from_str(&manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+        let source = mani
+            .get("lib")
+            .and_then(|lib| lib.get("path"))
+            .and_then(|path| path.as_str())
+            .ok_or_else(|| format!("no library in script dependency {:?}", entry.path()))?
+            .into();
+        packages.push(Package {
+            dir: entry.file_name().to_string_lossy().into_owned(),
+            manifest,
+            source,
+        });
+    }
+    packages.sort_by(|a, b| a.dir.cmp(&b.dir));
+    Ok(packages)
+}
+
+/**
+Computes a digest of the scripts the packages are generated for, so that changing any of them changes the digest.
+*/
+pub fn sources_digest(packages: &[Package]) -> String {
+    let mut hasher = Sha1::new();
+    for package in packages {
+        hasher.update(format!("script_dep={};", package.source.display()));
+        // A script that can't be read changes the digest as well.
+        hasher.update(fs::read(&package.source).unwrap_or_default());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/**
+Replaces the script dependencies in a manifest by path dependencies on library packages, returning the new manifest and the packages to generate.
+
+Paths to scripts must be absolute, as they are after the manifest paths have been fixed up.
+*/
+pub fn resolve(manifest: &str) -> MainResult<(String, Vec<Package>)> {
+    let mut mani: toml::value::Table =
+        toml::from_str(manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+    let mut packages = Vec::new();
+    if !resolve_table(&mut mani, &format!("{}/", DIR), &mut packages)? {
+        return Ok((manifest.into(), packages));
+    }
+    Ok((mani.to_string(), packages))
+}
+
+// Replace the script dependencies in the manifest, returning whether there were any.  `prefix`
+// is the path from the package of the manifest to the generated packages.
+fn resolve_table(
+    mani: &mut toml::value::Table,
+    prefix: &str,
+    packages: &mut Vec<Package>,
+) -> MainResult<bool> {
+    let mut found = false;
+    for deps in dependency_tables(mani) {
+        for (name, dep) in deps.iter_mut() {
+            let toml::Value::Table(dep) = dep else {
+                continue;
+            };
+            let Some(script) = dep.remove("script") else {
+                continue;
+            };
+            let script = script
+                .as_str()
+                .ok_or_else(|| format!("the script of dependency `{}` must be a path", name))?
+                .to_string();
+            let package_name = dep
+                .get("package")
+                .and_then(|v| v.as_str())
+                .unwrap_or(name.as_str())
+                .to_string();
+
+            let dir = format!("{}-{}", package_name, digest(&script));
+            dep.insert("path".into(), format!("{}{}", prefix, dir).into());
+            found = true;
+
+            // A script depended on more than once, or by itself, is only generated once.
+            if packages.iter().any(|package| package.dir == dir) {
+                continue;
+            }
+            info!(
+                "generating package {:?} for script dependency {:?}",
+                dir, script
+            );
+            let index = packages.len();
+            packages.push(Package {
+                dir,
+                manifest: String::new(),
+                source: script.clone().into(),
+            });
+
+            let tag = |err: MainError| {
+                MainError::Tag(
+                    format!("in script dependency {}", script).into(),
+                    Box::new(err),
+                )
+            };
+            let content = fs::read_to_string(&script).map_err(|err| tag(err.into()))?;
+            let mut lib_mani =
+                manifest::library_manifest(&package_name, Path::new(&script), &content)
+                    .map_err(tag)?;
+            resolve_table(&mut lib_mani, "../", packages)?;
+            packages[index].manifest = lib_mani.to_string();
+        }
+    }
+    Ok(found)
+}
+
+// The tables of the manifest that list dependencies, including those of specific targets.
+fn dependency_tables(mani: &mut toml::value::Table) -> Vec<&mut toml::value::Table> {
+    let mut tables = Vec::new();
+    for (key, value) in mani.iter_mut() {
+        let toml::Value::Table(table) = value else {
+            continue;
+        };
+        if DEPENDENCY_TABLES.contains(&key.as_str()) {
+            tables.push(table);
+        } else if key == "target" {
+            for (_, target) in table.iter_mut() {
+                if let toml::Value::Table(target) = target {
+                    tables.extend(dependency_tables(target));
+                }
+            }
+        }
+    }
+    tables
+}
+
+// Short digest of the path of a script, so that different scripts of the same name get
+// different packages.
+fn digest(path: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(path);
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+#[test]
+fn test_resolve() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let helper = dir.path().join("helper.rs");
+    let util = dir.path().join("util.rs");
+    fs::write(
+        &helper,
+        format!(
+            "//! ```cargo\n//! [dependencies]\n//! util = {{ script = {:?} }}\n//! ```\npub fn help() {{}}\n",
+            util
+        ),
+    )
+    .unwrap();
+    fs::write(&util, "pub fn util() {}\n").unwrap();
+
+    let manifest = format!(
+        "[dependencies]\nhelper = {{ script = {:?}, package = \"helper-lib\" }}\ntime = \"0.1.25\"\n",
+        helper
+    );
+    let (manifest, packages) = resolve(&manifest).unwrap();
+    let mani: toml::value::Table = toml::from_str(&manifest).unwrap();
+    let dep = &mani["dependencies"]["helper"];
+    let helper_dir = format!("helper-lib-{}", digest(helper.to_str().unwrap()));
+    assert_eq!(dep.get("script"), None);
+    assert_eq!(
+        dep["path"].as_str(),
+        Some(&*format!("script-deps/{}", helper_dir))
+    );
+    assert_eq!(mani["dependencies"]["time"].as_str(), Some("0.1.25"));
+
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].dir, helper_dir);
+    let lib_mani: toml::value::Table = toml::from_str(&packages[0].manifest).unwrap();
+    assert_eq!(lib_mani["package"]["name"].as_str(), Some("helper-lib"));
+    assert_eq!(lib_mani["lib"]["path"].as_str(), helper.to_str());
+    assert_eq!(
+        lib_mani["dependencies"]["util"]["path"].as_str(),
+        Some(&*format!("../{}", packages[1].dir))
+    );
+
+    // The packages can be loaded back once they're written, to check whether they're current.
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    for package in &packages {
+        let dir = pkg_dir.path().join(DIR).join(&package.dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Cargo.toml"), &package.manifest).unwrap();
+    }
+    let mut loaded = load(pkg_dir.path()).unwrap();
+    loaded.sort_by(|a, b| a.source.cmp(&b.source));
+    assert_eq!(loaded[0].source, helper);
+    assert_eq!(loaded[1].source, util);
+    let digest = sources_digest(&loaded);
+    fs::write(&util, "pub fn util() { println!(); }\n").unwrap();
+    assert_ne!(sources_digest(&loaded), digest);
+
+    assert_eq!(
+        resolve("[dependencies]\ntime = \"0.1.25\"\n")
+            .unwrap()
+            .1
+            .len(),
+        0
+    );
+}
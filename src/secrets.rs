@@ -0,0 +1,164 @@
+/*!
+This module implements `--with-secrets`: getting short-lived credentials for a script from a command, and revoking them with another once the script is done.
+
+The credentials command prints the credentials as environment variables, one `NAME=VALUE` per line, in the format of `.env` files.  They are only given to the script and the teardown command; `rust-script` itself neither keeps nor shows them.
+*/
+use std::process::{Command, Stdio};
+
+use log::info;
+
+use crate::error::{MainError, MainResult};
+
+/**
+Runs the credentials command, returning the environment variables it printed.
+*/
+pub fn provide(cmdline: &str) -> MainResult<Vec<(String, String)>> {
+    let mut cmd = command(cmdline)?;
+    // Its standard error is left alone, for prompts and progress.
+    cmd.stdin(Stdio::inherit()).stderr(Stdio::inherit());
+    info!("getting secrets from {:?}", cmd);
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(format!("secrets command failed with {}", output.status).into());
+    }
+    let output =
+        String::from_utf8(output.stdout).map_err(|_| "secrets command printed invalid UTF-8")?;
+    parse(&output)
+}
+
+/**
+Runs the teardown command, with the environment variables of the credentials.
+*/
+pub fn teardown(cmdline: &str, vars: &[(String, String)]) -> MainResult<()> {
+    let mut cmd = command(cmdline)?;
+    cmd.envs(vars.iter().map(|(name, value)| (name, value)));
+    info!("tearing down secrets with {:?}", cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        return Err(format!("secrets teardown command failed with {}", status).into());
+    }
+    Ok(())
+}
+
+/**
+Keeps `rust-script` running when the script is interrupted, as with Ctrl-C, so that the secrets are still torn down.  The script gets the interrupt as usual.
+
+Interrupts, including `SIGTERM` and `SIGHUP`, are ignored for the rest of the run, teardown included.
+*/
+pub fn survive_interrupts() -> MainResult<()> {
+    crate::interrupt::survive()
+}
+
+fn command(cmdline: &str) -> MainResult<Command> {
+    let words = shell_words::split(cmdline).map_err(|e| MainError::Other(Box::new(e)))?;
+    let (program, args) = words
+        .split_first()
+        .ok_or("The secrets command cannot be empty")?;
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+// Parse `NAME=VALUE` lines.  Errors never include the values, which are secret.
+fn parse(output: &str) -> MainResult<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (i, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .filter(|(name, _)| is_valid_name(name.trim()))
+            .ok_or_else(|| {
+                format!(
+                    "line {} of the secrets command output is not NAME=VALUE",
+                    i + 1
+                )
+            })?;
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|&(open, close)| {
+                value
+                    .strip_prefix(open)
+                    .and_then(|value| value.strip_suffix(close))
+            })
+            .unwrap_or(value);
+        vars.push((name.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[test]
+fn test_parse() {
+    let output = "\
+# Expires in 15 minutes
+AWS_ACCESS_KEY_ID=AKIAEXAMPLE
+export AWS_SECRET_ACCESS_KEY=\"abc=def\"
+AWS_SESSION_TOKEN='token'
+
+";
+    assert_eq!(
+        parse(output).unwrap(),
+        vec![
+            ("AWS_ACCESS_KEY_ID".to_string(), "AKIAEXAMPLE".to_string()),
+            ("AWS_SECRET_ACCESS_KEY".to_string(), "abc=def".to_string()),
+            ("AWS_SESSION_TOKEN".to_string(), "token".to_string()),
+        ]
+    );
+
+    let err = parse("TOKEN=ok\nhunter2\n").unwrap_err().to_string();
+    assert!(err.contains("line 2"));
+    assert!(!err.contains("hunter2"));
+    assert!(parse("1TOKEN=x").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_teardown_after_interrupt() {
+    use std::os::unix::process::ExitStatusExt;
+
+    // The interrupts go to a copy of this test run in a child process, not to the test harness.
+    let Some(revoked) = std::env::var_os("RUST_SCRIPT_TEST_REVOKED") else {
+        let dir = tempfile::TempDir::new().unwrap();
+        let revoked = dir.path().join("revoked");
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "secrets::test_teardown_after_interrupt",
+                "--exact",
+                "--quiet",
+            ])
+            .env("RUST_SCRIPT_TEST_REVOKED", &revoked)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}", status);
+        assert_eq!(std::fs::read_to_string(&revoked).unwrap(), "abc\n");
+        return;
+    };
+    let vars = vec![("TOKEN".to_string(), "abc".to_string())];
+
+    // Like Ctrl-C, interrupt both this process and the script, with each signal in turn.
+    survive_interrupts().unwrap();
+    for &signal in crate::interrupt::SIGNALS {
+        let status = Command::new("sh")
+            .args(["-c", &format!("kill -{0} $PPID; kill -{0} $$", signal)])
+            .status()
+            .unwrap();
+        assert_eq!(status.signal(), Some(signal));
+    }
+
+    let teardown_cmd = format!(
+        "sh -c 'echo $TOKEN > \"$0\"' {}",
+        shell_words::quote(&revoked.to_string_lossy())
+    );
+    teardown(&teardown_cmd, &vars).unwrap();
+}
@@ -0,0 +1,203 @@
+/*!
+This module implements `--snapshot`: running a script with recorded inputs, and comparing what it did with snapshots of what it did before.
+
+A snapshot directory holds one or more cases, each a set of files sharing a name:
+
+- `<case>.args`: arguments to pass to the script, one per line.
+- `<case>.stdin`: what to feed the script on standard input.
+- `<case>.stdout`, `<case>.stderr` and `<case>.status`: what the script wrote to standard output and standard error, and its exit code.
+
+A case is any name with an `.args` or a `.stdin` file.  A directory without any has a single case, `default`, which runs the script without arguments or input.  With `--snapshot-update`, the snapshots are written instead of compared.
+*/
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use log::info;
+
+use crate::error::{MainError, MainResult};
+
+/// The name of the case of a directory without any inputs.
+const DEFAULT_CASE: &str = "default";
+
+/**
+Runs every case in `dir`, with the script run by the commands `cmd` makes for the given arguments.
+
+Returns the exit code: 0 if all cases match their snapshots (or the snapshots were updated), and 1 otherwise.
+*/
+pub fn run(
+    dir: &Path,
+    update: bool,
+    mut cmd: impl FnMut(&[String]) -> MainResult<Command>,
+) -> MainResult<i32> {
+    if update {
+        fs::create_dir_all(dir)?;
+    }
+    let cases = cases(dir)?;
+    let mut failed = 0;
+    for case in &cases {
+        let args = match fs::read_to_string(dir.join(format!("{}.args", case))) {
+            Ok(args) => args.lines().map(String::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let stdin = match fs::read(dir.join(format!("{}.stdin", case))) {
+            Ok(stdin) => stdin,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut cmd = cmd(&args)?;
+        info!("running snapshot case {}: {:?}", case, cmd);
+        let actual = capture(&mut cmd, stdin)?;
+
+        if update {
+            for (ext, content) in &actual {
+                fs::write(dir.join(format!("{}.{}", case, ext)), content)?;
+            }
+            println!("updated {}", case);
+            continue;
+        }
+
+        let mut differences = Vec::new();
+        for (ext, content) in &actual {
+            let path = dir.join(format!("{}.{}", case, ext));
+            match fs::read(&path) {
+                Ok(expected) if expected == *content => {}
+                Ok(expected) => differences.push(format!(
+                    "{}: {}",
+                    ext,
+                    first_difference(
+                        &String::from_utf8_lossy(&expected),
+                        &String::from_utf8_lossy(content)
+                    )
+                )),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    differences.push(format!("{}: no snapshot in {}", ext, path.display()))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if differences.is_empty() {
+            println!("ok {}", case);
+        } else {
+            failed += 1;
+            println!("FAILED {}", case);
+            for difference in differences {
+                println!("    {}", difference);
+            }
+        }
+    }
+
+    if update {
+        return Ok(0);
+    }
+    println!(
+        "{} of {} snapshot cases passed{}",
+        cases.len() - failed,
+        cases.len(),
+        if failed > 0 {
+            "; run with --snapshot-update to accept the new output"
+        } else {
+            ""
+        }
+    );
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+// The names of the cases in the directory, in order.
+fn cases(dir: &Path) -> MainResult<Vec<String>> {
+    let entries = fs::read_dir(dir).map_err(|err| {
+        MainError::Tag(
+            format!("could not read snapshots in {}", dir.display()).into(),
+            Box::new(err.into()),
+        )
+    })?;
+    let mut cases = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_input = path
+            .extension()
+            .is_some_and(|ext| ext == "args" || ext == "stdin");
+        if let Some(case) = path.file_stem().and_then(|stem| stem.to_str()) {
+            if is_input && !cases.iter().any(|c| c == case) {
+                cases.push(case.to_string());
+            }
+        }
+    }
+    if cases.is_empty() {
+        cases.push(DEFAULT_CASE.into());
+    }
+    cases.sort();
+    Ok(cases)
+}
+
+// Run the command with the given input, returning what it did as the contents of the snapshot
+// files, by extension.
+fn capture(cmd: &mut Command, stdin: Vec<u8>) -> MainResult<[(&'static str, Vec<u8>); 3]> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Feed the input from another thread, so a script that writes before it's done reading can't
+    // deadlock with us.
+    let mut child_stdin = child.stdin.take().expect("child stdin is piped");
+    let writer = std::thread::spawn(move || child_stdin.write_all(&stdin));
+    let output = child.wait_with_output()?;
+    match writer.join().expect("snapshot input thread panicked") {
+        // A script doesn't have to read all of its input.
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(err.into()),
+        _ => {}
+    }
+
+    let status = match output.status.code() {
+        Some(code) => format!("{}\n", code),
+        None => "killed\n".into(),
+    };
+    Ok([
+        ("stdout", output.stdout),
+        ("stderr", output.stderr),
+        ("status", status.into_bytes()),
+    ])
+}
+
+// Describe where the actual output first differs from the expected one.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => {}
+            (Some(e), Some(a)) => {
+                return format!("line {}: expected {:?}, got {:?}", line, e, a);
+            }
+            (Some(e), None) => return format!("line {}: expected {:?}, got nothing", line, e),
+            (None, Some(a)) => return format!("line {}: expected nothing, got {:?}", line, a),
+            (None, None) => break,
+        }
+    }
+    "differs in line endings".into()
+}
+
+#[test]
+fn test_cases() {
+    let dir = tempfile::TempDir::new().unwrap();
+    assert_eq!(cases(dir.path()).unwrap(), vec![DEFAULT_CASE]);
+
+    for file in ["b.stdin", "b.stdout", "a.args", "a.stdin", "c.stdout"] {
+        fs::write(dir.path().join(file), "").unwrap();
+    }
+    assert_eq!(cases(dir.path()).unwrap(), vec!["a", "b"]);
+
+    assert_eq!(
+        first_difference("one\ntwo\n", "one\n2\n"),
+        "line 2: expected \"two\", got \"2\""
+    );
+    assert_eq!(
+        first_difference("one\n", "one\ntwo\n"),
+        "line 2: expected nothing, got \"two\""
+    );
+}
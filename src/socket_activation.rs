@@ -0,0 +1,202 @@
+/*!
+This module implements `--socket-activate`: binding sockets for a script that serves on them, and passing them to it as systemd does, so that a script daemon doesn't need any plumbing of its own for it.
+
+The sockets are passed with the `LISTEN_FDS` protocol: they are the file descriptors from 3 on, `LISTEN_FDS` is set to how many there are, and `LISTEN_PID` to the process ID of the script, so it can tell they're meant for it.  They're put in place by running the script through the `trampoline`.  Libraries like `listenfd` pick them up from there.  A script that is run again and again, as with `--watch`, is passed the same sockets every time; they stay bound in between, so connections made while the script restarts wait for it rather than being refused.
+
+Only Unix is supported.
+*/
+use std::process::Command;
+
+use crate::error::MainResult;
+
+/**
+Sockets bound for the script.
+*/
+#[derive(Debug, Default)]
+pub struct Listeners {
+    /// The file descriptors of the sockets.  They stay open for as long as `rust-script` runs.
+    #[cfg(unix)]
+    fds: Vec<std::os::fd::RawFd>,
+}
+
+impl Listeners {
+    /**
+    Binds a socket for each address, or takes over the sockets shared by the `rust-script` running this one with `--watch`.
+
+    An address is a TCP address like `127.0.0.1:8080`, or a port alone to listen on it on the loopback interface, or the path of a Unix domain socket, given as `unix:PATH` or any path with a `/` in it.
+    */
+    pub fn bind(addrs: &[String]) -> MainResult<Self> {
+        #[cfg(unix)]
+        {
+            if let Ok(inherited) = std::env::var(unix::INHERITED_VAR) {
+                return unix::inherit(&inherited);
+            }
+            let fds = addrs
+                .iter()
+                .map(|addr| unix::bind(addr))
+                .collect::<MainResult<_>>()?;
+            Ok(Listeners { fds })
+        }
+        #[cfg(not(unix))]
+        {
+            if addrs.is_empty() {
+                return Ok(Listeners {});
+            }
+            use crate::error::{FailureKind, MainError};
+            Err(MainError::with_kind(
+                FailureKind::Usage,
+                "--socket-activate is only supported on Unix",
+            ))
+        }
+    }
+
+    /**
+    Turns the command running the script into one passing the sockets to it.
+    */
+    pub fn pass_to(self, cmd: Command, arg0: Option<&str>) -> MainResult<Command> {
+        #[cfg(unix)]
+        {
+            unix::pass_to(&self.fds, cmd, arg0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = arg0;
+            Ok(cmd)
+        }
+    }
+
+    /**
+    Lets the `rust-script` run by the command take over the sockets.
+    */
+    pub fn share_with(&self, cmd: &mut Command) -> MainResult<()> {
+        #[cfg(unix)]
+        {
+            unix::share_with(&self.fds, cmd)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = cmd;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::fs;
+    use std::io;
+    use std::net::TcpListener;
+    use std::os::fd::{IntoRawFd, RawFd};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::process::Command;
+
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    use super::Listeners;
+    use crate::error::{FailureKind, MainError, MainResult};
+    use crate::trampoline;
+
+    /// The environment variable the sockets are shared in with the `rust-script` run by `--watch`,
+    /// as a comma-separated list of file descriptors.
+    pub const INHERITED_VAR: &str = "RUST_SCRIPT_LISTEN_FDS";
+
+    /// The first file descriptor passed, after standard input, output and error.
+    const LISTEN_FDS_START: RawFd = 3;
+
+    pub fn bind(addr: &str) -> MainResult<RawFd> {
+        let bound = match addr.strip_prefix("unix:") {
+            Some(path) => bind_unix(Path::new(path)),
+            None if addr.contains('/') => bind_unix(Path::new(addr)),
+            None => match addr.parse::<u16>() {
+                Ok(port) => TcpListener::bind(("127.0.0.1", port)).map(IntoRawFd::into_raw_fd),
+                Err(_) => TcpListener::bind(addr).map(IntoRawFd::into_raw_fd),
+            },
+        };
+        bound.map_err(|err| {
+            MainError::with_kind(
+                FailureKind::Usage,
+                MainError::Tag(
+                    format!("could not listen on {}", addr).into(),
+                    Box::new(err.into()),
+                ),
+            )
+        })
+    }
+
+    // Bind a Unix domain socket, replacing a socket left behind by an earlier run.
+    fn bind_unix(path: &Path) -> io::Result<RawFd> {
+        if fs::symlink_metadata(path).is_ok_and(|md| md.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        UnixListener::bind(path).map(IntoRawFd::into_raw_fd)
+    }
+
+    pub fn inherit(inherited: &str) -> MainResult<Listeners> {
+        let mut fds = Vec::new();
+        for fd in inherited.split(',').filter(|fd| !fd.is_empty()) {
+            let fd: RawFd = fd
+                .parse()
+                .map_err(|_| format!("invalid {}: {}", INHERITED_VAR, inherited))?;
+            // Keep them from whatever else is run, like Cargo, until they're passed to the script.
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(io::Error::from)?;
+            fds.push(fd);
+        }
+        Ok(Listeners { fds })
+    }
+
+    pub fn pass_to(fds: &[RawFd], cmd: Command, arg0: Option<&str>) -> MainResult<Command> {
+        // Make inheritable duplicates out of the way of where they're passed, so that putting one
+        // in place can't close another.
+        let count = fds.len() as RawFd;
+        let high = fds
+            .iter()
+            .map(|&fd| fcntl(fd, FcntlArg::F_DUPFD(LISTEN_FDS_START + count)))
+            .collect::<nix::Result<Vec<_>>>()
+            .map_err(io::Error::from)?;
+
+        let mut activated = trampoline::wrap(cmd, &high, arg0)?;
+        activated
+            .env("LISTEN_FDS", count.to_string())
+            .env_remove("LISTEN_FDNAMES")
+            .env_remove(INHERITED_VAR);
+        Ok(activated)
+    }
+
+    pub fn share_with(fds: &[RawFd], cmd: &mut Command) -> MainResult<()> {
+        if fds.is_empty() {
+            return Ok(());
+        }
+        for &fd in fds {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())).map_err(io::Error::from)?;
+        }
+        let list: Vec<String> = fds.iter().map(|fd| fd.to_string()).collect();
+        cmd.env(INHERITED_VAR, list.join(","));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("script.sock");
+        let fd = bind(&format!("unix:{}", path.display())).unwrap();
+        nix::unistd::close(fd).unwrap();
+        // The socket file is left behind, and replaced on the next run.
+        let fd = bind(path.to_str().unwrap()).unwrap();
+        nix::unistd::close(fd).unwrap();
+
+        let fd = bind("127.0.0.1:0").unwrap();
+        let listeners = Listeners { fds: vec![fd] };
+        let cmd = listeners
+            .pass_to(Command::new("script"), Some("./script.rs"))
+            .unwrap();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args[0], trampoline::ARG);
+        assert!(args.iter().any(|arg| *arg == "--arg0=./script.rs"));
+        assert!(args.iter().any(|arg| *arg == "script"));
+        assert!(cmd
+            .get_envs()
+            .any(|(name, value)| name == "LISTEN_FDS" && value == Some("1".as_ref())));
+    }
+}
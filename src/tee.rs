@@ -0,0 +1,143 @@
+/*!
+This module is concerned with copying the output of an executed script to a file.
+*/
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::error::MainResult;
+use crate::platform;
+use log::info;
+
+/**
+Where, and how, the output of the script should be recorded.
+*/
+#[derive(Debug)]
+pub struct Tee {
+    /// File the output is appended to.
+    pub path: PathBuf,
+
+    /// Should the output also be shown on the console?
+    pub echo: bool,
+
+    /// Should each recorded line be prefixed with a UTC timestamp?
+    pub timestamps: bool,
+}
+
+/**
+Runs the command, copying its standard output and standard error to the tee file.
+
+Returns the exit code of the command.
+*/
+pub fn run(cmd: &mut Command, tee: &Tee) -> MainResult<i32> {
+    info!("tee: {:?}", tee);
+    if let Some(dir) = tee.path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&tee.path)?;
+    let file = Arc::new(Mutex::new(file));
+
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    let stdout = child.stdout.take().expect("child stdout is piped");
+    let stderr = child.stderr.take().expect("child stderr is piped");
+
+    let copy_stdout = {
+        let file = file.clone();
+        let echo = tee.echo;
+        let timestamps = tee.timestamps;
+        thread::spawn(move || copy_lines(stdout, io::stdout(), echo, &file, timestamps))
+    };
+    let copy_stderr = {
+        let echo = tee.echo;
+        let timestamps = tee.timestamps;
+        thread::spawn(move || copy_lines(stderr, io::stderr(), echo, &file, timestamps))
+    };
+
+    let status = child.wait()?;
+    for copy in [copy_stdout, copy_stderr] {
+        copy.join().expect("output copying thread panicked")?;
+    }
+
+    Ok(status.code().unwrap_or(1))
+}
+
+// Copy `from` line by line into the tee file, and to `console` if `echo` is set.
+fn copy_lines<R: Read, W: Write>(
+    from: R,
+    mut console: W,
+    echo: bool,
+    file: &Mutex<fs::File>,
+    timestamps: bool,
+) -> io::Result<()> {
+    let mut from = BufReader::new(from);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if from.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+
+        if echo {
+            console.write_all(&line)?;
+            console.flush()?;
+        }
+
+        let mut file = file.lock().expect("tee file lock poisoned");
+        if timestamps {
+            write!(file, "[{}] ", format_timestamp(platform::current_time()))?;
+        }
+        file.write_all(&line)?;
+    }
+}
+
+/**
+Formats milliseconds since the UNIX epoch as an RFC 3339 UTC timestamp.
+
+See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days> for the date conversion.
+*/
+fn format_timestamp(millis: u128) -> String {
+    let secs = (millis / 1000) as i64;
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        millis % 1000
+    )
+}
+
+#[test]
+fn test_format_timestamp() {
+    assert_eq!(format_timestamp(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(
+        format_timestamp(951_782_400_123),
+        "2000-02-29T00:00:00.123Z"
+    );
+    assert_eq!(
+        format_timestamp(1_700_000_000_000),
+        "2023-11-14T22:13:20.000Z"
+    );
+}
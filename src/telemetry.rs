@@ -0,0 +1,231 @@
+/*!
+This module contains an opt-in exporter of OpenTelemetry spans for the phases of a `rust-script` run.
+
+It is disabled unless the `RUST_SCRIPT_OTLP_ENDPOINT` environment variable is set to an OTLP/HTTP collector URL such as `http://localhost:4318`.  Spans are sent as OTLP JSON once the run is over.  Only plain `http://` endpoints are supported.
+*/
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use sha1::{Digest, Sha1};
+
+use crate::consts;
+
+/// The environment variable holding the collector endpoint.
+const ENDPOINT_ENV: &str = "RUST_SCRIPT_OTLP_ENDPOINT";
+
+/// How long to wait on the collector before giving up.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Span {
+    name: &'static str,
+    span_id: String,
+    start_ns: u128,
+    end_ns: u128,
+}
+
+struct Trace {
+    trace_id: String,
+    root_span_id: String,
+    start_ns: u128,
+    spans: Vec<Span>,
+}
+
+static ENDPOINT: OnceLock<Option<String>> = OnceLock::new();
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+fn endpoint() -> Option<&'static str> {
+    ENDPOINT
+        .get_or_init(|| {
+            std::env::var(ENDPOINT_ENV)
+                .ok()
+                .filter(|endpoint| !endpoint.is_empty())
+        })
+        .as_deref()
+}
+
+/// Is span export enabled?
+pub fn enabled() -> bool {
+    endpoint().is_some()
+}
+
+/**
+Runs `f`, recording a span with the given name around it if export is enabled.
+*/
+pub fn span<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return f();
+    }
+
+    let start_ns = now_ns();
+    let result = f();
+    let end_ns = now_ns();
+
+    let mut trace = TRACE.lock().expect("telemetry lock poisoned");
+    let trace = trace.get_or_insert_with(|| Trace {
+        trace_id: new_id(32),
+        root_span_id: new_id(16),
+        start_ns,
+        spans: Vec::new(),
+    });
+    trace.spans.push(Span {
+        name,
+        span_id: new_id(16),
+        start_ns,
+        end_ns,
+    });
+    result
+}
+
+/**
+Sends the recorded spans, as children of a root `rust-script` span, to the collector.
+
+Failures are only logged; telemetry should never break a run.
+*/
+pub fn export() {
+    let Some(endpoint) = endpoint() else {
+        return;
+    };
+    let Some(trace) = TRACE.lock().expect("telemetry lock poisoned").take() else {
+        return;
+    };
+
+    let body = trace_json(&trace, now_ns());
+    match post_json(endpoint, &body) {
+        Ok(status) if (200..300).contains(&status) => {
+            info!("exported {} spans to {}", trace.spans.len() + 1, endpoint)
+        }
+        Ok(status) => error!("span export to {} failed with HTTP {}", endpoint, status),
+        Err(err) => error!("span export to {} failed: {}", endpoint, err),
+    }
+}
+
+fn now_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
+
+// A new random-enough hex id of the given length.
+fn new_id(len: usize) -> String {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut hasher = Sha1::new();
+    hasher.update(now_ns().to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    let mut id = format!("{:x}", hasher.finalize());
+    while id.len() < len {
+        id = id.repeat(2);
+    }
+    id.truncate(len);
+    id
+}
+
+fn trace_json(trace: &Trace, end_ns: u128) -> String {
+    let span_json = |name: &str, span_id: &str, parent: Option<&str>, start: u128, end: u128| {
+        format!(
+            r#"{{"traceId":"{}","spanId":"{}","parentSpanId":"{}","name":"{}","kind":1,"startTimeUnixNano":"{}","endTimeUnixNano":"{}"}}"#,
+            trace.trace_id,
+            span_id,
+            parent.unwrap_or(""),
+            name,
+            start,
+            end
+        )
+    };
+
+    let mut spans = vec![span_json(
+        consts::PROGRAM_NAME,
+        &trace.root_span_id,
+        None,
+        trace.start_ns,
+        end_ns,
+    )];
+    for span in &trace.spans {
+        spans.push(span_json(
+            span.name,
+            &span.span_id,
+            Some(&trace.root_span_id),
+            span.start_ns,
+            span.end_ns,
+        ));
+    }
+
+    format!(
+        r#"{{"resourceSpans":[{{"resource":{{"attributes":[{{"key":"service.name","value":{{"stringValue":"{}"}}}}]}},"scopeSpans":[{{"scope":{{"name":"{}","version":"{}"}},"spans":[{}]}}]}}]}}"#,
+        consts::PROGRAM_NAME,
+        consts::PROGRAM_NAME,
+        option_env!("CARGO_PKG_VERSION").unwrap_or("unknown"),
+        spans.join(",")
+    )
+}
+
+// POST a JSON body to an `http://` URL, returning the HTTP status code.
+fn post_json(url: &str, body: &str) -> std::io::Result<u16> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// endpoints are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) if i + 1 < rest.len() => (&rest[..i], &rest[i..]),
+        Some(i) => (&rest[..i], "/v1/traces"),
+        None => (rest, "/v1/traces"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let socket_addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid("could not resolve endpoint"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_addr, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("malformed HTTP response"))
+}
+
+#[test]
+fn test_trace_json() {
+    let trace = Trace {
+        trace_id: "t".repeat(32),
+        root_span_id: "r".repeat(16),
+        start_ns: 1,
+        spans: vec![Span {
+            name: "cargo build",
+            span_id: "s".repeat(16),
+            start_ns: 2,
+            end_ns: 3,
+        }],
+    };
+    let json = trace_json(&trace, 4);
+    assert!(json.starts_with(r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"rust-script"}}]}"#));
+    assert!(json.contains(r#""spanId":"rrrrrrrrrrrrrrrr","parentSpanId":"","name":"rust-script","kind":1,"startTimeUnixNano":"1","endTimeUnixNano":"4""#));
+    assert!(json.contains(r#""parentSpanId":"rrrrrrrrrrrrrrrr","name":"cargo build","kind":1,"startTimeUnixNano":"2","endTimeUnixNano":"3""#));
+}
@@ -1,9 +1,140 @@
 /*!
 This module contains code related to template support.
+
+Besides the built-in templates, users can keep templates of their own in the `templates` directory of the configuration directory, as `<name>.rs` files, and select them with `--template <name>`.  A user template of the same name as a built-in one takes its place.
 */
+use crate::arguments::TemplatesCommand;
+use crate::consts;
 use crate::error::{MainError, MainResult};
+use crate::platform;
 use regex::Regex;
 use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The built-in templates that can be selected by name, for expressions and loop scripts.
+pub const BUILTIN: [(&str, &str); 13] = [
+    ("expr", consts::EXPR_TEMPLATE),
+    ("expr-async", consts::EXPR_ASYNC_TEMPLATE),
+    ("expr-block", consts::EXPR_BLOCK_TEMPLATE),
+    ("expr-block-async", consts::EXPR_BLOCK_ASYNC_TEMPLATE),
+    ("expr-json", consts::EXPR_JSON_TEMPLATE),
+    ("expr-test", consts::EXPR_TEST_TEMPLATE),
+    ("loop", consts::LOOP_TEMPLATE),
+    ("loop-async", consts::LOOP_ASYNC_TEMPLATE),
+    ("loop-count", consts::LOOP_COUNT_TEMPLATE),
+    ("loop-chunk", consts::LOOP_CHUNK_TEMPLATE),
+    ("json", consts::JSON_TEMPLATE),
+    ("csv", consts::CSV_TEMPLATE),
+    ("parallel-map", consts::PARALLEL_MAP_TEMPLATE),
+];
+
+/**
+Loads the template with the given name, from the user's templates if there is one of that name, or else from the built-in ones.
+*/
+pub fn load(name: &str) -> MainResult<String> {
+    load_from(&platform::templates_path(), name)
+}
+
+fn load_from(dir: &Path, name: &str) -> MainResult<String> {
+    if name.is_empty() || name.contains(['/', '\\', '.']) {
+        return Err(format!("invalid template name `{}`", name).into());
+    }
+    match fs::read_to_string(dir.join(format!("{}.rs", name))) {
+        Ok(template) => return Ok(template),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    BUILTIN
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, template)| template.to_string())
+        .ok_or_else(|| {
+            format!(
+                "no template named `{}` in {} or among the built-in ones",
+                name,
+                dir.display()
+            )
+            .into()
+        })
+}
+
+/**
+Runs a `templates` subcommand.
+*/
+pub fn run(command: &TemplatesCommand) -> MainResult<i32> {
+    let dir = platform::templates_path();
+    match command {
+        TemplatesCommand::List => {
+            let user = user_templates(&dir)?;
+            println!("Built-in templates:");
+            for (name, _) in BUILTIN {
+                if user.iter().any(|user| user == name) {
+                    println!("  {} (overridden)", name);
+                } else {
+                    println!("  {}", name);
+                }
+            }
+            println!("Your templates, in {}:", dir.display());
+            for name in &user {
+                println!("  {}", name);
+            }
+        }
+        TemplatesCommand::Show(name) => print!("{}", load(name)?),
+        TemplatesCommand::Dump(out) => {
+            for written in dump(Path::new(out))? {
+                println!("{}", written.display());
+            }
+        }
+    }
+    Ok(0)
+}
+
+// The names of the user's templates, in order.
+fn user_templates(dir: &Path) -> MainResult<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "rs") {
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Write the built-in templates into `dir`, returning the files written.  Existing files are left
+// alone, so that dumping into the templates directory doesn't undo any changes made there.
+fn dump(dir: &Path) -> MainResult<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (name, template) in BUILTIN {
+        let path = dir.join(format!("{}.rs", name));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(template.trim_start().as_bytes())?;
+                written.push(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                eprintln!("Not overwriting {}", path.display());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(written)
+}
 
 pub fn expand(src: &str, subs: &HashMap<&str, &str>) -> MainResult<String> {
     let re_sub = Regex::new(r"#\{([A-Za-z_][A-Za-z0-9_]*)}").unwrap();
@@ -40,3 +171,43 @@ pub fn expand(src: &str, subs: &HashMap<&str, &str>) -> MainResult<String> {
     result.push_str(&src[anchor..]);
     Ok(result)
 }
+
+#[test]
+fn test_load() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::write(dir.path().join("timed.rs"), "fn main() { #{script} }").unwrap();
+    fs::write(dir.path().join("loop.rs"), "// mine\n#{script}").unwrap();
+
+    assert_eq!(
+        load_from(dir.path(), "timed").unwrap(),
+        "fn main() { #{script} }"
+    );
+    assert_eq!(load_from(dir.path(), "loop").unwrap(), "// mine\n#{script}");
+    assert_eq!(
+        load_from(dir.path(), "expr").unwrap(),
+        consts::EXPR_TEMPLATE
+    );
+    assert!(load_from(dir.path(), "missing").is_err());
+    assert!(load_from(dir.path(), "../timed").is_err());
+}
+
+#[test]
+fn test_dump() {
+    let dir = tempfile::TempDir::new().unwrap();
+    fs::write(dir.path().join("loop.rs"), "// mine\n#{script}").unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let written = dump(dir.path()).unwrap();
+    assert_eq!(written.len(), BUILTIN.len() - 1);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("loop.rs")).unwrap(),
+        "// mine\n#{script}"
+    );
+    assert_eq!(
+        load_from(dir.path(), "expr").unwrap(),
+        consts::EXPR_TEMPLATE.trim_start()
+    );
+    let mut names: Vec<_> = BUILTIN.iter().map(|(name, _)| name.to_string()).collect();
+    names.sort();
+    assert_eq!(user_templates(dir.path()).unwrap(), names);
+}
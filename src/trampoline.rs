@@ -0,0 +1,161 @@
+/*!
+This module is concerned with running a command through `rust-script` itself, for what has to be done in the process that becomes the script just before it starts: putting the sockets of `--socket-activate` where the script expects them, and giving the script the name it was run as when it's run through another program.
+
+`std::process::Command` can't do that without `unsafe` code, so instead `rust-script` runs itself with `ARG` as its first argument, does what's needed, and replaces itself with the command, which keeps its process ID.  As it's an ordinary command line, this also works through programs that exec the command they're given, such as `unshare` for `--no-network`.
+
+The command line is `rust-script ARG [--listen-fds=FD,..] [--arg0=NAME] -- PROGRAM ARGS..`.  Only Unix is supported.
+*/
+use std::ffi::OsString;
+use std::process::Command;
+
+use crate::error::MainResult;
+
+/**
+The first argument of `rust-script` when it's run as a trampoline.
+*/
+pub const ARG: &str = "--exec-trampoline";
+
+/// Option giving the file descriptors to put in place from 3 on, as a comma-separated list.
+const LISTEN_FDS_OPTION: &str = "--listen-fds=";
+
+/// Option giving the name to run the command as, instead of the program.
+const ARG0_OPTION: &str = "--arg0=";
+
+/**
+Turns the command into one run through the trampoline, with the given file descriptors put in place from 3 on, in that order, and run as `arg0`, if given.
+
+The file descriptors must be inheritable, and not among those they're put in place of.  The arguments, environment and working directory of the command are carried over, but not the name it's run as, which is what `arg0` is for.
+*/
+#[cfg(unix)]
+pub fn wrap(cmd: Command, fds: &[std::os::fd::RawFd], arg0: Option<&str>) -> MainResult<Command> {
+    let mut wrapped = Command::new(std::env::current_exe()?);
+    wrapped.arg(ARG);
+    if !fds.is_empty() {
+        let fds: Vec<String> = fds.iter().map(|fd| fd.to_string()).collect();
+        wrapped.arg(format!("{}{}", LISTEN_FDS_OPTION, fds.join(",")));
+    }
+    if let Some(arg0) = arg0 {
+        wrapped.arg(format!("{}{}", ARG0_OPTION, arg0));
+    }
+    wrapped
+        .arg("--")
+        .arg(cmd.get_program())
+        .args(cmd.get_args());
+    for (name, value) in cmd.get_envs() {
+        match value {
+            Some(value) => wrapped.env(name, value),
+            None => wrapped.env_remove(name),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    Ok(wrapped)
+}
+
+/**
+Runs the trampoline with the arguments following `ARG`.  This only returns if the command can't be run.
+*/
+pub fn run(args: Vec<OsString>) -> MainResult<i32> {
+    let (options, command) = parse(args)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        use nix::unistd::{close, dup2};
+
+        let mut cmd = Command::new(&command[0]);
+        cmd.args(&command[1..]);
+        if let Some(arg0) = &options.arg0 {
+            cmd.arg0(arg0);
+        }
+        if !options.listen_fds.is_empty() {
+            for (target, &fd) in (3..).zip(&options.listen_fds) {
+                // The duplicate is inherited, unlike what the original may be.
+                dup2(fd, target).map_err(std::io::Error::from)?;
+                close(fd).map_err(std::io::Error::from)?;
+            }
+            // The command replaces this process, so this is its process ID too.
+            cmd.env("LISTEN_PID", std::process::id().to_string());
+        }
+        let err = cmd.exec();
+        Err(crate::error::MainError::Tag(
+            format!("could not run {:?}", command[0]).into(),
+            Box::new(err.into()),
+        ))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (options, command);
+        Err("the trampoline is only supported on Unix".into())
+    }
+}
+
+/// What the trampoline was asked to do, besides running the command.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Options {
+    /// The file descriptors to put in place from 3 on.
+    listen_fds: Vec<i32>,
+    /// The name to run the command as.
+    arg0: Option<String>,
+}
+
+// Split the arguments following `ARG` into the options and the command.
+fn parse(args: Vec<OsString>) -> MainResult<(Options, Vec<OsString>)> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    for arg in args.by_ref() {
+        if arg == "--" {
+            break;
+        }
+        let arg = arg.to_string_lossy();
+        if let Some(arg0) = arg.strip_prefix(ARG0_OPTION) {
+            options.arg0 = Some(arg0.into());
+            continue;
+        }
+        let fds = arg
+            .strip_prefix(LISTEN_FDS_OPTION)
+            .ok_or_else(|| format!("unknown trampoline option `{}`", arg))?;
+        for fd in fds.split(',') {
+            let fd = fd
+                .parse()
+                .map_err(|_| format!("invalid file descriptor `{}`", fd))?;
+            options.listen_fds.push(fd);
+        }
+    }
+    let command: Vec<OsString> = args.collect();
+    if command.is_empty() {
+        return Err("the trampoline needs a command to run".into());
+    }
+    Ok((options, command))
+}
+
+#[test]
+fn test_parse() {
+    let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+    assert_eq!(
+        parse(args(&[
+            "--listen-fds=7,8",
+            "--arg0=./script.rs",
+            "--",
+            "script",
+            "--",
+            "arg"
+        ]))
+        .unwrap(),
+        (
+            Options {
+                listen_fds: vec![7, 8],
+                arg0: Some("./script.rs".into()),
+            },
+            args(&["script", "--", "arg"])
+        )
+    );
+    assert_eq!(
+        parse(args(&["--", "script"])).unwrap(),
+        (Options::default(), args(&["script"]))
+    );
+    assert!(parse(args(&["--listen-fds=x", "--", "script"])).is_err());
+    assert!(parse(args(&["--unknown", "--", "script"])).is_err());
+    assert!(parse(args(&["--"])).is_err());
+}
@@ -0,0 +1,120 @@
+/*!
+This module implements `--unused-deps`, which reports the dependencies a script declares but never uses.
+
+Dependencies tend to pile up in scripts whose manifests were copied from elsewhere, and each one slows down every cold build.  Rather than guessing from the source, the script is compiled with the compiler's `unused_crate_dependencies` lint, which knows exactly which crates were used.
+*/
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+use log::info;
+use regex::Regex;
+
+use crate::diagnostics;
+use crate::error::{FailureKind, MainError, MainResult};
+
+/// The arguments for `cargo rustc` enabling the lint for the script only.
+pub const LINT_ARGS: [&str; 3] = ["--", "-W", "unused-crate-dependencies"];
+
+/**
+Compiles the script with the given `cargo rustc` command, returning the dependencies in `manifest` it doesn't use.
+*/
+pub fn unused_deps(cmd: &mut Command, manifest: &str) -> MainResult<Vec<String>> {
+    cmd.arg(diagnostics::MESSAGE_FORMAT).args(LINT_ARGS);
+    info!("checking for unused dependencies: {:?}", cmd);
+    let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("child stdout is piped");
+
+    let mut rendered = Vec::new();
+    for line in BufReader::new(stdout).lines() {
+        if let Some(diagnostic) = diagnostics::rendered_diagnostic(&line?) {
+            rendered.push(diagnostic);
+        }
+    }
+    if !child.wait()?.success() {
+        for diagnostic in &rendered {
+            eprint!("{}", diagnostic);
+        }
+        return Err(MainError::with_kind(
+            FailureKind::Compile,
+            "Could not compile the script",
+        ));
+    }
+
+    let unused = unused_crates(&rendered);
+    Ok(declared_deps(manifest)?
+        .into_iter()
+        .filter(|dep| unused.contains(&dep.replace('-', "_")))
+        .collect())
+}
+
+/**
+Prints the unused dependencies.
+*/
+pub fn print_unused(unused: &[String]) {
+    if unused.is_empty() {
+        println!("No unused dependencies found.");
+        return;
+    }
+    for dep in unused {
+        println!("unused dependency: {}", dep);
+    }
+}
+
+// The crates the lint reported as unused, as they are named in the source.
+fn unused_crates(diagnostics: &[String]) -> BTreeSet<String> {
+    let re_unused = Regex::new(r"^warning: extern(?:al)? crate `(\w+)` (?:is )?unused").unwrap();
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| re_unused.captures(diagnostic))
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+// The names of the normal dependencies in the manifest, including those of specific targets.
+// Development and build dependencies aren't given to the script, so they can't be checked.
+fn declared_deps(manifest: &str) -> MainResult<Vec<String>> {
+    let mani: toml::value::Table =
+        toml::from_str(manifest).map_err(|e| MainError::Other(Box::new(e)))?;
+    let targets = mani
+        .get("target")
+        .and_then(|targets| targets.as_table())
+        .into_iter()
+        .flat_map(|targets| targets.values());
+    let tables = std::iter::once(&mani)
+        .chain(targets.filter_map(|target| target.as_table()))
+        .filter_map(|table| table.get("dependencies").and_then(|deps| deps.as_table()));
+    let mut deps: Vec<String> = tables.flat_map(|deps| deps.keys().cloned()).collect();
+    deps.sort();
+    deps.dedup();
+    Ok(deps)
+}
+
+#[test]
+fn test_unused_deps() {
+    let diagnostics = [
+        "warning: external crate `regex` unused in `script`: remove the dependency or add `use regex as _;`\n".to_string(),
+        "warning: extern crate `time` is unused in crate `script`\n".to_string(),
+        "warning: unused variable: `x`\n".to_string(),
+    ];
+    assert_eq!(
+        unused_crates(&diagnostics).into_iter().collect::<Vec<_>>(),
+        vec!["regex", "time"]
+    );
+
+    let manifest = r#"
+[dependencies]
+regex = "1"
+serde-json = "1"
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+
+[dev-dependencies]
+time = "0.1"
+"#;
+    assert_eq!(
+        declared_deps(manifest).unwrap(),
+        vec!["libc", "regex", "serde-json"]
+    );
+}
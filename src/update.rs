@@ -0,0 +1,77 @@
+/*!
+This module implements the `update` subcommand: updating the dependencies of a script to the newest versions its manifest allows, as `cargo update` does for a package, without clearing the whole cache.
+
+`cargo update` is run in the script's package in the cache, whose `Cargo.lock` is what the script is built with.  The copy of it kept next to the script, if there is one, is updated along with it, so that it isn't put back on the next run.  The next run then rebuilds the script with the new versions, even though the script itself hasn't changed.
+*/
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use log::info;
+
+use crate::arguments::UpdateCommand;
+use crate::error::MainResult;
+use crate::pin;
+
+/**
+Runs the `update` subcommand, passing `cargo_flags` on to Cargo.
+*/
+pub fn update(command: &UpdateCommand, cargo_flags: &[&str]) -> MainResult<i32> {
+    let pkg_path = pin::package_path(&command.script)?;
+    let pkg_lockfile = pkg_path.join("Cargo.lock");
+    let script_lockfile = crate::script_lockfile_path(Path::new(&command.script));
+    let kept_lockfile = fs::read_to_string(&script_lockfile).ok();
+
+    // Update from the versions the script is actually built with.
+    if let Some(lockfile) = &kept_lockfile {
+        crate::overwrite_file(&pkg_lockfile, lockfile)?;
+    }
+
+    let mut cmd = update_command(&pkg_path, &command.names, cargo_flags);
+    info!("updating the dependencies of {:?}: {:?}", pkg_path, cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        return Ok(status.code().unwrap_or(1));
+    }
+
+    if kept_lockfile.is_some() {
+        crate::overwrite_file(&script_lockfile, &fs::read_to_string(&pkg_lockfile)?)?;
+    }
+
+    // The binary is only rebuilt if it's older than the script or its manifest, so make the
+    // manifest newer than it.
+    let manifest_path = pkg_path.join("Cargo.toml");
+    fs::write(&manifest_path, fs::read(&manifest_path)?)?;
+    Ok(0)
+}
+
+// The `cargo update` command for the package, updating only the given dependencies if any.
+fn update_command(pkg_path: &Path, names: &[String], cargo_flags: &[&str]) -> Command {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("update").args(cargo_flags).current_dir(pkg_path);
+    for name in names {
+        cmd.arg("--package").arg(name);
+    }
+    cmd
+}
+
+#[test]
+fn test_update_command() {
+    let args = |names: &[&str], cargo_flags: &[&str]| {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let cmd = update_command(Path::new("/cache/package"), &names, cargo_flags);
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/cache/package")));
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(args(&[], &[]), ["update"]);
+    assert_eq!(
+        args(&["serde", "regex"], &[]),
+        ["update", "--package", "serde", "--package", "regex"]
+    );
+    assert_eq!(
+        args(&["serde"], &["--offline"]),
+        ["update", "--offline", "--package", "serde"]
+    );
+}
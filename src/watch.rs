@@ -0,0 +1,149 @@
+/*!
+This module implements `--watch`: running a script again whenever it, or a file next to it, changes.
+
+`rust-script` stays resident to supervise the script, which is run by a child `rust-script` with the same arguments, save `--watch`.  The files are polled for changes, as there's no portable way of being notified of them.
+
+On Unix, the child runs in a process group of its own, so that restarting it also stops the script when the child doesn't run it directly, as with `--tee` or `--retries`.  Interrupts are passed on to the group, as the terminal no longer sends them there.
+*/
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::info;
+
+use crate::error::MainResult;
+use crate::interrupt;
+use crate::socket_activation::Listeners;
+
+/// How often the files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Last-modified times of the watched files.
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+/**
+Runs the script, and runs it again whenever it, or any Rust file in its directory, changes.
+
+`args` are the arguments of the child `rust-script`.  The `listeners` for `--socket-activate` are passed on to each run of the script, and stay bound in between.  This only returns if the script can't be run.
+*/
+pub fn watch(script: &Path, args: &[OsString], listeners: &Listeners) -> MainResult<i32> {
+    let interrupted = Arc::new(AtomicUsize::new(0));
+    for &signal in interrupt::SIGNALS {
+        signal_hook::flag::register_usize(signal, interrupted.clone(), signal as usize)?;
+    }
+    let dir = script
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+
+    loop {
+        let snapshot = snapshot(script, &dir);
+        let mut child = Some(spawn(args, listeners)?);
+
+        while snapshot == self::snapshot(script, &dir) {
+            thread::sleep(POLL_INTERVAL);
+            let signal = interrupted.load(Ordering::SeqCst) as i32;
+            if signal != 0 {
+                if let Some(mut child) = child.take() {
+                    stop(&mut child, Some(signal))?;
+                }
+                signal_hook::low_level::emulate_default_handler(signal)?;
+            }
+            if let Some(status) = child.as_mut().map(Child::try_wait).transpose()?.flatten() {
+                eprintln!(
+                    "[rust-script] {} ({}), waiting for changes...",
+                    script.display(),
+                    status
+                );
+                child = None;
+            }
+        }
+
+        eprintln!("[rust-script] change detected, restarting...");
+        if let Some(mut child) = child {
+            stop(&mut child, None)?;
+        }
+    }
+}
+
+fn spawn(args: &[OsString], listeners: &Listeners) -> MainResult<Child> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(args);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    listeners.share_with(&mut cmd)?;
+    info!("running {:?}", cmd);
+    Ok(cmd.spawn()?)
+}
+
+// Send the child `rust-script`, and whatever it runs, `signal`, or kill them if there's none, and
+// wait for the child to exit.
+#[cfg(unix)]
+fn stop(child: &mut Child, signal: Option<i32>) -> MainResult<()> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    let signal = match signal {
+        Some(signal) => Signal::try_from(signal).map_err(|e| e.to_string())?,
+        None => Signal::SIGKILL,
+    };
+    // It may have exited in the meantime.
+    let _ = killpg(Pid::from_raw(child.id() as i32), signal);
+    child.wait()?;
+    Ok(())
+}
+
+// Kill the child `rust-script`, and wait for it to exit.  There are no process groups to stop the
+// script along with it, and interrupts reach them both anyway.
+#[cfg(not(unix))]
+fn stop(child: &mut Child, _signal: Option<i32>) -> MainResult<()> {
+    // It may have exited in the meantime.
+    let _ = child.kill();
+    child.wait()?;
+    Ok(())
+}
+
+// Last-modified times of the script, and the Rust files next to it.
+fn snapshot(script: &Path, dir: &Path) -> Snapshot {
+    let siblings = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("rs") | Some("ers")
+            )
+        });
+    std::iter::once(script.to_path_buf())
+        .chain(siblings)
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|md| md.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+#[test]
+fn test_snapshot() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("script.rs");
+    fs::write(&script, "mod helper;").unwrap();
+    fs::write(dir.path().join("helper.rs"), "").unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let files: Vec<_> = snapshot(&script, dir.path()).into_keys().collect();
+    assert_eq!(files, vec![dir.path().join("helper.rs"), script.clone()]);
+
+    let before = snapshot(&script, dir.path());
+    fs::remove_file(dir.path().join("helper.rs")).unwrap();
+    assert_ne!(snapshot(&script, dir.path()), before);
+}
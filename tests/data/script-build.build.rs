@@ -0,0 +1,4 @@
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(format!("{}/answer.rs", out_dir), "42").unwrap();
+}
@@ -0,0 +1,4 @@
+fn main() {
+    println!("--output--");
+    println!("{}", include!(concat!(env!("OUT_DIR"), "/answer.rs")));
+}
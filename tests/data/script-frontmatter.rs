@@ -0,0 +1,9 @@
+---cargo
+[dependencies]
+boolinator = "=0.1.0"
+---
+use boolinator::Boolinator;
+fn main() {
+    println!("--output--");
+    println!("{:?}", true.as_some(1));
+}
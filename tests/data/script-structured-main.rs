@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Answer {
+    answer: i32,
+}
+
+fn main() -> Result<Answer, String> {
+    match std::env::args().nth(1).as_deref() {
+        Some("fail") => Err("no answer".into()),
+        _ => Ok(Answer { answer: 42 }),
+    }
+}
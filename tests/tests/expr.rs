@@ -16,6 +16,58 @@ fn test_expr_comma() {
     .unwrap()
 }
 
+#[test]
+fn test_expr_multiple() {
+    let out = rust_script!("--stmt", "let x = 2", "-e", with_output_marker!("x * 3")).unwrap();
+    scan!(out.stdout_output();
+        ("6") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_expr_import() {
+    let out = rust_script!(
+        "-i",
+        "std::collections::BTreeSet",
+        "-e",
+        with_output_marker!("[3, 1, 3].into_iter().collect::<BTreeSet<_>>()")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("{1, 3}") => ()
+    )
+    .unwrap()
+}
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_expr_output_json() {
+    let out = rust_script!("--output", "json", "-e", r#"("ferris", [1, 2])"#).unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), r#"["ferris",[1,2]]"#);
+}
+
+#[test]
+fn test_expr_test() {
+    let out = rust_script!("--test-expr", "-e", "1 + 1 == 2").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), "");
+    let out = rust_script!("--test-expr", "-e", "1 + 1 == 3").unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    let out = rust_script!("--test-expr", "-e", "1 + 1").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), "2");
+}
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_expr_async() {
+    let out = rust_script!("-e", "tokio::task::spawn(async { 40 + 2 }).await?").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), "42");
+}
+
 #[test]
 fn test_expr_dnc() {
     let out = rust_script!("-e", "swing begin").unwrap();
@@ -62,3 +114,60 @@ fn test_expr_qmark() {
     )
     .unwrap();
 }
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_expr_extern_with_version() {
+    let out = rust_script!(
+        "--extern",
+        "maplit=1.0.2",
+        "-e",
+        with_output_marker!("hashmap!{1 => 2}")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("{1: 2}") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_expr_sh() {
+    let out = rust_script!(
+        "--sh",
+        "-e",
+        with_output_marker!("sh!(\"echo {}\", 6 * 7).to_string().parse::<i32>()?")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("42") => ()
+    )
+    .unwrap();
+}
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_expr_session() {
+    let session = format!("test-{}", std::process::id());
+    let out = rust_script!(
+        "--session",
+        &session,
+        "-e",
+        "session::set(\"x\", &vec![1, 2])"
+    )
+    .unwrap();
+    assert!(out.success());
+
+    let out = rust_script!(
+        "--session",
+        &session,
+        "-e",
+        with_output_marker!("session::get::<Vec<i32>>(\"x\")")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Some([1, 2])") => ()
+    )
+    .unwrap();
+}
@@ -13,3 +13,15 @@ fn test_clear_cache() {
     let out = rust_script!("--clear-cache").unwrap();
     assert!(out.success());
 }
+
+#[test]
+fn test_paths() {
+    let out = rust_script!(
+        #[env(RUST_SCRIPT_CONFIG_DIR = "/custom/config")]
+        "--paths"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("binary cache:"));
+    assert!(out.stdout.contains("/custom/config"));
+}
@@ -18,6 +18,16 @@ fn test_script_full_block() {
     .unwrap()
 }
 
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_script_frontmatter() {
+    let out = rust_script!("tests/data/script-frontmatter.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)") => ()
+    )
+    .unwrap()
+}
+
 #[cfg_attr(not(feature = "online_tests"), ignore)]
 #[test]
 fn test_script_full_line() {
@@ -56,6 +66,15 @@ fn test_script_invalid_doc_comment() {
     .unwrap()
 }
 
+#[test]
+fn test_script_build_script() {
+    let out = rust_script!("tests/data/script-build.rs").unwrap();
+    scan!(out.stdout_output();
+        ("42") => ()
+    )
+    .unwrap()
+}
+
 #[test]
 fn test_script_no_deps() {
     let out = rust_script!("tests/data/script-no-deps.rs").unwrap();
@@ -298,3 +317,82 @@ fn test_script_multiple_deps() {
     )
     .unwrap()
 }
+
+#[cfg_attr(not(feature = "online_tests"), ignore)]
+#[test]
+fn test_script_structured_main() {
+    let out = rust_script!("--structured-main", "tests/data/script-structured-main.rs").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "{\"answer\":42}\n");
+
+    let out = rust_script!(
+        "--structured-main",
+        "tests/data/script-structured-main.rs",
+        "fail"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(out.stderr, "{\"error\":\"no answer\"}\n");
+}
+
+#[test]
+fn test_script_local_cache() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let script = dir.path().join("local.rs");
+    std::fs::copy("tests/data/script-no-deps.rs", &script).unwrap();
+
+    let out = rust_script!("--local-cache", &script).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+    assert!(dir.path().join(".rust-script/projects").is_dir());
+    assert!(dir.path().join(".rust-script/binaries").is_dir());
+    assert!(dir.path().join(".rust-script/.gitignore").is_file());
+}
+
+#[test]
+fn test_script_from_git() {
+    let repo = tempfile::TempDir::new().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(repo.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "--quiet"]);
+    std::fs::copy("tests/data/script-no-deps.rs", repo.path().join("hello.rs")).unwrap();
+    git(&["add", "hello.rs"]);
+    git(&["commit", "--quiet", "-m", "Add hello"]);
+
+    let url = format!("git:file://{}#:hello.rs", repo.path().display());
+    let out = rust_script!(&url).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+
+    let out = rust_script!("--update", &url).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_directory() {
+    let out = rust_script!("tests/data").unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(out.stderr.contains("is a directory"));
+    assert!(out.stderr.contains("script-short.rs"));
+}
+
+#[test]
+fn test_script_extract_manifest() {
+    let out = rust_script!("--extract-manifest", "tests/data/script-full-block.rs").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "[dependencies]\nboolinator = \"=0.1.0\"\n");
+}